@@ -0,0 +1,109 @@
+import React, { useEffect, useState } from "react";
+import { z } from "zod";
+
+const historySampleSchema = z.object({
+  errors: z.number(),
+  requests_completed: z.number(),
+  slots_idle: z.number(),
+  slots_processing: z.number(),
+  taken_at: z.object({
+    nanos_since_epoch: z.number(),
+    secs_since_epoch: z.number(),
+  }),
+});
+
+const historyResponseSchema = z.object({
+  samples: z.array(historySampleSchema),
+});
+
+type HistorySample = z.infer<typeof historySampleSchema>;
+type HistoryResponse = z.infer<typeof historyResponseSchema>;
+
+// the balancer takes a sample every 5 seconds, no need to ask more often
+const REFRESH_EVERY_TICKS = 10;
+const SPARKLINE_HEIGHT = 30;
+const SPARKLINE_WIDTH = 120;
+
+function slotsUsage(sample: HistorySample): number {
+  const totalSlots = sample.slots_idle + sample.slots_processing;
+
+  if (totalSlots < 1) {
+    return 0;
+  }
+
+  return sample.slots_processing / totalSlots;
+}
+
+export function AgentHistorySparkline({
+  agentId,
+  currentTick,
+}: {
+  agentId: string;
+  currentTick: number;
+}) {
+  const [samples, setSamples] = useState<HistorySample[]>([]);
+  const refreshTick = Math.floor(currentTick / REFRESH_EVERY_TICKS);
+
+  useEffect(
+    function () {
+      const abortController = new AbortController();
+
+      fetch(`/api/v1/agents/${encodeURIComponent(agentId)}/history?minutes=15`, {
+        signal: abortController.signal,
+      })
+        .then((response) => response.json())
+        .then((history) => historyResponseSchema.parse(history))
+        .then(function (historyResponse: HistoryResponse) {
+          setSamples(historyResponse.samples);
+        })
+        .catch(function (error) {
+          // the main agents list already reports the connection issues
+          console.error(error);
+        });
+
+      return function () {
+        abortController.abort();
+      };
+    },
+    [agentId, refreshTick, setSamples],
+  );
+
+  if (samples.length < 2) {
+    return <span>Collecting...</span>;
+  }
+
+  const points = samples
+    .map(function (sample: HistorySample, index: number) {
+      const x = (index / (samples.length - 1)) * SPARKLINE_WIDTH;
+      const y = (1 - slotsUsage(sample)) * SPARKLINE_HEIGHT;
+
+      return `${x},${y}`;
+    })
+    .join(" ");
+
+  const errors = samples.reduce(function (sum: number, sample: HistorySample) {
+    return sum + sample.errors;
+  }, 0);
+
+  const requestsCompleted = samples.reduce(function (
+    sum: number,
+    sample: HistorySample,
+  ) {
+    return sum + sample.requests_completed;
+  }, 0);
+
+  return (
+    <div className="agent-history">
+      <svg
+        className="agent-history__sparkline"
+        preserveAspectRatio="none"
+        viewBox={`0 0 ${SPARKLINE_WIDTH} ${SPARKLINE_HEIGHT}`}
+      >
+        <polyline points={points} />
+      </svg>
+      <span>
+        {requestsCompleted} done, {errors} errors
+      </span>
+    </div>
+  );
+}
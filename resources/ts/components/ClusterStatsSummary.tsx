@@ -0,0 +1,75 @@
+import React, { useEffect, useState } from "react";
+import { z } from "zod";
+
+const windowStatsSchema = z.object({
+  p50_ms: z.number().nullable(),
+  p95_ms: z.number().nullable(),
+  p99_ms: z.number().nullable(),
+  requests_per_second: z.number(),
+});
+
+const clusterStatsSchema = z.object({
+  peers: z.number(),
+  requests_waiting_for_permit: z.number(),
+  slots_idle: z.number(),
+  slots_processing: z.number(),
+  slots_total: z.number(),
+  usable_peers: z.number(),
+  windows: z.object({
+    "1m": windowStatsSchema,
+  }),
+});
+
+type ClusterStats = z.infer<typeof clusterStatsSchema>;
+
+// stats change slowly, no need to ask every tick
+const REFRESH_EVERY_TICKS = 4;
+
+export function ClusterStatsSummary({ currentTick }: { currentTick: number }) {
+  const [clusterStats, setClusterStats] = useState<ClusterStats | null>(null);
+  const refreshTick = Math.floor(currentTick / REFRESH_EVERY_TICKS);
+
+  useEffect(
+    function () {
+      const abortController = new AbortController();
+
+      fetch("/api/v1/stats", {
+        signal: abortController.signal,
+      })
+        .then((response) => response.json())
+        .then((stats) => clusterStatsSchema.parse(stats))
+        .then(function (clusterStats: ClusterStats) {
+          setClusterStats(clusterStats);
+        })
+        .catch(function (error) {
+          // the main agents list already reports the connection issues
+          console.error(error);
+        });
+
+      return function () {
+        abortController.abort();
+      };
+    },
+    [refreshTick, setClusterStats],
+  );
+
+  if (!clusterStats) {
+    return null;
+  }
+
+  const lastMinute = clusterStats.windows["1m"];
+
+  return (
+    <p>
+      Usable agents: {clusterStats.usable_peers} / {clusterStats.peers}
+      {" | "}
+      Processing slots: {clusterStats.slots_processing} /{" "}
+      {clusterStats.slots_total}
+      {" | "}
+      Waiting: {clusterStats.requests_waiting_for_permit}
+      {" | "}
+      Last minute: {lastMinute.requests_per_second.toFixed(2)} req/s, p95{" "}
+      {null === lastMinute.p95_ms ? "-" : `${lastMinute.p95_ms} ms`}
+    </p>
+  );
+}
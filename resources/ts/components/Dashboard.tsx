@@ -2,25 +2,32 @@ import clsx from "clsx";
 import React, { useEffect, useState, CSSProperties } from "react";
 import { z } from "zod";
 
+import { AgentHistorySparkline } from "./AgentHistorySparkline";
+import { ClusterStatsSummary } from "./ClusterStatsSummary";
 import { DashboardLayout } from "./DashboardLayout";
 
 const agentSchema = z.object({
+  age_ms: z.number().nullable(),
   agent_id: z.string(),
   agent_name: z.string().nullable(),
   error: z.string().nullable(),
   external_llamacpp_addr: z.string(),
   is_authorized: z.boolean().nullable(),
+  is_draining: z.boolean(),
   is_slots_endpoint_enabled: z.boolean().nullable(),
+  is_stale: z.boolean(),
   last_update: z.object({
     nanos_since_epoch: z.number(),
     secs_since_epoch: z.number(),
   }),
+  max_concurrency: z.number().nullable(),
   quarantined_until: z
     .object({
       nanos_since_epoch: z.number(),
       secs_since_epoch: z.number(),
     })
     .nullable(),
+  requests_in_flight: z.number(),
   slots_idle: z.number(),
   slots_processing: z.number(),
 });
@@ -132,6 +139,7 @@ export function Dashboard() {
   return (
     <DashboardLayout currentTick={currentTick}>
       <h1>Paddler 🏓</h1>
+      <ClusterStatsSummary currentTick={currentTick} />
       <h2>Registered Agents</h2>
       <table>
         <thead>
@@ -142,6 +150,8 @@ export function Dashboard() {
             <th>Last update</th>
             <th>Idle slots</th>
             <th>Processing slots</th>
+            <th>In flight</th>
+            <th>Last 15 minutes</th>
           </tr>
         </thead>
         <tbody>
@@ -150,12 +160,14 @@ export function Dashboard() {
               agent.error ||
               true !== agent.is_authorized ||
               true !== agent.is_slots_endpoint_enabled ||
+              agent.is_draining ||
               agent.quarantined_until;
 
             return (
               <tr
                 className={clsx("agent-row", {
                   "agent-row--error": hasIssues,
+                  "agent-row--stale": !hasIssues && agent.is_stale,
                 })}
                 key={agent.agent_id}
               >
@@ -186,6 +198,12 @@ export function Dashboard() {
                       </p>
                     </>
                   )}
+                  {agent.is_draining && (
+                    <>
+                      <p>Draining</p>
+                      <p>Rolling drain takes this agent out of the pool.</p>
+                    </>
+                  )}
                   {agent.quarantined_until && (
                     <>
                       <p>
@@ -203,9 +221,28 @@ export function Dashboard() {
                     {agent.external_llamacpp_addr}
                   </a>
                 </td>
-                <td>{formatTimestamp(agent.last_update.secs_since_epoch)}</td>
+                <td>
+                  {formatTimestamp(agent.last_update.secs_since_epoch)}
+                  {null !== agent.age_ms && (
+                    <p>
+                      Reported {agent.age_ms} ms ago
+                      {agent.is_stale && ", missed its reports"}
+                    </p>
+                  )}
+                </td>
                 <td>{agent.slots_idle}</td>
                 <td>{agent.slots_processing}</td>
+                <td>
+                  {agent.requests_in_flight}
+                  {null !== agent.max_concurrency &&
+                    ` / ${agent.max_concurrency}`}
+                </td>
+                <td>
+                  <AgentHistorySparkline
+                    agentId={agent.agent_id}
+                    currentTick={currentTick}
+                  />
+                </td>
                 <td
                   className="agent-usage"
                   style={
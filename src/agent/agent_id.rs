@@ -0,0 +1,50 @@
+use log::info;
+use std::{fs, net::SocketAddr, path::Path};
+use uuid::Uuid;
+
+use crate::errors::result::Result;
+
+const AGENT_ID_FILE: &str = "agent_id";
+
+/// The id has to stay the same across agent restarts, otherwise the balancer would briefly
+/// see the same llama.cpp instance twice
+pub fn resolve_agent_id(
+    agent_id: Option<String>,
+    external_llamacpp_addr: SocketAddr,
+    name: Option<&str>,
+    state_dir: Option<&Path>,
+) -> Result<String> {
+    if let Some(agent_id) = agent_id {
+        return Ok(agent_id);
+    }
+
+    let state_dir = match state_dir {
+        Some(state_dir) => state_dir,
+        None => return Ok(derive_agent_id(external_llamacpp_addr, name)),
+    };
+
+    let agent_id_file = state_dir.join(AGENT_ID_FILE);
+
+    if agent_id_file.exists() {
+        let agent_id = fs::read_to_string(&agent_id_file)?.trim().to_string();
+
+        if !agent_id.is_empty() {
+            return Ok(agent_id);
+        }
+    }
+
+    let agent_id = Uuid::new_v4().to_string();
+
+    fs::create_dir_all(state_dir)?;
+    fs::write(&agent_id_file, &agent_id)?;
+
+    info!("Persisted agent id in {}", agent_id_file.display());
+
+    Ok(agent_id)
+}
+
+fn derive_agent_id(external_llamacpp_addr: SocketAddr, name: Option<&str>) -> String {
+    let seed = format!("{}@{}", name.unwrap_or_default(), external_llamacpp_addr);
+
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, seed.as_bytes()).to_string()
+}
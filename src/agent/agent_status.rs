@@ -0,0 +1,164 @@
+use serde::Serialize;
+use std::{
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    balancer::status_update::StatusUpdate, errors::result::Result, llamacpp::props::Props,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AgentStatusSnapshot {
+    pub consecutive_report_failures: usize,
+    pub is_authorized: Option<bool>,
+    pub is_connected_to_balancer: bool,
+    /// Result of the last llama.cpp health check
+    pub is_llamacpp_healthy: Option<bool>,
+    pub is_slots_endpoint_enabled: Option<bool>,
+    pub last_report_error: Option<String>,
+    pub last_scrape_error: Option<String>,
+    pub last_successful_report: Option<SystemTime>,
+    pub last_successful_scrape: Option<SystemTime>,
+    /// Last llama.cpp props, None if they were never fetched or llama.cpp does not expose them
+    pub llamacpp_props: Option<Props>,
+    pub slots_idle: usize,
+    pub slots_processing: usize,
+    pub version: &'static str,
+}
+
+pub struct AgentStatus {
+    monitoring_interval: Duration,
+    snapshot: RwLock<AgentStatusSnapshot>,
+}
+
+impl AgentStatus {
+    pub fn new(monitoring_interval: Duration) -> Self {
+        AgentStatus {
+            monitoring_interval,
+            snapshot: RwLock::new(AgentStatusSnapshot {
+                consecutive_report_failures: 0,
+                is_authorized: None,
+                is_connected_to_balancer: false,
+                is_llamacpp_healthy: None,
+                is_slots_endpoint_enabled: None,
+                last_report_error: None,
+                last_scrape_error: None,
+                last_successful_report: None,
+                last_successful_scrape: None,
+                llamacpp_props: None,
+                slots_idle: 0,
+                slots_processing: 0,
+                version: env!("CARGO_PKG_VERSION"),
+            }),
+        }
+    }
+
+    /// Agent is healthy if both llama.cpp and the balancer were reachable within the last few
+    /// monitoring intervals
+    pub fn is_healthy(&self) -> Result<bool> {
+        let max_age = self.monitoring_interval * 3;
+
+        self.with_snapshot_read(|snapshot| {
+            Ok(is_recent(snapshot.last_successful_scrape, max_age)
+                && is_recent(snapshot.last_successful_report, max_age))
+        })
+    }
+
+    pub fn register_connection_closed(&self, error: Option<String>) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.is_connected_to_balancer = false;
+
+            if let Some(error) = error {
+                snapshot.consecutive_report_failures += 1;
+                snapshot.last_report_error = Some(error);
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn register_connection_opened(&self) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.is_connected_to_balancer = true;
+
+            Ok(())
+        })
+    }
+
+    pub fn register_health(&self, is_llamacpp_healthy: bool) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.is_llamacpp_healthy = Some(is_llamacpp_healthy);
+
+            Ok(())
+        })
+    }
+
+    pub fn register_props(&self, llamacpp_props: Option<Props>) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.llamacpp_props = llamacpp_props;
+
+            Ok(())
+        })
+    }
+
+    /// Called when the balancer acknowledged a status update
+    pub fn register_report(&self) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.consecutive_report_failures = 0;
+            snapshot.last_report_error = None;
+            snapshot.last_successful_report = Some(SystemTime::now());
+
+            Ok(())
+        })
+    }
+
+    pub fn register_scrape(&self, status_update: &StatusUpdate) -> Result<()> {
+        self.with_snapshot_write(|snapshot| {
+            snapshot.is_authorized = status_update.is_authorized;
+            snapshot.is_slots_endpoint_enabled = status_update.is_slots_endpoint_enabled;
+            snapshot.last_scrape_error = status_update.error.to_owned();
+            snapshot.slots_idle = status_update.idle_slots_count;
+            snapshot.slots_processing = status_update.processing_slots_count;
+
+            if status_update.error.is_none() {
+                snapshot.last_successful_scrape = Some(SystemTime::now());
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn snapshot(&self) -> Result<AgentStatusSnapshot> {
+        self.with_snapshot_read(|snapshot| Ok(snapshot.clone()))
+    }
+
+    #[inline]
+    fn with_snapshot_read<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
+    where
+        TCallback: FnOnce(&AgentStatusSnapshot) -> Result<TResult>,
+    {
+        match self.snapshot.read() {
+            Ok(snapshot) => cb(&snapshot),
+            Err(_) => Err("Failed to acquire read lock".into()),
+        }
+    }
+
+    #[inline]
+    fn with_snapshot_write<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
+    where
+        TCallback: FnOnce(&mut AgentStatusSnapshot) -> Result<TResult>,
+    {
+        match self.snapshot.write() {
+            Ok(mut snapshot) => cb(&mut snapshot),
+            Err(_) => Err("Failed to acquire write lock".into()),
+        }
+    }
+}
+
+fn is_recent(time: Option<SystemTime>, max_age: Duration) -> bool {
+    match time.map(|time| time.elapsed()) {
+        Some(Ok(elapsed)) => elapsed <= max_age,
+        _ => false,
+    }
+}
@@ -0,0 +1,16 @@
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::agent::agent_status::AgentStatus;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/healthz")]
+async fn respond(agent_status: web::Data<AgentStatus>) -> Result<HttpResponse, Error> {
+    if agent_status.is_healthy()? {
+        Ok(HttpResponse::Ok().body("OK"))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().body("Unhealthy"))
+    }
+}
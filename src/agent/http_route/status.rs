@@ -0,0 +1,12 @@
+use actix_web::{get, web, Error, Responder};
+
+use crate::agent::agent_status::AgentStatus;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/status")]
+async fn respond(agent_status: web::Data<AgentStatus>) -> Result<impl Responder, Error> {
+    Ok(web::Json(agent_status.snapshot()?))
+}
@@ -1,2 +1,7 @@
+pub mod agent_id;
+pub mod agent_status;
+pub mod http_route;
 pub mod monitoring_service;
 pub mod reporting_service;
+pub mod status_service;
+pub mod supervisor_service;
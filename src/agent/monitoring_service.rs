@@ -1,64 +1,264 @@
 use actix_web::web::Bytes;
 use async_trait::async_trait;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use pingora::{server::ShutdownWatch, services::Service};
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
 use tokio::{
-    sync::broadcast::Sender,
-    time::{interval, Duration, MissedTickBehavior},
+    sync::{broadcast::Sender, watch::Receiver},
+    time::{interval, Duration, Instant, MissedTickBehavior},
 };
 
 #[cfg(unix)]
 use pingora::server::ListenFds;
 
 use crate::{
-    balancer::status_update::StatusUpdate, errors::result::Result,
-    llamacpp::llamacpp_client::LlamacppClient,
+    agent::agent_status::AgentStatus,
+    balancer::{status_update::StatusUpdate, upstream_protocol::UpstreamProtocol},
+    errors::result::Result,
+    llamacpp::{llamacpp_client::LlamacppClient, model_info::ModelInfo},
 };
 
+const MODEL_INFO_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct MonitoringService {
+    agent_status: Arc<AgentStatus>,
+    external_host: Option<String>,
     external_llamacpp_addr: SocketAddr,
+    hardware_class: Option<String>,
+    is_llamacpp_reachable: Option<bool>,
+    labels: BTreeMap<String, String>,
+    llamacpp_build_info: Option<String>,
     llamacpp_client: LlamacppClient,
+    llamacpp_error_rx: Option<Receiver<Option<String>>>,
+    max_concurrency: Option<usize>,
+    model_info: Option<ModelInfo>,
+    model_info_refreshed_at: Option<Instant>,
+    model_version: Option<String>,
     monitoring_interval: Duration,
     name: Option<String>,
+    restart_epoch: u64,
     status_update_tx: Sender<Bytes>,
+    tier: usize,
+    upstream_headers: BTreeMap<String, String>,
+    upstream_protocol: Option<UpstreamProtocol>,
 }
 
 impl MonitoringService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        agent_status: Arc<AgentStatus>,
+        external_host: Option<String>,
         external_llamacpp_addr: SocketAddr,
+        hardware_class: Option<String>,
+        labels: BTreeMap<String, String>,
         llamacpp_client: LlamacppClient,
+        llamacpp_error_rx: Option<Receiver<Option<String>>>,
+        max_concurrency: Option<usize>,
+        model_version: Option<String>,
         monitoring_interval: Duration,
         name: Option<String>,
         status_update_tx: Sender<Bytes>,
+        tier: usize,
+        upstream_headers: BTreeMap<String, String>,
+        upstream_protocol: Option<UpstreamProtocol>,
     ) -> Result<Self> {
         Ok(MonitoringService {
+            agent_status,
+            external_host,
             external_llamacpp_addr,
+            hardware_class,
+            is_llamacpp_reachable: None,
+            labels,
+            llamacpp_build_info: None,
             llamacpp_client,
+            llamacpp_error_rx,
+            max_concurrency,
+            model_info: None,
+            model_info_refreshed_at: None,
+            model_version,
             monitoring_interval,
             name,
+            restart_epoch: 0,
             status_update_tx,
+            tier,
+            upstream_headers,
+            upstream_protocol,
         })
     }
 
-    async fn fetch_status(&self) -> Result<StatusUpdate> {
-        match self.llamacpp_client.get_available_slots().await {
-            Ok(slots_response) => Ok(StatusUpdate::new(
-                self.name.to_owned(),
-                None,
-                self.external_llamacpp_addr.to_owned(),
-                slots_response.is_authorized,
-                slots_response.is_slot_endpoint_enabled,
-                slots_response.slots,
-            )),
-            Err(err) => Ok(StatusUpdate::new(
+    async fn refresh_model_info(&mut self) {
+        let is_fresh = self
+            .model_info_refreshed_at
+            .is_some_and(|refreshed_at| refreshed_at.elapsed() < MODEL_INFO_REFRESH_INTERVAL);
+
+        if is_fresh {
+            return;
+        }
+
+        match self.llamacpp_client.get_props().await {
+            Ok(props) => {
+                let build_info = props.as_ref().and_then(|props| props.build_info.to_owned());
+
+                if self.llamacpp_build_info.is_some() && self.llamacpp_build_info != build_info {
+                    self.register_restart();
+                }
+
+                if let Err(err) = self.agent_status.register_props(props.to_owned()) {
+                    error!("Failed to register llama.cpp props: {}", err);
+                }
+
+                self.llamacpp_build_info = build_info;
+                self.model_info = props.map(ModelInfo::new_from_props);
+                self.model_info_refreshed_at = Some(Instant::now());
+            }
+            Err(err) => {
+                // try again with the next status check
+                warn!("Failed to fetch llama.cpp props: {}", err);
+            }
+        }
+    }
+
+    /// All the slots and caches are gone after a restart, so the balancer needs to know
+    fn register_restart(&mut self) {
+        info!("Detected llama.cpp restart");
+
+        self.restart_epoch += 1;
+        self.model_info_refreshed_at = None;
+    }
+
+    /// Only shown in the agent status, the slots decide whether the balancer uses the agent
+    async fn check_health(&self) {
+        let is_healthy = match self.llamacpp_client.is_healthy().await {
+            Ok(is_healthy) => is_healthy,
+            Err(err) => {
+                debug!("Failed to check llama.cpp health: {}", err);
+
+                false
+            }
+        };
+
+        if let Err(err) = self.agent_status.register_health(is_healthy) {
+            error!("Failed to register llama.cpp health: {}", err);
+        }
+    }
+
+    fn register_reachability(&mut self, is_reachable: bool) {
+        if is_reachable && matches!(self.is_llamacpp_reachable, Some(false)) {
+            self.register_restart();
+        }
+
+        self.is_llamacpp_reachable = Some(is_reachable);
+    }
+
+    async fn fetch_status(&mut self) -> Result<StatusUpdate> {
+        // llama.cpp is supervised by the agent and is currently down or restarting
+        let llamacpp_error = match &self.llamacpp_error_rx {
+            Some(llamacpp_error_rx) => llamacpp_error_rx.borrow().to_owned(),
+            None => None,
+        };
+
+        if let Some(llamacpp_error) = llamacpp_error {
+            self.register_reachability(false);
+
+            if let Err(err) = self.agent_status.register_health(false) {
+                error!("Failed to register llama.cpp health: {}", err);
+            }
+
+            return Ok(StatusUpdate::new(
                 self.name.to_owned(),
-                Some(err.to_string()),
+                Some(llamacpp_error),
+                self.external_host.to_owned(),
                 self.external_llamacpp_addr.to_owned(),
+                self.hardware_class.to_owned(),
+                None,
                 None,
+                self.labels.to_owned(),
+                self.max_concurrency,
                 None,
+                self.model_version.to_owned(),
+                None,
+                self.restart_epoch,
                 vec![],
-            )),
+                Some(self.monitoring_interval),
+                self.tier,
+                self.upstream_headers.to_owned(),
+                self.upstream_protocol,
+            ));
+        }
+
+        self.check_health().await;
+
+        match self.llamacpp_client.get_available_slots().await {
+            Ok(slots_response) => {
+                self.register_reachability(true);
+                self.refresh_model_info().await;
+
+                let mut model_info = self.model_info.to_owned();
+                let queued_requests_count =
+                    match self.llamacpp_client.get_requests_deferred().await {
+                        Ok(queued_requests_count) => queued_requests_count,
+                        Err(err) => {
+                            // the queue is only a hint for the balancer, the slots are enough
+                            debug!("Failed to fetch llama.cpp metrics: {}", err);
+
+                            None
+                        }
+                    };
+
+                // older llama.cpp versions do not report the total slots in props
+                if matches!(slots_response.is_slot_endpoint_enabled, Some(true)) {
+                    let model_info = model_info.get_or_insert_with(ModelInfo::default);
+
+                    model_info
+                        .total_slots
+                        .get_or_insert(slots_response.slots.len());
+                }
+
+                Ok(StatusUpdate::new(
+                    self.name.to_owned(),
+                    None,
+                    self.external_host.to_owned(),
+                    self.external_llamacpp_addr.to_owned(),
+                    self.hardware_class.to_owned(),
+                    slots_response.is_authorized,
+                    slots_response.is_slot_endpoint_enabled,
+                    self.labels.to_owned(),
+                    self.max_concurrency,
+                    model_info,
+                    self.model_version.to_owned(),
+                    queued_requests_count,
+                    self.restart_epoch,
+                    slots_response.slots,
+                    Some(self.monitoring_interval),
+                    self.tier,
+                    self.upstream_headers.to_owned(),
+                    self.upstream_protocol,
+                ))
+            }
+            Err(err) => {
+                self.register_reachability(false);
+
+                Ok(StatusUpdate::new(
+                    self.name.to_owned(),
+                    Some(err.to_string()),
+                    self.external_host.to_owned(),
+                    self.external_llamacpp_addr.to_owned(),
+                    self.hardware_class.to_owned(),
+                    None,
+                    None,
+                    self.labels.to_owned(),
+                    self.max_concurrency,
+                    None,
+                    self.model_version.to_owned(),
+                    None,
+                    self.restart_epoch,
+                    vec![],
+                    Some(self.monitoring_interval),
+                    self.tier,
+                    self.upstream_headers.to_owned(),
+                    self.upstream_protocol,
+                ))
+            }
         }
     }
 
@@ -89,6 +289,10 @@ impl Service for MonitoringService {
                 _ = ticker.tick() => {
                     match self.fetch_status().await {
                         Ok(status) => {
+                            if let Err(err) = self.agent_status.register_scrape(&status) {
+                                error!("Failed to register scrape: {}", err);
+                            }
+
                             if let Err(err) = self.report_status(status).await {
                                 error!("Failed to report status: {}", err);
                             }
@@ -1,30 +1,41 @@
 use actix_web::web::Bytes;
 use async_trait::async_trait;
+use futures_util::StreamExt as _;
 use log::{debug, error, info};
 use pingora::{server::ShutdownWatch, services::Service};
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::{
-    sync::broadcast::Sender,
+    sync::{broadcast::Sender, watch::Receiver},
     time::{interval, Duration, MissedTickBehavior},
 };
 use tokio_stream::wrappers::BroadcastStream;
-use uuid::Uuid;
 
 #[cfg(unix)]
 use pingora::server::ListenFds;
 
-use crate::errors::result::Result;
+use crate::{
+    agent::agent_status::AgentStatus,
+    errors::{app_error::AppError, result::Result},
+};
 
 pub struct ReportingService {
+    agent_status: Arc<AgentStatus>,
+    llamacpp_error_rx: Option<Receiver<Option<String>>>,
     stats_endpoint_url: String,
     status_update_tx: Sender<Bytes>,
 }
 
 impl ReportingService {
-    pub fn new(management_addr: SocketAddr, status_update_tx: Sender<Bytes>) -> Result<Self> {
-        let agent_id = Uuid::new_v4();
-
+    pub fn new(
+        agent_id: String,
+        agent_status: Arc<AgentStatus>,
+        llamacpp_error_rx: Option<Receiver<Option<String>>>,
+        management_addr: SocketAddr,
+        status_update_tx: Sender<Bytes>,
+    ) -> Result<Self> {
         Ok(ReportingService {
+            agent_status,
+            llamacpp_error_rx,
             stats_endpoint_url: format!("http://{}/status_update/{}", management_addr, agent_id),
             status_update_tx,
         })
@@ -32,23 +43,53 @@ impl ReportingService {
 
     async fn keep_connection_alive(&self) -> Result<()> {
         let status_update_rx = self.status_update_tx.subscribe();
-        let stream = BroadcastStream::new(status_update_rx);
-        let reqwest_body = reqwest::Body::wrap_stream(stream);
+        let reqwest_body = reqwest::Body::wrap_stream(BroadcastStream::new(status_update_rx));
 
         info!("Establishing connection with management server");
 
+        self.agent_status.register_connection_opened()?;
+
         match reqwest::Client::new()
             .post(self.stats_endpoint_url.to_owned())
             .body(reqwest_body)
             .send()
             .await
         {
-            Ok(_) => {
+            Ok(response) if response.status().is_success() => {
+                // the balancer acknowledges every status update it registered
+                let mut acks = response.bytes_stream();
+
+                while let Some(ack) = acks.next().await {
+                    if let Err(err) = ack {
+                        self.agent_status
+                            .register_connection_closed(Some(err.to_string()))?;
+
+                        return Err(err.into());
+                    }
+
+                    self.agent_status.register_report()?;
+                }
+
                 error!("Management server connection closed");
 
+                self.agent_status.register_connection_closed(None)?;
+
                 Ok(())
             }
-            Err(err) => Err(err.into()),
+            Ok(response) => {
+                let error = format!("Management server responded with {}", response.status());
+
+                self.agent_status
+                    .register_connection_closed(Some(error.to_owned()))?;
+
+                Err(AppError::UnexpectedError(error))
+            }
+            Err(err) => {
+                self.agent_status
+                    .register_connection_closed(Some(err.to_string()))?;
+
+                Err(err.into())
+            }
         }
     }
 }
@@ -60,6 +101,23 @@ impl Service for ReportingService {
         #[cfg(unix)] _fds: Option<ListenFds>,
         mut shutdown: ShutdownWatch,
     ) {
+        // do not register with the balancer before the supervised llama.cpp becomes healthy
+        if let Some(llamacpp_error_rx) = self.llamacpp_error_rx.as_mut() {
+            info!("Waiting for llama.cpp to become healthy");
+
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down reporting service");
+                    return;
+                },
+                result = llamacpp_error_rx.wait_for(|llamacpp_error| llamacpp_error.is_none()) => {
+                    if let Err(err) = result {
+                        error!("Failed to wait for llama.cpp: {}", err);
+                    }
+                }
+            }
+        }
+
         let mut ticker = interval(Duration::from_secs(1));
 
         ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
@@ -0,0 +1,51 @@
+use actix_web::{web::Data, App, HttpServer};
+use async_trait::async_trait;
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{net::SocketAddr, sync::Arc};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::agent::{agent_status::AgentStatus, http_route};
+
+pub struct StatusService {
+    addr: SocketAddr,
+    agent_status: Arc<AgentStatus>,
+}
+
+impl StatusService {
+    pub fn new(addr: SocketAddr, agent_status: Arc<AgentStatus>) -> Self {
+        StatusService { addr, agent_status }
+    }
+}
+
+#[async_trait]
+impl Service for StatusService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut _shutdown: ShutdownWatch,
+    ) {
+        let agent_status: Data<AgentStatus> = self.agent_status.clone().into();
+
+        HttpServer::new(move || {
+            App::new()
+                .app_data(agent_status.clone())
+                .configure(http_route::healthz::register)
+                .configure(http_route::status::register)
+        })
+        .bind(self.addr)
+        .expect("Unable to bind server to address")
+        .run()
+        .await
+        .expect("Server unexpectedly stopped");
+    }
+
+    fn name(&self) -> &str {
+        "status"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::process::{ExitStatus, Stdio};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::watch::{Receiver, Sender},
+    time::{interval, sleep, timeout, Duration, Instant, MissedTickBehavior},
+};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{errors::result::Result, llamacpp::llamacpp_client::LlamacppClient};
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+
+pub struct SupervisorService {
+    args: Vec<String>,
+    grace_period: Duration,
+    llamacpp_client: LlamacppClient,
+    llamacpp_error_tx: Sender<Option<String>>,
+    program: String,
+}
+
+impl SupervisorService {
+    pub fn new(
+        command_line: &str,
+        grace_period: Duration,
+        llamacpp_client: LlamacppClient,
+        llamacpp_error_tx: Sender<Option<String>>,
+    ) -> Result<Self> {
+        // quoted the same way as in a shell, so the paths and the chat templates can have spaces
+        let mut parts = match shlex::split(command_line) {
+            Some(parts) => parts.into_iter(),
+            None => return Err("llama.cpp command line has an unclosed quote".into()),
+        };
+        let program = match parts.next() {
+            Some(program) => program,
+            None => return Err("llama.cpp command line is empty".into()),
+        };
+
+        Ok(SupervisorService {
+            args: parts.collect(),
+            grace_period,
+            llamacpp_client,
+            llamacpp_error_tx,
+            program,
+        })
+    }
+
+    pub fn subscribe(&self) -> Receiver<Option<String>> {
+        self.llamacpp_error_tx.subscribe()
+    }
+
+    fn set_error(&self, error: Option<String>) {
+        self.llamacpp_error_tx.send_replace(error);
+    }
+
+    fn spawn(&self) -> Result<Child> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_output(stdout));
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_output(stderr));
+        }
+
+        Ok(child)
+    }
+
+    async fn terminate(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SAFETY: the pid belongs to our own child process, which has not been reaped yet
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+
+            match timeout(self.grace_period, child.wait()).await {
+                Ok(Ok(status)) => {
+                    info!("llama.cpp exited with {}", status);
+
+                    return;
+                }
+                Ok(Err(err)) => error!("Failed to wait for llama.cpp to exit: {}", err),
+                Err(_) => warn!("llama.cpp did not exit within the grace period, killing it"),
+            }
+        }
+
+        if let Err(err) = child.kill().await {
+            error!("Failed to kill llama.cpp: {}", err);
+        }
+    }
+
+    /// Returns None if the service is shutting down, otherwise the exit status of llama.cpp
+    async fn supervise(
+        &self,
+        child: &mut Child,
+        shutdown: &mut ShutdownWatch,
+    ) -> Option<std::io::Result<ExitStatus>> {
+        let mut ticker = interval(HEALTH_CHECK_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut is_healthy = false;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    return None;
+                },
+                status = child.wait() => {
+                    return Some(status);
+                },
+                _ = ticker.tick(), if !is_healthy => {
+                    match self.llamacpp_client.is_healthy().await {
+                        Ok(true) => {
+                            info!("llama.cpp is healthy");
+
+                            is_healthy = true;
+                            self.set_error(None);
+                        }
+                        Ok(false) => debug!("llama.cpp is not healthy yet"),
+                        Err(err) => debug!("llama.cpp is not reachable yet: {}", err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Service for SupervisorService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut backoff = RESTART_BACKOFF_MIN;
+
+        loop {
+            info!("Starting llama.cpp: {} {}", self.program, self.args.join(" "));
+
+            self.set_error(Some("llama.cpp is starting".to_string()));
+
+            let started_at = Instant::now();
+
+            match self.spawn() {
+                Ok(mut child) => match self.supervise(&mut child, &mut shutdown).await {
+                    None => {
+                        debug!("Shutting down supervisor service");
+                        self.terminate(&mut child).await;
+
+                        return;
+                    }
+                    Some(Ok(status)) => {
+                        error!("llama.cpp exited with {}", status);
+                        self.set_error(Some(format!("llama.cpp exited with {}", status)));
+                    }
+                    Some(Err(err)) => {
+                        error!("Failed to wait for llama.cpp: {}", err);
+                        self.set_error(Some(format!("Failed to wait for llama.cpp: {}", err)));
+                    }
+                },
+                Err(err) => {
+                    error!("Failed to start llama.cpp: {}", err);
+                    self.set_error(Some(format!("Failed to start llama.cpp: {}", err)));
+                }
+            }
+
+            // process was stable for a while, so this is not a crash loop
+            if started_at.elapsed() > RESTART_BACKOFF_MAX {
+                backoff = RESTART_BACKOFF_MIN;
+            }
+
+            info!("Restarting llama.cpp in {:?}", backoff);
+
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down supervisor service");
+                    return;
+                },
+                _ = sleep(backoff) => {
+                    backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "supervisor"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+async fn forward_output<TReader>(reader: TReader)
+where
+    TReader: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => info!("[llama.cpp] {}", line),
+            Ok(None) => return,
+            Err(err) => {
+                error!("Failed to read llama.cpp output: {}", err);
+
+                return;
+            }
+        }
+    }
+}
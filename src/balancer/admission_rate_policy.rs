@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+/// Spreads the new requests to a single peer over time, so llama.cpp does not get all of them
+/// in the same batch even if it has the idle slots
+#[derive(Clone, Copy, Debug)]
+pub struct AdmissionRatePolicy {
+    /// New requests the peer can take at once after it was idle for a while
+    pub burst: usize,
+    pub per_second: f64,
+}
+
+impl AdmissionRatePolicy {
+    pub fn time_per_admission(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.per_second)
+    }
+}
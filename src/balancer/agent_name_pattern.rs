@@ -0,0 +1,70 @@
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// `gpu-old-*` matches the names starting with `gpu-old-`: `*` stands for any characters and
+/// `?` for a single one. A pattern without them matches the names that contain it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AgentNamePattern(String);
+
+impl AgentNamePattern {
+    pub fn matches(&self, agent_name: &str) -> bool {
+        if !self.0.contains(['*', '?']) {
+            return agent_name.contains(&self.0);
+        }
+
+        let pattern: Vec<char> = self.0.chars().collect();
+        let agent_name: Vec<char> = agent_name.chars().collect();
+        let mut pattern_pos = 0;
+        let mut agent_name_pos = 0;
+        // where the last `*` is in the pattern, and where it started matching in the name
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while agent_name_pos < agent_name.len() {
+            match pattern.get(pattern_pos) {
+                Some('*') => {
+                    backtrack = Some((pattern_pos, agent_name_pos));
+                    pattern_pos += 1;
+                }
+                Some(&c) if c == '?' || c == agent_name[agent_name_pos] => {
+                    pattern_pos += 1;
+                    agent_name_pos += 1;
+                }
+                _ => match backtrack {
+                    // let the `*` take one more character
+                    Some((star_pos, star_agent_name_pos)) => {
+                        backtrack = Some((star_pos, star_agent_name_pos + 1));
+                        pattern_pos = star_pos + 1;
+                        agent_name_pos = star_agent_name_pos + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[pattern_pos..].iter().all(|c| *c == '*')
+    }
+}
+
+impl FromStr for AgentNamePattern {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        // an empty pattern would match every agent
+        if arg.is_empty() {
+            return Err("Agent name pattern cannot be empty".into());
+        }
+
+        Ok(AgentNamePattern(arg.to_string()))
+    }
+}
+
+impl TryFrom<String> for AgentNamePattern {
+    type Error = AppError;
+
+    fn try_from(arg: String) -> Result<Self, Self::Error> {
+        arg.parse()
+    }
+}
@@ -0,0 +1,77 @@
+use pingora::protocols::SocketDigest;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+};
+
+/// Caps the connections a single client IP can have open at the same time, across all the
+/// listeners. Pingora does not tell when a connection is accepted or closed, so the connections
+/// are found by their socket digest, which lives as long as the connection, when they send a
+/// request.
+pub struct ClientConnectionLimiter {
+    /// The weak references also keep the addresses of the digests from being reused while they
+    /// are counted
+    connections: Mutex<HashMap<IpAddr, Vec<Weak<SocketDigest>>>>,
+    /// Clients that are never limited, for example internal monitoring
+    exempt: Vec<IpAddr>,
+    max_connections_per_client: usize,
+}
+
+impl ClientConnectionLimiter {
+    pub fn new(exempt: Vec<IpAddr>, max_connections_per_client: usize) -> Self {
+        ClientConnectionLimiter {
+            connections: Mutex::new(HashMap::new()),
+            exempt,
+            max_connections_per_client,
+        }
+    }
+
+    /// False means the connection is new, and the client already has
+    /// `max_connections_per_client` open. The requests on the connections that are already
+    /// counted are never limited.
+    pub fn admit(&self, ip: IpAddr, socket_digest: &Arc<SocketDigest>) -> bool {
+        if self.exempt.contains(&ip) {
+            return true;
+        }
+
+        let mut connections = self.connections();
+
+        if !connections.contains_key(&ip) {
+            // do not keep the clients that went away
+            connections.retain(|_, client_connections| {
+                client_connections
+                    .iter()
+                    .any(|connection| connection.strong_count() > 0)
+            });
+        }
+
+        let client_connections = connections.entry(ip).or_default();
+
+        client_connections.retain(|connection| connection.strong_count() > 0);
+
+        if client_connections
+            .iter()
+            .any(|connection| connection.as_ptr() == Arc::as_ptr(socket_digest))
+        {
+            return true;
+        }
+
+        if client_connections.len() >= self.max_connections_per_client {
+            return false;
+        }
+
+        client_connections.push(Arc::downgrade(socket_digest));
+
+        true
+    }
+
+    #[inline]
+    fn connections(&self) -> MutexGuard<'_, HashMap<IpAddr, Vec<Weak<SocketDigest>>>> {
+        // the lock only guards the connections, which are always left consistent
+        match self.connections.lock() {
+            Ok(connections) => connections,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Requests are counted in buckets of this duration, so the memory does not grow with the
+/// traffic
+const BUCKET_DURATION: Duration = Duration::from_secs(10);
+
+/// Longest window, 15 minutes
+const BUCKETS_COUNT: usize = 90;
+
+/// Upper bounds of the latency histogram, the percentiles are rounded up to them
+const LATENCY_BOUNDS_MS: [u64; 16] = [
+    5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 20_000, 30_000, 60_000, 120_000,
+    300_000,
+];
+
+const WINDOWS: [(&str, usize); 3] = [("1m", 6), ("5m", 30), ("15m", 90)];
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// `--max-downstream-connections` are open
+    ConnectionLimit,
+    /// No agent with an idle slot meets the request requirements
+    NoPeers,
+    /// `--max-queued-requests` is reached
+    QueueFull,
+    /// `--token-quota` of the listener is used up
+    QuotaExceeded,
+    /// `--max-connections-per-client` is reached
+    RateLimited,
+    /// Missing or invalid listener API key
+    Unauthorized,
+}
+
+impl RejectionReason {
+    const ALL: [RejectionReason; 6] = [
+        RejectionReason::ConnectionLimit,
+        RejectionReason::NoPeers,
+        RejectionReason::QueueFull,
+        RejectionReason::QuotaExceeded,
+        RejectionReason::RateLimited,
+        RejectionReason::Unauthorized,
+    ];
+}
+
+struct Bucket {
+    /// The last one counts all the latencies above the bounds
+    latency_counts: [usize; LATENCY_BOUNDS_MS.len() + 1],
+    rejections: BTreeMap<RejectionReason, usize>,
+    requests: usize,
+    started_at: Instant,
+}
+
+impl Bucket {
+    fn new(started_at: Instant) -> Self {
+        Bucket {
+            latency_counts: [0; LATENCY_BOUNDS_MS.len() + 1],
+            rejections: BTreeMap::new(),
+            requests: 0,
+            started_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WindowStats {
+    /// None if no request finished within the window, in milliseconds
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub rejections: BTreeMap<RejectionReason, usize>,
+    pub requests_per_second: f64,
+}
+
+/// Requests, their durations and rejections of all the listeners, over the last 15 minutes
+#[derive(Default)]
+pub struct ClusterStats {
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl ClusterStats {
+    pub fn register_rejection(&self, reason: RejectionReason) {
+        self.with_current_bucket(|bucket| {
+            *bucket.rejections.entry(reason).or_default() += 1;
+        });
+    }
+
+    /// Duration is None for the requests that never reached llama.cpp
+    pub fn register_request(&self, duration: Option<Duration>) {
+        self.with_current_bucket(|bucket| {
+            bucket.requests += 1;
+
+            if let Some(duration) = duration {
+                let duration_ms = duration.as_millis() as u64;
+                let index = LATENCY_BOUNDS_MS
+                    .iter()
+                    .position(|bound| duration_ms <= *bound)
+                    .unwrap_or(LATENCY_BOUNDS_MS.len());
+
+                bucket.latency_counts[index] += 1;
+            }
+        });
+    }
+
+    /// Keyed by the window name (`1m`, `5m`, `15m`)
+    pub fn windows(&self) -> BTreeMap<&'static str, WindowStats> {
+        let now = Instant::now();
+        let buckets = self.buckets();
+
+        WINDOWS
+            .iter()
+            .map(|(name, buckets_count)| {
+                let window = BUCKET_DURATION * *buckets_count as u32;
+                let mut latency_counts = [0; LATENCY_BOUNDS_MS.len() + 1];
+                let mut rejections: BTreeMap<RejectionReason, usize> = RejectionReason::ALL
+                    .iter()
+                    .map(|reason| (*reason, 0))
+                    .collect();
+                let mut requests = 0;
+
+                for bucket in buckets
+                    .iter()
+                    .filter(|bucket| now.duration_since(bucket.started_at) < window)
+                {
+                    for (total, count) in latency_counts.iter_mut().zip(bucket.latency_counts) {
+                        *total += count;
+                    }
+
+                    for (reason, count) in &bucket.rejections {
+                        *rejections.entry(*reason).or_default() += count;
+                    }
+
+                    requests += bucket.requests;
+                }
+
+                (
+                    *name,
+                    WindowStats {
+                        p50_ms: Self::percentile(&latency_counts, 0.50),
+                        p95_ms: Self::percentile(&latency_counts, 0.95),
+                        p99_ms: Self::percentile(&latency_counts, 0.99),
+                        rejections,
+                        requests_per_second: requests as f64 / window.as_secs_f64(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn percentile(latency_counts: &[usize], percentile: f64) -> Option<u64> {
+        let total: usize = latency_counts.iter().sum();
+
+        if total < 1 {
+            return None;
+        }
+
+        let rank = ((total as f64 * percentile).ceil() as usize).max(1);
+        let mut seen = 0;
+
+        for (index, count) in latency_counts.iter().enumerate() {
+            seen += count;
+
+            if seen >= rank {
+                // latencies above the bounds are reported as the highest bound
+                return Some(LATENCY_BOUNDS_MS[index.min(LATENCY_BOUNDS_MS.len() - 1)]);
+            }
+        }
+
+        None
+    }
+
+    fn with_current_bucket<TCallback>(&self, cb: TCallback)
+    where
+        TCallback: FnOnce(&mut Bucket),
+    {
+        let now = Instant::now();
+        let mut buckets = self.buckets();
+
+        let is_current = buckets
+            .back()
+            .is_some_and(|bucket| now.duration_since(bucket.started_at) < BUCKET_DURATION);
+
+        if !is_current {
+            if buckets.len() >= BUCKETS_COUNT {
+                buckets.pop_front();
+            }
+
+            buckets.push_back(Bucket::new(now));
+        }
+
+        if let Some(bucket) = buckets.back_mut() {
+            cb(bucket);
+        }
+    }
+
+    #[inline]
+    fn buckets(&self) -> MutexGuard<'_, VecDeque<Bucket>> {
+        // the lock only guards the counters, which are always left consistent
+        match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+
+use crate::{
+    balancer::{
+        agent_name_pattern::AgentNamePattern, method_policy::MethodPolicy,
+        oversized_batch_policy::OversizedBatchPolicy,
+        parameter_overrides::ParameterOverridesPolicy, priority_policy::PriorityPolicy,
+        proxy_settings::ProxySettings, request_priority::RequestPriority,
+        routing_rule::RoutingRule, upstream_protocol::UpstreamProtocol,
+        upstream_status_retry_policy::UpstreamStatusRetryPolicy,
+    },
+    errors::result::Result,
+};
+
+/// Hot-reloadable settings. Fields that are not set fall back to the command line flags.
+#[derive(Debug, Deserialize)]
+pub struct ConfigFile {
+    pub context_chars_per_token: Option<f64>,
+    pub excluded_agent_names: Option<Vec<AgentNamePattern>>,
+    pub head_request_policy: Option<MethodPolicy>,
+    pub max_queued_requests: Option<usize>,
+    pub max_response_bytes: Option<usize>,
+    pub max_retries_per_request: Option<usize>,
+    pub model_priorities: Option<BTreeMap<String, RequestPriority>>,
+    /// In seconds
+    pub no_capacity_retry_after: Option<u64>,
+    pub no_capacity_status: Option<u16>,
+    pub options_request_policy: Option<MethodPolicy>,
+    pub oversized_batch_policy: Option<OversizedBatchPolicy>,
+    pub parameter_overrides: Option<ParameterOverridesPolicy>,
+    pub priority_header: Option<String>,
+    pub priority_policy: Option<PriorityPolicy>,
+    pub retry_buffer_bytes: Option<usize>,
+    pub rewrite_content_type: Option<bool>,
+    pub rewrite_host_header: Option<bool>,
+    pub rewrite_host_header_value: Option<String>,
+    pub routing_rules: Option<Vec<RoutingRule>>,
+    pub slots_endpoint_token: Option<String>,
+    pub stream_error_event: Option<bool>,
+    pub target_agent_token: Option<String>,
+    /// In seconds
+    pub upstream_connect_timeout: Option<u64>,
+    /// In seconds
+    pub upstream_connection_max_lifetime: Option<u64>,
+    pub upstream_protocol: Option<UpstreamProtocol>,
+    pub upstream_status_retry_policy: Option<UpstreamStatusRetryPolicy>,
+    /// Anything else (listen addresses, listeners) requires a restart
+    #[serde(flatten)]
+    pub ignored: BTreeMap<String, Value>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn apply_to(&self, proxy_settings: &ProxySettings) -> ProxySettings {
+        for key in self.ignored.keys() {
+            warn!(
+                "Ignoring '{}' in the config file, it is not hot-reloadable and requires a restart",
+                key
+            );
+        }
+
+        ProxySettings {
+            context_chars_per_token: self
+                .context_chars_per_token
+                .or(proxy_settings.context_chars_per_token),
+            excluded_agent_names: self
+                .excluded_agent_names
+                .to_owned()
+                .unwrap_or_else(|| proxy_settings.excluded_agent_names.to_owned()),
+            head_request_policy: self
+                .head_request_policy
+                .unwrap_or(proxy_settings.head_request_policy),
+            max_queued_requests: self
+                .max_queued_requests
+                .or(proxy_settings.max_queued_requests),
+            max_response_bytes: self
+                .max_response_bytes
+                .or(proxy_settings.max_response_bytes),
+            max_retries_per_request: self
+                .max_retries_per_request
+                .unwrap_or(proxy_settings.max_retries_per_request),
+            model_priorities: self
+                .model_priorities
+                .to_owned()
+                .unwrap_or_else(|| proxy_settings.model_priorities.to_owned()),
+            no_capacity_retry_after: self
+                .no_capacity_retry_after
+                .map(Duration::from_secs)
+                .unwrap_or(proxy_settings.no_capacity_retry_after),
+            no_capacity_status: match self.no_capacity_status {
+                Some(status @ 400..=599) => status,
+                Some(status) => {
+                    warn!(
+                        "Ignoring no_capacity_status {} in the config file, it has to be 4xx or 5xx",
+                        status
+                    );
+
+                    proxy_settings.no_capacity_status
+                }
+                None => proxy_settings.no_capacity_status,
+            },
+            options_request_policy: self
+                .options_request_policy
+                .unwrap_or(proxy_settings.options_request_policy),
+            oversized_batch_policy: self
+                .oversized_batch_policy
+                .unwrap_or(proxy_settings.oversized_batch_policy),
+            parameter_overrides: self
+                .parameter_overrides
+                .to_owned()
+                .or_else(|| proxy_settings.parameter_overrides.to_owned()),
+            // paths are only set with the command line flags
+            path_rewrite_policy: proxy_settings.path_rewrite_policy.to_owned(),
+            priority_header: self
+                .priority_header
+                .to_owned()
+                .or_else(|| proxy_settings.priority_header.to_owned()),
+            priority_policy: self
+                .priority_policy
+                .unwrap_or(proxy_settings.priority_policy),
+            // compression is only set with the command line flags
+            response_compression_policy: proxy_settings.response_compression_policy.to_owned(),
+            retry_buffer_bytes: self
+                .retry_buffer_bytes
+                .unwrap_or(proxy_settings.retry_buffer_bytes),
+            rewrite_content_type: self
+                .rewrite_content_type
+                .unwrap_or(proxy_settings.rewrite_content_type),
+            rewrite_host_header: self
+                .rewrite_host_header
+                .unwrap_or(proxy_settings.rewrite_host_header),
+            rewrite_host_header_value: self
+                .rewrite_host_header_value
+                .to_owned()
+                .or_else(|| proxy_settings.rewrite_host_header_value.to_owned()),
+            routing_rules: self
+                .routing_rules
+                .to_owned()
+                .unwrap_or_else(|| proxy_settings.routing_rules.to_owned()),
+            slots_endpoint_token: self
+                .slots_endpoint_token
+                .to_owned()
+                .or_else(|| proxy_settings.slots_endpoint_token.to_owned()),
+            stream_error_event: self
+                .stream_error_event
+                .unwrap_or(proxy_settings.stream_error_event),
+            target_agent_token: self
+                .target_agent_token
+                .to_owned()
+                .or_else(|| proxy_settings.target_agent_token.to_owned()),
+            upstream_connect_timeout: self
+                .upstream_connect_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(proxy_settings.upstream_connect_timeout),
+            upstream_connection_max_lifetime: self
+                .upstream_connection_max_lifetime
+                .map(Duration::from_secs)
+                .or(proxy_settings.upstream_connection_max_lifetime),
+            // headers are only set with the command line flags
+            upstream_headers_policy: proxy_settings.upstream_headers_policy.to_owned(),
+            upstream_protocol: self
+                .upstream_protocol
+                .unwrap_or(proxy_settings.upstream_protocol),
+            upstream_status_retry_policy: self
+                .upstream_status_retry_policy
+                .unwrap_or(proxy_settings.upstream_status_retry_policy),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use log::{debug, error, info};
+use pingora::{
+    server::{ListenFds, ShutdownWatch},
+    services::Service,
+};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::balancer::config_reloader::ConfigReloader;
+
+#[cfg(feature = "systemd")]
+use crate::systemd::sd_notify;
+
+pub struct ConfigReloadService {
+    config_reloader: Arc<ConfigReloader>,
+}
+
+impl ConfigReloadService {
+    pub fn new(config_reloader: Arc<ConfigReloader>) -> Self {
+        ConfigReloadService { config_reloader }
+    }
+}
+
+#[async_trait]
+impl Service for ConfigReloadService {
+    async fn start_service(&mut self, _fds: Option<ListenFds>, mut shutdown: ShutdownWatch) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                error!("Failed to install SIGHUP handler: {}", err);
+
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down config reload service");
+                    return;
+                },
+                _ = hangup.recv() => {
+                    info!(
+                        "Received SIGHUP, reloading {}",
+                        self.config_reloader.config_file().display()
+                    );
+
+                    #[cfg(feature = "systemd")]
+                    if let Err(err) = sd_notify::notify("RELOADING=1") {
+                        error!("Failed to notify systemd: {}", err);
+                    }
+
+                    if let Err(err) = self.config_reloader.reload() {
+                        error!("Failed to reload config file: {}", err);
+                    }
+
+                    #[cfg(feature = "systemd")]
+                    if let Err(err) = sd_notify::notify("READY=1") {
+                        error!("Failed to notify systemd: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "config_reload"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,56 @@
+use log::info;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    balancer::{
+        config_file::ConfigFile,
+        proxy_settings::{ProxySettings, ProxySettingsStore},
+        upstream_peer_pool::UpstreamPeerPool,
+    },
+    errors::result::Result,
+};
+
+/// Shared by the `SIGHUP` handler and the management API
+pub struct ConfigReloader {
+    config_file: PathBuf,
+    /// Settings from the command line flags, the config file is applied on top of them
+    flag_settings: ProxySettings,
+    proxy_settings: Arc<ProxySettingsStore>,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_file: PathBuf,
+        flag_settings: ProxySettings,
+        proxy_settings: Arc<ProxySettingsStore>,
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+    ) -> Self {
+        ConfigReloader {
+            config_file,
+            flag_settings,
+            proxy_settings,
+            upstream_peer_pool,
+        }
+    }
+
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
+    }
+
+    /// On failure the previous settings stay in place
+    pub fn reload(&self) -> Result<()> {
+        let proxy_settings = ConfigFile::load(&self.config_file)?.apply_to(&self.flag_settings);
+
+        info!("Reloaded config file: {:?}", proxy_settings);
+
+        self.upstream_peer_pool
+            .set_excluded_agent_names(proxy_settings.excluded_agent_names.to_owned());
+        self.proxy_settings.store(proxy_settings);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+use std::time::Duration;
+
+/// Gives busy peers some breathing room, by making them look less idle for a while after they
+/// served many requests
+#[derive(Clone, Copy, Debug)]
+pub struct CooldownPolicy {
+    /// Peer starts cooling down after serving this many requests within the window
+    pub after_requests: usize,
+    /// Idle slots of a cooling down peer are multiplied by this factor during the selection
+    pub slots_factor: f64,
+    pub window: Duration,
+}
@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use log::{debug, error};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::lookup_host,
+    time::{interval, Duration, MissedTickBehavior},
+};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{balancer::upstream_peer_pool::UpstreamPeerPool, errors::result::Result};
+
+pub struct DnsDiscoveryService {
+    /// `host:port`, every A/AAAA record is a llama.cpp address
+    discovery_dns_name: String,
+    discovery_dns_interval: Duration,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl DnsDiscoveryService {
+    pub fn new(
+        discovery_dns_name: String,
+        discovery_dns_interval: Duration,
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+    ) -> Self {
+        DnsDiscoveryService {
+            discovery_dns_name,
+            discovery_dns_interval,
+            upstream_peer_pool,
+        }
+    }
+
+    async fn discover_peers(&self) -> Result<()> {
+        // if the resolution fails, the previously discovered peers stay in place
+        let mut discovered_addrs: Vec<SocketAddr> =
+            lookup_host(&self.discovery_dns_name).await?.collect();
+
+        discovered_addrs.sort();
+        discovered_addrs.dedup();
+
+        self.upstream_peer_pool
+            .reconcile_discovered_peers(&discovered_addrs)
+    }
+}
+
+#[async_trait]
+impl Service for DnsDiscoveryService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut ticker = interval(self.discovery_dns_interval);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down DNS discovery service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.discover_peers().await {
+                        error!("Failed to discover peers in DNS: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "dns_discovery"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,188 @@
+use log::debug;
+use pingora::protocols::{SocketDigest, UniqueIDType};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    mem::ManuallyDrop,
+    net::{Shutdown, TcpStream},
+    os::fd::FromRawFd,
+};
+
+use crate::balancer::excess_connection_policy::ExcessConnectionPolicy;
+
+struct TrackedConnection {
+    idle_since: Instant,
+    /// File descriptor of the socket on unix
+    raw_socket: UniqueIDType,
+    /// Connections are never closed while a request is in progress, streamed responses
+    /// included
+    requests_in_progress: usize,
+    /// The connection drops its digest when it closes, the weak reference also keeps the
+    /// address of the digest from being reused while it is tracked
+    socket_digest: Weak<SocketDigest>,
+}
+
+/// HTTP/1 client connections of all the listeners. Pingora does not tell when a connection is
+/// accepted or closed, so the connections are found by their socket digest, which lives as
+/// long as the connection, when they send a request.
+pub struct DownstreamConnections {
+    connections: Mutex<HashMap<usize, TrackedConnection>>,
+    pub excess_connection_policy: ExcessConnectionPolicy,
+    /// Keep-alive connections without a request for that long are closed, only on unix
+    idle_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+}
+
+impl DownstreamConnections {
+    pub fn new(
+        excess_connection_policy: ExcessConnectionPolicy,
+        idle_timeout: Option<Duration>,
+        max_connections: Option<usize>,
+    ) -> Self {
+        DownstreamConnections {
+            connections: Mutex::new(HashMap::new()),
+            excess_connection_policy,
+            idle_timeout,
+            max_connections,
+        }
+    }
+
+    /// None means the connection is new, and `max_connections` are already open. The requests
+    /// on the connections that are already tracked are never limited.
+    pub fn acquire(
+        self: &Arc<Self>,
+        raw_socket: UniqueIDType,
+        socket_digest: &Arc<SocketDigest>,
+    ) -> Option<DownstreamConnection> {
+        let key = Arc::as_ptr(socket_digest) as usize;
+        let mut connections = self.connections();
+
+        if let Some(connection) = connections.get_mut(&key) {
+            connection.requests_in_progress += 1;
+        } else {
+            if let Some(max_connections) = self.max_connections {
+                if connections.len() >= max_connections {
+                    Self::remove_closed(&mut connections);
+                }
+
+                if connections.len() >= max_connections {
+                    return None;
+                }
+            }
+
+            connections.insert(
+                key,
+                TrackedConnection {
+                    idle_since: Instant::now(),
+                    raw_socket,
+                    requests_in_progress: 1,
+                    socket_digest: Arc::downgrade(socket_digest),
+                },
+            );
+        }
+
+        Some(DownstreamConnection {
+            connections: self.clone(),
+            key,
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        let mut connections = self.connections();
+
+        Self::remove_closed(&mut connections);
+
+        connections.len()
+    }
+
+    /// Forgets the closed connections, and closes the ones that were idle for too long
+    pub fn reap(&self) {
+        let mut connections = self.connections();
+
+        Self::remove_closed(&mut connections);
+
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+
+        connections.retain(|_, connection| {
+            if connection.requests_in_progress > 0 || connection.idle_since.elapsed() < idle_timeout
+            {
+                return true;
+            }
+
+            if let Some(socket_digest) = connection.socket_digest.upgrade() {
+                debug!(
+                    "Closing the connection of {:?}, it was idle for {} seconds",
+                    socket_digest.peer_addr(),
+                    idle_timeout.as_secs()
+                );
+
+                shutdown_socket(connection.raw_socket, &socket_digest);
+            }
+
+            false
+        });
+    }
+
+    #[inline]
+    fn connections(&self) -> MutexGuard<'_, HashMap<usize, TrackedConnection>> {
+        // the lock only guards the counters, which are always left consistent
+        match self.connections.lock() {
+            Ok(connections) => connections,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn release(&self, key: usize) {
+        if let Some(connection) = self.connections().get_mut(&key) {
+            connection.requests_in_progress = connection.requests_in_progress.saturating_sub(1);
+            connection.idle_since = Instant::now();
+        }
+    }
+
+    fn remove_closed(connections: &mut HashMap<usize, TrackedConnection>) {
+        connections.retain(|_, connection| connection.socket_digest.strong_count() > 0);
+    }
+}
+
+/// Shutting the socket down makes pingora see the client closing it, so the connection is
+/// cleaned up the usual way
+#[cfg(unix)]
+fn shutdown_socket(raw_socket: UniqueIDType, socket_digest: &SocketDigest) {
+    // pingora owns the descriptor, so it must not be closed here
+    let socket = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(raw_socket) });
+
+    // the connection could have closed in the meantime, and the descriptor could belong to
+    // another one already
+    let is_same_connection = match (socket.peer_addr(), socket_digest.peer_addr()) {
+        (Ok(peer_addr), Some(digest_peer_addr)) => digest_peer_addr.as_inet() == Some(&peer_addr),
+        _ => false,
+    };
+
+    if is_same_connection {
+        if let Err(err) = socket.shutdown(Shutdown::Both) {
+            debug!("Failed to close an idle connection: {}", err);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn shutdown_socket(_raw_socket: UniqueIDType, _socket_digest: &SocketDigest) {}
+
+/// Request in progress on a connection, it counts as idle again when this is dropped
+pub struct DownstreamConnection {
+    connections: Arc<DownstreamConnections>,
+    key: usize,
+}
+
+impl Drop for DownstreamConnection {
+    fn drop(&mut self) {
+        self.connections.release(self.key);
+    }
+}
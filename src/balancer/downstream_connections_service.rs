@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use log::debug;
+use pingora::{server::ShutdownWatch, services::Service};
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::balancer::downstream_connections::DownstreamConnections;
+
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Forgets the closed client connections, and closes the idle ones
+pub struct DownstreamConnectionsService {
+    downstream_connections: Arc<DownstreamConnections>,
+}
+
+impl DownstreamConnectionsService {
+    pub fn new(downstream_connections: Arc<DownstreamConnections>) -> Self {
+        DownstreamConnectionsService {
+            downstream_connections,
+        }
+    }
+}
+
+#[async_trait]
+impl Service for DownstreamConnectionsService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut ticker = interval(REAP_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down downstream connections service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    self.downstream_connections.reap();
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "downstream_connections"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,27 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// What to do when an agent registers with the id of an agent that is already connected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateAgentIdPolicy {
+    /// Keep the agent that registered first, and close the connection of the new one
+    Reject,
+    /// Remove the agent that registered first (with its permits), and keep the new one
+    Replace,
+}
+
+impl FromStr for DuplicateAgentIdPolicy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "reject" => Ok(DuplicateAgentIdPolicy::Reject),
+            "replace" => Ok(DuplicateAgentIdPolicy::Replace),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid duplicate agent id policy: {} (expected \"reject\" or \"replace\")",
+                arg
+            ))),
+        }
+    }
+}
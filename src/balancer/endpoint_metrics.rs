@@ -0,0 +1,99 @@
+use std::{collections::BTreeMap, sync::RwLock, time::Duration};
+
+use crate::{balancer::response_status_counts::ResponseStatusCounts, errors::result::Result};
+
+/// Paths are reported under their names, everything else is `other`, so the number of metrics
+/// stays bounded no matter what the clients request
+const KNOWN_ENDPOINTS: [(&str, &str); 14] = [
+    ("/chat/completions", "chat_completions"),
+    ("/completion", "completion"),
+    ("/completions", "completions"),
+    ("/detokenize", "detokenize"),
+    ("/embedding", "embedding"),
+    ("/embeddings", "embeddings"),
+    ("/health", "health"),
+    ("/props", "props"),
+    ("/slots", "slots"),
+    ("/tokenize", "tokenize"),
+    ("/v1/chat/completions", "v1_chat_completions"),
+    ("/v1/completions", "v1_completions"),
+    ("/v1/embeddings", "v1_embeddings"),
+    ("/v1/models", "v1_models"),
+];
+
+/// Latencies above this count between two reports are dropped, the requests are still counted
+const MAX_LATENCIES_PER_ENDPOINT: usize = 10_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct EndpointStats {
+    pub latencies: Vec<Duration>,
+    pub requests: usize,
+    pub response_status_counts: ResponseStatusCounts,
+}
+
+/// Requests and their latencies per endpoint, collected between the statsd reports
+#[derive(Default)]
+pub struct EndpointMetrics {
+    endpoints: RwLock<BTreeMap<&'static str, EndpointStats>>,
+}
+
+impl EndpointMetrics {
+    pub fn endpoint_name(path: &str) -> &'static str {
+        KNOWN_ENDPOINTS
+            .iter()
+            .find(|(known_path, _)| *known_path == path)
+            .map_or("other", |(_, name)| name)
+    }
+
+    pub fn register_latency(&self, endpoint: &'static str, latency: Duration) -> Result<()> {
+        let mut endpoints = self.endpoints.write()?;
+        let endpoint_stats = endpoints.entry(endpoint).or_default();
+
+        if endpoint_stats.latencies.len() < MAX_LATENCIES_PER_ENDPOINT {
+            endpoint_stats.latencies.push(latency);
+        }
+
+        Ok(())
+    }
+
+    pub fn register_response_status(&self, endpoint: &'static str, status: u16) -> Result<()> {
+        self.endpoints
+            .write()?
+            .entry(endpoint)
+            .or_default()
+            .response_status_counts
+            .register(status);
+
+        Ok(())
+    }
+
+    pub fn register_stream_error(&self, endpoint: &'static str) -> Result<()> {
+        self.endpoints
+            .write()?
+            .entry(endpoint)
+            .or_default()
+            .response_status_counts
+            .register_stream_error();
+
+        Ok(())
+    }
+
+    pub fn register_request(&self, endpoint: &'static str) -> Result<()> {
+        self.endpoints.write()?.entry(endpoint).or_default().requests += 1;
+
+        Ok(())
+    }
+
+    /// Returns the stats collected since the last call
+    pub fn take(&self) -> Result<BTreeMap<&'static str, EndpointStats>> {
+        let mut endpoints = self.endpoints.write()?;
+        let taken = endpoints.clone();
+
+        // keep the endpoints, so they are reported as zero instead of disappearing
+        endpoints
+            .values_mut()
+            .for_each(|endpoint_stats| *endpoint_stats = EndpointStats::default());
+
+        Ok(taken)
+    }
+}
@@ -0,0 +1,10 @@
+use std::time::Duration;
+
+/// Makes peers that recently returned errors less preferred, without taking them out of the
+/// pool like the quarantine does
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorPenaltyPolicy {
+    /// Idle slots of a peer are divided by `1 + weight * recent errors` during the selection
+    pub weight: f64,
+    pub window: Duration,
+}
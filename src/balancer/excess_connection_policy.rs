@@ -0,0 +1,28 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// What to do with the new client connections once `--max-downstream-connections` are open
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExcessConnectionPolicy {
+    /// Respond with `503` to the first request and close the connection
+    #[default]
+    Reject,
+    /// Hold the first request until another connection closes
+    Wait,
+}
+
+impl FromStr for ExcessConnectionPolicy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "reject" => Ok(ExcessConnectionPolicy::Reject),
+            "wait" => Ok(ExcessConnectionPolicy::Wait),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid excess connection policy: {} (expected \"reject\" or \"wait\")",
+                arg
+            ))),
+        }
+    }
+}
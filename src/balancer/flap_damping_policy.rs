@@ -0,0 +1,11 @@
+use std::time::Duration;
+
+/// Keeps a peer whose reported capacity keeps changing from reshuffling the pool, by holding
+/// its slots at the last stable total until the reports settle
+#[derive(Clone, Copy, Debug)]
+pub struct FlapDampingPolicy {
+    /// Capacity is frozen once the reported total slots changed more than this many times
+    /// within the window
+    pub max_capacity_changes: usize,
+    pub window: Duration,
+}
@@ -0,0 +1,111 @@
+use async_trait::async_trait;
+use log::{debug, error, info};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tonic::transport::Server;
+use tonic_health::{server::health_reporter, ServingStatus};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Empty service name stands for the overall health of the server
+const OVERALL_SERVICE_NAME: &str = "";
+
+pub struct GrpcHealthService {
+    addr: SocketAddr,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl GrpcHealthService {
+    pub fn new(addr: SocketAddr, upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        GrpcHealthService {
+            addr,
+            upstream_peer_pool,
+        }
+    }
+
+    fn serving_status(&self) -> ServingStatus {
+        match self.upstream_peer_pool.has_usable_peers() {
+            Ok(true) => ServingStatus::Serving,
+            Ok(false) => ServingStatus::NotServing,
+            Err(err) => {
+                error!("Failed to check usable peers: {}", err);
+
+                ServingStatus::NotServing
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Service for GrpcHealthService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let (mut health_reporter, health_server) = health_reporter();
+
+        health_reporter
+            .set_service_status(OVERALL_SERVICE_NAME, ServingStatus::NotServing)
+            .await;
+
+        let mut server_shutdown = shutdown.clone();
+        let server = Server::builder()
+            .add_service(health_server)
+            .serve_with_shutdown(self.addr, async move {
+                let _ = server_shutdown.changed().await;
+            });
+
+        tokio::pin!(server);
+
+        let mut ticker = interval(HEALTH_CHECK_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut current_status = ServingStatus::NotServing;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down grpc health service");
+                    return;
+                },
+                result = &mut server => {
+                    if let Err(err) = result {
+                        error!("gRPC health server unexpectedly stopped: {}", err);
+                    }
+
+                    return;
+                },
+                _ = ticker.tick() => {
+                    let serving_status = self.serving_status();
+
+                    // watchers are only notified about the transitions
+                    if serving_status != current_status {
+                        info!("gRPC health status changed to {:?}", serving_status);
+
+                        health_reporter
+                            .set_service_status(OVERALL_SERVICE_NAME, serving_status)
+                            .await;
+
+                        current_status = serving_status;
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "grpc_health"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
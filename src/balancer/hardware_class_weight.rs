@@ -0,0 +1,36 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// `gpu=4` makes the idle slots of the peers in the `gpu` hardware class count four times when
+/// the peers are compared, `cpu=0.25` makes them count a quarter
+#[derive(Clone, Debug)]
+pub struct HardwareClassWeight {
+    pub hardware_class: String,
+    pub weight: f64,
+}
+
+impl FromStr for HardwareClassWeight {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::UnexpectedError(format!(
+                "Invalid hardware class weight: \"{}\" (expected \"class=weight\", with a positive weight)",
+                arg
+            ))
+        };
+
+        let (hardware_class, weight) = arg.split_once('=').ok_or_else(invalid)?;
+        let weight: f64 = weight.trim().parse().map_err(|_| invalid())?;
+
+        if hardware_class.trim().is_empty() || !weight.is_finite() || weight <= 0.0 {
+            return Err(invalid());
+        }
+
+        Ok(HardwareClassWeight {
+            hardware_class: hardware_class.trim().to_string(),
+            weight,
+        })
+    }
+}
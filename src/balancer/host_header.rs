@@ -0,0 +1,35 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+/// `Host` header value for the `--external-host` or `--rewrite-host-header-value` override. A
+/// bare IPv6 address is not a valid `Host` without the brackets, and it can not be told apart
+/// from one with a port (`fd00::12:8080`), so it is taken as an address. Use `[fd00::12]:8080`
+/// to send the port too.
+pub fn format_host_header(host: &str) -> String {
+    match host.parse::<Ipv6Addr>() {
+        Ok(addr) => format!("[{}]", addr),
+        Err(_) => host.to_string(),
+    }
+}
+
+/// Server name for the TLS handshake with the peer. Empty for the IP addresses, they are not
+/// allowed in SNI.
+pub fn server_name(host: &str) -> String {
+    let name = if let Some(bracketed) = host.strip_prefix('[') {
+        bracketed
+            .split_once(']')
+            .map_or(bracketed, |(name, _)| name)
+    } else if host.parse::<Ipv6Addr>().is_ok() {
+        host
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => name,
+            _ => host,
+        }
+    };
+
+    if name.parse::<IpAddr>().is_ok() {
+        String::new()
+    } else {
+        name.to_string()
+    }
+}
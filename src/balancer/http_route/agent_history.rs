@@ -0,0 +1,53 @@
+use actix_web::{get, web, Error, HttpResponse};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+fn default_minutes() -> u64 {
+    15
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    agent_id: String,
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    #[serde(default = "default_minutes")]
+    minutes: u64,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/agents/{agent_id}/history",
+        params(
+            ("agent_id" = String, Path, description = "Id of the agent"),
+            ("minutes" = Option<u64>, Query, description = "15 by default")
+        ),
+        responses(
+            (status = 200, description = "Samples of the agent, taken every 5 seconds", body = Object),
+            (status = 404, description = "No such agent")
+        )
+    )
+)]
+#[get("/api/v1/agents/{agent_id}/history")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    query_params: web::Query<QueryParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    let since = SystemTime::now() - Duration::from_secs(query_params.minutes * 60);
+
+    match upstream_peer_pool.history(&path_params.agent_id, since)? {
+        Some(samples) => Ok(HttpResponse::Ok().json(serde_json::json!({ "samples": samples }))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
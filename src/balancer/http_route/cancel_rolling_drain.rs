@@ -0,0 +1,27 @@
+use actix_web::{delete, web, Error, HttpResponse};
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/v1/rolling_drain",
+        responses(
+            (status = 204, description = "Rolling drain cancelled"),
+            (status = 404, description = "No rolling drain in progress")
+        )
+    )
+)]
+#[delete("/api/v1/rolling_drain")]
+async fn respond(upstream_peer_pool: web::Data<UpstreamPeerPool>) -> Result<HttpResponse, Error> {
+    if upstream_peer_pool.cancel_rolling_drain()? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
@@ -0,0 +1,39 @@
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::balancer::{
+    cluster_stats::ClusterStats, downstream_connections::DownstreamConnections,
+    upstream_peer_pool::UpstreamPeerPool,
+};
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/stats",
+        responses((status = 200, description = "Peers, slots, and the request windows", body = Object))
+    )
+)]
+#[get("/api/v1/stats")]
+async fn respond(
+    cluster_stats: web::Data<ClusterStats>,
+    downstream_connections: web::Data<DownstreamConnections>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    let (peers, usable_peers) = upstream_peer_pool.peers_count()?;
+    let (slots_idle, slots_processing) = upstream_peer_pool.total_slots()?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "downstream_connections": downstream_connections.count(),
+        "peers": peers,
+        "requests_waiting_for_permit": upstream_peer_pool.requests_waiting_for_permit(),
+        "slots_idle": slots_idle,
+        "slots_processing": slots_processing,
+        "slots_total": slots_idle + slots_processing,
+        "usable_peers": usable_peers,
+        "windows": cluster_stats.windows(),
+    })))
+}
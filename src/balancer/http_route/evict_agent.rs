@@ -0,0 +1,37 @@
+use actix_web::{delete, web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    agent_id: String,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/v1/agents/{agent_id}",
+        params(("agent_id" = String, Path, description = "Id of the agent")),
+        responses(
+            (status = 204, description = "Agent removed until it reports again"),
+            (status = 404, description = "No such agent, or it is a static agent")
+        )
+    )
+)]
+#[delete("/api/v1/agents/{agent_id}")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    if upstream_peer_pool.evict_peer(&path_params.agent_id)? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().body("No such agent, or it is a static agent"))
+    }
+}
@@ -0,0 +1,26 @@
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/rolling_drain",
+        responses(
+            (status = 200, body = RollingDrain),
+            (status = 404, description = "No rolling drain was started")
+        )
+    )
+)]
+#[get("/api/v1/rolling_drain")]
+async fn respond(upstream_peer_pool: web::Data<UpstreamPeerPool>) -> Result<HttpResponse, Error> {
+    match upstream_peer_pool.rolling_drain()? {
+        Some(rolling_drain) => Ok(HttpResponse::Ok().json(rolling_drain)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
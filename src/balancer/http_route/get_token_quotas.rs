@@ -0,0 +1,20 @@
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::balancer::token_quotas::TokenQuotas;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/quotas",
+        responses((status = 200, body = [TokenQuotaSummary]))
+    )
+)]
+#[get("/api/v1/quotas")]
+async fn respond(token_quotas: web::Data<TokenQuotas>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(token_quotas.summaries()))
+}
@@ -1,8 +1,29 @@
+pub mod agent_history;
+pub mod cancel_rolling_drain;
+pub mod cluster_stats;
+pub mod evict_agent;
+pub mod get_rolling_drain;
+pub mod get_token_quotas;
+pub mod pool_events;
+pub mod quarantine_agent;
 pub mod receive_status_update;
 pub mod registered_agents;
+pub mod reload_config;
+pub mod set_max_concurrency;
+pub mod set_token_quota;
+pub mod set_upstream_headers;
+pub mod simulate_selection;
+pub mod start_rolling_drain;
+pub mod webhook_stats;
 
 #[cfg(feature = "web_dashboard")]
 pub mod dashboard;
 
+#[cfg(feature = "openapi")]
+pub mod openapi;
+
+#[cfg(feature = "pool_inspection")]
+pub mod pool_inspection;
+
 #[cfg(feature = "web_dashboard")]
 pub mod static_files;
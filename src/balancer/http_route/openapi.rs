@@ -0,0 +1,194 @@
+use actix_web::{get, web, Error, HttpResponse};
+use utoipa::OpenApi;
+
+use crate::{
+    balancer::{
+        http_route::{
+            agent_history, cancel_rolling_drain, cluster_stats, evict_agent, get_rolling_drain,
+            get_token_quotas, pool_events, quarantine_agent, receive_status_update,
+            registered_agents, reload_config, set_max_concurrency, set_token_quota,
+            set_upstream_headers, simulate_selection, start_rolling_drain, webhook_stats,
+        },
+        request_priority::RequestPriority,
+        response_status_counts::ResponseStatusCounts,
+        rolling_drain::RollingDrain,
+        routing_explanation::{
+            CandidateStatus, RoutingCandidate, RoutingExplanation, RoutingScore,
+            SelectionSimulation,
+        },
+        status_update::StatusUpdate,
+        token_quota::TokenQuotaPeriod,
+        token_quotas::{TokenQuotaSummary, TokenQuotaUpdate},
+        upstream_peer::UpstreamPeer,
+        upstream_peer_pool::UpstreamPeerPool,
+        upstream_protocol::UpstreamProtocol,
+        webhook_stats::WebhookStatsSummary,
+    },
+    llamacpp::{model_info::ModelInfo, slot::Slot},
+};
+
+/// Generated from the route annotations and the response types, so it can't drift from the
+/// handlers. The routes that depend on the settings (config reload, quotas, pool events) are
+/// always listed.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        agent_history::respond,
+        cancel_rolling_drain::respond,
+        cluster_stats::respond,
+        evict_agent::respond,
+        get_rolling_drain::respond,
+        get_token_quotas::respond,
+        pool_events::respond,
+        quarantine_agent::respond,
+        receive_status_update::respond,
+        registered_agents::respond,
+        reload_config::respond,
+        set_max_concurrency::respond,
+        set_token_quota::respond,
+        set_upstream_headers::respond,
+        simulate_selection::respond,
+        start_rolling_drain::respond,
+        webhook_stats::respond,
+    ),
+    components(schemas(
+        CandidateStatus,
+        ModelInfo,
+        RequestPriority,
+        ResponseStatusCounts,
+        RollingDrain,
+        RoutingCandidate,
+        RoutingExplanation,
+        RoutingScore,
+        SelectionSimulation,
+        Slot,
+        StatusUpdate,
+        TokenQuotaPeriod,
+        TokenQuotaSummary,
+        TokenQuotaUpdate,
+        UpstreamPeer,
+        UpstreamPeerPool,
+        UpstreamProtocol,
+        WebhookStatsSummary,
+    ))
+)]
+struct ManagementApi;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/api/v1/openapi.json")]
+async fn respond() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(ManagementApi::openapi()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => refs.push(reference),
+                        _ => collect_refs(value, refs),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    collect_refs(value, refs);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn document() -> Value {
+        serde_json::to_value(ManagementApi::openapi()).expect("document serializes")
+    }
+
+    #[test]
+    fn describes_every_path_parameter() {
+        let document = document();
+        let paths = document["paths"].as_object().expect("document has paths");
+
+        assert!(!paths.is_empty());
+
+        for (path, path_item) in paths {
+            let placeholders: Vec<&str> = path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+                .collect();
+
+            for (method, operation) in path_item.as_object().expect("path item is an object") {
+                let parameters = operation["parameters"].as_array();
+
+                for placeholder in &placeholders {
+                    let parameter = parameters
+                        .and_then(|parameters| {
+                            parameters.iter().find(|parameter| {
+                                parameter["in"] == "path" && parameter["name"] == *placeholder
+                            })
+                        })
+                        .unwrap_or_else(|| {
+                            panic!("{} {} does not declare {{{}}}", method, path, placeholder)
+                        });
+
+                    assert_eq!(parameter["required"], true, "{} {}", method, path);
+                    assert!(
+                        parameter["description"].is_string(),
+                        "{} {} has no description of {{{}}}",
+                        method,
+                        path,
+                        placeholder
+                    );
+                }
+
+                assert!(
+                    operation["responses"]
+                        .as_object()
+                        .is_some_and(|responses| !responses.is_empty()),
+                    "{} {} has no responses",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_every_schema_reference() {
+        let document = document();
+        let schemas = document["components"]["schemas"]
+            .as_object()
+            .expect("document has schemas");
+        let mut refs = Vec::new();
+
+        collect_refs(&document, &mut refs);
+
+        assert!(!refs.is_empty());
+
+        for reference in refs {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("unexpected reference {}", reference));
+
+            assert!(schemas.contains_key(name), "{} is not a component", name);
+        }
+    }
+
+    #[test]
+    fn parses_back_as_openapi() {
+        let document = document();
+
+        assert!(document["openapi"]
+            .as_str()
+            .is_some_and(|version| version.starts_with("3.")));
+        assert!(serde_json::from_value::<utoipa::openapi::OpenApi>(document).is_ok());
+    }
+}
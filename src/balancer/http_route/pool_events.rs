@@ -0,0 +1,91 @@
+use actix_web::{get, rt, web, Error, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures_util::StreamExt as _;
+use log::{debug, error, warn};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{interval, Duration, MissedTickBehavior},
+};
+
+use crate::balancer::{pool_event::PoolEvent, upstream_peer_pool::UpstreamPeerPool};
+
+const UTILIZATION_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/events",
+        responses((status = 101, description = "Websocket streaming the pool events"))
+    )
+)]
+#[get("/api/v1/events")]
+async fn respond(
+    req: HttpRequest,
+    body: web::Payload,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut message_stream) = actix_ws::handle(&req, body)?;
+    let mut pool_events_rx = upstream_peer_pool.subscribe_events();
+
+    rt::spawn(async move {
+        let mut ticker = interval(UTILIZATION_SNAPSHOT_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let pool_event = tokio::select! {
+                message = message_stream.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+                pool_event = pool_events_rx.recv() => match pool_event {
+                    Ok(pool_event) => pool_event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Pool events subscriber lagged behind, skipped {} events", skipped);
+
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => match upstream_peer_pool.total_slots() {
+                    Ok((slots_idle, slots_processing)) => PoolEvent::Utilization {
+                        slots_idle,
+                        slots_processing,
+                    },
+                    Err(err) => {
+                        error!("Failed to compute utilization: {}", err);
+
+                        continue;
+                    }
+                },
+            };
+
+            match serde_json::to_string(&pool_event) {
+                Ok(json) => {
+                    if session.text(json).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => error!("Failed to serialize pool event: {}", err),
+            }
+        }
+
+        debug!("Pool events subscriber disconnected");
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
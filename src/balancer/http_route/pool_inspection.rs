@@ -0,0 +1,12 @@
+use actix_web::{get, web, Error, Responder};
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/api/v1/pool/inspection")]
+async fn respond(upstream_peer_pool: web::Data<UpstreamPeerPool>) -> Result<impl Responder, Error> {
+    Ok(web::Json(upstream_peer_pool.inspect()?))
+}
@@ -0,0 +1,50 @@
+use actix_web::{post, web, Error, HttpResponse};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    agent_id: String,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct QuarantineParams {
+    duration_secs: u64,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/agents/{agent_id}/quarantine",
+        params(("agent_id" = String, Path, description = "Id of the agent")),
+        request_body(content = inline(QuarantineParams)),
+        responses(
+            (status = 204, description = "Agent quarantined"),
+            (status = 404, description = "No such agent")
+        )
+    )
+)]
+#[post("/api/v1/agents/{agent_id}/quarantine")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    params: web::Json<QuarantineParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    if upstream_peer_pool.quarantine_peer_for(
+        &path_params.agent_id,
+        Duration::from_secs(params.duration_secs),
+        "manual",
+    )? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
@@ -1,5 +1,5 @@
-use actix_web::{post, web, Error, HttpResponse};
-use futures_util::StreamExt as _;
+use actix_web::{error::ErrorConflict, post, web, web::Bytes, Error, HttpResponse};
+use futures_util::{future::ready, stream, StreamExt as _};
 use log::{error, info};
 use serde::Deserialize;
 
@@ -14,51 +14,108 @@ struct PathParams {
     agent_id: String,
 }
 
-struct RemovePeerGuard<'a> {
-    pool: &'a UpstreamPeerPool,
+/// Sent back for every status update the balancer registered, the agent counts its reports by
+/// these
+const STATUS_UPDATE_ACK: &[u8] = b"\n";
+
+struct RemovePeerGuard {
+    pool: web::Data<UpstreamPeerPool>,
     agent_id: String,
+    connection_id: u64,
 }
 
-impl Drop for RemovePeerGuard<'_> {
+impl Drop for RemovePeerGuard {
     fn drop(&mut self) {
         info!("Removing agent: {}", self.agent_id);
 
-        if let Err(e) = self.pool.remove_peer(&self.agent_id) {
+        if let Err(e) = self.pool.remove_peer(&self.agent_id, self.connection_id) {
             error!("Failed to remove peer: {}", e);
         }
     }
 }
 
+/// False if another connection of the agent replaced this one
+fn register_status_update(
+    remove_peer_guard: &RemovePeerGuard,
+    chunk: &[u8],
+) -> Result<bool, Error> {
+    match serde_json::from_slice::<StatusUpdate>(chunk) {
+        Ok(status_update) => {
+            match remove_peer_guard.pool.register_status_update(
+                &remove_peer_guard.agent_id,
+                remove_peer_guard.connection_id,
+                status_update,
+            ) {
+                Ok(is_registered) => Ok(is_registered),
+                Err(e) => {
+                    error!("Failed to register status update: {}", e);
+
+                    Err(Error::from(e))
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to parse status update: {}", e);
+
+            Err(Error::from(e))
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/status_update/{agent_id}",
+        params(("agent_id" = String, Path, description = "Id of the agent")),
+        request_body(
+            content = StatusUpdate,
+            description = "Streamed by the agent, one status update per chunk"
+        ),
+        responses(
+            (
+                status = 200,
+                description = "Streamed until the agent disconnects, one line per registered status update"
+            ),
+            (status = 202, description = "Agent disconnected before sending a status update"),
+            (status = 409, description = "Agent was replaced by another connection")
+        )
+    )
+)]
 #[post("/status_update/{agent_id}")]
 async fn respond(
     path_params: web::Path<PathParams>,
     mut payload: web::Payload,
     upstream_peer_pool: web::Data<UpstreamPeerPool>,
 ) -> Result<HttpResponse, Error> {
-    let _guard = RemovePeerGuard {
-        pool: &upstream_peer_pool,
+    let remove_peer_guard = RemovePeerGuard {
+        pool: upstream_peer_pool.clone(),
         agent_id: path_params.agent_id.clone(),
+        connection_id: upstream_peer_pool.next_connection_id(),
     };
 
     info!("Registering agent: {}", path_params.agent_id);
 
-    while let Some(chunk) = payload.next().await {
-        match serde_json::from_slice::<StatusUpdate>(&chunk?) {
-            Ok(status_update) => {
-                if let Err(e) =
-                    upstream_peer_pool.register_status_update(&path_params.agent_id, status_update)
-                {
-                    error!("Failed to register status update: {}", e);
-
-                    return Err(Error::from(e));
-                }
-            }
-            Err(e) => {
-                error!("Failed to parse status update: {}", e);
-
-                return Err(Error::from(e));
+    // the first status update decides the response status, the later ones are acknowledged
+    // in its body
+    match payload.next().await {
+        Some(chunk) => {
+            if !register_status_update(&remove_peer_guard, &chunk?)? {
+                return Ok(HttpResponse::Conflict().finish());
             }
         }
+        None => return Ok(HttpResponse::Accepted().finish()),
     }
-    Ok(HttpResponse::Accepted().finish())
+
+    // the agent stays registered for as long as the response is streamed, an error ends it
+    let first_ack = stream::once(ready(Ok(Bytes::from_static(STATUS_UPDATE_ACK))));
+    let acks = payload.map(move |chunk| {
+        if register_status_update(&remove_peer_guard, &chunk?)? {
+            Ok(Bytes::from_static(STATUS_UPDATE_ACK))
+        } else {
+            Err(ErrorConflict("Agent was replaced by another connection"))
+        }
+    });
+
+    Ok(HttpResponse::Ok().streaming::<_, Error>(first_ack.chain(acks)))
 }
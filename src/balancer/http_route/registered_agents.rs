@@ -6,6 +6,14 @@ pub fn register(cfg: &mut web::ServiceConfig) {
     cfg.service(respond);
 }
 
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/agents",
+        responses((status = 200, body = UpstreamPeerPool))
+    )
+)]
 #[get("/api/v1/agents")]
 async fn respond(upstream_peer_pool: web::Data<UpstreamPeerPool>) -> Result<impl Responder, Error> {
     Ok(web::Json(upstream_peer_pool))
@@ -0,0 +1,22 @@
+use actix_web::{post, web, Error, HttpResponse};
+
+use crate::balancer::config_reloader::ConfigReloader;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/config/reload",
+        responses((status = 204, description = "Config file reloaded"))
+    )
+)]
+#[post("/api/v1/config/reload")]
+async fn respond(config_reloader: web::Data<ConfigReloader>) -> Result<HttpResponse, Error> {
+    config_reloader.reload()?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,46 @@
+use actix_web::{put, web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    agent_id: String,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct MaxConcurrencyParams {
+    /// None removes the override, and the limit reported by the agent applies again
+    max_concurrency: Option<usize>,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/agents/{agent_id}/max_concurrency",
+        params(("agent_id" = String, Path, description = "Id of the agent")),
+        request_body(content = inline(MaxConcurrencyParams)),
+        responses(
+            (status = 204, description = "Limit changed"),
+            (status = 404, description = "No such agent")
+        )
+    )
+)]
+#[put("/api/v1/agents/{agent_id}/max_concurrency")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    params: web::Json<MaxConcurrencyParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    if upstream_peer_pool.set_max_concurrency(&path_params.agent_id, params.max_concurrency)? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
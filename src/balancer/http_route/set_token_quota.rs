@@ -0,0 +1,38 @@
+use actix_web::{put, web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::balancer::token_quotas::{TokenQuotaUpdate, TokenQuotas};
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    listener: String,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/quotas/{listener}",
+        params(("listener" = String, Path, description = "Address of the proxy listener")),
+        request_body(content = TokenQuotaUpdate),
+        responses(
+            (status = 200, body = TokenQuotaSummary),
+            (status = 404, description = "The listener has no quota")
+        )
+    )
+)]
+#[put("/api/v1/quotas/{listener}")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    params: web::Json<TokenQuotaUpdate>,
+    token_quotas: web::Data<TokenQuotas>,
+) -> Result<HttpResponse, Error> {
+    match token_quotas.update(&path_params.listener, params.into_inner()) {
+        Some(token_quota) => Ok(HttpResponse::Ok().json(token_quota)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
@@ -0,0 +1,69 @@
+use actix_web::{
+    http::header::{HeaderName, HeaderValue},
+    put, web, Error, HttpResponse,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::balancer::{
+    upstream_headers_policy::is_protected_header, upstream_peer_pool::UpstreamPeerPool,
+};
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    agent_id: String,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct UpstreamHeadersParams {
+    /// None removes the override, and the headers reported by the agent apply again
+    upstream_headers: Option<BTreeMap<String, String>>,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/agents/{agent_id}/upstream_headers",
+        params(("agent_id" = String, Path, description = "Id of the agent")),
+        request_body(content = inline(UpstreamHeadersParams)),
+        responses(
+            (status = 204, description = "Headers changed"),
+            (status = 400, description = "Invalid header, or one set by the balancer"),
+            (status = 404, description = "No such agent")
+        )
+    )
+)]
+#[put("/api/v1/agents/{agent_id}/upstream_headers")]
+async fn respond(
+    path_params: web::Path<PathParams>,
+    params: web::Json<UpstreamHeadersParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    for (name, value) in params.upstream_headers.iter().flatten() {
+        if is_protected_header(name) {
+            return Ok(
+                HttpResponse::BadRequest().body(format!("Header {} is set by the balancer", name))
+            );
+        }
+
+        if HeaderName::try_from(name.as_str()).is_err()
+            || HeaderValue::try_from(value.as_str()).is_err()
+        {
+            return Ok(HttpResponse::BadRequest().body(format!("Header {} is invalid", name)));
+        }
+    }
+
+    let UpstreamHeadersParams { upstream_headers } = params.into_inner();
+
+    if upstream_peer_pool.set_upstream_headers(&path_params.agent_id, upstream_headers)? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
@@ -0,0 +1,152 @@
+use actix_web::{post, web, Error, HttpResponse};
+use pingora::http::RequestHeader;
+use serde::Deserialize;
+use std::{collections::BTreeMap, net::IpAddr};
+
+use crate::balancer::{
+    label::Label,
+    oversized_batch_policy::OversizedBatchPolicy,
+    proxy_service::{takes_slot, PROMPT_TOKENS_HEADER, REQUEST_ID_HEADER, REQUIRE_HEADER},
+    proxy_settings::ProxySettingsStore,
+    request_priority::RequestPriority,
+    routing_explanation::SelectionSimulation,
+    routing_rule::find_routing_rule,
+    tie_break_strategy::request_hash,
+    upstream_peer_pool::UpstreamPeerPool,
+};
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+/// Request as the client would send it, the body is not needed
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct SimulateSelectionParams {
+    /// Only used by the `request-hash` tie-break strategy, if there is no `X-Request-Id` header
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
+    client_ip: Option<IpAddr>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default = "default_method")]
+    method: String,
+    /// `model` field of the request body, used by the routing rules and the model priorities
+    model: Option<String>,
+    /// Before the path rewrites, without the query
+    path: String,
+    /// Estimated prompt length, unless the `X-Paddler-Prompt-Tokens` header is set
+    prompt_tokens: Option<usize>,
+    /// Length of the `prompt` array of the request body, if it is a batch of prompts
+    prompts_count: Option<usize>,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/simulate-selection",
+        request_body(content = inline(SimulateSelectionParams)),
+        responses(
+            (status = 200, body = SelectionSimulation),
+            (status = 400, description = "Invalid method, path, header, or label selectors, or a rejected batch")
+        )
+    )
+)]
+#[post("/api/v1/simulate-selection")]
+async fn respond(
+    params: web::Json<SimulateSelectionParams>,
+    proxy_settings: web::Data<ProxySettingsStore>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    let proxy_settings = proxy_settings.load();
+    let (path, _) = proxy_settings.path_rewrite_policy.rewrite(&params.path);
+    let mut request_header =
+        match RequestHeader::build(params.method.as_str(), path.as_bytes(), None) {
+            Ok(request_header) => request_header,
+            Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+        };
+
+    for (name, value) in &params.headers {
+        if let Err(err) = request_header.insert_header(name.to_owned(), value) {
+            return Ok(HttpResponse::BadRequest().body(err.to_string()));
+        }
+    }
+
+    let header_value = |name: &str| {
+        request_header
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    let mut label_selectors = match header_value(REQUIRE_HEADER).map(Label::parse_list) {
+        Some(Ok(label_selectors)) => label_selectors,
+        Some(Err(err)) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+        None => vec![],
+    };
+
+    // the same as in `ProxyService::request_filter`, the body is only read for these requests
+    let uses_slots = takes_slot(request_header.uri.path())
+        && params.method != "HEAD"
+        && params.method != "OPTIONS";
+    let model = params.model.as_deref().filter(|_| uses_slots);
+
+    if let Some(routing_rule) =
+        find_routing_rule(&proxy_settings.routing_rules, &request_header, model)
+    {
+        label_selectors.extend(routing_rule.require.to_owned());
+    }
+
+    let priority = if uses_slots {
+        proxy_settings.resolve_priority(&request_header, model)
+    } else {
+        RequestPriority::default()
+    };
+    let prompt_tokens = header_value(PROMPT_TOKENS_HEADER)
+        .and_then(|value| value.parse::<usize>().ok())
+        .or(params.prompt_tokens)
+        .filter(|_| uses_slots);
+    let mut slots = params
+        .prompts_count
+        .filter(|_| uses_slots)
+        .unwrap_or(1)
+        .max(1);
+
+    if slots > 1 {
+        let max_peer_slots = upstream_peer_pool.max_peer_slots(&label_selectors, prompt_tokens)?;
+
+        if slots > max_peer_slots {
+            match proxy_settings.oversized_batch_policy {
+                OversizedBatchPolicy::Clamp => slots = max_peer_slots.max(1),
+                OversizedBatchPolicy::Reject => {
+                    return Ok(HttpResponse::BadRequest().body(format!(
+                        "Batch of {} prompts does not fit in any agent (at most {} slots)",
+                        slots, max_peer_slots
+                    )));
+                }
+            }
+        }
+    }
+
+    let explanation = upstream_peer_pool.explain_routing(
+        &label_selectors,
+        priority,
+        prompt_tokens,
+        request_hash(header_value(REQUEST_ID_HEADER), params.client_ip),
+        slots,
+        uses_slots,
+    )?;
+
+    Ok(HttpResponse::Ok().json(SelectionSimulation {
+        explanation,
+        label_selectors: label_selectors.iter().map(Label::to_string).collect(),
+        priority,
+        prompt_tokens,
+        slots,
+        uses_slots,
+    }))
+}
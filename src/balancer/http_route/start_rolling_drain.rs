@@ -0,0 +1,49 @@
+use actix_web::{post, web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct RollingDrainParams {
+    /// None drains all the peers in the pool
+    agent_ids: Option<Vec<String>>,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/rolling_drain",
+        request_body(content = inline(RollingDrainParams)),
+        responses(
+            (status = 201, body = RollingDrain),
+            (status = 400, description = "Concurrency is less than 1"),
+            (status = 409, description = "Rolling drain is already in progress")
+        )
+    )
+)]
+#[post("/api/v1/rolling_drain")]
+async fn respond(
+    params: web::Json<RollingDrainParams>,
+    upstream_peer_pool: web::Data<UpstreamPeerPool>,
+) -> Result<HttpResponse, Error> {
+    if params.concurrency < 1 {
+        return Ok(HttpResponse::BadRequest().body("Concurrency must be at least 1"));
+    }
+
+    match upstream_peer_pool.start_rolling_drain(params.agent_ids.to_owned(), params.concurrency)? {
+        Some(rolling_drain) => Ok(HttpResponse::Created().json(rolling_drain)),
+        None => Ok(HttpResponse::Conflict().body("Rolling drain is already in progress")),
+    }
+}
@@ -0,0 +1,20 @@
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::balancer::webhook_stats::WebhookStats;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/webhooks/stats",
+        responses((status = 200, body = WebhookStatsSummary))
+    )
+)]
+#[get("/api/v1/webhooks/stats")]
+async fn respond(webhook_stats: web::Data<WebhookStats>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(webhook_stats.summary()))
+}
@@ -0,0 +1,27 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// `name: value` header added to every request forwarded to llama.cpp
+#[derive(Clone, Debug, PartialEq)]
+pub struct InjectedHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for InjectedHeader {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> std::result::Result<Self, Self::Err> {
+        match arg.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => Ok(InjectedHeader {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid header: \"{}\" (expected \"name: value\")",
+                arg
+            ))),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Parts of the completion request body the balancer looks at, everything else is forwarded
+/// as-is
+#[derive(Debug, Default, Deserialize)]
+pub struct InspectedRequest {
+    /// Chat completions
+    #[serde(default)]
+    pub messages: Vec<Value>,
+    pub model: Option<String>,
+    /// Completions
+    pub prompt: Option<Value>,
+    pub stream: Option<bool>,
+    pub temperature: Option<f64>,
+}
+
+impl InspectedRequest {
+    /// Request bodies that can't be parsed are forwarded anyway, llama.cpp responds with the
+    /// appropriate error
+    pub fn parse(request_body: &[u8]) -> Self {
+        serde_json::from_slice(request_body).unwrap_or_default()
+    }
+
+    /// Completions can have an array of prompts, each generated separately
+    pub fn prompts_count(&self) -> usize {
+        match &self.prompt {
+            // an array of numbers is a single tokenized prompt
+            Some(Value::Array(prompts))
+                if !prompts.is_empty()
+                    && prompts
+                        .iter()
+                        .all(|prompt| prompt.is_string() || prompt.is_array()) =>
+            {
+                prompts.len()
+            }
+            _ => 1,
+        }
+    }
+
+    /// Greedy sampling always picks the same tokens, llama.cpp samples with a non-zero
+    /// temperature by default, so it has to be set explicitly. The seed does not matter then.
+    pub fn is_deterministic(&self) -> bool {
+        self.temperature == Some(0.0) && self.stream != Some(true)
+    }
+
+    pub fn prompt_chars(&self) -> usize {
+        let messages_chars: usize = self
+            .messages
+            .iter()
+            .filter_map(|message| message.get("content"))
+            .map(count_text_chars)
+            .sum();
+
+        messages_chars + self.prompt.as_ref().map_or(0, count_text_chars)
+    }
+}
+
+/// Prompts can be plain strings, arrays of strings, or arrays of content parts
+fn count_text_chars(value: &Value) -> usize {
+    match value {
+        Value::String(text) => text.chars().count(),
+        Value::Array(values) => values.iter().map(count_text_chars).sum(),
+        Value::Object(object) => object.get("text").map_or(0, count_text_chars),
+        _ => 0,
+    }
+}
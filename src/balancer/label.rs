@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+use crate::errors::app_error::AppError;
+
+#[cfg(feature = "balancer")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "balancer")]
+use crate::errors::result::Result;
+
+/// `key=value` pair, used both to tag the agents and to select them
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+#[cfg(feature = "balancer")]
+impl Label {
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        labels.get(&self.key) == Some(&self.value)
+    }
+
+    /// Parses a comma separated list of labels, for example `gpu=h100,region=eu`
+    pub fn parse_list(arg: &str) -> Result<Vec<Self>> {
+        arg.split(',').map(|label| label.trim().parse()).collect()
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl FromStr for Label {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> std::result::Result<Self, Self::Err> {
+        match arg.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => Ok(Label {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid label: \"{}\" (expected \"key=value\")",
+                arg
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for Label {
+    type Error = AppError;
+
+    fn try_from(arg: String) -> std::result::Result<Self, Self::Error> {
+        arg.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(key: &str, value: &str) -> Label {
+        Label {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_the_label_with_surrounding_whitespace() {
+        assert_eq!(
+            " gpu = h100 ".parse::<Label>().unwrap(),
+            label("gpu", "h100")
+        );
+    }
+
+    #[test]
+    fn keeps_the_equal_signs_in_the_value() {
+        assert_eq!("tag=a=b".parse::<Label>().unwrap(), label("tag", "a=b"));
+    }
+
+    #[test]
+    fn rejects_the_malformed_labels() {
+        for arg in ["", " ", "gpu", "=h100", "gpu=", " = ", "gpu h100"] {
+            assert!(
+                arg.parse::<Label>().is_err(),
+                "{:?} should be rejected",
+                arg
+            );
+        }
+    }
+
+    #[cfg(feature = "balancer")]
+    #[test]
+    fn parses_the_list_of_labels() {
+        assert_eq!(
+            Label::parse_list("gpu=h100, region=eu").unwrap(),
+            vec![label("gpu", "h100"), label("region", "eu")]
+        );
+    }
+
+    #[cfg(feature = "balancer")]
+    #[test]
+    fn rejects_the_list_with_a_malformed_label() {
+        for arg in [
+            "",
+            ",",
+            "gpu=h100,",
+            ",gpu=h100",
+            "gpu=h100,,region=eu",
+            "gpu=h100,region",
+        ] {
+            assert!(
+                Label::parse_list(arg).is_err(),
+                "{:?} should be rejected",
+                arg
+            );
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{net::SocketAddr, str::FromStr};
+
+use crate::errors::app_error::AppError;
+
+/// Inference routes that are safe to expose to the outside world
+const PUBLIC_PATHS: [&str; 10] = [
+    "/chat/completions",
+    "/completion",
+    "/completions",
+    "/embedding",
+    "/embeddings",
+    "/health",
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/embeddings",
+    "/v1/models",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListenerPaths {
+    All,
+    Public,
+}
+
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub addr: SocketAddr,
+    /// If set, clients have to send `Authorization: Bearer <api_key>`
+    pub api_key: Option<String>,
+    pub name: String,
+    pub paths: ListenerPaths,
+    pub slots_endpoint_enable: bool,
+}
+
+impl Listener {
+    pub fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        match &self.api_key {
+            Some(api_key) => authorization == Some(format!("Bearer {}", api_key).as_str()),
+            None => true,
+        }
+    }
+
+    pub fn is_path_allowed(&self, path: &str) -> bool {
+        match self.paths {
+            ListenerPaths::All => true,
+            ListenerPaths::Public => PUBLIC_PATHS.contains(&path),
+        }
+    }
+}
+
+/// Parses `name=public,addr=0.0.0.0:8080,paths=public,slots_endpoint_enable=false,api_key=...`
+/// Only `addr` is required.
+impl FromStr for Listener {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let mut addr = None;
+        let mut listener = Listener {
+            addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            api_key: None,
+            name: String::new(),
+            paths: ListenerPaths::All,
+            slots_endpoint_enable: false,
+        };
+
+        for option in arg.split(',') {
+            match option.split_once('=') {
+                Some(("addr", value)) => addr = Some(value.parse()?),
+                Some(("api_key", value)) => listener.api_key = Some(value.to_string()),
+                Some(("name", value)) => listener.name = value.to_string(),
+                Some(("paths", "all")) => listener.paths = ListenerPaths::All,
+                Some(("paths", "public")) => listener.paths = ListenerPaths::Public,
+                Some(("slots_endpoint_enable", value)) => {
+                    listener.slots_endpoint_enable = match value {
+                        "true" => true,
+                        "false" => false,
+                        _ => {
+                            return Err(AppError::UnexpectedError(format!(
+                                "Invalid listener slots_endpoint_enable value: {}",
+                                value
+                            )))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(AppError::UnexpectedError(format!(
+                        "Invalid listener option: {}",
+                        option
+                    )))
+                }
+            }
+        }
+
+        listener.addr = match addr {
+            Some(addr) => addr,
+            None => return Err("Listener address (addr=...) is required".into()),
+        };
+
+        if listener.name.is_empty() {
+            listener.name = listener.addr.to_string();
+        }
+
+        Ok(listener)
+    }
+}
@@ -5,27 +5,59 @@ use std::{net::SocketAddr, sync::Arc};
 
 #[cfg(unix)]
 use pingora::server::ListenFds;
+#[cfg(unix)]
+use std::{
+    io,
+    net::TcpListener,
+    os::fd::{AsRawFd, FromRawFd},
+};
 
-use crate::balancer::{http_route, upstream_peer_pool::UpstreamPeerPool};
+use crate::balancer::{
+    cluster_stats::ClusterStats, config_reloader::ConfigReloader,
+    downstream_connections::DownstreamConnections, http_route, proxy_settings::ProxySettingsStore,
+    token_quotas::TokenQuotas, upstream_peer_pool::UpstreamPeerPool, webhook_stats::WebhookStats,
+};
 
 pub struct ManagementService {
     addr: SocketAddr,
+    cluster_stats: Arc<ClusterStats>,
+    config_reloader: Option<Arc<ConfigReloader>>,
+    downstream_connections: Arc<DownstreamConnections>,
     #[cfg(feature = "web_dashboard")]
     management_dashboard_enable: bool,
+    management_events_enable: bool,
+    proxy_settings: Arc<ProxySettingsStore>,
+    token_quotas: Option<Arc<TokenQuotas>>,
     upstream_peers: Arc<UpstreamPeerPool>,
+    webhook_stats: Arc<WebhookStats>,
 }
 
 impl ManagementService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         addr: SocketAddr,
+        cluster_stats: Arc<ClusterStats>,
+        config_reloader: Option<Arc<ConfigReloader>>,
+        downstream_connections: Arc<DownstreamConnections>,
         #[cfg(feature = "web_dashboard")] management_dashboard_enable: bool,
+        management_events_enable: bool,
+        proxy_settings: Arc<ProxySettingsStore>,
+        token_quotas: Option<Arc<TokenQuotas>>,
         upstream_peers: Arc<UpstreamPeerPool>,
+        webhook_stats: Arc<WebhookStats>,
     ) -> Self {
         ManagementService {
             addr,
+            cluster_stats,
+            config_reloader,
+            downstream_connections,
             #[cfg(feature = "web_dashboard")]
             management_dashboard_enable,
+            management_events_enable,
+            proxy_settings,
+            token_quotas,
             upstream_peers,
+            webhook_stats,
         }
     }
 }
@@ -34,20 +66,80 @@ impl ManagementService {
 impl Service for ManagementService {
     async fn start_service(
         &mut self,
-        #[cfg(unix)] _fds: Option<ListenFds>,
+        #[cfg(unix)] fds: Option<ListenFds>,
         mut _shutdown: ShutdownWatch,
     ) {
         #[cfg(feature = "web_dashboard")]
         let management_dashboard_enable = self.management_dashboard_enable;
 
+        let cluster_stats: Data<ClusterStats> = self.cluster_stats.clone().into();
+        let config_reloader: Option<Data<ConfigReloader>> =
+            self.config_reloader.clone().map(Data::from);
+        let downstream_connections: Data<DownstreamConnections> =
+            self.downstream_connections.clone().into();
+        let management_events_enable = self.management_events_enable;
+        let proxy_settings: Data<ProxySettingsStore> = self.proxy_settings.clone().into();
+        let token_quotas: Option<Data<TokenQuotas>> = self.token_quotas.clone().map(Data::from);
         let upstream_peers: Data<UpstreamPeerPool> = self.upstream_peers.clone().into();
+        let webhook_stats: Data<WebhookStats> = self.webhook_stats.clone().into();
+
+        // the listening socket is handed over to the next balancer on upgrade, like the ones
+        // of the proxy listeners; it is taken before building the server, which is not `Send`
+        #[cfg(unix)]
+        let listener = match fds {
+            Some(fds) => Some(upgradable_listener(self.addr, fds).await),
+            None => None,
+        };
 
-        HttpServer::new(move || {
-            #[allow(unused_mut)]
+        let http_server = HttpServer::new(move || {
             let mut app = App::new()
+                .app_data(cluster_stats.clone())
+                .app_data(downstream_connections.clone())
+                .app_data(proxy_settings.clone())
                 .app_data(upstream_peers.clone())
+                .app_data(webhook_stats.clone())
+                .configure(http_route::agent_history::register)
+                .configure(http_route::cancel_rolling_drain::register)
+                .configure(http_route::cluster_stats::register)
+                .configure(http_route::evict_agent::register)
+                .configure(http_route::get_rolling_drain::register)
+                .configure(http_route::quarantine_agent::register)
                 .configure(http_route::registered_agents::register)
-                .configure(http_route::receive_status_update::register);
+                .configure(http_route::receive_status_update::register)
+                .configure(http_route::set_max_concurrency::register)
+                .configure(http_route::set_upstream_headers::register)
+                .configure(http_route::simulate_selection::register)
+                .configure(http_route::start_rolling_drain::register)
+                .configure(http_route::webhook_stats::register);
+
+            // reloading only makes sense with a config file
+            if let Some(config_reloader) = &config_reloader {
+                app = app
+                    .app_data(config_reloader.clone())
+                    .configure(http_route::reload_config::register);
+            }
+
+            // there is nothing to show or change without the quotas
+            if let Some(token_quotas) = &token_quotas {
+                app = app
+                    .app_data(token_quotas.clone())
+                    .configure(http_route::get_token_quotas::register)
+                    .configure(http_route::set_token_quota::register);
+            }
+
+            if management_events_enable {
+                app = app.configure(http_route::pool_events::register);
+            }
+
+            #[cfg(feature = "openapi")]
+            {
+                app = app.configure(http_route::openapi::register);
+            }
+
+            #[cfg(feature = "pool_inspection")]
+            {
+                app = app.configure(http_route::pool_inspection::register);
+            }
 
             #[cfg(feature = "web_dashboard")]
             if management_dashboard_enable {
@@ -57,12 +149,22 @@ impl Service for ManagementService {
             }
 
             app
-        })
-        .bind(self.addr)
-        .expect("Unable to bind server to address")
-        .run()
-        .await
-        .expect("Server unexpectedly stopped");
+        });
+
+        #[cfg(unix)]
+        let http_server = match listener {
+            Some(listener) => listener.and_then(|listener| http_server.listen(listener)),
+            None => http_server.bind(self.addr),
+        };
+
+        #[cfg(windows)]
+        let http_server = http_server.bind(self.addr);
+
+        http_server
+            .expect("Unable to bind server to address")
+            .run()
+            .await
+            .expect("Server unexpectedly stopped");
     }
 
     fn name(&self) -> &str {
@@ -73,3 +175,22 @@ impl Service for ManagementService {
         Some(1)
     }
 }
+
+/// Takes over the socket of the balancer that is being upgraded, or binds a new one and adds it
+/// to the sockets that are handed over to the next one
+#[cfg(unix)]
+async fn upgradable_listener(addr: SocketAddr, fds: ListenFds) -> io::Result<TcpListener> {
+    let addr = addr.to_string();
+    let mut fds = fds.lock().await;
+
+    if let Some(fd) = fds.get(&addr) {
+        // the descriptor came from the previous balancer and nothing else in this one owns it
+        return Ok(unsafe { TcpListener::from_raw_fd(*fd) });
+    }
+
+    let listener = TcpListener::bind(&addr)?;
+
+    fds.add(addr, listener.as_raw_fd());
+
+    Ok(listener)
+}
@@ -0,0 +1,33 @@
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// What to do with the `HEAD` or `OPTIONS` requests. Forwarded ones never take a slot.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodPolicy {
+    /// Send the request to the best agent, like any other request
+    #[default]
+    Forward,
+    /// Respond with `405`, without contacting any agent
+    Reject,
+    /// Respond from the balancer, without contacting any agent
+    Respond,
+}
+
+impl FromStr for MethodPolicy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "forward" => Ok(MethodPolicy::Forward),
+            "reject" => Ok(MethodPolicy::Reject),
+            "respond" => Ok(MethodPolicy::Respond),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid method policy: {} (expected \"forward\", \"reject\", or \"respond\")",
+                arg
+            ))),
+        }
+    }
+}
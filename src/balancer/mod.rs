@@ -1,9 +1,224 @@
+#[cfg(any(feature = "agent", feature = "balancer"))]
+pub mod injected_header;
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+pub mod label;
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+pub mod status_update;
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+pub mod upstream_protocol;
+
+#[cfg(feature = "balancer")]
+pub mod admission_rate_policy;
+
+#[cfg(feature = "balancer")]
+pub mod agent_name_pattern;
+
+#[cfg(feature = "balancer")]
+pub mod client_connection_limiter;
+
+#[cfg(feature = "balancer")]
+pub mod cluster_stats;
+
+#[cfg(feature = "balancer")]
+pub mod config_file;
+
+#[cfg(all(unix, feature = "balancer"))]
+pub mod config_reload_service;
+
+#[cfg(feature = "balancer")]
+pub mod config_reloader;
+
+#[cfg(feature = "balancer")]
+pub mod cooldown_policy;
+
+#[cfg(feature = "balancer")]
+pub mod dns_discovery_service;
+
+#[cfg(feature = "balancer")]
+pub mod downstream_connections;
+
+#[cfg(feature = "balancer")]
+pub mod downstream_connections_service;
+
+#[cfg(feature = "balancer")]
+pub mod duplicate_agent_id_policy;
+
+#[cfg(feature = "statsd_reporter")]
+pub mod endpoint_metrics;
+
+#[cfg(feature = "balancer")]
+pub mod error_penalty_policy;
+
+#[cfg(feature = "balancer")]
+pub mod excess_connection_policy;
+
+#[cfg(feature = "balancer")]
+pub mod flap_damping_policy;
+
+#[cfg(feature = "grpc_health")]
+pub mod grpc_health_service;
+
+#[cfg(feature = "balancer")]
+pub mod hardware_class_weight;
+
+#[cfg(feature = "balancer")]
+pub mod host_header;
+
+#[cfg(feature = "balancer")]
 pub mod http_route;
+
+#[cfg(feature = "balancer")]
+pub mod inspected_request;
+
+#[cfg(feature = "balancer")]
+pub mod listener;
+
+#[cfg(feature = "balancer")]
 pub mod management_service;
+
+#[cfg(feature = "balancer")]
+pub mod method_policy;
+
+#[cfg(feature = "balancer")]
+pub mod model_loading_probe;
+
+#[cfg(feature = "balancer")]
+pub mod model_version;
+
+#[cfg(feature = "balancer")]
+pub mod oversized_batch_policy;
+
+#[cfg(feature = "balancer")]
+pub mod parameter_overrides;
+
+#[cfg(feature = "balancer")]
+pub mod path_rewrite_policy;
+
+#[cfg(feature = "balancer")]
+pub mod peer_history;
+
+#[cfg(feature = "balancer")]
+pub mod peer_history_service;
+
+#[cfg(feature = "balancer")]
+pub mod placement_strategy;
+
+#[cfg(feature = "balancer")]
+pub mod pool_audit;
+
+#[cfg(feature = "balancer")]
+pub mod pool_event;
+
+#[cfg(any(all(test, feature = "balancer"), feature = "pool_inspection"))]
+pub mod pool_inspection;
+
+#[cfg(feature = "balancer")]
+pub mod pool_snapshot;
+
+#[cfg(feature = "balancer")]
+pub mod pool_snapshot_service;
+
+#[cfg(feature = "balancer")]
+pub mod priority_policy;
+
+#[cfg(feature = "balancer")]
 pub mod proxy_service;
-pub mod status_update;
-pub mod upstream_peer;
-pub mod upstream_peer_pool;
+
+#[cfg(feature = "balancer")]
+pub mod proxy_service_builder;
+
+#[cfg(feature = "balancer")]
+pub mod proxy_settings;
+
+#[cfg(feature = "balancer")]
+pub mod request_coalescer;
+
+#[cfg(feature = "balancer")]
+pub mod request_priority;
+
+#[cfg(feature = "balancer")]
+pub mod response_cache;
+
+#[cfg(feature = "balancer")]
+pub mod response_compression_policy;
+
+#[cfg(feature = "balancer")]
+pub mod response_status_counts;
+
+#[cfg(feature = "balancer")]
+pub mod retry_budget;
+
+#[cfg(feature = "balancer")]
+pub mod rolling_drain;
+
+#[cfg(feature = "balancer")]
+pub mod rolling_drain_service;
+
+#[cfg(feature = "balancer")]
+pub mod routing_explanation;
+
+#[cfg(feature = "balancer")]
+pub mod routing_rule;
+
+#[cfg(feature = "balancer")]
+pub mod shutdown_audit_service;
+
+#[cfg(feature = "balancer")]
+pub mod slots_endpoint_disabled_policy;
+
+#[cfg(feature = "balancer")]
+pub mod slots_probe;
+
+#[cfg(feature = "balancer")]
+pub mod static_peers_config;
+
+#[cfg(feature = "balancer")]
+pub mod static_peers_probe_service;
 
 #[cfg(feature = "statsd_reporter")]
 pub mod statsd_service;
+
+#[cfg(feature = "balancer")]
+pub mod stream_error_detector;
+
+#[cfg(feature = "balancer")]
+pub mod tie_break_strategy;
+
+#[cfg(feature = "balancer")]
+pub mod token_quota;
+
+#[cfg(feature = "balancer")]
+pub mod token_quotas;
+
+#[cfg(feature = "balancer")]
+pub mod token_usage;
+
+#[cfg(feature = "balancer")]
+pub mod upstream_headers_policy;
+
+#[cfg(feature = "balancer")]
+pub mod upstream_peer;
+
+#[cfg(feature = "balancer")]
+pub mod upstream_peer_pool;
+
+#[cfg(feature = "balancer")]
+pub mod upstream_status_retry_policy;
+
+#[cfg(feature = "balancer")]
+pub mod warmup_probe_service;
+
+#[cfg(feature = "balancer")]
+pub mod webhook_event;
+
+#[cfg(feature = "balancer")]
+pub mod webhook_policy;
+
+#[cfg(feature = "balancer")]
+pub mod webhook_service;
+
+#[cfg(feature = "balancer")]
+pub mod webhook_stats;
@@ -0,0 +1,49 @@
+use tokio::time::Duration;
+
+use crate::{balancer::upstream_peer::UpstreamPeerInfo, errors::result::Result};
+
+/// Only checked after a `503`, which the client is waiting for
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// llama.cpp responds with `503` both while it loads the model and when it is overloaded, its
+/// health endpoint tells them apart
+pub struct ModelLoadingProbe {
+    client: reqwest::Client,
+    /// How long the peer gets no requests after it responded that it is loading the model
+    pub loading_period: Duration,
+}
+
+impl ModelLoadingProbe {
+    pub fn new(loading_period: Duration) -> Result<Self> {
+        Ok(ModelLoadingProbe {
+            client: reqwest::Client::builder()
+                .timeout(HEALTH_CHECK_TIMEOUT)
+                .build()?,
+            loading_period,
+        })
+    }
+
+    pub async fn is_loading_model(&self, peer: &UpstreamPeerInfo) -> Result<bool> {
+        let mut request = self
+            .client
+            .get(format!("http://{}/health", peer.external_llamacpp_addr));
+
+        if let Some(api_key) = &peer.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+
+        if response.status() != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(false);
+        }
+
+        // `{"error": {"message": "Loading model", ...}}`, or `{"status": "loading model"}` in
+        // the older versions
+        Ok(response
+            .text()
+            .await?
+            .to_ascii_lowercase()
+            .contains("loading model"))
+    }
+}
@@ -0,0 +1,23 @@
+use std::cmp::Ordering;
+
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Segment<'version> {
+    Number(u64),
+    Text(&'version str),
+}
+
+fn segments(version: &str) -> impl Iterator<Item = Segment<'_>> {
+    version
+        .split(|character: char| !character.is_ascii_alphanumeric())
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment.parse() {
+            Ok(number) => Segment::Number(number),
+            Err(_) => Segment::Text(segment),
+        })
+}
+
+/// Versions are compared segment by segment, numbers numerically, so `1.10` is newer than
+/// `1.9`, and `2024-06-01` is newer than `2024-05-31`
+pub fn cmp_model_versions(version: &str, other: &str) -> Ordering {
+    segments(version).cmp(segments(other))
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// What to do with a batch of prompts that needs more slots than any agent it can go to has
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedBatchPolicy {
+    /// Take as many slots as the largest of these agents has
+    #[default]
+    Clamp,
+    /// Respond with an error
+    Reject,
+}
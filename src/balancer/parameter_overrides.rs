@@ -0,0 +1,81 @@
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Changes to the generation parameters in the completion request body
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ParameterOverrides {
+    /// Added if the client did not set them
+    #[serde(default)]
+    pub defaults: Map<String, Value>,
+    /// Numeric parameters are lowered to these values if the client set them higher
+    #[serde(default)]
+    pub max: BTreeMap<String, f64>,
+    /// Always replace whatever the client set
+    #[serde(default)]
+    pub set: Map<String, Value>,
+}
+
+impl ParameterOverrides {
+    fn apply_to(&self, request: &mut Map<String, Value>, skips_defaults: bool) {
+        if !skips_defaults {
+            for (parameter, value) in &self.defaults {
+                request
+                    .entry(parameter.to_owned())
+                    .or_insert_with(|| value.to_owned());
+            }
+        }
+
+        for (parameter, max) in &self.max {
+            if let Some(value) = request.get_mut(parameter) {
+                if value.as_f64().is_some_and(|number| number > *max) {
+                    // keep integers as integers, llama.cpp rejects `"n_predict": 512.0`
+                    *value = if value.is_f64() || max.fract() != 0.0 {
+                        Value::from(*max)
+                    } else {
+                        Value::from(*max as i64)
+                    };
+                }
+            }
+        }
+
+        for (parameter, value) in &self.set {
+            request.insert(parameter.to_owned(), value.to_owned());
+        }
+    }
+}
+
+/// Global overrides, and the overrides for specific paths, which are applied after them
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ParameterOverridesPolicy {
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, ParameterOverrides>,
+    #[serde(flatten)]
+    pub global: ParameterOverrides,
+}
+
+impl ParameterOverridesPolicy {
+    /// Returns None if the body is not a JSON object, or if nothing changed. Fields that are
+    /// not overridden are kept as they are, the `max` and `set` overrides are applied even if
+    /// the defaults are skipped.
+    pub fn apply(&self, path: &str, request_body: &[u8], skips_defaults: bool) -> Option<Bytes> {
+        let Ok(Value::Object(original_request)) = serde_json::from_slice(request_body) else {
+            return None;
+        };
+
+        let mut request = original_request.clone();
+
+        self.global.apply_to(&mut request, skips_defaults);
+
+        if let Some(endpoint_overrides) = self.endpoints.get(path) {
+            endpoint_overrides.apply_to(&mut request, skips_defaults);
+        }
+
+        if request == original_request {
+            return None;
+        }
+
+        serde_json::to_vec(&request).ok().map(Bytes::from)
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use crate::errors::{app_error::AppError, result::Result};
+
+/// `from=to` pair, the request for exactly `from` is forwarded to `to`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for PathRewrite {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> std::result::Result<Self, Self::Err> {
+        match arg.split_once('=') {
+            Some((from, to)) if from.starts_with('/') && to.starts_with('/') => Ok(PathRewrite {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid path rewrite: \"{}\" (expected \"/from=/to\")",
+                arg
+            ))),
+        }
+    }
+}
+
+/// Maps the paths the clients use to the paths llama.cpp expects
+#[derive(Clone, Debug, Default)]
+pub struct PathRewritePolicy {
+    /// Without the trailing slash, for example `/llm`
+    pub path_prefix: Option<String>,
+    /// Applied after the prefix is stripped
+    pub path_rewrites: Vec<PathRewrite>,
+}
+
+impl PathRewritePolicy {
+    /// The prefix has to start with a slash, the trailing slash is optional
+    pub fn parse_path_prefix(arg: &str) -> Result<String> {
+        let path_prefix = arg.trim_end_matches('/');
+
+        if !path_prefix.starts_with('/') {
+            return Err(AppError::UnexpectedError(format!(
+                "Invalid path prefix: \"{}\" (expected for example \"/llm\")",
+                arg
+            )));
+        }
+
+        Ok(path_prefix.to_string())
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.path_prefix.is_none() && self.path_rewrites.is_empty()
+    }
+
+    /// Returns the path to forward, and whether the prefix was stripped from it. Paths outside
+    /// of the prefix are left as they are.
+    pub fn rewrite(&self, path: &str) -> (String, bool) {
+        let (path, is_prefix_stripped) = match self.strip_prefix(path) {
+            Some(stripped_path) => (stripped_path, true),
+            None => (path, false),
+        };
+
+        let path = self
+            .path_rewrites
+            .iter()
+            .find(|path_rewrite| path_rewrite.from == path)
+            .map_or(path, |path_rewrite| path_rewrite.to.as_str());
+
+        (path.to_string(), is_prefix_stripped)
+    }
+
+    /// Upstream only knows the stripped paths, so the redirects need the prefix back
+    pub fn restore_prefix(&self, location: &str) -> Option<String> {
+        match &self.path_prefix {
+            Some(path_prefix) if location.starts_with('/') && !location.starts_with("//") => {
+                Some(format!("{}{}", path_prefix, location))
+            }
+            _ => None,
+        }
+    }
+
+    /// `/llm` and `/llm/` both become `/`, but `/llmfoo` or `/v1/llm/` are not under the prefix
+    fn strip_prefix<'path>(&self, path: &'path str) -> Option<&'path str> {
+        let stripped_path = path.strip_prefix(self.path_prefix.as_deref()?)?;
+
+        if stripped_path.is_empty() {
+            Some("/")
+        } else if stripped_path.starts_with('/') {
+            Some(stripped_path)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
+/// How often `PeerHistoryService` takes the samples
+pub const PEER_HISTORY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 30 minutes of samples, about 17 KiB per peer
+const MAX_SAMPLES: usize = 360;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerHistorySample {
+    /// Errors since the previous sample
+    pub errors: usize,
+    /// Requests completed since the previous sample
+    pub requests_completed: usize,
+    pub slots_idle: usize,
+    pub slots_processing: usize,
+    pub taken_at: SystemTime,
+}
+
+/// Recent samples of the peer state, the oldest ones are dropped
+#[derive(Debug, Default)]
+pub struct PeerHistory {
+    errors_since_sample: usize,
+    requests_completed_since_sample: usize,
+    samples: VecDeque<PeerHistorySample>,
+}
+
+impl PeerHistory {
+    pub fn register_error(&mut self) {
+        self.errors_since_sample += 1;
+    }
+
+    pub fn register_request_completed(&mut self) {
+        self.requests_completed_since_sample += 1;
+    }
+
+    pub fn take_sample(&mut self, slots_idle: usize, slots_processing: usize) {
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(PeerHistorySample {
+            errors: self.errors_since_sample,
+            requests_completed: self.requests_completed_since_sample,
+            slots_idle,
+            slots_processing,
+            taken_at: SystemTime::now(),
+        });
+
+        self.errors_since_sample = 0;
+        self.requests_completed_since_sample = 0;
+    }
+
+    /// Oldest sample first
+    pub fn samples_since(&self, since: SystemTime) -> Vec<PeerHistorySample> {
+        self.samples
+            .iter()
+            .filter(|sample| sample.taken_at >= since)
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use log::{debug, error};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::sync::Arc;
+use tokio::time::{interval, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::balancer::{peer_history::PEER_HISTORY_INTERVAL, upstream_peer_pool::UpstreamPeerPool};
+
+pub struct PeerHistoryService {
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl PeerHistoryService {
+    pub fn new(upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        PeerHistoryService { upstream_peer_pool }
+    }
+}
+
+#[async_trait]
+impl Service for PeerHistoryService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut ticker = interval(PEER_HISTORY_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down peer history service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.upstream_peer_pool.take_history_samples() {
+                        error!("Failed to take peer history samples: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "peer_history"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
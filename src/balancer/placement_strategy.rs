@@ -0,0 +1,29 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// How to distribute the requests between the agents that can take them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PlacementStrategy {
+    /// Prefer the busiest agents that still have an idle slot, so the other ones stay idle and
+    /// can be powered down
+    Pack,
+    /// Prefer the agents with the most idle slots
+    #[default]
+    Spread,
+}
+
+impl FromStr for PlacementStrategy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "pack" => Ok(PlacementStrategy::Pack),
+            "spread" => Ok(PlacementStrategy::Spread),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid placement strategy: {} (expected \"pack\" or \"spread\")",
+                arg
+            ))),
+        }
+    }
+}
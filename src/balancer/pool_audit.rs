@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct PeerInFlight {
+    pub agent_id: String,
+    pub agent_name: Option<String>,
+    /// Requests the balancer sent and did not see finish
+    pub requests_in_flight: usize,
+    /// As reported by the agent, it also counts the requests that did not go through the
+    /// balancer
+    pub slots_processing: usize,
+}
+
+/// Bookkeeping of the pool checked against itself, to tell if the requests and permits were
+/// all accounted for
+#[derive(Debug, Serialize)]
+pub struct PoolAudit {
+    pub available_permits: usize,
+    /// Slots of all the peers, minus the permits the peers hold for the requests in progress
+    pub expected_permits: usize,
+    pub peers_in_flight: Vec<PeerInFlight>,
+}
+
+impl PoolAudit {
+    pub fn is_permits_count_consistent(&self) -> bool {
+        self.available_permits == self.expected_permits
+    }
+
+    pub fn requests_in_flight(&self) -> usize {
+        self.peers_in_flight
+            .iter()
+            .map(|peer_in_flight| peer_in_flight.requests_in_flight)
+            .sum()
+    }
+}
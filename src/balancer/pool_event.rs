@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PoolEvent {
+    PeerAdded {
+        agent_id: String,
+    },
+    PeerDrained {
+        agent_id: String,
+    },
+    /// `connect_failed`, `warmup_probe_timeout`, or `manual` when quarantined through the
+    /// management server
+    PeerQuarantined {
+        agent_id: String,
+        reason: &'static str,
+    },
+    PeerRecovered {
+        agent_id: String,
+    },
+    /// `disconnected`, `evicted`, `stale`, `replaced` (registered again under the same id),
+    /// `superseded` (another agent reported the same llama.cpp address), or `undiscovered`
+    /// (the address disappeared from DNS)
+    PeerRemoved {
+        agent_id: String,
+        reason: &'static str,
+    },
+    SlotReleased {
+        agent_id: String,
+    },
+    SlotTaken {
+        agent_id: String,
+    },
+    Utilization {
+        slots_idle: usize,
+        slots_processing: usize,
+    },
+}
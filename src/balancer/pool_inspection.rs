@@ -0,0 +1,14 @@
+use serde::Serialize;
+
+/// Internal state of the pool that is otherwise hidden, meant for asserting invariants in the
+/// integration tests
+#[derive(Debug, Serialize)]
+pub struct PoolInspection {
+    pub available_permits: usize,
+    pub peers_count: usize,
+    pub quarantined_agent_ids: Vec<String>,
+    pub requests_in_flight: usize,
+    pub requests_waiting_for_permit: usize,
+    pub slots_idle: usize,
+    pub slots_processing: usize,
+}
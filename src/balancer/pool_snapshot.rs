@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, net::SocketAddr, path::Path, time::SystemTime};
+
+use crate::{
+    balancer::{
+        token_quotas::TokenQuotaUsage, upstream_peer::UpstreamPeer,
+        upstream_protocol::UpstreamProtocol,
+    },
+    errors::result::Result,
+    llamacpp::model_info::ModelInfo,
+};
+
+/// Capacity topology of a peer, without anything tied to the requests in progress
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PeerSnapshot {
+    pub agent_id: String,
+    pub agent_max_concurrency: Option<usize>,
+    pub agent_name: Option<String>,
+    pub external_llamacpp_addr: SocketAddr,
+    pub hardware_class: Option<String>,
+    pub is_slots_endpoint_enabled: Option<bool>,
+    pub labels: BTreeMap<String, String>,
+    pub max_concurrency_override: Option<usize>,
+    pub model_info: Option<ModelInfo>,
+    pub quarantined_until: Option<SystemTime>,
+    pub restart_epoch: u64,
+    pub slots: usize,
+    pub tier: usize,
+    pub upstream_protocol: Option<UpstreamProtocol>,
+}
+
+impl PeerSnapshot {
+    pub fn new_from_upstream_peer(upstream_peer: &UpstreamPeer) -> Self {
+        Self {
+            agent_id: upstream_peer.agent_id.clone(),
+            agent_max_concurrency: upstream_peer.agent_max_concurrency,
+            agent_name: upstream_peer.agent_name.clone(),
+            external_llamacpp_addr: upstream_peer.external_llamacpp_addr,
+            hardware_class: upstream_peer.hardware_class.clone(),
+            is_slots_endpoint_enabled: upstream_peer.is_slots_endpoint_enabled,
+            labels: upstream_peer.labels.clone(),
+            max_concurrency_override: upstream_peer.max_concurrency_override,
+            model_info: upstream_peer.model_info.clone(),
+            quarantined_until: upstream_peer.quarantined_until,
+            restart_epoch: upstream_peer.restart_epoch,
+            slots: upstream_peer.slots_count(),
+            tier: upstream_peer.tier,
+            upstream_protocol: upstream_peer.upstream_protocol,
+        }
+    }
+}
+
+/// Static peers are not included, they come from the static peers file anyway
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PoolSnapshot {
+    pub peers: Vec<PeerSnapshot>,
+    /// Tokens used by the listeners with a quota, the snapshots from before the quotas do not
+    /// have them
+    #[serde(default)]
+    pub token_quotas: BTreeMap<String, TokenQuotaUsage>,
+}
+
+impl PoolSnapshot {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Writes to a temporary file first, so a crash in the middle never leaves a truncated
+    /// snapshot behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
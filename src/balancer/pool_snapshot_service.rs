@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use log::{debug, error};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{path::PathBuf, sync::Arc};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{
+    balancer::{token_quotas::TokenQuotas, upstream_peer_pool::UpstreamPeerPool},
+    errors::result::Result,
+};
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct PoolSnapshotService {
+    state_file: PathBuf,
+    token_quotas: Option<Arc<TokenQuotas>>,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl PoolSnapshotService {
+    pub fn new(
+        state_file: PathBuf,
+        token_quotas: Option<Arc<TokenQuotas>>,
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+    ) -> Self {
+        PoolSnapshotService {
+            state_file,
+            token_quotas,
+            upstream_peer_pool,
+        }
+    }
+
+    fn save_snapshot(&self) -> Result<()> {
+        let mut pool_snapshot = self.upstream_peer_pool.snapshot()?;
+
+        if let Some(token_quotas) = &self.token_quotas {
+            pool_snapshot.token_quotas = token_quotas.snapshot();
+        }
+
+        pool_snapshot.save(&self.state_file)
+    }
+}
+
+#[async_trait]
+impl Service for PoolSnapshotService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut ticker = interval(SNAPSHOT_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down pool snapshot service");
+
+                    if let Err(err) = self.save_snapshot() {
+                        error!("Failed to save pool snapshot: {}", err);
+                    }
+
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.upstream_peer_pool.evict_stale_peers() {
+                        error!("Failed to evict stale peers: {}", err);
+                    }
+
+                    if let Err(err) = self.save_snapshot() {
+                        error!("Failed to save pool snapshot: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "pool_snapshot"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,13 @@
+use serde::Deserialize;
+
+/// Which source of the request priority wins if both the client header and the model mapping
+/// provide one
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityPolicy {
+    /// Clients can raise or lower the priority of their requests
+    HeaderFirst,
+    /// Clients can only set the priority of models that are not mapped
+    #[default]
+    ModelFirst,
+}
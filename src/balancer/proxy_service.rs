@@ -1,50 +1,764 @@
 use async_trait::async_trait;
-use bytes::Bytes;
-use log::error;
+use bytes::{Bytes, BytesMut};
+use log::{debug, error, info, warn};
 use pingora::{
-    http::RequestHeader,
+    http::{Method, RequestHeader, ResponseHeader, Version},
+    modules::http::{
+        compression::{ResponseCompression, ResponseCompressionBuilder},
+        HttpModules,
+    },
     protocols::Digest,
     proxy::{ProxyHttp, Session},
     upstreams::peer::HttpPeer,
-    Error, ErrorSource, Result,
+    Error, ErrorSource, ErrorType, Result,
 };
-use std::{sync::Arc, time::Duration};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
 
 use crate::{
-    balancer::{upstream_peer::UpstreamPeerInfo, upstream_peer_pool::UpstreamPeerPool},
-    errors::result::Result as PaddlerResult,
+    balancer::{
+        client_connection_limiter::ClientConnectionLimiter,
+        cluster_stats::{ClusterStats, RejectionReason},
+        downstream_connections::{DownstreamConnection, DownstreamConnections},
+        excess_connection_policy::ExcessConnectionPolicy,
+        host_header::{format_host_header, server_name},
+        inspected_request::InspectedRequest,
+        label::Label,
+        listener::Listener,
+        method_policy::MethodPolicy,
+        model_loading_probe::ModelLoadingProbe,
+        oversized_batch_policy::OversizedBatchPolicy,
+        proxy_settings::{ProxySettings, ProxySettingsStore},
+        request_coalescer::{CoalescedRequest, CoalescingLeader, RequestCoalescer},
+        request_priority::RequestPriority,
+        response_cache::{CachedResponse, ResponseCache},
+        retry_budget::RetryBudget,
+        routing_rule::{find_routing_rule, RoutingRule},
+        slots_probe::SlotsProbe,
+        stream_error_detector::{StreamError, StreamErrorDetector},
+        tie_break_strategy::request_hash,
+        token_quotas::TokenQuotas,
+        token_usage::TokenUsageCollector,
+        upstream_headers_policy::inject_peer_headers,
+        upstream_peer::UpstreamPeerInfo,
+        upstream_peer_pool::UpstreamPeerPool,
+        upstream_protocol::UpstreamProtocol,
+    },
+    errors::{app_error::AppError, result::Result as PaddlerResult},
 };
 
+#[cfg(feature = "statsd_reporter")]
+use crate::balancer::endpoint_metrics::EndpointMetrics;
+
+/// Batches of prompts sent to this endpoint take a slot per prompt
+const BATCH_ENDPOINT_PATH: &str = "/v1/completions";
+
+/// New connections over `--max-downstream-connections` check for a free one that often
+const DOWNSTREAM_CONNECTION_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bodies are only buffered when the balancer needs to look inside them, the proxy sends the
+/// buffered body in place of its retry buffer, so it has to fit that buffer
+const MAX_INSPECTED_BODY_SIZE: usize = 64 * 1024;
+
+/// Set to `1` to skip the `defaults` of the parameter overrides, for example to see what the
+/// client actually sent; requires `TARGET_AGENT_TOKEN_HEADER` if the token is set
+const NO_DEFAULTS_HEADER: &str = "X-Paddler-No-Defaults";
+
+/// Set to `1` to confirm the idle slots of the selected agent with llama.cpp first, see
+/// `SlotsProbe`
+const PROBE_SLOTS_HEADER: &str = "X-Paddler-Probe-Slots";
+
+/// Clients that know the exact prompt length can skip the estimation
+pub const PROMPT_TOKENS_HEADER: &str = "X-Paddler-Prompt-Tokens";
+
+/// Agents found busy by the slots probe are skipped, after that the request goes to the best
+/// agent without a probe
+const MAX_SLOTS_PROBES: usize = 3;
+
+/// Comma separated `key=value` labels the agent needs to have
+pub const REQUIRE_HEADER: &str = "X-Paddler-Require";
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Required to access the slots endpoint if `slots_endpoint_token` is set
+const SLOTS_TOKEN_HEADER: &str = "X-Paddler-Slots-Token";
+
+/// Set on the responses served from the response cache
+const RESPONSE_CACHE_HEADER: &str = "X-Paddler-Cache";
+
+/// Confirms which agent handled the request forced with `TARGET_AGENT_HEADER`
+const RESPONSE_AGENT_HEADER: &str = "X-Paddler-Agent";
+
+/// Id or name of the agent that has to handle the request, bypasses the peer selection
+const TARGET_AGENT_HEADER: &str = "X-Paddler-Target-Agent";
+
+const TARGET_AGENT_TOKEN_HEADER: &str = "X-Paddler-Target-Agent-Token";
+
+/// Requests in progress that can share an HTTP/2 connection with llama.cpp, the slots of the
+/// agent limit them anyway
+const UPSTREAM_MAX_H2_STREAMS: usize = 128;
+
+/// Completion endpoints, the other paths are forwarded without waiting for a slot
+pub fn takes_slot(path: &str) -> bool {
+    matches!(
+        path,
+        "/chat/completions" | "/completion" | "/v1/chat/completions" | BATCH_ENDPOINT_PATH
+    )
+}
+
 pub struct LlamaCppContext {
+    /// Response collected for the response cache and the coalesced requests, None if it can't
+    /// be shared
+    cacheable_response_body: Option<BytesMut>,
+    cacheable_response_content_type: Option<String>,
+    /// Set if the upstream connection outlived `upstream_connection_max_lifetime`, so it is
+    /// closed once the response is complete
+    closes_upstream_connection: bool,
+    /// Set if identical requests wait for the response of this one
+    coalescing_leader: Option<CoalescingLeader>,
+    /// Keeps the connection from counting as idle until the request ends
+    downstream_connection: Option<DownstreamConnection>,
+    /// Set if the endpoint metrics are collected
+    #[cfg(feature = "statsd_reporter")]
+    endpoint: Option<&'static str>,
+    /// Balancer answers `Expect: 100-continue` itself, so it's never forwarded to llama.cpp
+    expects_continue: bool,
+    /// Set when the selected peer responded that it is still loading the model
+    is_model_loading: bool,
+    /// Set when no agent can take the request, `fail_to_proxy` responds with the
+    /// `no_capacity_status`
+    is_over_capacity: bool,
+    /// `Location` headers of the response need the prefix back
+    is_path_prefix_stripped: bool,
+    /// Set when the response was cut off at `max_response_bytes`
+    is_response_too_large: bool,
+    /// Set when the upstream error response is turned into an error, to retry the request
+    is_retrying_upstream_status: bool,
+    /// Set if a retry was not allowed because of the retry budget
+    is_retry_budget_exhausted: bool,
+    /// Set when the selected peer became unusable, or its slot could not be taken, before the
+    /// request was sent to it
+    is_selection_invalidated: bool,
+    label_selectors: Vec<Label>,
+    priority: RequestPriority,
+    /// Set if the idle slots of the selected agent are confirmed with llama.cpp
+    probes_slots: bool,
+    /// Estimated or declared by the client, None if the context size is not checked
+    prompt_tokens: Option<usize>,
+    proxy_settings: Arc<ProxySettings>,
+    /// Set if the body was read in `request_filter`, and has to be replayed to the upstream
+    request_body: Option<Bytes>,
+    /// Set if the body did not fit `MAX_INSPECTED_BODY_SIZE`, the part read in `request_filter`
+    /// goes ahead of the rest of the body
+    request_body_read: Option<Bytes>,
+    request_started_at: Instant,
+    /// Set if the response can be cached or shared with the coalesced requests
+    response_cache_key: Option<u64>,
+    /// Only counted if the response size is limited
+    response_bytes: usize,
+    /// Once the client got a part of the response, the request can't be retried anymore
+    response_bytes_forwarded: bool,
+    retries: usize,
     slot_taken: bool,
     selected_peer: Option<UpstreamPeerInfo>,
+    /// Agents that responded with a retried status, the retries go to the other ones
+    skipped_agent_ids: Vec<String>,
+    /// Set by `NO_DEFAULTS_HEADER`
+    skips_parameter_defaults: bool,
+    /// Requests with a batch of prompts take more than one slot
+    slots: usize,
+    /// Set when llama.cpp aborted the stream with an error, after responding with 200
+    stream_error: Option<StreamError>,
+    /// Set if the response is a stream
+    stream_error_detector: Option<StreamErrorDetector>,
+    target_agent: Option<String>,
+    /// Set if the response counts towards the token quota of the listener
+    token_usage: Option<TokenUsageCollector>,
+    tried_agent_ids: Vec<String>,
+    /// Status of the last response from llama.cpp
+    upstream_status: Option<u16>,
     uses_slots: bool,
 }
 
 pub struct ProxyService {
-    rewrite_host_header: bool,
-    slots_endpoint_enable: bool,
+    client_connection_limiter: Option<Arc<ClientConnectionLimiter>>,
+    cluster_stats: Option<Arc<ClusterStats>>,
+    downstream_connections: Option<Arc<DownstreamConnections>>,
+    #[cfg(feature = "statsd_reporter")]
+    endpoint_metrics: Option<Arc<EndpointMetrics>>,
+    /// Set in the observe mode, the requests take no permits and no slots then
+    is_slot_accounting_disabled: bool,
+    listener: Listener,
+    model_loading_probe: Option<Arc<ModelLoadingProbe>>,
+    proxy_settings: Arc<ProxySettingsStore>,
+    request_coalescer: Option<Arc<RequestCoalescer>>,
+    response_cache: Option<Arc<ResponseCache>>,
+    retry_budget: Option<Arc<RetryBudget>>,
+    slots_probe: Option<Arc<SlotsProbe>>,
+    token_quotas: Option<Arc<TokenQuotas>>,
     upstream_peer_pool: Arc<UpstreamPeerPool>,
 }
 
 impl ProxyService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        rewrite_host_header: bool,
-        slots_endpoint_enable: bool,
+        client_connection_limiter: Option<Arc<ClientConnectionLimiter>>,
+        cluster_stats: Option<Arc<ClusterStats>>,
+        downstream_connections: Option<Arc<DownstreamConnections>>,
+        #[cfg(feature = "statsd_reporter")] endpoint_metrics: Option<Arc<EndpointMetrics>>,
+        is_slot_accounting_disabled: bool,
+        listener: Listener,
+        model_loading_probe: Option<Arc<ModelLoadingProbe>>,
+        proxy_settings: Arc<ProxySettingsStore>,
+        request_coalescer: Option<Arc<RequestCoalescer>>,
+        response_cache: Option<Arc<ResponseCache>>,
+        retry_budget: Option<Arc<RetryBudget>>,
+        slots_probe: Option<Arc<SlotsProbe>>,
+        token_quotas: Option<Arc<TokenQuotas>>,
         upstream_peer_pool: Arc<UpstreamPeerPool>,
     ) -> Self {
         Self {
-            rewrite_host_header,
-            slots_endpoint_enable,
+            client_connection_limiter,
+            cluster_stats,
+            downstream_connections,
+            #[cfg(feature = "statsd_reporter")]
+            endpoint_metrics,
+            is_slot_accounting_disabled,
+            listener,
+            model_loading_probe,
+            proxy_settings,
+            request_coalescer,
+            response_cache,
+            retry_budget,
+            slots_probe,
+            token_quotas,
             upstream_peer_pool,
         }
     }
 
+    /// Counts the retries that are given up because the body can't be sent again, so it is
+    /// checked last
+    fn can_resend_request_body(&self, session: &Session, ctx: &LlamaCppContext) -> bool {
+        if Self::is_request_body_resendable(session, ctx) {
+            return true;
+        }
+
+        self.upstream_peer_pool.register_retry_skipped_by_body_size();
+
+        false
+    }
+
+    /// Only the bodies that fit the retry buffer can be sent to another peer
+    fn is_request_body_resendable(session: &Session, ctx: &LlamaCppContext) -> bool {
+        !session.as_ref().retry_buffer_truncated()
+            && session.as_ref().body_bytes_read() <= ctx.proxy_settings.retry_buffer_bytes
+    }
+
+    /// Every retry decision has to go through here, so the retries are bounded per request
+    #[inline]
+    fn allow_retry(&self, ctx: &mut LlamaCppContext) -> bool {
+        if ctx.response_bytes_forwarded {
+            error!(
+                "Not retrying, the response was already partially sent, tried agents: {}",
+                ctx.tried_agent_ids.join(", ")
+            );
+
+            return false;
+        }
+
+        if ctx.retries >= ctx.proxy_settings.max_retries_per_request {
+            error!(
+                "Retries limit ({}) reached, tried agents: {}",
+                ctx.proxy_settings.max_retries_per_request,
+                ctx.tried_agent_ids.join(", ")
+            );
+
+            return false;
+        }
+
+        if let Some(retry_budget) = &self.retry_budget {
+            if !retry_budget.try_spend() {
+                error!(
+                    "Retry budget exhausted, tried agents: {}",
+                    ctx.tried_agent_ids.join(", ")
+                );
+
+                ctx.is_retry_budget_exhausted = true;
+
+                return false;
+            }
+        }
+
+        ctx.retries += 1;
+
+        true
+    }
+
+    #[inline]
+    /// None if the connection does not report when it was established
+    fn connection_age(digest: Option<&Digest>) -> Option<Duration> {
+        digest?
+            .timing_digest
+            .iter()
+            .flatten()
+            .next()?
+            .established_ts
+            .elapsed()
+            .ok()
+    }
+
+    /// The response was a success as far as the status goes, so it is counted and penalized
+    /// here instead of in `response_filter`
+    fn register_stream_error(&self, ctx: &LlamaCppContext, stream_error: &StreamError) {
+        warn!(
+            "Agent {} aborted the stream: {}",
+            ctx.selected_peer
+                .as_ref()
+                .map_or("-", |peer| peer.agent_id.as_str()),
+            stream_error.message
+        );
+
+        #[cfg(feature = "statsd_reporter")]
+        if let (Some(endpoint_metrics), Some(endpoint)) = (&self.endpoint_metrics, ctx.endpoint) {
+            if let Err(err) = endpoint_metrics.register_stream_error(endpoint) {
+                error!("Failed to register endpoint stream error: {}", err);
+            }
+        }
+
+        if let Some(peer) = &ctx.selected_peer {
+            if let Err(err) = self
+                .upstream_peer_pool
+                .register_stream_error(&peer.agent_id)
+            {
+                error!("Failed to register stream error: {}", err);
+            }
+
+            if let Err(err) = self.upstream_peer_pool.register_error(&peer.agent_id) {
+                error!("Failed to register error: {}", err);
+            }
+        }
+    }
+
+    fn register_rejection(&self, reason: RejectionReason) {
+        if let Some(cluster_stats) = &self.cluster_stats {
+            cluster_stats.register_rejection(reason);
+        }
+    }
+
+    /// Clients and load balancers in front of the balancer might back off differently
+    /// depending on the status, see `fail_to_proxy`
+    fn no_capacity_error(ctx: &mut LlamaCppContext) -> Box<Error> {
+        ctx.is_over_capacity = true;
+
+        Error::explain(
+            ErrorType::HTTPStatus(ctx.proxy_settings.no_capacity_status),
+            "No agent can take the request",
+        )
+    }
+
+    /// Clients can tell the requests that failed because of the retry budget from the other
+    /// upstream errors
+    fn retry_budget_exhausted_error() -> Box<Error> {
+        Error::explain(ErrorType::HTTPStatus(503), "Retry budget exhausted")
+    }
+
+    /// Returns None if the body does not fit `MAX_INSPECTED_BODY_SIZE`, the part that was read
+    /// is kept in `ctx.request_body_read` then
+    async fn read_request_body(
+        session: &mut Session,
+        ctx: &mut LlamaCppContext,
+    ) -> Result<Option<Bytes>> {
+        let mut request_body = BytesMut::new();
+
+        while let Some(chunk) = session.read_request_body().await? {
+            request_body.extend_from_slice(&chunk);
+
+            if request_body.len() > MAX_INSPECTED_BODY_SIZE {
+                // the proxy only passes the body it still has to read to `request_body_filter`,
+                // which never happens if the chunk that went over the limit was the last one
+                if session.is_body_done() {
+                    return Err(Error::create(
+                        ErrorType::HTTPStatus(413),
+                        ErrorSource::Downstream,
+                        None,
+                        None,
+                    ));
+                }
+
+                ctx.request_body_read = Some(request_body.freeze());
+
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(request_body.freeze()))
+    }
+
+    /// The debugging headers are allowed to everyone if there is no token
+    fn has_target_agent_token(session: &Session, ctx: &LlamaCppContext) -> bool {
+        let Some(target_agent_token) = &ctx.proxy_settings.target_agent_token else {
+            return true;
+        };
+
+        session
+            .req_header()
+            .headers
+            .get(TARGET_AGENT_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            == Some(target_agent_token.as_str())
+    }
+
+    /// The slots endpoint is open to every client of the listener if there is no token
+    fn has_slots_endpoint_token(session: &Session, ctx: &LlamaCppContext) -> bool {
+        let Some(slots_endpoint_token) = &ctx.proxy_settings.slots_endpoint_token else {
+            return true;
+        };
+
+        session
+            .req_header()
+            .headers
+            .get(SLOTS_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            == Some(slots_endpoint_token.as_str())
+    }
+
+    /// Reads the body only if some of the features need to look inside it
+    async fn inspect_request(
+        &self,
+        session: &mut Session,
+        ctx: &mut LlamaCppContext,
+    ) -> Result<Option<InspectedRequest>> {
+        if !ctx.expects_continue
+            && session.req_header().uri.path() != BATCH_ENDPOINT_PATH
+            && ctx.proxy_settings.model_priorities.is_empty()
+            && ctx.proxy_settings.context_chars_per_token.is_none()
+            && ctx.proxy_settings.parameter_overrides.is_none()
+            && !ctx
+                .proxy_settings
+                .routing_rules
+                .iter()
+                .any(RoutingRule::needs_model)
+            && self.response_cache.is_none()
+            && self.request_coalescer.is_none()
+        {
+            return Ok(None);
+        }
+
+        let content_length = session
+            .req_header()
+            .headers
+            .get("Content-Length")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        // otherwise the proxy would never send the body it can't replay
+        if content_length.is_some_and(|content_length| content_length > MAX_INSPECTED_BODY_SIZE) {
+            warn!(
+                "Request body is larger than {} bytes, forwarding it without inspection",
+                MAX_INSPECTED_BODY_SIZE
+            );
+
+            return Ok(None);
+        }
+
+        // retry buffer is what gets sent upstream before the rest of the body
+        session.as_mut().enable_retry_buffering();
+
+        // bodies sent without `Content-Length` only turn out to be too large while reading them
+        let Some(mut request_body) = Self::read_request_body(session, ctx).await? else {
+            warn!(
+                "Request body is larger than {} bytes, forwarding it without inspection",
+                MAX_INSPECTED_BODY_SIZE
+            );
+
+            return Ok(None);
+        };
+
+        if let Some(parameter_overrides) = &ctx.proxy_settings.parameter_overrides {
+            if let Some(overridden_request_body) = parameter_overrides.apply(
+                session.req_header().uri.path(),
+                &request_body,
+                ctx.skips_parameter_defaults,
+            ) {
+                request_body = overridden_request_body;
+            }
+        }
+
+        let inspected_request = InspectedRequest::parse(&request_body);
+
+        ctx.request_body = Some(request_body);
+
+        Ok(Some(inspected_request))
+    }
+
+    /// Same request id, or the same client if there is none, always hashes the same
+    fn request_hash(session: &Session) -> u64 {
+        request_hash(
+            session
+                .req_header()
+                .headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok()),
+            session
+                .client_addr()
+                .and_then(|client_addr| client_addr.as_inet())
+                .map(|client_addr| client_addr.ip()),
+        )
+    }
+
+    fn resolve_prompt_tokens(
+        session: &Session,
+        ctx: &LlamaCppContext,
+        inspected_request: Option<&InspectedRequest>,
+    ) -> Option<usize> {
+        let header_prompt_tokens = session
+            .req_header()
+            .headers
+            .get(PROMPT_TOKENS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        if header_prompt_tokens.is_some() {
+            return header_prompt_tokens;
+        }
+
+        let chars_per_token = ctx.proxy_settings.context_chars_per_token?;
+
+        inspected_request.map(|inspected_request| {
+            (inspected_request.prompt_chars() as f64 / chars_per_token).ceil() as usize
+        })
+    }
+
+    /// Methods that are not rejected by the `HEAD` and `OPTIONS` policies
+    fn allowed_methods(proxy_settings: &ProxySettings) -> String {
+        let mut allowed_methods = vec!["GET", "POST"];
+
+        if proxy_settings.head_request_policy != MethodPolicy::Reject {
+            allowed_methods.push("HEAD");
+        }
+
+        if proxy_settings.options_request_policy != MethodPolicy::Reject {
+            allowed_methods.push("OPTIONS");
+        }
+
+        allowed_methods.join(", ")
+    }
+
+    async fn respond_with_method_not_allowed(
+        session: &mut Session,
+        ctx: &LlamaCppContext,
+    ) -> Result<bool> {
+        let body = Bytes::from(
+            serde_json::json!({
+                "error": format!("{} requests are not allowed", session.req_header().method),
+            })
+            .to_string(),
+        );
+        let mut response_header = ResponseHeader::build(405, None)?;
+
+        response_header.insert_header("Allow", Self::allowed_methods(&ctx.proxy_settings))?;
+        response_header.insert_header("Content-Type", "application/json")?;
+        response_header.insert_header("Content-Length", body.len().to_string())?;
+
+        session
+            .write_response_header(Box::new(response_header), false)
+            .await?;
+        session.write_response_body(Some(body), true).await?;
+
+        Ok(true)
+    }
+
+    /// `200` if any agent could take a request, so `HEAD` works as a cheap availability check
+    async fn respond_to_head(&self, session: &mut Session) -> Result<bool> {
+        let has_usable_peers = self.upstream_peer_pool.has_usable_peers().map_err(|err| {
+            error!("Failed to check usable peers: {}", err);
+
+            Error::new(pingora::InternalError)
+        })?;
+        let mut response_header =
+            ResponseHeader::build(if has_usable_peers { 200 } else { 503 }, None)?;
+
+        response_header.insert_header("Content-Length", "0")?;
+
+        session
+            .write_response_header(Box::new(response_header), true)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Also answers the CORS preflight requests, allowing the origin that asked
+    async fn respond_to_options(session: &mut Session, ctx: &LlamaCppContext) -> Result<bool> {
+        let allowed_methods = Self::allowed_methods(&ctx.proxy_settings);
+        let headers = &session.req_header().headers;
+        let origin = headers.get("Origin").cloned();
+        let requested_headers = headers.get("Access-Control-Request-Headers").cloned();
+        let mut response_header = ResponseHeader::build(204, None)?;
+
+        response_header.insert_header("Allow", allowed_methods.as_str())?;
+
+        if let Some(origin) = origin {
+            response_header.insert_header("Access-Control-Allow-Origin", origin)?;
+            response_header.insert_header("Access-Control-Allow-Methods", allowed_methods)?;
+
+            if let Some(requested_headers) = requested_headers {
+                response_header.insert_header("Access-Control-Allow-Headers", requested_headers)?;
+            }
+
+            response_header.insert_header("Vary", "Origin")?;
+        }
+
+        session
+            .write_response_header(Box::new(response_header), true)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Responds with 404 if no agent has all of the labels, otherwise only the agents that
+    /// have them are selected
+    async fn require_labels(
+        &self,
+        session: &mut Session,
+        ctx: &mut LlamaCppContext,
+        label_selectors: Vec<Label>,
+    ) -> Result<bool> {
+        let unmatched_label_selectors = self
+            .upstream_peer_pool
+            .unmatched_label_selectors(&label_selectors)
+            .map_err(|err| {
+                error!("Failed to match labels: {}", err);
+
+                Error::new(pingora::InternalError)
+            })?;
+
+        if let Some(unmatched_label_selectors) = unmatched_label_selectors {
+            return Self::respond_with_json(
+                session,
+                404,
+                serde_json::json!({
+                    "error": "No agent matches the required labels",
+                    "unmatched_labels": unmatched_label_selectors
+                        .iter()
+                        .map(Label::to_string)
+                        .collect::<Vec<String>>(),
+                }),
+            )
+            .await;
+        }
+
+        ctx.label_selectors.extend(label_selectors);
+
+        Ok(false)
+    }
+
+    async fn respond_with_retry_after(
+        session: &mut Session,
+        status: u16,
+        retry_after: Duration,
+    ) -> Result<bool> {
+        let mut response_header = ResponseHeader::build(status, None)?;
+
+        response_header.insert_header("Content-Length", "0")?;
+        response_header.insert_header("Retry-After", retry_after.as_secs().to_string())?;
+
+        session
+            .write_response_header(Box::new(response_header), true)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn respond_with_error(session: &mut Session, status: u16, message: &str) -> Result<bool> {
+        Self::respond_with_json(session, status, serde_json::json!({ "error": message })).await
+    }
+
+    async fn respond_with_json(
+        session: &mut Session,
+        status: u16,
+        response: serde_json::Value,
+    ) -> Result<bool> {
+        let body = Bytes::from(response.to_string());
+        let mut response_header = ResponseHeader::build(status, None)?;
+
+        response_header.insert_header("Content-Type", "application/json")?;
+        response_header.insert_header("Content-Length", body.len().to_string())?;
+
+        session
+            .write_response_header(Box::new(response_header), false)
+            .await?;
+        session.write_response_body(Some(body), true).await?;
+
+        Ok(true)
+    }
+
+    /// `cache_status` tells the client whether the response came from the cache, or from an
+    /// identical request that was in progress
+    async fn respond_with_cached_response(
+        session: &mut Session,
+        cached_response: CachedResponse,
+        cache_status: &str,
+    ) -> Result<bool> {
+        let mut response_header = ResponseHeader::build(200, None)?;
+
+        if let Some(content_type) = cached_response.content_type {
+            response_header.insert_header("Content-Type", content_type)?;
+        }
+
+        response_header.insert_header("Content-Length", cached_response.body.len().to_string())?;
+        response_header.insert_header(RESPONSE_CACHE_HEADER, cache_status)?;
+
+        session
+            .write_response_header(Box::new(response_header), false)
+            .await?;
+        session
+            .write_response_body(Some(cached_response.body), true)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Responses larger than this are neither cached nor shared with the coalesced requests,
+    /// None if neither is enabled
+    fn max_shared_response_size(&self) -> Option<usize> {
+        self.response_cache
+            .as_ref()
+            .map(|response_cache| response_cache.max_response_size)
+            .or_else(|| {
+                self.request_coalescer
+                    .as_ref()
+                    .map(|request_coalescer| request_coalescer.max_response_size)
+            })
+    }
+
+    /// Requests with the same path, labels, and body get the same response
+    fn response_cache_key(session: &Session, ctx: &LlamaCppContext, request_body: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        session.req_header().uri.path().hash(&mut hasher);
+
+        for label_selector in &ctx.label_selectors {
+            label_selector.to_string().hash(&mut hasher);
+        }
+
+        request_body.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
     #[inline]
     fn release_slot(&self, ctx: &mut LlamaCppContext) -> PaddlerResult<()> {
+        if self.is_slot_accounting_disabled {
+            return Ok(());
+        }
+
         if let Some(peer) = &ctx.selected_peer {
             self.upstream_peer_pool
-                .release_slot(&peer.agent_id, peer.last_update)?;
+                .release_slot(&peer.agent_id, peer.last_update, peer.restart_epoch, ctx.slots)?;
             self.upstream_peer_pool.restore_integrity()?;
 
             ctx.slot_taken = false;
@@ -55,8 +769,14 @@ impl ProxyService {
 
     #[inline]
     fn release_permit(&self, ctx: &mut LlamaCppContext) -> PaddlerResult<()> {
+        // the permits stored on the peer belong to other requests then
+        if self.is_slot_accounting_disabled {
+            return Ok(());
+        }
+
         if let Some(peer) = &ctx.selected_peer {
-            self.upstream_peer_pool.release_one_permit(&peer.agent_id)?;
+            self.upstream_peer_pool
+                .release_permits(&peer.agent_id, peer.restart_epoch, ctx.slots)?;
 
             ctx.slot_taken = false;
         }
@@ -64,17 +784,161 @@ impl ProxyService {
         Ok(())
     }
 
+    /// Gives back the permit and the slots taken on the selected peer, the error makes
+    /// `error_while_proxy` retry the request on another one
+    fn invalidate_selection(&self, ctx: &mut LlamaCppContext) -> Box<Error> {
+        // the permit is stored on the peer, and the slots might be taken already
+        if ctx.slot_taken {
+            if let Err(err) = self.release_slot(ctx) {
+                error!("Failed to release slot: {}", err);
+
+                return Error::new(pingora::InternalError);
+            }
+        }
+
+        if let Err(err) = self.release_permit(ctx) {
+            error!("Failed to release permit: {}", err);
+
+            return Error::new(pingora::InternalError);
+        }
+
+        ctx.is_selection_invalidated = true;
+        ctx.selected_peer = None;
+
+        Error::create(
+            ErrorType::HTTPStatus(503),
+            ErrorSource::Upstream,
+            None,
+            None,
+        )
+    }
+
     #[inline]
     fn take_slot(&self, ctx: &mut LlamaCppContext) -> PaddlerResult<()> {
         if let Some(peer) = &ctx.selected_peer {
-            self.upstream_peer_pool.take_slot(&peer.agent_id)?;
-            self.upstream_peer_pool.restore_integrity()?;
+            if !self
+                .upstream_peer_pool
+                .take_slot(&peer.agent_id, peer.restart_epoch, ctx.slots)?
+            {
+                return Err(AppError::UnexpectedError(format!(
+                    "Agent {} left the pool or restarted after it was selected",
+                    peer.agent_id
+                )));
+            }
 
+            // set before the pool is sorted, so the slots are released even if that fails
             ctx.slot_taken = true;
+
+            self.upstream_peer_pool.restore_integrity()?;
         }
 
         Ok(())
     }
+
+    /// Marks the peer so it gets no requests until it is expected to be ready
+    async fn is_loading_model(&self, ctx: &LlamaCppContext) -> bool {
+        let (Some(model_loading_probe), Some(peer)) =
+            (&self.model_loading_probe, &ctx.selected_peer)
+        else {
+            return false;
+        };
+
+        match model_loading_probe.is_loading_model(peer).await {
+            Ok(true) => {
+                if let Err(err) = self
+                    .upstream_peer_pool
+                    .mark_model_loading(&peer.agent_id, model_loading_probe.loading_period)
+                {
+                    error!("Failed to mark the model as loading: {}", err);
+                }
+
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                warn!("Failed to check the health of {}: {}", peer.agent_id, err);
+
+                false
+            }
+        }
+    }
+
+    /// Probe failures leave the decision to the reported state, the same as without a probe
+    async fn is_busy(&self, ctx: &LlamaCppContext, peer: &UpstreamPeerInfo) -> bool {
+        let Some(slots_probe) = &self.slots_probe else {
+            return false;
+        };
+
+        if !ctx.probes_slots || !ctx.uses_slots || peer.is_slots_endpoint_enabled == Some(false) {
+            return false;
+        }
+
+        match slots_probe.has_idle_slots(peer, ctx.slots).await {
+            Ok(true) => false,
+            Ok(false) => {
+                debug!("Agent {} has no idle slots, skipping it", peer.agent_id);
+
+                true
+            }
+            Err(err) => {
+                warn!("Failed to probe the slots of {}: {}", peer.agent_id, err);
+
+                false
+            }
+        }
+    }
+
+    /// Request already holds a permit, so if the peers with idle slots only held back because
+    /// of the admission rate, it waits for the first one to take it
+    async fn use_best_peer(
+        &self,
+        session: &Session,
+        ctx: &LlamaCppContext,
+    ) -> PaddlerResult<Option<UpstreamPeerInfo>> {
+        let request_hash = Self::request_hash(session);
+        let mut busy_agent_ids: Vec<String> = vec![];
+        let mut skipped_agent_ids = ctx.skipped_agent_ids.to_owned();
+
+        loop {
+            let selected_peer = self.upstream_peer_pool.use_best_peer(
+                &ctx.label_selectors,
+                ctx.prompt_tokens,
+                request_hash,
+                &skipped_agent_ids,
+                ctx.slots,
+                ctx.uses_slots,
+            )?;
+
+            if let Some(peer) = selected_peer {
+                if busy_agent_ids.len() < MAX_SLOTS_PROBES && self.is_busy(ctx, &peer).await {
+                    busy_agent_ids.push(peer.agent_id.to_owned());
+                    skipped_agent_ids.push(peer.agent_id);
+
+                    continue;
+                }
+
+                return Ok(Some(peer));
+            }
+
+            if !busy_agent_ids.is_empty() {
+                // all the agents that are left turned out to be busy, llama.cpp queues the
+                // request on the best one
+                return self.upstream_peer_pool.use_best_peer(
+                    &ctx.label_selectors,
+                    ctx.prompt_tokens,
+                    request_hash,
+                    &ctx.skipped_agent_ids,
+                    ctx.slots,
+                    ctx.uses_slots,
+                );
+            }
+
+            match self.upstream_peer_pool.next_admission_in(ctx.uses_slots)? {
+                Some(next_admission_in) => sleep(next_admission_in).await,
+                None => return Ok(None),
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -83,136 +947,1076 @@ impl ProxyHttp for ProxyService {
 
     fn new_ctx(&self) -> Self::CTX {
         LlamaCppContext {
+            cacheable_response_body: None,
+            cacheable_response_content_type: None,
+            closes_upstream_connection: false,
+            coalescing_leader: None,
+            downstream_connection: None,
+            #[cfg(feature = "statsd_reporter")]
+            endpoint: None,
+            expects_continue: false,
+            is_model_loading: false,
+            is_over_capacity: false,
+            is_path_prefix_stripped: false,
+            is_response_too_large: false,
+            is_retrying_upstream_status: false,
+            is_retry_budget_exhausted: false,
+            is_selection_invalidated: false,
+            label_selectors: Vec::new(),
+            priority: RequestPriority::default(),
+            probes_slots: false,
+            prompt_tokens: None,
+            proxy_settings: self.proxy_settings.load(),
+            request_body: None,
+            request_body_read: None,
+            request_started_at: Instant::now(),
+            response_bytes: 0,
+            response_bytes_forwarded: false,
+            response_cache_key: None,
+            retries: 0,
             selected_peer: None,
+            skipped_agent_ids: Vec::new(),
+            skips_parameter_defaults: false,
             slot_taken: false,
+            slots: 1,
+            stream_error: None,
+            stream_error_detector: None,
+            target_agent: None,
+            token_usage: None,
+            tried_agent_ids: Vec::new(),
+            upstream_status: None,
             uses_slots: false,
         }
     }
 
-    async fn connected_to_upstream(
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        _reused: bool,
+        _peer: &HttpPeer,
+        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
+        #[cfg(windows)] _sock: std::os::windows::io::RawSocket,
+        digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if let Some(max_lifetime) = ctx.proxy_settings.upstream_connection_max_lifetime {
+            // checked for every request, since a retry can get a different connection
+            ctx.closes_upstream_connection =
+                Self::connection_age(digest).is_some_and(|age| age >= max_lifetime);
+        }
+
+        // without the slot accounting the peers are not expected to have idle slots anyway
+        if ctx.target_agent.is_none() && !ctx.slot_taken && !self.is_slot_accounting_disabled {
+            if let Some(peer) = &ctx.selected_peer {
+                let is_selection_invalidated = self
+                    .upstream_peer_pool
+                    .is_selection_invalidated(&peer.agent_id)
+                    .map_err(|err| {
+                        error!("Failed to check the selected peer: {}", err);
+
+                        Error::new(pingora::InternalError)
+                    })?;
+
+                if is_selection_invalidated {
+                    warn!(
+                        "Agent {} became unusable after it was selected, picking another one",
+                        peer.agent_id
+                    );
+
+                    return Err(self.invalidate_selection(ctx));
+                }
+            }
+        }
+
+        if ctx.uses_slots && !ctx.slot_taken {
+            if let Err(e) = self.take_slot(ctx) {
+                error!("Failed to take slot, picking another agent: {}", e);
+
+                return Err(self.invalidate_selection(ctx));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        error!("Error while proxying: {}", e);
+
+        let is_model_loading = ctx.is_model_loading;
+        let is_retrying_upstream_status = ctx.is_retrying_upstream_status;
+        let is_selection_invalidated = ctx.is_selection_invalidated;
+
+        ctx.is_model_loading = false;
+        ctx.is_retrying_upstream_status = false;
+        ctx.is_selection_invalidated = false;
+
+        // the peer is not broken, it just is not ready yet, or the cap on the response size
+        // tripped
+        if let (Some(selected_peer), false) = (
+            &ctx.selected_peer,
+            is_model_loading || ctx.is_response_too_large,
+        ) {
+            if let Err(err) = self.upstream_peer_pool.register_error(&selected_peer.agent_id) {
+                error!("Failed to register error: {}", err);
+            }
+        }
+
+        // error responses and invalidated selections can be retried on fresh connections as well,
+        // but a response that was cut off would be just as large on another peer
+        let retry = (is_retrying_upstream_status
+            || is_selection_invalidated
+            || is_model_loading
+            || client_reused)
+            && !ctx.is_response_too_large
+            && self.can_resend_request_body(session, ctx)
+            && self.allow_retry(ctx);
+
+        if ctx.slot_taken {
+            if let Err(err) = self.release_slot(ctx) {
+                error!("Failed to release slot: {}", err);
+
+                return Error::new(pingora::InternalError);
+            }
+            // a peer that is loading the model gets no requests, and the one that responded with
+            // an error is skipped, so the retry needs a new permit
+            if !retry || is_model_loading || is_retrying_upstream_status {
+                if let Err(err) = self.release_permit(ctx) {
+                    error!("Failed to release permit: {}", err);
+
+                    return Error::new(pingora::InternalError);
+                }
+            }
+        }
+
+        if retry && is_retrying_upstream_status {
+            if let Some(selected_peer) = &ctx.selected_peer {
+                ctx.skipped_agent_ids
+                    .push(selected_peer.agent_id.to_owned());
+            }
+        }
+
+        if retry && (is_model_loading || is_retrying_upstream_status) {
+            ctx.selected_peer = None;
+        }
+
+        let mut e = e.more_context(format!("Peer: {}", peer));
+
+        if ctx.is_retry_budget_exhausted {
+            return Self::retry_budget_exhausted_error();
+        }
+
+        if is_retrying_upstream_status || is_selection_invalidated || is_model_loading {
+            e.set_retry(retry);
+        } else {
+            // only reused client connections where retry buffer is not truncated
+            e.retry.decide_reuse(retry);
+        }
+
+        e
+    }
+
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<Error>,
+    ) -> Box<Error> {
+        error!("Failed to connect: {}", e);
+        if let Some(peer) = &ctx.selected_peer {
+            match self.upstream_peer_pool.quarantine_peer(&peer.agent_id) {
+                Ok(true) => {
+                    if let Err(err) = self.upstream_peer_pool.restore_integrity() {
+                        error!("Failed to restore integrity: {}", err);
+
+                        return Error::new(pingora::InternalError);
+                    }
+
+                    if self.allow_retry(ctx) {
+                        // ask server to retry, but try a different best peer
+                        ctx.selected_peer = None;
+                        e.set_retry(true);
+                    } else if ctx.is_retry_budget_exhausted {
+                        return Self::retry_budget_exhausted_error();
+                    }
+                }
+                Ok(false) => {
+                    // no need to quarantine for some reason
+                }
+                Err(err) => {
+                    error!("Failed to quarantine peer: {}", err);
+
+                    return Error::new(pingora::InternalError);
+                }
+            }
+        }
+
+        e
+    }
+
+    /// Same as the default, except for the requests that no agent can take, which get
+    /// `Retry-After` whatever their status is
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> u16
+    where
+        Self::CTX: Send + Sync,
+    {
+        let code = match e.etype() {
+            ErrorType::HTTPStatus(code) => *code,
+            _ => match e.esource() {
+                ErrorSource::Upstream => 502,
+                ErrorSource::Downstream => match e.etype() {
+                    // the connection is already gone
+                    ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                ErrorSource::Internal | ErrorSource::Unset => 500,
+            },
+        };
+
+        if code == 0 {
+            return code;
+        }
+
+        if !ctx.is_over_capacity {
+            session.as_mut().respond_error(code).await;
+
+            return code;
+        }
+
+        if let Err(err) = Self::respond_with_retry_after(
+            session,
+            code,
+            ctx.proxy_settings.no_capacity_retry_after,
+        )
+        .await
+        {
+            error!("Failed to respond with {}: {}", code, err);
+        }
+
+        code
+    }
+
+    /// Compression stays off unless `response_filter` sets the level for the response
+    fn init_downstream_modules(&self, modules: &mut HttpModules) {
+        modules.add_module(ResponseCompressionBuilder::enable(0));
+    }
+
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        if let Some(downstream_connections) = &self.downstream_connections {
+            // HTTP/2 connections have no stream of their own, so they are not tracked
+            let connection = session.stream().map(|stream| stream.id()).zip(
+                session
+                    .digest()
+                    .and_then(|digest| digest.socket_digest.clone()),
+            );
+
+            if let Some((raw_socket, socket_digest)) = connection {
+                loop {
+                    ctx.downstream_connection =
+                        downstream_connections.acquire(raw_socket, &socket_digest);
+
+                    if ctx.downstream_connection.is_some() {
+                        break;
+                    }
+
+                    // pingora already accepted the connection, so its first request waits
+                    // instead of the connection waiting in the backlog
+                    match downstream_connections.excess_connection_policy {
+                        ExcessConnectionPolicy::Reject => {
+                            self.register_rejection(RejectionReason::ConnectionLimit);
+                            session.set_keepalive(None);
+
+                            return Self::respond_with_retry_after(
+                                session,
+                                503,
+                                ctx.proxy_settings.no_capacity_retry_after,
+                            )
+                            .await;
+                        }
+                        ExcessConnectionPolicy::Wait => {
+                            sleep(DOWNSTREAM_CONNECTION_WAIT_INTERVAL).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(client_connection_limiter) = &self.client_connection_limiter {
+            let connection = session
+                .client_addr()
+                .and_then(|client_addr| client_addr.as_inet())
+                .map(|client_addr| client_addr.ip())
+                .zip(
+                    session
+                        .digest()
+                        .and_then(|digest| digest.socket_digest.clone()),
+                );
+
+            if let Some((client_ip, socket_digest)) = connection {
+                if !client_connection_limiter.admit(client_ip, &socket_digest) {
+                    self.register_rejection(RejectionReason::RateLimited);
+
+                    return Err(Error::create(
+                        ErrorType::HTTPStatus(429),
+                        ErrorSource::Downstream,
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        let method = session.req_header().method.clone();
+
+        // browsers never send the credentials with the CORS preflight requests, so the API key
+        // is not checked for them
+        if method == Method::OPTIONS {
+            match ctx.proxy_settings.options_request_policy {
+                MethodPolicy::Forward => {}
+                MethodPolicy::Reject => {
+                    return Self::respond_with_method_not_allowed(session, ctx).await;
+                }
+                MethodPolicy::Respond => return Self::respond_to_options(session, ctx).await,
+            }
+        }
+
+        let authorization = session
+            .req_header()
+            .headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok());
+
+        if !self.listener.is_authorized(authorization) {
+            self.register_rejection(RejectionReason::Unauthorized);
+
+            return Err(Error::create(
+                ErrorType::HTTPStatus(401),
+                ErrorSource::Downstream,
+                None,
+                None,
+            ));
+        }
+
+        if let Some(token_quota) = self
+            .token_quotas
+            .as_ref()
+            .and_then(|token_quotas| token_quotas.exhausted(&self.listener.name))
+        {
+            self.register_rejection(RejectionReason::QuotaExceeded);
+
+            return Self::respond_with_json(
+                session,
+                429,
+                serde_json::json!({
+                    "error": "Token quota exceeded",
+                    "resets_at": token_quota.resets_at,
+                    "type": "quota_exceeded",
+                }),
+            )
+            .await;
+        }
+
+        if !ctx.proxy_settings.path_rewrite_policy.is_noop() {
+            let uri = &session.req_header().uri;
+            let (path, is_path_prefix_stripped) =
+                ctx.proxy_settings.path_rewrite_policy.rewrite(uri.path());
+            let path_and_query = match uri.query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+
+            // everything after this point, including the upstream request, sees the rewritten
+            // path only
+            match path_and_query.parse() {
+                Ok(rewritten_uri) => session.req_header_mut().set_uri(rewritten_uri),
+                Err(_) => {
+                    return Err(Error::create(
+                        ErrorType::HTTPStatus(400),
+                        ErrorSource::Downstream,
+                        None,
+                        None,
+                    ));
+                }
+            }
+
+            ctx.is_path_prefix_stripped = is_path_prefix_stripped;
+        }
+
+        let path = session.req_header().uri.path();
+
+        #[cfg(feature = "statsd_reporter")]
+        if let Some(endpoint_metrics) = &self.endpoint_metrics {
+            let endpoint = EndpointMetrics::endpoint_name(path);
+
+            if let Err(err) = endpoint_metrics.register_request(endpoint) {
+                error!("Failed to register endpoint request: {}", err);
+            }
+
+            ctx.endpoint = Some(endpoint);
+        }
+
+        if !self.listener.is_path_allowed(path) {
+            return Err(Error::create(
+                ErrorType::HTTPStatus(404),
+                ErrorSource::Downstream,
+                None,
+                None,
+            ));
+        }
+
+        if method == Method::HEAD {
+            match ctx.proxy_settings.head_request_policy {
+                MethodPolicy::Forward => {}
+                MethodPolicy::Reject => {
+                    return Self::respond_with_method_not_allowed(session, ctx).await;
+                }
+                MethodPolicy::Respond => return self.respond_to_head(session).await,
+            }
+        }
+
+        if let Some(retry_budget) = &self.retry_budget {
+            retry_budget.register_request();
+        }
+
+        if let (Some(max_queued_requests), false) = (
+            ctx.proxy_settings.max_queued_requests,
+            self.is_slot_accounting_disabled,
+        ) {
+            // no point in queueing the request if it is unlikely to get a slot soon
+            if self.upstream_peer_pool.is_saturated(max_queued_requests) {
+                self.register_rejection(RejectionReason::QueueFull);
+
+                return Err(Self::no_capacity_error(ctx));
+            }
+        }
+
+        ctx.uses_slots = match path {
+            "/slots" => {
+                if !self.listener.slots_endpoint_enable {
+                    return Err(Error::create(
+                        pingora::Custom("Slots endpoint is disabled"),
+                        ErrorSource::Downstream,
+                        None,
+                        None,
+                    ));
+                }
+
+                // the listener API key is shared with the tenants, monitoring has its own token
+                if !Self::has_slots_endpoint_token(session, ctx) {
+                    return Self::respond_with_error(
+                        session,
+                        403,
+                        "Invalid or missing slots endpoint token",
+                    )
+                    .await;
+                }
+
+                false
+            }
+            _ => takes_slot(path),
+        };
+
+        // nothing is generated for these, so they never wait for a slot, and in the observe
+        // mode no request does
+        if method == Method::HEAD || method == Method::OPTIONS || self.is_slot_accounting_disabled {
+            ctx.uses_slots = false;
+        }
+
+        if let Some(target_agent) = session.req_header().headers.get(TARGET_AGENT_HEADER) {
+            if !Self::has_target_agent_token(session, ctx) {
+                return Self::respond_with_error(
+                    session,
+                    403,
+                    "Invalid or missing target agent token",
+                )
+                .await;
+            }
+
+            let target_agent = match target_agent.to_str() {
+                Ok(target_agent) => target_agent.to_string(),
+                Err(err) => {
+                    return Self::respond_with_error(session, 400, &err.to_string()).await;
+                }
+            };
+
+            let has_peer = self
+                .upstream_peer_pool
+                .has_peer(&target_agent)
+                .map_err(|err| {
+                    error!("Failed to find target agent: {}", err);
+
+                    Error::new(pingora::InternalError)
+                })?;
+
+            if !has_peer {
+                return Self::respond_with_error(
+                    session,
+                    404,
+                    &format!("Unknown agent: {}", target_agent),
+                )
+                .await;
+            }
+
+            ctx.target_agent = Some(target_agent);
+        }
+
+        if session
+            .req_header()
+            .headers
+            .get(NO_DEFAULTS_HEADER)
+            .is_some_and(|value| value == "1")
+        {
+            if !Self::has_target_agent_token(session, ctx) {
+                return Self::respond_with_error(
+                    session,
+                    403,
+                    "Invalid or missing target agent token",
+                )
+                .await;
+            }
+
+            ctx.skips_parameter_defaults = true;
+        }
+
+        ctx.probes_slots = self
+            .slots_probe
+            .as_ref()
+            .is_some_and(|slots_probe| slots_probe.probes_all_requests)
+            || session
+                .req_header()
+                .headers
+                .get(PROBE_SLOTS_HEADER)
+                .is_some_and(|value| value == "1");
+
+        if let Some(require) = session.req_header().headers.get(REQUIRE_HEADER) {
+            let label_selectors = match require.to_str() {
+                Ok(require) => Label::parse_list(require),
+                Err(err) => Err(AppError::UnexpectedError(err.to_string())),
+            };
+            let label_selectors = match label_selectors {
+                Ok(label_selectors) => label_selectors,
+                Err(err) => {
+                    return Self::respond_with_error(session, 400, &err.to_string()).await;
+                }
+            };
+
+            if self.require_labels(session, ctx, label_selectors).await? {
+                return Ok(true);
+            }
+        }
+
+        ctx.expects_continue = session
+            .req_header()
+            .headers
+            .get("Expect")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"));
+
+        if ctx.expects_continue {
+            // otherwise llama.cpp would answer it, while the request already holds a slot, and
+            // a retried request would wait for the interim response that never comes again
+            session.as_mut().write_continue_response().await?;
+        }
+
+        let inspected_request = if ctx.uses_slots {
+            self.inspect_request(session, ctx).await?
+        } else {
+            None
+        };
+
+        // before the response cache, since the responses are cached separately for each set of
+        // required labels
+        let proxy_settings = ctx.proxy_settings.clone();
+
+        if let Some(routing_rule) = find_routing_rule(
+            &proxy_settings.routing_rules,
+            session.req_header(),
+            inspected_request
+                .as_ref()
+                .and_then(|inspected_request| inspected_request.model.as_deref()),
+        ) {
+            if self
+                .require_labels(session, ctx, routing_rule.require.to_owned())
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+
+        if ctx.uses_slots {
+            if let (Some(inspected_request), Some(request_body)) =
+                (inspected_request.as_ref(), &ctx.request_body)
+            {
+                // forced agents are usually debugged, so they always get the request
+                if (self.response_cache.is_some() || self.request_coalescer.is_some())
+                    && inspected_request.is_deterministic()
+                    && ctx.target_agent.is_none()
+                {
+                    let response_cache_key = Self::response_cache_key(session, ctx, request_body);
+
+                    if let Some(response_cache) = &self.response_cache {
+                        if let Some(cached_response) = response_cache.get(response_cache_key) {
+                            return Self::respond_with_cached_response(
+                                session,
+                                cached_response,
+                                "hit",
+                            )
+                            .await;
+                        }
+                    }
+
+                    if let Some(request_coalescer) = &self.request_coalescer {
+                        match request_coalescer.join(response_cache_key) {
+                            CoalescedRequest::Leader(coalescing_leader) => {
+                                ctx.coalescing_leader = Some(coalescing_leader);
+                            }
+                            CoalescedRequest::Follower(coalescing_follower) => {
+                                // before taking a permit, so the waiting requests take no slots
+                                if let Some(coalesced_response) = coalescing_follower.wait().await {
+                                    return Self::respond_with_cached_response(
+                                        session,
+                                        coalesced_response,
+                                        "coalesced",
+                                    )
+                                    .await;
+                                }
+
+                                debug!(
+                                    "Identical request failed, sending the coalesced request on its own"
+                                );
+                            }
+                        }
+                    }
+
+                    ctx.response_cache_key = Some(response_cache_key);
+                }
+            }
+
+            ctx.priority = ctx.proxy_settings.resolve_priority(
+                session.req_header(),
+                inspected_request
+                    .as_ref()
+                    .and_then(|inspected_request| inspected_request.model.as_deref()),
+            );
+            ctx.prompt_tokens =
+                Self::resolve_prompt_tokens(session, ctx, inspected_request.as_ref());
+            ctx.slots = inspected_request
+                .as_ref()
+                .map_or(1, InspectedRequest::prompts_count);
+
+            if ctx.slots > 1 {
+                let max_peer_slots = self
+                    .upstream_peer_pool
+                    .max_peer_slots(&ctx.label_selectors, ctx.prompt_tokens)
+                    .map_err(|err| {
+                        error!("Failed to check slots counts: {}", err);
+
+                        Error::new(pingora::InternalError)
+                    })?;
+
+                if ctx.slots > max_peer_slots {
+                    match ctx.proxy_settings.oversized_batch_policy {
+                        // llama.cpp queues the prompts that do not fit internally
+                        OversizedBatchPolicy::Clamp => ctx.slots = max_peer_slots.max(1),
+                        OversizedBatchPolicy::Reject => {
+                            return Self::respond_with_error(
+                                session,
+                                400,
+                                &format!(
+                                    "Batch of {} prompts does not fit in any agent (at most {} slots)",
+                                    ctx.slots, max_peer_slots
+                                ),
+                            )
+                            .await;
+                        }
+                    }
+                }
+            }
+
+            if let Some(prompt_tokens) = ctx.prompt_tokens {
+                let fits_in_context = self
+                    .upstream_peer_pool
+                    .fits_in_context(prompt_tokens)
+                    .map_err(|err| {
+                        error!("Failed to check context sizes: {}", err);
+
+                        Error::new(pingora::InternalError)
+                    })?;
+
+                if !fits_in_context {
+                    return Self::respond_with_error(
+                        session,
+                        400,
+                        &format!(
+                            "Prompt has about {} tokens, which does not fit in the context of any agent",
+                            prompt_tokens
+                        ),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX)
+    where
+        Self::CTX: Send + Sync,
+    {
+        let status = session
+            .response_written()
+            .map_or(0, |response| response.status.as_u16());
+
+        if let Some(cluster_stats) = &self.cluster_stats {
+            // rejected requests would make the durations look better than they are
+            cluster_stats.register_request(
+                ctx.upstream_status
+                    .map(|_| ctx.request_started_at.elapsed()),
+            );
+        }
+
+        // interrupted streams are charged too, the fallback cost is used if they did not get
+        // to report the usage
+        if let (Some(token_quotas), Some(token_usage)) =
+            (&self.token_quotas, ctx.token_usage.take())
+        {
+            token_quotas.register_usage(
+                &self.listener.name,
+                token_usage
+                    .total_tokens()
+                    .unwrap_or(token_quotas.fallback_cost),
+            );
+        }
+
+        info!(
+            "[{}] {} {} {} agent={} upstream_status={} prompt_tokens={} n_ctx={} retryable={} error={}",
+            self.listener.name,
+            session.req_header().method,
+            session.req_header().uri.path(),
+            status,
+            ctx.selected_peer
+                .as_ref()
+                .map_or("-", |peer| peer.agent_id.as_str()),
+            ctx.upstream_status
+                .map_or("-".to_string(), |upstream_status| upstream_status.to_string()),
+            ctx.prompt_tokens
+                .map_or("-".to_string(), |prompt_tokens| prompt_tokens.to_string()),
+            ctx.selected_peer
+                .as_ref()
+                .and_then(|peer| peer.context_size)
+                .map_or("-".to_string(), |context_size| context_size.to_string()),
+            Self::is_request_body_resendable(session, ctx),
+            e.map(|e| e.to_string())
+                .or_else(|| ctx
+                    .stream_error
+                    .as_ref()
+                    .map(|stream_error| format!("Stream aborted: {}", stream_error.message)))
+                .unwrap_or_else(|| "-".to_string()),
+        );
+    }
+
+    async fn request_body_filter(
         &self,
         _session: &mut Session,
-        _reused: bool,
-        _peer: &HttpPeer,
-        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
-        #[cfg(windows)] _sock: std::os::windows::io::RawSocket,
-        _digest: Option<&Digest>,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
         ctx: &mut Self::CTX,
-    ) -> Result<()> {
-        if ctx.uses_slots && !ctx.slot_taken {
-            if let Err(e) = self.take_slot(ctx) {
-                error!("Failed to take slot: {}", e);
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        // the retry buffer could not keep the part that was already read
+        if let Some(request_body_read) = ctx.request_body_read.take() {
+            let mut request_body = BytesMut::from(&request_body_read[..]);
 
-                return Err(Error::new(pingora::InternalError));
+            if let Some(chunk) = body {
+                request_body.extend_from_slice(chunk);
             }
+
+            *body = Some(request_body.freeze());
+        }
+
+        // the whole body was already read, so it replaces whatever was buffered for retries
+        if let Some(request_body) = &ctx.request_body {
+            *body = if end_of_stream {
+                Some(request_body.clone())
+            } else {
+                None
+            };
         }
 
         Ok(())
     }
 
-    fn error_while_proxy(
+    async fn response_filter(
         &self,
-        peer: &HttpPeer,
         session: &mut Session,
-        e: Box<Error>,
+        upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
-        client_reused: bool,
-    ) -> Box<Error> {
-        error!("Error while proxying: {}", e);
-
-        let retry = client_reused && !session.as_ref().retry_buffer_truncated();
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        let status = upstream_response.status.as_u16();
 
-        if ctx.slot_taken {
-            if let Err(err) = self.release_slot(ctx) {
-                error!("Failed to release slot: {}", err);
+        ctx.upstream_status = Some(status);
 
-                return Error::new(pingora::InternalError);
+        #[cfg(feature = "statsd_reporter")]
+        if let (Some(endpoint_metrics), Some(endpoint)) = (&self.endpoint_metrics, ctx.endpoint) {
+            if let Err(err) = endpoint_metrics.register_response_status(endpoint, status) {
+                error!("Failed to register endpoint response status: {}", err);
             }
-            if !retry {
-                if let Err(err) = self.release_permit(ctx) {
-                    error!("Failed to release permit: {}", err);
+        }
 
-                    return Error::new(pingora::InternalError);
-                }
+        if let Some(peer) = &ctx.selected_peer {
+            if let Err(err) = self
+                .upstream_peer_pool
+                .register_response_status(&peer.agent_id, status)
+            {
+                error!("Failed to register response status: {}", err);
             }
         }
 
-        let mut e = e.more_context(format!("Peer: {}", peer));
+        let is_model_loading = status == 503 && ctx.uses_slots && self.is_loading_model(ctx).await;
 
-        // only reused client connections where retry buffer is not truncated
-        e.retry.decide_reuse(retry);
+        // nothing was sent to the client yet, and the other peers are likely ready
+        if is_model_loading
+            && ctx.target_agent.is_none()
+            && self.can_resend_request_body(session, ctx)
+        {
+            ctx.is_model_loading = true;
 
-        e
-    }
+            // `error_while_proxy` picks another peer for the retry
+            return Err(Error::explain(
+                ErrorType::HTTPStatus(status),
+                "Upstream is still loading the model",
+            ));
+        }
 
-    fn fail_to_connect(
-        &self,
-        _session: &mut Session,
-        _peer: &HttpPeer,
-        ctx: &mut Self::CTX,
-        mut e: Box<Error>,
-    ) -> Box<Error> {
-        error!("Failed to connect: {}", e);
-        if let Some(peer) = &ctx.selected_peer {
-            match self.upstream_peer_pool.quarantine_peer(&peer.agent_id) {
-                Ok(true) => {
-                    if let Err(err) = self.upstream_peer_pool.restore_integrity() {
-                        error!("Failed to restore integrity: {}", err);
+        // overloaded agents are not broken, but they should get fewer requests for a while, and
+        // loading the model is expected after a restart
+        if !is_model_loading && (upstream_response.status.is_server_error() || status == 429) {
+            // nothing was sent to the client yet, so the request can still go somewhere else
+            if ctx.proxy_settings.upstream_status_retry_policy.allows_retry(status)
+                && ctx.target_agent.is_none()
+                && self.can_resend_request_body(session, ctx)
+            {
+                ctx.is_retrying_upstream_status = true;
 
-                        return Error::new(pingora::InternalError);
-                    }
+                // `error_while_proxy` registers the error and decides about the retry
+                return Err(Error::explain(
+                    ErrorType::HTTPStatus(status),
+                    format!("Upstream responded with {}", status),
+                ));
+            }
 
-                    // ask server to retry, but try a different best peer
-                    ctx.selected_peer = None;
-                    e.set_retry(true);
-                }
-                Ok(false) => {
-                    // no need to quarantine for some reason
+            if let Some(peer) = &ctx.selected_peer {
+                if let Err(err) = self.upstream_peer_pool.register_error(&peer.agent_id) {
+                    error!("Failed to register error: {}", err);
                 }
-                Err(err) => {
-                    error!("Failed to quarantine peer: {}", err);
+            }
+        }
 
-                    return Error::new(pingora::InternalError);
-                }
+        if ctx.target_agent.is_some() {
+            if let Some(peer) = &ctx.selected_peer {
+                upstream_response
+                    .insert_header(RESPONSE_AGENT_HEADER, peer.agent_id.to_string())?;
             }
         }
 
-        e
-    }
+        if ctx.response_cache_key.is_some() && upstream_response.status.as_u16() == 200 {
+            let content_type = upstream_response
+                .headers
+                .get("Content-Type")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
 
-    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
-        ctx.uses_slots = match session.req_header().uri.path() {
-            "/slots" => {
-                if !self.slots_endpoint_enable {
-                    return Err(Error::create(
-                        pingora::Custom("Slots endpoint is disabled"),
-                        ErrorSource::Downstream,
-                        None,
-                        None,
-                    ));
+            // streamed responses are never cached, even if the request did not ask for it
+            if !content_type
+                .as_deref()
+                .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
+            {
+                ctx.cacheable_response_body = Some(BytesMut::new());
+                ctx.cacheable_response_content_type = content_type;
+            }
+        }
+
+        // the waiting requests go to llama.cpp on their own, instead of getting an error
+        if ctx.cacheable_response_body.is_none() {
+            ctx.coalescing_leader = None;
+        }
+
+        let is_event_stream = upstream_response
+            .headers
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+
+        if self.token_quotas.is_some() && ctx.uses_slots && upstream_response.status.is_success()
+        {
+            ctx.token_usage = Some(TokenUsageCollector::new(is_event_stream));
+        }
+
+        if is_event_stream && upstream_response.status.is_success() {
+            ctx.stream_error_detector = Some(StreamErrorDetector::default());
+
+            // the error event makes the stream longer than llama.cpp said
+            if ctx.proxy_settings.stream_error_event {
+                upstream_response.remove_header("Content-Length");
+            }
+        }
+
+        if let Some(response_compression_policy) = &ctx.proxy_settings.response_compression_policy
+        {
+            // the client's `Accept-Encoding` still decides whether and how it is compressed
+            if response_compression_policy.should_compress(upstream_response) {
+                if let Some(response_compression) = session
+                    .downstream_modules_ctx
+                    .get_mut::<ResponseCompression>()
+                {
+                    response_compression.adjust_level(response_compression_policy.level);
                 }
+            }
+        }
 
-                false
+        if ctx.is_path_prefix_stripped {
+            if let Some(location) = upstream_response
+                .headers
+                .get("Location")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|location| ctx.proxy_settings.path_rewrite_policy.restore_prefix(location))
+            {
+                upstream_response.insert_header("Location", location)?;
             }
-            "/chat/completions" => true,
-            "/completion" => true,
-            "/v1/chat/completions" => true,
-            _ => false,
-        };
+        }
 
-        Ok(false)
+        Ok(())
     }
 
     fn response_body_filter(
         &self,
         _session: &mut Session,
-        _body: &mut Option<Bytes>,
+        body: &mut Option<Bytes>,
         end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> Result<Option<Duration>>
     where
         Self::CTX: Send + Sync,
     {
+        if let (Some(max_response_bytes), Some(body)) =
+            (ctx.proxy_settings.max_response_bytes, body.as_ref())
+        {
+            ctx.response_bytes += body.len();
+
+            if ctx.response_bytes > max_response_bytes {
+                warn!(
+                    "Response from agent {} is larger than {} bytes, cutting it off",
+                    ctx.selected_peer
+                        .as_ref()
+                        .map_or("-", |peer| peer.agent_id.as_str()),
+                    max_response_bytes
+                );
+
+                ctx.is_response_too_large = true;
+
+                // the stream ends with an error, so the end of stream below is never reached
+                if ctx.slot_taken {
+                    if let Err(err) = self.release_slot(ctx) {
+                        error!("Failed to release slot: {}", err);
+                    } else if let Err(err) = self.release_permit(ctx) {
+                        error!("Failed to release permit: {}", err);
+                    }
+                }
+
+                return Err(Error::explain(
+                    ErrorType::Custom("ResponseTooLarge"),
+                    "Response is larger than max_response_bytes",
+                ));
+            }
+        }
+
+        if body.as_ref().is_some_and(|body| !body.is_empty()) {
+            ctx.response_bytes_forwarded = true;
+        }
+
+        if let Some(max_shared_response_size) = self.max_shared_response_size() {
+            if let (Some(cacheable_response_body), Some(body)) =
+                (ctx.cacheable_response_body.as_mut(), body.as_ref())
+            {
+                if cacheable_response_body.len() + body.len() > max_shared_response_size {
+                    ctx.cacheable_response_body = None;
+                    ctx.coalescing_leader = None;
+                } else {
+                    cacheable_response_body.extend_from_slice(body);
+                }
+            }
+
+            if end_of_stream {
+                if let (Some(response_cache_key), Some(cacheable_response_body)) =
+                    (ctx.response_cache_key, ctx.cacheable_response_body.take())
+                {
+                    let cached_response = CachedResponse {
+                        body: cacheable_response_body.freeze(),
+                        content_type: ctx.cacheable_response_content_type.take(),
+                    };
+
+                    // the waiting requests get the response as soon as it is complete
+                    if let Some(mut coalescing_leader) = ctx.coalescing_leader.take() {
+                        coalescing_leader.complete(cached_response.clone());
+                    }
+
+                    if let Some(response_cache) = &self.response_cache {
+                        response_cache.insert(response_cache_key, cached_response);
+                    }
+                }
+            }
+        }
+
+        if let Some(stream_error_detector) = ctx.stream_error_detector.as_mut() {
+            if let Some(body) = body.as_ref() {
+                stream_error_detector.extend(body);
+            }
+
+            if end_of_stream {
+                stream_error_detector.finish();
+            }
+
+            if let Some(stream_error) = stream_error_detector.take_stream_error() {
+                // nothing after the error matters, llama.cpp ends the stream anyway
+                ctx.stream_error_detector = None;
+
+                self.register_stream_error(ctx, &stream_error);
+
+                ctx.stream_error = Some(stream_error);
+            }
+        }
+
+        if end_of_stream && ctx.proxy_settings.stream_error_event {
+            if let Some(stream_error) = ctx
+                .stream_error
+                .as_ref()
+                .filter(|stream_error| !stream_error.is_visible_to_openai_clients)
+            {
+                let mut last_chunk = BytesMut::from(body.take().unwrap_or_default().as_ref());
+
+                last_chunk.extend_from_slice(&stream_error.openai_event());
+
+                *body = Some(last_chunk.freeze());
+            }
+        }
+
+        if let (Some(token_usage), Some(body)) = (ctx.token_usage.as_mut(), body.as_ref()) {
+            token_usage.extend(body);
+        }
+
         if ctx.slot_taken && end_of_stream {
             if let Err(err) = self.release_slot(ctx) {
                 error!("Failed to release slot: {}", err);
@@ -224,25 +2028,119 @@ impl ProxyHttp for ProxyService {
             }
         }
 
+        #[cfg(feature = "statsd_reporter")]
+        if end_of_stream {
+            if let (Some(endpoint_metrics), Some(endpoint)) = (&self.endpoint_metrics, ctx.endpoint)
+            {
+                if let Err(err) = endpoint_metrics
+                    .register_latency(endpoint, ctx.request_started_at.elapsed())
+                {
+                    error!("Failed to register endpoint latency: {}", err);
+                }
+            }
+        }
+
         Ok(None)
     }
 
     async fn upstream_peer(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> Result<Box<HttpPeer>> {
-        if ctx.selected_peer.is_none() {
-            let smaphore = self.upstream_peer_pool.upstream_slots_permits.clone();
-            let permit = match smaphore.acquire_owned().await {
-                Ok(p) => p,
+        // `/slots` takes no slot, so it does not wait for a permit, but only some of the agents
+        // serve it
+        if ctx.selected_peer.is_none()
+            && ctx.target_agent.is_none()
+            && session.req_header().uri.path() == "/slots"
+        {
+            ctx.selected_peer = match self
+                .upstream_peer_pool
+                .use_slots_endpoint_peer(&ctx.tried_agent_ids)
+            {
+                Ok(peer) => peer,
+                Err(e) => {
+                    error!("Failed to get peer serving the slots endpoint: {e}");
+                    return Err(Error::new(pingora::InternalError));
+                }
+            };
+
+            match ctx.selected_peer.as_ref() {
+                Some(peer) => ctx.tried_agent_ids.push(peer.agent_id.clone()),
+                None => {
+                    return Err(Error::create(
+                        ErrorType::HTTPStatus(404),
+                        ErrorSource::Upstream,
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        // no permit is taken in the observe mode, so there is nothing to release afterwards
+        if ctx.selected_peer.is_none() && self.is_slot_accounting_disabled {
+            ctx.selected_peer = match self.upstream_peer_pool.use_reachable_peer(
+                Self::request_hash(session),
+                &ctx.tried_agent_ids,
+                ctx.target_agent.as_deref(),
+            ) {
+                Ok(peer) => peer,
                 Err(e) => {
-                    error!("Failed to get slot permit: {}", e);
+                    error!("Failed to get reachable peer: {e}");
                     return Err(Error::new(pingora::InternalError));
                 }
             };
 
-            ctx.selected_peer = match self.upstream_peer_pool.use_best_peer() {
+            match ctx.selected_peer.as_ref() {
+                Some(peer) => ctx.tried_agent_ids.push(peer.agent_id.clone()),
+                None => {
+                    self.register_rejection(RejectionReason::NoPeers);
+
+                    return Err(Self::no_capacity_error(ctx));
+                }
+            }
+        }
+
+        if ctx.selected_peer.is_none() {
+            let permit = if ctx.target_agent.is_some() {
+                // forced requests are for debugging, there is no point in queueing them
+                match self.upstream_peer_pool.try_acquire_permit(ctx.slots) {
+                    Some(p) => p,
+                    None => {
+                        self.register_rejection(RejectionReason::NoPeers);
+
+                        return Err(Self::no_capacity_error(ctx));
+                    }
+                }
+            } else {
+                match self
+                    .upstream_peer_pool
+                    .acquire_permit(
+                        &ctx.label_selectors,
+                        ctx.priority,
+                        ctx.prompt_tokens,
+                        ctx.slots,
+                        ctx.uses_slots,
+                    )
+                    .await
+                {
+                    Ok(p) => p,
+                    Err(e) => {
+                        error!("Failed to get slot permit: {}", e);
+                        return Err(Error::new(pingora::InternalError));
+                    }
+                }
+            };
+
+            let selected_peer = match &ctx.target_agent {
+                Some(target_agent) => self
+                    .upstream_peer_pool
+                    .use_target_peer(target_agent, ctx.uses_slots),
+                None => self.use_best_peer(session, ctx).await,
+            };
+
+            ctx.selected_peer = match selected_peer {
                 Ok(peer) => peer,
                 Err(e) => {
                     // ideally unreachable
@@ -251,21 +2149,47 @@ impl ProxyHttp for ProxyService {
                 }
             };
 
-            if ctx.selected_peer.is_none() {
-                error!("Failed to get peer even under permits!");
-                return Err(Error::new(pingora::InternalError));
+            match ctx.selected_peer.as_ref() {
+                Some(peer) => ctx.tried_agent_ids.push(peer.agent_id.clone()),
+                None if ctx.target_agent.is_some() => {
+                    error!("Target agent has no idle slot");
+                    self.register_rejection(RejectionReason::NoPeers);
+
+                    return Err(Self::no_capacity_error(ctx));
+                }
+                None if ctx.prompt_tokens.is_some()
+                    || !ctx.label_selectors.is_empty()
+                    || !ctx.skipped_agent_ids.is_empty() =>
+                {
+                    // idle slots are only on the agents that do not meet the requirements, or
+                    // that already responded with an error
+                    error!("No agent meeting the request requirements is available");
+                    self.register_rejection(RejectionReason::NoPeers);
+
+                    return Err(Self::no_capacity_error(ctx));
+                }
+                None => {
+                    error!("Failed to get peer even under permits!");
+                    return Err(Error::new(pingora::InternalError));
+                }
             }
 
-            let store_res = self
-                .upstream_peer_pool
-                .store_permit(&ctx.selected_peer.as_ref().unwrap().agent_id, permit);
+            let selected_peer = ctx.selected_peer.as_ref().unwrap();
+            let store_res = self.upstream_peer_pool.store_permit(
+                &selected_peer.agent_id,
+                selected_peer.restart_epoch,
+                permit,
+            );
 
             match store_res {
                 Ok(r) => {
                     if !r {
-                        // ideally unreachable
-                        error!("Failed to get peer even under permits!");
-                        return Err(Error::new(pingora::InternalError));
+                        // the permit went back to the pool, and taking the slot fails the same
+                        // way, so the request is retried on another agent
+                        warn!(
+                            "Agent {} left the pool or restarted after it was selected",
+                            selected_peer.agent_id
+                        );
                     }
                 }
                 Err(e) => {
@@ -289,11 +2213,28 @@ impl ProxyHttp for ProxyService {
             }
         };
 
-        Ok(Box::new(HttpPeer::new(
-            selected_peer.external_llamacpp_addr,
-            false,
-            "".to_string(),
-        )))
+        let sni = selected_peer
+            .host_header
+            .as_ref()
+            .or(ctx.proxy_settings.rewrite_host_header_value.as_ref())
+            .map(String::as_str)
+            .map(server_name)
+            .unwrap_or_default();
+        let mut peer = HttpPeer::new(selected_peer.external_llamacpp_addr, false, sni);
+
+        peer.options.connection_timeout = Some(ctx.proxy_settings.upstream_connect_timeout);
+
+        let upstream_protocol = selected_peer
+            .upstream_protocol
+            .unwrap_or(ctx.proxy_settings.upstream_protocol);
+
+        if upstream_protocol == UpstreamProtocol::H2 {
+            // there is no TLS to negotiate the protocol, so llama.cpp has to expect HTTP/2
+            peer.options.set_http_version(2, 2);
+            peer.options.max_h2_streams = UPSTREAM_MAX_H2_STREAMS;
+        }
+
+        Ok(Box::new(peer))
     }
 
     async fn upstream_request_filter(
@@ -302,13 +2243,296 @@ impl ProxyHttp for ProxyService {
         upstream_request: &mut RequestHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
-        if self.rewrite_host_header {
-            if let Some(peer) = &ctx.selected_peer {
+        if ctx.expects_continue {
+            // the body is already buffered, there is nothing to wait for
+            upstream_request.remove_header("Expect");
+        }
+
+        if let Some(request_body) = &ctx.request_body {
+            // the buffered body is sent at once, and it might have been rewritten
+            upstream_request.remove_header("Transfer-Encoding");
+            upstream_request.insert_header("Content-Length", request_body.len().to_string())?;
+        }
+
+        // only the completion endpoints are known to always take JSON
+        if ctx.proxy_settings.rewrite_content_type
+            && ctx.uses_slots
+            && upstream_request.method == Method::POST
+            && !upstream_request
+                .headers
+                .get("Content-Type")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|content_type| {
+                    content_type
+                        .trim_start()
+                        .to_ascii_lowercase()
+                        .starts_with("application/json")
+                })
+        {
+            upstream_request.insert_header("Content-Type", "application/json")?;
+        }
+
+        ctx.proxy_settings
+            .upstream_headers_policy
+            .apply(upstream_request)?;
+
+        // HTTP/2 connections are shared by the requests in progress, and can't carry the
+        // `Connection` header
+        if ctx.closes_upstream_connection && upstream_request.version != Version::HTTP_2 {
+            // the response still arrives in full, the connection is just not reused after it
+            upstream_request.insert_header("Connection", "close")?;
+        }
+
+        if let Some(peer) = &ctx.selected_peer {
+            inject_peer_headers(upstream_request, &peer.upstream_headers);
+
+            if ctx.proxy_settings.rewrite_host_header {
+                let host = match peer
+                    .host_header
+                    .as_ref()
+                    .or(ctx.proxy_settings.rewrite_host_header_value.as_ref())
+                {
+                    Some(host) => format_host_header(host),
+                    // IPv6 addresses are already in brackets
+                    None => peer.external_llamacpp_addr.to_string(),
+                };
+
+                upstream_request.insert_header("Host".to_string(), host)?;
+            }
+
+            if let Some(api_key) = &peer.api_key {
                 upstream_request
-                    .insert_header("Host".to_string(), peer.external_llamacpp_addr.to_string())?;
+                    .insert_header("Authorization".to_string(), format!("Bearer {}", api_key))?;
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::Cursor,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+    };
+
+    use super::*;
+    use crate::{
+        balancer::{
+            duplicate_agent_id_policy::DuplicateAgentIdPolicy, listener::ListenerPaths,
+            path_rewrite_policy::PathRewritePolicy, placement_strategy::PlacementStrategy,
+            priority_policy::PriorityPolicy,
+            slots_endpoint_disabled_policy::SlotsEndpointDisabledPolicy,
+            status_update::StatusUpdate, tie_break_strategy::TieBreakStrategy,
+            upstream_headers_policy::UpstreamHeadersPolicy,
+            upstream_status_retry_policy::UpstreamStatusRetryPolicy,
+        },
+        llamacpp::slot::Slot,
+    };
+
+    const COMPLETION_REQUEST: &str =
+        "POST /completion HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}";
+
+    /// Same as the defaults of the balancer flags
+    fn proxy_settings() -> ProxySettings {
+        ProxySettings {
+            context_chars_per_token: None,
+            excluded_agent_names: Vec::new(),
+            head_request_policy: MethodPolicy::default(),
+            max_queued_requests: None,
+            max_response_bytes: None,
+            max_retries_per_request: 3,
+            model_priorities: BTreeMap::new(),
+            no_capacity_retry_after: Duration::from_secs(1),
+            no_capacity_status: 503,
+            options_request_policy: MethodPolicy::default(),
+            oversized_batch_policy: OversizedBatchPolicy::default(),
+            parameter_overrides: None,
+            path_rewrite_policy: PathRewritePolicy::default(),
+            priority_header: None,
+            priority_policy: PriorityPolicy::default(),
+            response_compression_policy: None,
+            retry_buffer_bytes: 64 * 1024,
+            rewrite_content_type: false,
+            rewrite_host_header: false,
+            rewrite_host_header_value: None,
+            routing_rules: Vec::new(),
+            slots_endpoint_token: None,
+            stream_error_event: false,
+            target_agent_token: None,
+            upstream_connect_timeout: Duration::from_secs(5),
+            upstream_connection_max_lifetime: None,
+            upstream_headers_policy: UpstreamHeadersPolicy::default(),
+            upstream_protocol: UpstreamProtocol::default(),
+            upstream_status_retry_policy: UpstreamStatusRetryPolicy::default(),
+        }
+    }
+
+    fn proxy_service(upstream_peer_pool: Arc<UpstreamPeerPool>) -> ProxyService {
+        ProxyService::new(
+            None,
+            None,
+            None,
+            #[cfg(feature = "statsd_reporter")]
+            None,
+            false,
+            Listener {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080),
+                api_key: None,
+                name: "default".to_string(),
+                paths: ListenerPaths::All,
+                slots_endpoint_enable: false,
+            },
+            None,
+            Arc::new(ProxySettingsStore::new(proxy_settings())),
+            None,
+            None,
+            None,
+            None,
+            None,
+            upstream_peer_pool,
+        )
+    }
+
+    fn upstream_peer_pool() -> Arc<UpstreamPeerPool> {
+        Arc::new(UpstreamPeerPool::new(
+            None,
+            None,
+            DuplicateAgentIdPolicy::Replace,
+            None,
+            None,
+            BTreeMap::new(),
+            0,
+            PlacementStrategy::Spread,
+            false,
+            SlotsEndpointDisabledPolicy::Exclude,
+            None,
+            TieBreakStrategy::Address,
+            None,
+            false,
+        ))
+    }
+
+    /// Session of a client that sent the given request
+    async fn session(request: &str) -> Session {
+        let mut session = Session::new_h1(Box::new(Cursor::new(request.as_bytes().to_vec())));
+
+        assert!(session.read_request().await.unwrap());
+
+        session
+    }
+
+    /// Status update of an idle llama.cpp instance listening on the given port
+    fn status_update(port: u16, slots_count: usize) -> StatusUpdate {
+        StatusUpdate::new(
+            None,
+            None,
+            None,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            None,
+            Some(true),
+            Some(true),
+            BTreeMap::new(),
+            None,
+            None,
+            None,
+            None,
+            0,
+            (0..slots_count)
+                .map(|id| Slot {
+                    id,
+                    is_processing: false,
+                })
+                .collect(),
+            None,
+            1,
+            BTreeMap::new(),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn failed_take_slot_gives_back_the_permit_and_retries_the_request() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 2))
+            .unwrap();
+        upstream_peer_pool
+            .register_status_update(
+                "other-agent",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 2),
+            )
+            .unwrap();
+
+        let proxy_service = proxy_service(upstream_peer_pool.clone());
+        let mut session = session(COMPLETION_REQUEST).await;
+        let mut ctx = proxy_service.new_ctx();
+
+        // the forced requests skip the check if the selected peer is still usable, so the slot
+        // of a peer that left the pool can't be taken
+        ctx.target_agent = Some("agent".to_string());
+        ctx.uses_slots = true;
+
+        let peer = proxy_service
+            .upstream_peer(&mut session, &mut ctx)
+            .await
+            .unwrap();
+
+        assert_eq!(upstream_peer_pool.inspect().unwrap().available_permits, 3);
+
+        upstream_peer_pool
+            .remove_peer("agent", connection_id)
+            .unwrap();
+
+        let err = proxy_service
+            .connected_to_upstream(&mut session, false, &peer, 0, None, &mut ctx)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(503));
+        assert!(ctx.is_selection_invalidated);
+        assert!(ctx.selected_peer.is_none());
+        assert!(!ctx.slot_taken);
+
+        let pool_inspection = upstream_peer_pool.inspect().unwrap();
+
+        assert_eq!(pool_inspection.available_permits, 2);
+        assert_eq!(pool_inspection.slots_idle, 2);
+        assert_eq!(pool_inspection.slots_processing, 0);
+        assert_eq!(pool_inspection.requests_in_flight, 0);
+
+        let pool_audit = upstream_peer_pool.audit().unwrap();
+
+        assert_eq!(pool_audit.available_permits, pool_audit.expected_permits);
+
+        let err = proxy_service.error_while_proxy(&peer, &mut session, err, &mut ctx, false);
+
+        assert!(err.retry());
+        assert_eq!(ctx.retries, 1);
+    }
+
+    #[test]
+    fn request_is_retried_before_the_client_got_a_part_of_the_response() {
+        let proxy_service = proxy_service(upstream_peer_pool());
+        let mut ctx = proxy_service.new_ctx();
+
+        assert!(proxy_service.allow_retry(&mut ctx));
+        assert_eq!(ctx.retries, 1);
+    }
+
+    #[test]
+    fn request_is_not_retried_once_the_client_got_a_part_of_the_response() {
+        let proxy_service = proxy_service(upstream_peer_pool());
+        let mut ctx = proxy_service.new_ctx();
+
+        ctx.response_bytes_forwarded = true;
+
+        assert!(!proxy_service.allow_retry(&mut ctx));
+        assert_eq!(ctx.retries, 0);
+    }
+}
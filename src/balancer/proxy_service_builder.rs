@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use crate::{
+    balancer::{
+        client_connection_limiter::ClientConnectionLimiter,
+        cluster_stats::ClusterStats,
+        downstream_connections::DownstreamConnections,
+        listener::{Listener, ListenerPaths},
+        model_loading_probe::ModelLoadingProbe,
+        proxy_service::ProxyService,
+        proxy_settings::ProxySettingsStore,
+        request_coalescer::RequestCoalescer,
+        response_cache::ResponseCache,
+        retry_budget::RetryBudget,
+        slots_probe::SlotsProbe,
+        token_quotas::TokenQuotas,
+        upstream_peer_pool::UpstreamPeerPool,
+    },
+    errors::{app_error::AppError, result::Result},
+};
+
+#[cfg(feature = "statsd_reporter")]
+use crate::balancer::endpoint_metrics::EndpointMetrics;
+
+/// Named alternative to `ProxyService::new`, which checks that the options make sense together
+#[derive(Default)]
+pub struct ProxyServiceBuilder {
+    client_connection_limiter: Option<Arc<ClientConnectionLimiter>>,
+    cluster_stats: Option<Arc<ClusterStats>>,
+    downstream_connections: Option<Arc<DownstreamConnections>>,
+    #[cfg(feature = "statsd_reporter")]
+    endpoint_metrics: Option<Arc<EndpointMetrics>>,
+    is_slot_accounting_disabled: bool,
+    listener: Option<Listener>,
+    model_loading_probe: Option<Arc<ModelLoadingProbe>>,
+    proxy_settings: Option<Arc<ProxySettingsStore>>,
+    request_coalescer: Option<Arc<RequestCoalescer>>,
+    response_cache: Option<Arc<ResponseCache>>,
+    retry_budget: Option<Arc<RetryBudget>>,
+    slots_probe: Option<Arc<SlotsProbe>>,
+    token_quotas: Option<Arc<TokenQuotas>>,
+    upstream_peer_pool: Option<Arc<UpstreamPeerPool>>,
+}
+
+impl ProxyServiceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Optional, clients are not limited if not set
+    pub fn client_connection_limiter(
+        mut self,
+        client_connection_limiter: Arc<ClientConnectionLimiter>,
+    ) -> Self {
+        self.client_connection_limiter = Some(client_connection_limiter);
+        self
+    }
+
+    /// Optional, the requests are not counted in `/api/v1/stats` if not set
+    pub fn cluster_stats(mut self, cluster_stats: Arc<ClusterStats>) -> Self {
+        self.cluster_stats = Some(cluster_stats);
+        self
+    }
+
+    /// Optional, the client connections are not tracked if not set
+    pub fn downstream_connections(
+        mut self,
+        downstream_connections: Arc<DownstreamConnections>,
+    ) -> Self {
+        self.downstream_connections = Some(downstream_connections);
+        self
+    }
+
+    /// Optional, the requests per endpoint are not collected if not set
+    #[cfg(feature = "statsd_reporter")]
+    pub fn endpoint_metrics(mut self, endpoint_metrics: Arc<EndpointMetrics>) -> Self {
+        self.endpoint_metrics = Some(endpoint_metrics);
+        self
+    }
+
+    /// Optional, the requests are forwarded to any reachable agent, without waiting for an
+    /// idle slot
+    pub fn disable_slot_accounting(mut self) -> Self {
+        self.is_slot_accounting_disabled = true;
+        self
+    }
+
+    pub fn listener(mut self, listener: Listener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Optional, the `503` responses are treated as overload if not set
+    pub fn model_loading_probe(mut self, model_loading_probe: Arc<ModelLoadingProbe>) -> Self {
+        self.model_loading_probe = Some(model_loading_probe);
+        self
+    }
+
+    pub fn proxy_settings(mut self, proxy_settings: Arc<ProxySettingsStore>) -> Self {
+        self.proxy_settings = Some(proxy_settings);
+        self
+    }
+
+    /// Optional, identical requests in progress are not coalesced if not set
+    pub fn request_coalescer(mut self, request_coalescer: Arc<RequestCoalescer>) -> Self {
+        self.request_coalescer = Some(request_coalescer);
+        self
+    }
+
+    /// Optional, responses are not cached if not set
+    pub fn response_cache(mut self, response_cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(response_cache);
+        self
+    }
+
+    /// Optional, only the retries per request are limited if not set
+    pub fn retry_budget(mut self, retry_budget: Arc<RetryBudget>) -> Self {
+        self.retry_budget = Some(retry_budget);
+        self
+    }
+
+    /// Optional, the idle slots are never confirmed with llama.cpp if not set
+    pub fn slots_probe(mut self, slots_probe: Arc<SlotsProbe>) -> Self {
+        self.slots_probe = Some(slots_probe);
+        self
+    }
+
+    /// Optional, the used tokens are not counted if not set
+    pub fn token_quotas(mut self, token_quotas: Arc<TokenQuotas>) -> Self {
+        self.token_quotas = Some(token_quotas);
+        self
+    }
+
+    pub fn upstream_peer_pool(mut self, upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        self.upstream_peer_pool = Some(upstream_peer_pool);
+        self
+    }
+
+    pub fn build(self) -> Result<ProxyService> {
+        let listener = self
+            .listener
+            .ok_or_else(|| AppError::UnexpectedError("Proxy service needs a listener".to_string()))?;
+
+        if listener.slots_endpoint_enable && listener.paths == ListenerPaths::Public {
+            return Err(AppError::UnexpectedError(format!(
+                "Listener {} enables the slots endpoint, but only allows the public paths",
+                listener.name
+            )));
+        }
+
+        // the used tokens are only counted for the requests that take a slot
+        if self.is_slot_accounting_disabled && self.token_quotas.is_some() {
+            return Err(AppError::UnexpectedError(
+                "Token quotas can't be used with the slot accounting disabled".to_string(),
+            ));
+        }
+
+        let proxy_settings = self.proxy_settings.ok_or_else(|| {
+            AppError::UnexpectedError("Proxy service needs the proxy settings".to_string())
+        })?;
+        let upstream_peer_pool = self.upstream_peer_pool.ok_or_else(|| {
+            AppError::UnexpectedError("Proxy service needs the upstream peer pool".to_string())
+        })?;
+
+        Ok(ProxyService::new(
+            self.client_connection_limiter,
+            self.cluster_stats,
+            self.downstream_connections,
+            #[cfg(feature = "statsd_reporter")]
+            self.endpoint_metrics,
+            self.is_slot_accounting_disabled,
+            listener,
+            self.model_loading_probe,
+            proxy_settings,
+            self.request_coalescer,
+            self.response_cache,
+            self.retry_budget,
+            self.slots_probe,
+            self.token_quotas,
+            upstream_peer_pool,
+        ))
+    }
+}
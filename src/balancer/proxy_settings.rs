@@ -0,0 +1,135 @@
+use log::warn;
+use pingora::http::RequestHeader;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::balancer::{
+    agent_name_pattern::AgentNamePattern, method_policy::MethodPolicy,
+    oversized_batch_policy::OversizedBatchPolicy, parameter_overrides::ParameterOverridesPolicy,
+    path_rewrite_policy::PathRewritePolicy, priority_policy::PriorityPolicy,
+    request_priority::RequestPriority, response_compression_policy::ResponseCompressionPolicy,
+    routing_rule::RoutingRule, upstream_headers_policy::UpstreamHeadersPolicy,
+    upstream_protocol::UpstreamProtocol, upstream_status_retry_policy::UpstreamStatusRetryPolicy,
+};
+
+/// Settings that can be changed while the balancer is running
+#[derive(Clone, Debug)]
+pub struct ProxySettings {
+    /// Used to estimate the prompt length, the context size is not checked if not set
+    pub context_chars_per_token: Option<f64>,
+    /// Applied to the pool by `ConfigReloader`, since the pool does the selection
+    pub excluded_agent_names: Vec<AgentNamePattern>,
+    pub head_request_policy: MethodPolicy,
+    /// Requests are rejected with 503 instead of queued when there are no idle slots and at
+    /// least this many requests are already waiting
+    pub max_queued_requests: Option<usize>,
+    /// Responses are cut off once the client got this many bytes, not limited if not set
+    pub max_response_bytes: Option<usize>,
+    pub max_retries_per_request: usize,
+    /// Priority of the requests for the given model (the `model` field of the request body)
+    pub model_priorities: BTreeMap<String, RequestPriority>,
+    /// `Retry-After` of the responses to the requests that no agent can take
+    pub no_capacity_retry_after: Duration,
+    /// Status of the responses to the requests that no agent can take, 4xx or 5xx
+    pub no_capacity_status: u16,
+    pub options_request_policy: MethodPolicy,
+    pub oversized_batch_policy: OversizedBatchPolicy,
+    /// Request bodies are rewritten only if set
+    pub parameter_overrides: Option<ParameterOverridesPolicy>,
+    pub path_rewrite_policy: PathRewritePolicy,
+    /// Header that clients can use to set the request priority, disabled if not set
+    pub priority_header: Option<String>,
+    pub priority_policy: PriorityPolicy,
+    /// Responses to the clients are not compressed if not set
+    pub response_compression_policy: Option<ResponseCompressionPolicy>,
+    /// Requests with larger bodies are not retried, the proxy only keeps 64 KiB of the body
+    /// to send again, so larger values have no effect
+    pub retry_buffer_bytes: usize,
+    /// Sets `Content-Type: application/json` on the completion requests that do not have it
+    pub rewrite_content_type: bool,
+    pub rewrite_host_header: bool,
+    /// Used when rewriting the `Host` header of the peers that do not have their own
+    pub rewrite_host_header_value: Option<String>,
+    /// Labels the agents need to have, depending on the request
+    pub routing_rules: Vec<RoutingRule>,
+    /// If set, the slots endpoint requires this token, on top of the listener API key
+    pub slots_endpoint_token: Option<String>,
+    /// Appends an OpenAI-style `error` event to the streams llama.cpp aborted with an error
+    pub stream_error_event: bool,
+    /// If set, forcing the agent with `X-Paddler-Target-Agent` requires this token
+    pub target_agent_token: Option<String>,
+    /// Connect timeouts go through the `fail_to_connect` quarantine path
+    pub upstream_connect_timeout: Duration,
+    /// Older connections are closed after the request instead of going back to the pool
+    pub upstream_connection_max_lifetime: Option<Duration>,
+    pub upstream_headers_policy: UpstreamHeadersPolicy,
+    /// Used for the peers whose agent does not set its own
+    pub upstream_protocol: UpstreamProtocol,
+    pub upstream_status_retry_policy: UpstreamStatusRetryPolicy,
+}
+
+impl ProxySettings {
+    /// `model` is the field of the request body, None if the body was not read
+    pub fn resolve_priority(
+        &self,
+        request_header: &RequestHeader,
+        model: Option<&str>,
+    ) -> RequestPriority {
+        let header_priority = self
+            .priority_header
+            .as_ref()
+            .and_then(|priority_header| request_header.headers.get(priority_header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| match value.parse::<RequestPriority>() {
+                Ok(priority) => Some(priority),
+                Err(err) => {
+                    warn!("Ignoring request priority header: {}", err);
+
+                    None
+                }
+            });
+
+        let model_priority = model.and_then(|model| self.model_priorities.get(model).copied());
+
+        let priority = match self.priority_policy {
+            PriorityPolicy::HeaderFirst => header_priority.or(model_priority),
+            PriorityPolicy::ModelFirst => model_priority.or(header_priority),
+        };
+
+        priority.unwrap_or_default()
+    }
+}
+
+/// Requests hold on to the snapshot they started with, so a reload never changes the
+/// settings in the middle of a request
+pub struct ProxySettingsStore {
+    current: RwLock<Arc<ProxySettings>>,
+}
+
+impl ProxySettingsStore {
+    pub fn new(proxy_settings: ProxySettings) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(proxy_settings)),
+        }
+    }
+
+    pub fn load(&self) -> Arc<ProxySettings> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            // the lock only guards a pointer swap, so the value is never left half-written
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn store(&self, proxy_settings: ProxySettings) {
+        let proxy_settings = Arc::new(proxy_settings);
+
+        match self.current.write() {
+            Ok(mut current) => *current = proxy_settings,
+            Err(poisoned) => *poisoned.into_inner() = proxy_settings,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::watch::{channel, Receiver, Sender};
+
+use crate::balancer::response_cache::CachedResponse;
+
+#[derive(Clone)]
+enum CoalescedResponse {
+    /// The first request is still in progress
+    Pending,
+    Completed(CachedResponse),
+    /// The first request failed, or its response can't be shared, so every waiting request
+    /// goes to llama.cpp on its own
+    Failed,
+}
+
+pub enum CoalescedRequest {
+    /// No identical request is in progress, so this one goes to llama.cpp and shares its
+    /// response
+    Leader(CoalescingLeader),
+    /// Identical request is already in progress, this one waits for its response
+    Follower(CoalescingFollower),
+}
+
+/// Identical deterministic requests in progress, the ones that come later wait for the
+/// response of the first one instead of taking a slot each
+pub struct RequestCoalescer {
+    in_flight: Mutex<HashMap<u64, Sender<CoalescedResponse>>>,
+    /// Larger responses are not shared, the waiting requests go to llama.cpp then
+    pub max_response_size: usize,
+}
+
+impl RequestCoalescer {
+    pub fn new(max_response_size: usize) -> Self {
+        RequestCoalescer {
+            in_flight: Mutex::new(HashMap::new()),
+            max_response_size,
+        }
+    }
+
+    /// The key is the same as the response cache key
+    pub fn join(self: &Arc<Self>, key: u64) -> CoalescedRequest {
+        let mut in_flight = self.in_flight();
+
+        if let Some(sender) = in_flight.get(&key) {
+            return CoalescedRequest::Follower(CoalescingFollower {
+                receiver: sender.subscribe(),
+            });
+        }
+
+        let (sender, _) = channel(CoalescedResponse::Pending);
+
+        in_flight.insert(key, sender);
+
+        CoalescedRequest::Leader(CoalescingLeader {
+            key,
+            request_coalescer: self.clone(),
+            response: None,
+        })
+    }
+
+    #[inline]
+    fn in_flight(&self) -> MutexGuard<'_, HashMap<u64, Sender<CoalescedResponse>>> {
+        // the lock only guards the map, which is always left consistent
+        match self.in_flight.lock() {
+            Ok(in_flight) => in_flight,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn finish(&self, key: u64, response: Option<CachedResponse>) {
+        // requests that come after this one start a new round
+        if let Some(sender) = self.in_flight().remove(&key) {
+            sender.send_replace(match response {
+                Some(response) => CoalescedResponse::Completed(response),
+                None => CoalescedResponse::Failed,
+            });
+        }
+    }
+}
+
+/// First of the identical requests, the waiting ones get its response once this is dropped
+pub struct CoalescingLeader {
+    key: u64,
+    request_coalescer: Arc<RequestCoalescer>,
+    response: Option<CachedResponse>,
+}
+
+impl CoalescingLeader {
+    pub fn complete(&mut self, response: CachedResponse) {
+        self.response = Some(response);
+    }
+}
+
+impl Drop for CoalescingLeader {
+    fn drop(&mut self) {
+        self.request_coalescer
+            .finish(self.key, self.response.take());
+    }
+}
+
+pub struct CoalescingFollower {
+    receiver: Receiver<CoalescedResponse>,
+}
+
+impl CoalescingFollower {
+    /// None if the request has to go to llama.cpp after all
+    pub async fn wait(mut self) -> Option<CachedResponse> {
+        match self
+            .receiver
+            .wait_for(|response| !matches!(response, CoalescedResponse::Pending))
+            .await
+        {
+            Ok(response) => match &*response {
+                CoalescedResponse::Completed(response) => Some(response.clone()),
+                _ => None,
+            },
+            Err(_) => None,
+        }
+    }
+}
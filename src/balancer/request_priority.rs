@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// Requests with higher priority get slot permits before the ones with lower priority
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RequestPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl RequestPriority {
+    pub const COUNT: usize = 3;
+
+    /// Lower index means higher priority
+    pub fn index(self) -> usize {
+        match self {
+            RequestPriority::High => 0,
+            RequestPriority::Normal => 1,
+            RequestPriority::Low => 2,
+        }
+    }
+}
+
+impl FromStr for RequestPriority {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "high" => Ok(RequestPriority::High),
+            "normal" => Ok(RequestPriority::Normal),
+            "low" => Ok(RequestPriority::Low),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid request priority: {} (expected \"high\", \"normal\", or \"low\")",
+                arg
+            ))),
+        }
+    }
+}
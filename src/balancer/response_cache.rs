@@ -0,0 +1,118 @@
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub body: Bytes,
+    pub content_type: Option<String>,
+}
+
+struct CacheEntry {
+    cached_at: Instant,
+    /// Value of the cache clock when the entry was last used, the smallest one is evicted first
+    last_used: u64,
+    response: CachedResponse,
+}
+
+struct CacheEntries {
+    clock: u64,
+    entries: HashMap<u64, CacheEntry>,
+}
+
+/// Least recently used responses to deterministic completion requests, kept in memory
+pub struct ResponseCache {
+    entries: Mutex<CacheEntries>,
+    max_entries: usize,
+    /// Larger responses are not cached
+    pub max_response_size: usize,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: usize, max_response_size: usize, ttl: Duration) -> Self {
+        ResponseCache {
+            entries: Mutex::new(CacheEntries {
+                clock: 0,
+                entries: HashMap::new(),
+            }),
+            max_entries,
+            max_response_size,
+            ttl,
+        }
+    }
+
+    pub fn get(&self, key: u64) -> Option<CachedResponse> {
+        let mut cache_entries = self.entries();
+
+        cache_entries.clock += 1;
+
+        let clock = cache_entries.clock;
+
+        match cache_entries.entries.get_mut(&key) {
+            Some(cache_entry) if cache_entry.cached_at.elapsed() <= self.ttl => {
+                cache_entry.last_used = clock;
+
+                Some(cache_entry.response.clone())
+            }
+            Some(_) => {
+                cache_entries.entries.remove(&key);
+
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: u64, response: CachedResponse) {
+        if self.max_entries == 0 || response.body.len() > self.max_response_size {
+            return;
+        }
+
+        let mut cache_entries = self.entries();
+
+        cache_entries.clock += 1;
+
+        let clock = cache_entries.clock;
+
+        if !cache_entries.entries.contains_key(&key) {
+            let ttl = self.ttl;
+
+            cache_entries
+                .entries
+                .retain(|_, cache_entry| cache_entry.cached_at.elapsed() <= ttl);
+
+            if cache_entries.entries.len() >= self.max_entries {
+                if let Some(least_recently_used) = cache_entries
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, cache_entry)| cache_entry.last_used)
+                    .map(|(key, _)| *key)
+                {
+                    cache_entries.entries.remove(&least_recently_used);
+                }
+            }
+        }
+
+        cache_entries.entries.insert(
+            key,
+            CacheEntry {
+                cached_at: Instant::now(),
+                last_used: clock,
+                response,
+            },
+        );
+    }
+
+    #[inline]
+    fn entries(&self) -> MutexGuard<'_, CacheEntries> {
+        // the lock only guards the map, which is always left consistent
+        match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
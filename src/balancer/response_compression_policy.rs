@@ -0,0 +1,49 @@
+use pingora::http::ResponseHeader;
+
+/// Streamed responses would wait in the encoder instead of reaching the client token by token
+const STREAMED_CONTENT_TYPE: &str = "text/event-stream";
+
+/// Which responses are compressed for the clients that accept it (gzip, brotli, or zstd)
+#[derive(Clone, Debug)]
+pub struct ResponseCompressionPolicy {
+    /// Media types, without the parameters like `charset`
+    pub content_types: Vec<String>,
+    pub level: u32,
+    /// Responses without `Content-Length` are not compressed, since their size is unknown
+    pub min_size: usize,
+}
+
+impl ResponseCompressionPolicy {
+    pub fn should_compress(&self, response: &ResponseHeader) -> bool {
+        if response.headers.contains_key("Content-Encoding") {
+            return false;
+        }
+
+        let content_type = response
+            .headers
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|content_type| content_type.split(';').next())
+            .map(str::trim);
+
+        let Some(content_type) = content_type else {
+            return false;
+        };
+
+        if content_type.eq_ignore_ascii_case(STREAMED_CONTENT_TYPE)
+            || !self
+                .content_types
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(content_type))
+        {
+            return false;
+        }
+
+        response
+            .headers
+            .get("Content-Length")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|content_length| content_length.parse::<usize>().ok())
+            .is_some_and(|content_length| content_length >= self.min_size)
+    }
+}
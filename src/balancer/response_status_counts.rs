@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+/// Responses from llama.cpp, bucketed by the status class. Overloaded responses (`429` and
+/// `503`) are counted separately from the other client and server errors.
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ResponseStatusCounts {
+    pub overloaded: usize,
+    pub status_2xx: usize,
+    pub status_3xx: usize,
+    pub status_4xx: usize,
+    pub status_5xx: usize,
+    /// Streams that llama.cpp aborted with an error event, they are counted in `status_2xx`
+    /// as well, since that is the status they were sent with
+    pub stream_errors: usize,
+}
+
+impl ResponseStatusCounts {
+    /// llama.cpp responds with these when its internal queue is full
+    pub fn is_overloaded_status(status: u16) -> bool {
+        status == 429 || status == 503
+    }
+
+    pub fn register(&mut self, status: u16) {
+        match status {
+            _ if Self::is_overloaded_status(status) => self.overloaded += 1,
+            200..=299 => self.status_2xx += 1,
+            300..=399 => self.status_3xx += 1,
+            400..=499 => self.status_4xx += 1,
+            500..=599 => self.status_5xx += 1,
+            _ => {}
+        }
+    }
+
+    pub fn register_stream_error(&mut self) {
+        self.stream_errors += 1;
+    }
+}
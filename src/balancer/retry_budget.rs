@@ -0,0 +1,96 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "statsd_reporter")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Default)]
+struct RecentRequests {
+    requests: VecDeque<Instant>,
+    retries: VecDeque<Instant>,
+}
+
+impl RecentRequests {
+    fn forget_older_than(&mut self, window: Duration) {
+        for recent in [&mut self.requests, &mut self.retries] {
+            while recent
+                .front()
+                .is_some_and(|happened_at| happened_at.elapsed() > window)
+            {
+                recent.pop_front();
+            }
+        }
+    }
+}
+
+/// Caps the retries of all the requests together to a fraction of the recent requests, so
+/// the retries do not overload the agents that are still healthy
+pub struct RetryBudget {
+    /// Retries that were not allowed, since the last report
+    #[cfg(feature = "statsd_reporter")]
+    exhausted: AtomicUsize,
+    /// Allowed within the window regardless of the ratio, so a few requests can still be
+    /// retried when the traffic is low
+    min_retries: usize,
+    ratio: f64,
+    recent: Mutex<RecentRequests>,
+    window: Duration,
+}
+
+impl RetryBudget {
+    pub fn new(min_retries: usize, ratio: f64, window: Duration) -> Self {
+        RetryBudget {
+            #[cfg(feature = "statsd_reporter")]
+            exhausted: AtomicUsize::new(0),
+            min_retries,
+            ratio,
+            recent: Mutex::new(RecentRequests::default()),
+            window,
+        }
+    }
+
+    pub fn register_request(&self) {
+        let mut recent = self.recent();
+
+        recent.forget_older_than(self.window);
+        recent.requests.push_back(Instant::now());
+    }
+
+    /// Returns false if the retry would go over the budget
+    pub fn try_spend(&self) -> bool {
+        let mut recent = self.recent();
+
+        recent.forget_older_than(self.window);
+
+        let allowed_retries =
+            ((recent.requests.len() as f64 * self.ratio) as usize).max(self.min_retries);
+
+        if recent.retries.len() >= allowed_retries {
+            #[cfg(feature = "statsd_reporter")]
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+
+            return false;
+        }
+
+        recent.retries.push_back(Instant::now());
+
+        true
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_exhausted(&self) -> usize {
+        self.exhausted.swap(0, Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn recent(&self) -> MutexGuard<'_, RecentRequests> {
+        // the lock only guards the timestamps, which are always left consistent
+        match self.recent.lock() {
+            Ok(recent) => recent,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
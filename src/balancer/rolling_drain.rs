@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::balancer::upstream_peer::UpstreamPeer;
+
+/// Drains the peers a few at a time, for rolling upgrades of the fleet. Drained peers stay
+/// out of the pool until their llama.cpp restarts (or their agent registers again), and
+/// count towards the concurrency until then, so the rollout never takes down more peers
+/// than allowed.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RollingDrain {
+    /// Drained, and back in the pool after the restart
+    pub completed: Vec<String>,
+    /// Peers out of the pool at the same time
+    pub concurrency: usize,
+    /// Not processing anything, waiting for the restart
+    pub drained: Vec<String>,
+    /// Not getting new requests, waiting for the ones in progress to finish
+    pub draining: Vec<String>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub pending: VecDeque<String>,
+    /// Restart epochs of the peers when they started draining
+    #[serde(skip_serializing)]
+    restart_epochs: BTreeMap<String, u64>,
+}
+
+impl RollingDrain {
+    pub fn new(agent_ids: Vec<String>, concurrency: usize) -> Self {
+        RollingDrain {
+            completed: Vec::new(),
+            concurrency,
+            drained: Vec::new(),
+            draining: Vec::new(),
+            pending: agent_ids.into(),
+            restart_epochs: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the peers that just finished draining
+    pub fn advance(&mut self, agents: &mut [UpstreamPeer]) -> Vec<String> {
+        self.drained.retain(|agent_id| {
+            match agents.iter_mut().find(|peer| &peer.agent_id == agent_id) {
+                // peers that registered again start without the draining flag
+                Some(peer)
+                    if !peer.is_draining
+                        || self.restart_epochs.get(agent_id) != Some(&peer.restart_epoch) =>
+                {
+                    peer.is_draining = false;
+                    self.completed.push(agent_id.to_owned());
+
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        let mut just_drained = Vec::new();
+
+        self.draining.retain(|agent_id| {
+            // peers that left the pool are not processing anything either
+            let is_drained = agents
+                .iter()
+                .find(|peer| &peer.agent_id == agent_id)
+                .is_none_or(|peer| peer.requests_in_flight == 0 && peer.slots_processing == 0);
+
+            if is_drained {
+                self.drained.push(agent_id.to_owned());
+                just_drained.push(agent_id.to_owned());
+            }
+
+            !is_drained
+        });
+
+        while self.draining.len() + self.drained.len() < self.concurrency {
+            let Some(agent_id) = self.pending.pop_front() else {
+                break;
+            };
+
+            match agents.iter_mut().find(|peer| peer.agent_id == agent_id) {
+                Some(peer) => {
+                    peer.is_draining = true;
+                    self.restart_epochs
+                        .insert(agent_id.to_owned(), peer.restart_epoch);
+                    self.draining.push(agent_id);
+                }
+                // left the pool before its turn, there is nothing to drain
+                None => self.completed.push(agent_id),
+            }
+        }
+
+        just_drained
+    }
+
+    /// Puts the peers that are still out of the pool back
+    pub fn cancel(&self, agents: &mut [UpstreamPeer]) {
+        for peer in agents.iter_mut() {
+            if self.draining.contains(&peer.agent_id) || self.drained.contains(&peer.agent_id) {
+                peer.is_draining = false;
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.draining.is_empty() && self.drained.is_empty()
+    }
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use log::{debug, error};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{sync::Arc, time::Duration};
+use tokio::time::{interval, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
+
+const ROLLING_DRAIN_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct RollingDrainService {
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl RollingDrainService {
+    pub fn new(upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        RollingDrainService { upstream_peer_pool }
+    }
+}
+
+#[async_trait]
+impl Service for RollingDrainService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let mut ticker = interval(ROLLING_DRAIN_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down rolling drain service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.upstream_peer_pool.advance_rolling_drain() {
+                        error!("Failed to advance the rolling drain: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "rolling_drain"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+use crate::balancer::{request_priority::RequestPriority, upstream_peer::UpstreamPeer};
+
+/// Why the peer would or would not get the request
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateStatus {
+    /// Took its share of new requests for now, see `AdmissionRatePolicy`
+    AdmissionDeferred,
+    /// Name matches one of the `--exclude-agent-name` patterns
+    AgentNameExcluded,
+    ContextTooSmall,
+    /// Could take the request, but a better peer is available
+    Eligible,
+    LabelsNotMatched,
+    /// Has fewer idle slots than the batch has prompts, while another peer has enough
+    NotEnoughIdleSlots,
+    NotUsable,
+    Selected,
+    /// Already responded to the request with an error, or turned out to be busy
+    Skipped,
+    SlotsEndpointDisabled,
+    /// Scores the same as the selected peer, the tie-break strategy picked the other one
+    Tied,
+    /// Took as many requests as it can while it is warming up
+    WarmupSaturated,
+}
+
+/// Compared in this order, the first difference decides which peer is better
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RoutingScore {
+    pub is_usable: bool,
+    pub is_stale: bool,
+    pub tier: usize,
+    pub is_model_outdated: bool,
+    pub slots_idle_effective: usize,
+    pub slots_processing: usize,
+    pub queued_requests_count: usize,
+}
+
+impl From<&UpstreamPeer> for RoutingScore {
+    fn from(peer: &UpstreamPeer) -> Self {
+        RoutingScore {
+            is_usable: peer.is_usable(),
+            is_stale: peer.is_stale,
+            tier: peer.tier,
+            is_model_outdated: peer.is_model_outdated,
+            slots_idle_effective: peer.slots_idle_effective(),
+            slots_processing: peer.slots_processing,
+            queued_requests_count: peer.queued_requests_count.unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RoutingCandidate {
+    pub agent_id: String,
+    pub agent_name: Option<String>,
+    /// Position of the peer when sorted by the score, starting from the best one
+    pub rank: usize,
+    pub score: RoutingScore,
+    pub status: CandidateStatus,
+    /// Only listed for the peers that are not usable
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub unusable_reasons: Vec<&'static str>,
+}
+
+/// Which peer a request would be sent to right now, and why
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RoutingExplanation {
+    pub candidates: Vec<RoutingCandidate>,
+    pub selected_agent_id: Option<String>,
+    /// Whether the request would wait for a slot permit before the peer is selected, in which
+    /// case the selection might be different by the time it gets one
+    pub waits_for_permit: bool,
+}
+
+/// Attributes the balancer would read from the simulated request, and where it would go
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SelectionSimulation {
+    pub explanation: RoutingExplanation,
+    /// From the `X-Paddler-Require` header and the routing rule that matched
+    pub label_selectors: Vec<String>,
+    pub priority: RequestPriority,
+    pub prompt_tokens: Option<usize>,
+    /// More than one for the batches of prompts
+    pub slots: usize,
+    pub uses_slots: bool,
+}
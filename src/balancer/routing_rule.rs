@@ -0,0 +1,53 @@
+use pingora::http::RequestHeader;
+use serde::Deserialize;
+
+use crate::balancer::label::Label;
+
+/// What the request has to have for the rule to apply
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingRuleCondition {
+    /// Header name is case-insensitive, the value is not
+    Header { name: String, value: String },
+    /// `model` field of the request body, only known for the requests that take a slot
+    Model(String),
+    /// Path after the path rewrites
+    Path(String),
+}
+
+/// For example `{"when": {"header": {"name": "X-Tier", "value": "premium"}}, "require":
+/// ["tier=premium"]}` sends the premium requests only to the agents labeled `tier=premium`
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoutingRule {
+    pub require: Vec<Label>,
+    pub when: RoutingRuleCondition,
+}
+
+impl RoutingRule {
+    pub fn matches(&self, request_header: &RequestHeader, model: Option<&str>) -> bool {
+        match &self.when {
+            RoutingRuleCondition::Header { name, value } => request_header
+                .headers
+                .get(name.as_str())
+                .is_some_and(|header_value| header_value == value.as_str()),
+            RoutingRuleCondition::Model(rule_model) => model == Some(rule_model.as_str()),
+            RoutingRuleCondition::Path(path) => request_header.uri.path() == path,
+        }
+    }
+
+    pub fn needs_model(&self) -> bool {
+        matches!(self.when, RoutingRuleCondition::Model(_))
+    }
+}
+
+/// Rules are checked in order, and the first one that matches decides the labels the agent
+/// needs to have. None of them matching leaves the selection unconstrained.
+pub fn find_routing_rule<'rules>(
+    routing_rules: &'rules [RoutingRule],
+    request_header: &RequestHeader,
+    model: Option<&str>,
+) -> Option<&'rules RoutingRule> {
+    routing_rules
+        .iter()
+        .find(|routing_rule| routing_rule.matches(request_header, model))
+}
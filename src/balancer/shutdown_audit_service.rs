@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use log::{error, info, warn};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::{process, sync::Arc};
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{
+    balancer::{pool_audit::PoolAudit, upstream_peer_pool::UpstreamPeerPool},
+    errors::result::Result,
+};
+
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exit code when the requests were still in progress after `--shutdown-drain-timeout`
+const EXIT_CODE_REQUESTS_DROPPED: i32 = 1;
+
+/// Waits for the requests in progress to finish when the balancer shuts down, then logs
+/// whether the pool bookkeeping ended up clean
+pub struct ShutdownAuditService {
+    shutdown_drain_timeout: Duration,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl ShutdownAuditService {
+    pub fn new(
+        shutdown_drain_timeout: Duration,
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+    ) -> Self {
+        ShutdownAuditService {
+            shutdown_drain_timeout,
+            upstream_peer_pool,
+        }
+    }
+
+    async fn drain(&self) -> Result<(PoolAudit, bool)> {
+        let drain_deadline = Instant::now() + self.shutdown_drain_timeout;
+        let mut ticker = interval(DRAIN_CHECK_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let pool_audit = self.upstream_peer_pool.audit()?;
+
+            if pool_audit.requests_in_flight() == 0 {
+                return Ok((pool_audit, true));
+            }
+
+            if Instant::now() >= drain_deadline {
+                return Ok((pool_audit, false));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Service for ShutdownAuditService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+
+        info!("Waiting for the requests in progress to finish");
+
+        let (pool_audit, is_drained) = match self.drain().await {
+            Ok(drain) => drain,
+            Err(err) => {
+                error!("Failed to audit the pool on shutdown: {}", err);
+
+                return;
+            }
+        };
+
+        for peer_in_flight in &pool_audit.peers_in_flight {
+            warn!(
+                "Agent {} ({}) has {} requests in flight and {} slots processing on shutdown",
+                peer_in_flight.agent_id,
+                peer_in_flight.agent_name.as_deref().unwrap_or("unnamed"),
+                peer_in_flight.requests_in_flight,
+                peer_in_flight.slots_processing
+            );
+        }
+
+        if pool_audit.is_permits_count_consistent() {
+            info!(
+                "Slot permits on shutdown: {} available, as expected",
+                pool_audit.available_permits
+            );
+        } else {
+            warn!(
+                "Slot permits on shutdown: {} available, {} expected",
+                pool_audit.available_permits, pool_audit.expected_permits
+            );
+        }
+
+        if is_drained {
+            info!("All the requests finished, shutdown is graceful");
+
+            return;
+        }
+
+        error!(
+            "{} requests were still in flight after {:?}, they are dropped",
+            pool_audit.requests_in_flight(),
+            self.shutdown_drain_timeout
+        );
+
+        // pingora always exits with success once its grace period ends
+        process::exit(EXIT_CODE_REQUESTS_DROPPED);
+    }
+
+    fn name(&self) -> &str {
+        "shutdown_audit"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
@@ -0,0 +1,30 @@
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// How to treat peers that have the llama.cpp slots endpoint disabled, and therefore cannot
+/// report their slots availability
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotsEndpointDisabledPolicy {
+    /// Assume the peer has a fixed number of slots and track their usage on the balancer side
+    AssumeCapacity(usize),
+    /// Never use the peer for requests that consume slots
+    Exclude,
+}
+
+impl FromStr for SlotsEndpointDisabledPolicy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg.split_once(':') {
+            None if arg == "exclude" => Ok(SlotsEndpointDisabledPolicy::Exclude),
+            Some(("assume-capacity", capacity)) => {
+                Ok(SlotsEndpointDisabledPolicy::AssumeCapacity(capacity.parse()?))
+            }
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid slots endpoint disabled policy: {} (expected \"exclude\" or \"assume-capacity:N\")",
+                arg
+            ))),
+        }
+    }
+}
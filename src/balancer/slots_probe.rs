@@ -0,0 +1,53 @@
+use tokio::time::Duration;
+
+use crate::{
+    balancer::upstream_peer::UpstreamPeerInfo,
+    errors::{app_error::AppError, result::Result},
+    llamacpp::slot::Slot,
+};
+
+/// Asks llama.cpp for its slots right before a request is sent to it, the last status update
+/// of the agent might be outdated by then
+pub struct SlotsProbe {
+    client: reqwest::Client,
+    /// Otherwise only the requests with the `X-Paddler-Probe-Slots` header are probed
+    pub probes_all_requests: bool,
+}
+
+impl SlotsProbe {
+    pub fn new(probes_all_requests: bool, timeout: Duration) -> Result<Self> {
+        Ok(SlotsProbe {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            probes_all_requests,
+        })
+    }
+
+    /// Returns false if llama.cpp has fewer idle slots than the request needs
+    pub async fn has_idle_slots(&self, peer: &UpstreamPeerInfo, slots: usize) -> Result<bool> {
+        let mut request = self
+            .client
+            .get(format!("http://{}/slots", peer.external_llamacpp_addr));
+
+        if let Some(api_key) = &peer.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            return Err(AppError::UnexpectedError(format!(
+                "Unexpected response status {}",
+                response.status()
+            )));
+        }
+
+        let slots_idle = response
+            .json::<Vec<Slot>>()
+            .await?
+            .iter()
+            .filter(|slot| !slot.is_processing)
+            .count();
+
+        Ok(slots_idle >= slots)
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, net::SocketAddr, path::Path};
+
+use crate::{balancer::upstream_protocol::UpstreamProtocol, errors::result::Result};
+
+fn default_tier() -> usize {
+    1
+}
+
+fn default_weight() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StaticPeerConfig {
+    /// Agents reporting with this id (`paddler agent --agent-id`) update the peer's status
+    pub agent_id: String,
+    /// API key the balancer sends to llama.cpp when forwarding requests
+    pub api_key: Option<String>,
+    pub external_llamacpp_addr: SocketAddr,
+    pub hardware_class: Option<String>,
+    /// Sent in the `Host` header instead of the llama.cpp address, if the balancer rewrites it
+    pub host_header: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub max_concurrency: Option<usize>,
+    pub model: Option<String>,
+    pub name: Option<String>,
+    /// Slots assumed to be available until an agent reports the actual number
+    pub slots: usize,
+    #[serde(default = "default_tier")]
+    pub tier: usize,
+    /// Added to the requests forwarded to the peer, unless its agent reports its own
+    #[serde(default)]
+    pub upstream_headers: BTreeMap<String, String>,
+    /// Protocol to speak to the peer, unless its agent reports its own
+    pub upstream_protocol: Option<UpstreamProtocol>,
+    #[serde(default = "default_weight")]
+    pub weight: usize,
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StaticPeersConfig {
+    pub peers: Vec<StaticPeerConfig>,
+}
+
+impl StaticPeersConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{debug, error, info, warn};
+use pingora::{server::ShutdownWatch, services::Service};
+use std::sync::Arc;
+use tokio::{
+    net::TcpStream,
+    time::{interval, timeout, Duration, MissedTickBehavior},
+};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{
+    balancer::{upstream_peer::UpstreamPeerInfo, upstream_peer_pool::UpstreamPeerPool},
+    errors::{app_error::AppError, result::Result},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connects to the llama.cpp of every static peer once the balancer starts, the unreachable
+/// ones are excluded instead of failing the startup, and connected to again until they
+/// respond
+pub struct StaticPeersProbeService {
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl StaticPeersProbeService {
+    pub fn new(upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        StaticPeersProbeService { upstream_peer_pool }
+    }
+
+    async fn connect(peer: &UpstreamPeerInfo) -> Result<()> {
+        match timeout(
+            CONNECT_TIMEOUT,
+            TcpStream::connect(peer.external_llamacpp_addr),
+        )
+        .await
+        {
+            Ok(connection) => {
+                connection?;
+
+                Ok(())
+            }
+            Err(_) => Err(AppError::UnexpectedError(format!(
+                "Connecting timed out after {} seconds",
+                CONNECT_TIMEOUT.as_secs()
+            ))),
+        }
+    }
+
+    /// Returns true if the peer is unreachable
+    async fn probe_peer(&self, peer: UpstreamPeerInfo) -> Result<bool> {
+        if let Err(err) = Self::connect(&peer).await {
+            let error = format!(
+                "llama.cpp at {} is unreachable: {}",
+                peer.external_llamacpp_addr, err
+            );
+
+            // later probes only go to the peers that are already unreachable
+            if self
+                .upstream_peer_pool
+                .mark_static_peer_unreachable(&peer.agent_id, error.clone())?
+            {
+                warn!("Static agent {} started degraded, {}", peer.agent_id, error);
+            }
+
+            return Ok(true);
+        }
+
+        if self
+            .upstream_peer_pool
+            .mark_static_peer_reachable(&peer.agent_id)?
+        {
+            info!("Static agent {} is reachable again", peer.agent_id);
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the number of peers that are unreachable
+    async fn probe_peers(&self, only_unreachable: bool) -> Result<usize> {
+        let peers = self
+            .upstream_peer_pool
+            .static_peers_to_probe(only_unreachable)?;
+
+        let mut unreachable_peers = 0;
+
+        // a peer that times out does not hold the others back
+        for is_unreachable in join_all(peers.into_iter().map(|peer| self.probe_peer(peer))).await {
+            if is_unreachable? {
+                unreachable_peers += 1;
+            }
+        }
+
+        Ok(unreachable_peers)
+    }
+}
+
+#[async_trait]
+impl Service for StaticPeersProbeService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        match self.probe_peers(false).await {
+            Ok(0) => info!("All static agents are reachable"),
+            Ok(unreachable_peers) => warn!(
+                "{} static agents are unreachable, serving from the others until they recover",
+                unreachable_peers
+            ),
+            Err(err) => error!("Failed to probe static agents: {}", err),
+        }
+
+        let mut ticker = interval(PROBE_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the first tick completes right away, and the peers were just probed
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down static peers probe service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.probe_peers(true).await {
+                        error!("Failed to probe static agents: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "static_peers_probe"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
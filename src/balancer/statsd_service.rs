@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use cadence::{BufferedUdpMetricSink, Gauged, StatsdClient};
+use cadence::{BufferedUdpMetricSink, Gauged, StatsdClient, Timed};
 use log::{debug, error};
 use pingora::{server::ShutdownWatch, services::Service};
 use std::{
@@ -11,9 +11,18 @@ use tokio::time::{interval, Duration, MissedTickBehavior};
 #[cfg(unix)]
 use pingora::server::ListenFds;
 
-use crate::{balancer::upstream_peer_pool::UpstreamPeerPool, errors::result::Result};
+use crate::{
+    balancer::{
+        downstream_connections::DownstreamConnections, endpoint_metrics::EndpointMetrics,
+        retry_budget::RetryBudget, upstream_peer_pool::UpstreamPeerPool,
+    },
+    errors::result::Result,
+};
 
 pub struct StatsdService {
+    downstream_connections: Arc<DownstreamConnections>,
+    endpoint_metrics: Arc<EndpointMetrics>,
+    retry_budget: Option<Arc<RetryBudget>>,
     statsd_addr: SocketAddr,
     statsd_prefix: String,
     statsd_reporting_interval: Duration,
@@ -22,12 +31,18 @@ pub struct StatsdService {
 
 impl StatsdService {
     pub fn new(
+        downstream_connections: Arc<DownstreamConnections>,
+        endpoint_metrics: Arc<EndpointMetrics>,
+        retry_budget: Option<Arc<RetryBudget>>,
         statsd_addr: SocketAddr,
         statsd_prefix: String,
         statsd_reporting_interval: Duration,
         upstream_peer_pool: Arc<UpstreamPeerPool>,
     ) -> Result<Self> {
         Ok(StatsdService {
+            downstream_connections,
+            endpoint_metrics,
+            retry_budget,
             statsd_addr,
             statsd_prefix,
             statsd_reporting_interval,
@@ -40,6 +55,70 @@ impl StatsdService {
 
         client.gauge("slots_idle", slots_idle as u64)?;
         client.gauge("slots_processing", slots_processing as u64)?;
+        client.gauge(
+            "downstream_connections",
+            self.downstream_connections.count() as u64,
+        )?;
+        client.gauge(
+            "model_loading.responses",
+            self.upstream_peer_pool.take_model_loading_responses() as u64,
+        )?;
+        client.gauge(
+            "permit_handoffs",
+            self.upstream_peer_pool.take_permit_handoffs() as u64,
+        )?;
+        client.gauge(
+            "selections_invalidated",
+            self.upstream_peer_pool.take_selections_invalidated() as u64,
+        )?;
+        client.gauge(
+            "warmup.requests_deferred",
+            self.upstream_peer_pool.take_requests_deferred_by_warmup() as u64,
+        )?;
+        client.gauge(
+            "retry_buffer.retries_skipped",
+            self.upstream_peer_pool.take_retries_skipped_by_body_size() as u64,
+        )?;
+        client.gauge(
+            "status_updates.coalesced",
+            self.upstream_peer_pool.take_status_updates_coalesced() as u64,
+        )?;
+
+        if let Some(retry_budget) = &self.retry_budget {
+            client.gauge("retry_budget.exhausted", retry_budget.take_exhausted() as u64)?;
+        }
+
+        for (tier, requests) in self.upstream_peer_pool.take_requests_per_tier()? {
+            client.gauge(&format!("tier_{}.requests", tier), requests as u64)?;
+        }
+
+        for (endpoint, endpoint_stats) in self.endpoint_metrics.take()? {
+            client.gauge(
+                &format!("endpoint.{}.requests", endpoint),
+                endpoint_stats.requests as u64,
+            )?;
+
+            let response_status_counts = endpoint_stats.response_status_counts;
+
+            for (status_class, responses) in [
+                ("2xx", response_status_counts.status_2xx),
+                ("3xx", response_status_counts.status_3xx),
+                ("4xx", response_status_counts.status_4xx),
+                ("5xx", response_status_counts.status_5xx),
+                ("overloaded", response_status_counts.overloaded),
+                ("stream_errors", response_status_counts.stream_errors),
+            ] {
+                client.gauge(
+                    &format!("endpoint.{}.responses.{}", endpoint, status_class),
+                    responses as u64,
+                )?;
+            }
+
+            for latency in endpoint_stats.latencies {
+                client.time(&format!("endpoint.{}.latency", endpoint), latency)?;
+            }
+        }
+
         client.flush()?;
 
         Ok(())
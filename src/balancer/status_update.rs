@@ -1,40 +1,109 @@
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
 
-use crate::llamacpp::slot::Slot;
+use crate::{
+    balancer::upstream_protocol::UpstreamProtocol,
+    llamacpp::{model_info::ModelInfo, slot::Slot},
+};
+
+fn default_tier() -> usize {
+    1
+}
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StatusUpdate {
     pub agent_name: Option<String>,
     pub error: Option<String>,
+    /// Host name to send in the `Host` header instead of the llama.cpp address
+    #[serde(default)]
+    pub external_host: Option<String>,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub external_llamacpp_addr: SocketAddr,
+    /// Set by the operator with `paddler agent --hardware-class`, None if not set
+    #[serde(default)]
+    pub hardware_class: Option<String>,
     pub idle_slots_count: usize,
     pub is_authorized: Option<bool>,
     pub is_slots_endpoint_enabled: Option<bool>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// None if the agent is older or could not determine it
+    pub model_info: Option<ModelInfo>,
+    /// Set by the operator with `paddler agent --model-version`, None if not set
+    #[serde(default)]
+    pub model_version: Option<String>,
     pub processing_slots_count: usize,
+    /// Requests waiting in the llama.cpp queue, None if the agent is older or llama.cpp does
+    /// not expose its metrics
+    #[serde(default)]
+    pub queued_requests_count: Option<usize>,
+    /// Incremented by the agent every time it detects that llama.cpp restarted
+    #[serde(default)]
+    pub restart_epoch: u64,
     slots: Vec<Slot>,
+    /// How often the agent reports, None if the agent is older
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub status_interval: Option<Duration>,
+    #[serde(default = "default_tier")]
+    pub tier: usize,
+    /// Added to the requests forwarded to this llama.cpp instance
+    #[serde(default)]
+    pub upstream_headers: BTreeMap<String, String>,
+    /// Set by the operator with `paddler agent --upstream-protocol`, None if not set
+    #[serde(default)]
+    pub upstream_protocol: Option<UpstreamProtocol>,
 }
 
+#[cfg(any(test, feature = "agent"))]
 impl StatusUpdate {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         agent_name: Option<String>,
         error: Option<String>,
+        external_host: Option<String>,
         external_llamacpp_addr: SocketAddr,
+        hardware_class: Option<String>,
         is_authorized: Option<bool>,
         is_slots_endpoint_enabled: Option<bool>,
+        labels: BTreeMap<String, String>,
+        max_concurrency: Option<usize>,
+        model_info: Option<ModelInfo>,
+        model_version: Option<String>,
+        queued_requests_count: Option<usize>,
+        restart_epoch: u64,
         slots: Vec<Slot>,
+        status_interval: Option<Duration>,
+        tier: usize,
+        upstream_headers: BTreeMap<String, String>,
+        upstream_protocol: Option<UpstreamProtocol>,
     ) -> Self {
         let idle_slots_count = slots.iter().filter(|slot| !slot.is_processing).count();
 
         Self {
             agent_name,
             error,
+            external_host,
             external_llamacpp_addr,
+            hardware_class,
             idle_slots_count,
             is_authorized,
             is_slots_endpoint_enabled,
+            labels,
+            max_concurrency,
+            model_info,
+            model_version,
             processing_slots_count: slots.len() - idle_slots_count,
+            queued_requests_count,
+            restart_epoch,
             slots,
+            status_interval,
+            tier,
+            upstream_headers,
+            upstream_protocol,
         }
     }
 }
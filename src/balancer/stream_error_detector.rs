@@ -0,0 +1,177 @@
+use bytes::{Bytes, BytesMut};
+use serde_json::{json, Value};
+
+/// Longer lines are skipped, they are generated tokens rather than errors
+const MAX_LINE_SIZE: usize = 64 * 1024;
+
+/// Error llama.cpp reported in the middle of a stream, after it already responded with 200
+#[derive(Clone, Debug)]
+pub struct StreamError {
+    pub code: Option<u64>,
+    pub error_type: String,
+    /// Set if the error came as a `data` event with the `error` field, which the OpenAI SDKs
+    /// already turn into an exception
+    pub is_visible_to_openai_clients: bool,
+    pub message: String,
+}
+
+impl StreamError {
+    /// Either `{"error": {...}}`, or the error object itself
+    fn from_value(value: &Value, is_visible_to_openai_clients: bool) -> Self {
+        let error = value
+            .get("error")
+            .filter(|error| !error.is_null())
+            .unwrap_or(value);
+
+        StreamError {
+            code: error.get("code").and_then(Value::as_u64),
+            error_type: error
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("server_error")
+                .to_string(),
+            is_visible_to_openai_clients,
+            message: error
+                .get("message")
+                .and_then(Value::as_str)
+                .or_else(|| error.as_str())
+                .map_or_else(|| error.to_string(), str::to_string),
+        }
+    }
+
+    /// The message is the data itself if it is not JSON
+    fn parse(data: &str) -> Self {
+        let value =
+            serde_json::from_str::<Value>(data).unwrap_or_else(|_| Value::String(data.to_string()));
+
+        Self::from_value(&value, false)
+    }
+
+    /// `error` event in the shape of the OpenAI API, so the SDKs raise a meaningful exception
+    pub fn openai_event(&self) -> Bytes {
+        let event = json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+                "type": self.error_type,
+            }
+        });
+
+        Bytes::from(format!("data: {}\n\n", event))
+    }
+}
+
+#[derive(Default)]
+struct Frame {
+    data: Vec<String>,
+    /// llama.cpp sends the errors as a separate `error:` field instead of `data:`
+    error: Option<String>,
+    event: Option<String>,
+}
+
+/// Follows the server-sent events of a streamed response, without changing them, and finds
+/// the error llama.cpp sends when it aborts the generation
+#[derive(Default)]
+pub struct StreamErrorDetector {
+    frame: Frame,
+    is_line_too_long: bool,
+    /// Part of the line that was cut off by the end of the chunk
+    line: BytesMut,
+    stream_error: Option<StreamError>,
+}
+
+impl StreamErrorDetector {
+    pub fn extend(&mut self, chunk: &[u8]) {
+        let mut rest = chunk;
+
+        while let Some(position) = rest.iter().position(|byte| *byte == b'\n') {
+            self.push_line_part(&rest[..position]);
+            self.end_line();
+
+            rest = &rest[position + 1..];
+        }
+
+        self.push_line_part(rest);
+    }
+
+    /// The last event does not need the blank line after it
+    pub fn finish(&mut self) {
+        if !self.line.is_empty() {
+            self.end_line();
+        }
+
+        self.dispatch_frame();
+    }
+
+    /// Only the first error is reported, llama.cpp ends the stream after it anyway
+    pub fn take_stream_error(&mut self) -> Option<StreamError> {
+        self.stream_error.take()
+    }
+
+    fn dispatch_frame(&mut self) {
+        let frame = std::mem::take(&mut self.frame);
+        let data = frame.data.join("\n");
+
+        let stream_error = if let Some(error) = frame.error {
+            Some(StreamError::parse(&error))
+        } else if frame.event.as_deref() == Some("error") {
+            Some(StreamError::parse(&data))
+        } else if frame.event.is_none() && data.contains("\"error\"") {
+            // most of the token events are not parsed at all
+            serde_json::from_str::<Value>(&data)
+                .ok()
+                .filter(|value| value.get("error").is_some_and(|error| !error.is_null()))
+                .map(|value| StreamError::from_value(&value, true))
+        } else {
+            None
+        };
+
+        if self.stream_error.is_none() {
+            self.stream_error = stream_error;
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+
+        if std::mem::take(&mut self.is_line_too_long) {
+            return;
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+
+        if line.is_empty() {
+            self.dispatch_frame();
+
+            return;
+        }
+
+        // lines starting with a colon are comments
+        let (field, value) = match line.split_once(':') {
+            Some(("", _)) => return,
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "data" => self.frame.data.push(value.to_string()),
+            "error" => self.frame.error = Some(value.to_string()),
+            "event" => self.frame.event = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn push_line_part(&mut self, part: &[u8]) {
+        if self.is_line_too_long {
+            return;
+        }
+
+        if self.line.len() + part.len() > MAX_LINE_SIZE {
+            self.line.clear();
+            self.is_line_too_long = true;
+        } else {
+            self.line.extend_from_slice(part);
+        }
+    }
+}
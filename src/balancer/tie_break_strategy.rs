@@ -0,0 +1,47 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    str::FromStr,
+};
+
+use crate::errors::app_error::AppError;
+
+/// Used by `TieBreakStrategy::RequestHash`, requests without an id are hashed by the client IP
+pub fn request_hash(request_id: Option<&str>, client_ip: Option<IpAddr>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    match request_id {
+        Some(request_id) => request_id.hash(&mut hasher),
+        None => client_ip.hash(&mut hasher),
+    }
+
+    hasher.finish()
+}
+
+/// How to pick between peers that are equally good for the request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TieBreakStrategy {
+    /// Always the peer with the smallest llama.cpp address
+    Address,
+    /// Hash of the request id (or the client IP) modulo the number of tied peers, so the
+    /// choice is stateless
+    RequestHash,
+    /// Next peer in turn, based on a counter shared by all the requests
+    RoundRobin,
+}
+
+impl FromStr for TieBreakStrategy {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "address" => Ok(TieBreakStrategy::Address),
+            "request-hash" => Ok(TieBreakStrategy::RequestHash),
+            "round-robin" => Ok(TieBreakStrategy::RoundRobin),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid tie break strategy: {} (expected \"address\", \"request-hash\", or \"round-robin\")",
+                arg
+            ))),
+        }
+    }
+}
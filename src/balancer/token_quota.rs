@@ -0,0 +1,105 @@
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// Calendar period the tokens are counted in, it starts at midnight UTC
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TokenQuotaPeriod {
+    Day,
+    Month,
+}
+
+impl TokenQuotaPeriod {
+    fn first_day(&self, now: DateTime<Utc>) -> NaiveDate {
+        match self {
+            TokenQuotaPeriod::Day => now.date_naive(),
+            TokenQuotaPeriod::Month => now.date_naive().with_day(1).unwrap_or(now.date_naive()),
+        }
+    }
+
+    /// `2024-06-01` for a day, `2024-06` for a month, the used tokens from a different period
+    /// are not counted anymore
+    pub fn key(&self, now: DateTime<Utc>) -> String {
+        match self {
+            TokenQuotaPeriod::Day => now.format("%Y-%m-%d").to_string(),
+            TokenQuotaPeriod::Month => now.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// RFC 3339, in UTC
+    pub fn resets_at(&self, now: DateTime<Utc>) -> String {
+        let first_day = self.first_day(now);
+        let next_first_day = match self {
+            TokenQuotaPeriod::Day => first_day.checked_add_days(Days::new(1)),
+            TokenQuotaPeriod::Month => first_day.checked_add_months(Months::new(1)),
+        }
+        .unwrap_or(first_day);
+
+        next_first_day
+            .and_time(Default::default())
+            .and_utc()
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+impl FromStr for TokenQuotaPeriod {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "day" => Ok(TokenQuotaPeriod::Day),
+            "month" => Ok(TokenQuotaPeriod::Month),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid token quota period: {} (expected \"day\" or \"month\")",
+                arg
+            ))),
+        }
+    }
+}
+
+/// Tokens the clients of a listener (which has its own API key) can use in a period
+#[derive(Clone, Debug)]
+pub struct TokenQuota {
+    pub listener: String,
+    pub period: TokenQuotaPeriod,
+    pub tokens: u64,
+}
+
+/// Parses `listener=public,tokens=1000000,period=day`. Only `listener` and `tokens` are
+/// required, the period is a day by default.
+impl FromStr for TokenQuota {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let mut listener = None;
+        let mut period = TokenQuotaPeriod::Day;
+        let mut tokens = None;
+
+        for option in arg.split(',') {
+            match option.split_once('=') {
+                Some(("listener", value)) => listener = Some(value.to_string()),
+                Some(("period", value)) => period = value.parse()?,
+                Some(("tokens", value)) => tokens = Some(value.parse()?),
+                _ => {
+                    return Err(AppError::UnexpectedError(format!(
+                        "Invalid token quota option: {}",
+                        option
+                    )))
+                }
+            }
+        }
+
+        match (listener, tokens) {
+            (Some(listener), Some(tokens)) => Ok(TokenQuota {
+                listener,
+                period,
+                tokens,
+            }),
+            _ => Err("Token quota needs the listener=... and tokens=... options".into()),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::balancer::token_quota::{TokenQuota, TokenQuotaPeriod};
+
+/// Tokens used in a period, kept in the state file so a restart does not reset them
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenQuotaUsage {
+    /// See `TokenQuotaPeriod::key`
+    pub period: String,
+    pub used: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TokenQuotaSummary {
+    pub listener: String,
+    pub period: TokenQuotaPeriod,
+    pub remaining: u64,
+    /// RFC 3339, in UTC
+    pub resets_at: String,
+    pub tokens: u64,
+    pub used: u64,
+}
+
+/// Fields that are not set are left as they are
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TokenQuotaUpdate {
+    pub tokens: Option<u64>,
+    pub used: Option<u64>,
+}
+
+struct TokenQuotaState {
+    period: TokenQuotaPeriod,
+    tokens: u64,
+    usage: TokenQuotaUsage,
+}
+
+impl TokenQuotaState {
+    /// The used tokens start from zero in the next period
+    fn refresh(&mut self) {
+        let period = self.period.key(Utc::now());
+
+        if self.usage.period != period {
+            self.usage = TokenQuotaUsage { period, used: 0 };
+        }
+    }
+
+    fn summary(&self, listener: &str) -> TokenQuotaSummary {
+        TokenQuotaSummary {
+            listener: listener.to_string(),
+            period: self.period,
+            remaining: self.tokens.saturating_sub(self.usage.used),
+            resets_at: self.period.resets_at(Utc::now()),
+            tokens: self.tokens,
+            used: self.usage.used,
+        }
+    }
+}
+
+/// Tokens used by the clients of each listener with a `--token-quota`, the requests are
+/// rejected once the quota is used up
+pub struct TokenQuotas {
+    /// Charged for the responses that do not say how many tokens they used, for example the
+    /// streams that were interrupted
+    pub fallback_cost: u64,
+    quotas: Mutex<BTreeMap<String, TokenQuotaState>>,
+}
+
+impl TokenQuotas {
+    pub fn new(fallback_cost: u64, token_quotas: Vec<TokenQuota>) -> Self {
+        let now = Utc::now();
+
+        TokenQuotas {
+            fallback_cost,
+            quotas: Mutex::new(
+                token_quotas
+                    .into_iter()
+                    .map(|token_quota| {
+                        (
+                            token_quota.listener,
+                            TokenQuotaState {
+                                period: token_quota.period,
+                                tokens: token_quota.tokens,
+                                usage: TokenQuotaUsage {
+                                    period: token_quota.period.key(now),
+                                    used: 0,
+                                },
+                            },
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the summary if the listener has a quota and it is used up
+    pub fn exhausted(&self, listener: &str) -> Option<TokenQuotaSummary> {
+        let mut quotas = self.quotas();
+        let quota = quotas.get_mut(listener)?;
+
+        quota.refresh();
+
+        (quota.usage.used >= quota.tokens).then(|| quota.summary(listener))
+    }
+
+    /// A request that started before the quota was used up still finishes, so the used
+    /// tokens can go over the quota
+    pub fn register_usage(&self, listener: &str, tokens: u64) {
+        if let Some(quota) = self.quotas().get_mut(listener) {
+            quota.refresh();
+            quota.usage.used = quota.usage.used.saturating_add(tokens);
+        }
+    }
+
+    /// Restores the tokens used before a restart, unless their period is already over
+    pub fn restore(&self, usages: &BTreeMap<String, TokenQuotaUsage>) {
+        for (listener, quota) in self.quotas().iter_mut() {
+            if let Some(usage) = usages.get(listener) {
+                quota.usage = usage.to_owned();
+                quota.refresh();
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, TokenQuotaUsage> {
+        self.quotas()
+            .iter()
+            .map(|(listener, quota)| (listener.to_owned(), quota.usage.to_owned()))
+            .collect()
+    }
+
+    pub fn summaries(&self) -> Vec<TokenQuotaSummary> {
+        self.quotas()
+            .iter_mut()
+            .map(|(listener, quota)| {
+                quota.refresh();
+                quota.summary(listener)
+            })
+            .collect()
+    }
+
+    /// Returns None if the listener has no quota. The changed limit only lasts until the
+    /// restart, the used tokens are kept in the state file.
+    pub fn update(
+        &self,
+        listener: &str,
+        token_quota_update: TokenQuotaUpdate,
+    ) -> Option<TokenQuotaSummary> {
+        let mut quotas = self.quotas();
+        let quota = quotas.get_mut(listener)?;
+
+        quota.refresh();
+
+        if let Some(tokens) = token_quota_update.tokens {
+            quota.tokens = tokens;
+        }
+
+        if let Some(used) = token_quota_update.used {
+            quota.usage.used = used;
+        }
+
+        Some(quota.summary(listener))
+    }
+
+    fn quotas(&self) -> MutexGuard<'_, BTreeMap<String, TokenQuotaState>> {
+        // the lock only guards the counters, which are always left consistent
+        match self.quotas.lock() {
+            Ok(quotas) => quotas,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
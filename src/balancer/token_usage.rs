@@ -0,0 +1,87 @@
+use bytes::BytesMut;
+use serde_json::Value;
+
+/// Larger responses are not parsed, they are charged the fallback cost
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Streams report the usage with their last events, so only their end is kept
+const MAX_STREAM_TAIL_SIZE: usize = 64 * 1024;
+
+/// Keeps the part of the response that tells how many tokens llama.cpp processed
+pub struct TokenUsageCollector {
+    body: BytesMut,
+    is_event_stream: bool,
+    is_too_large: bool,
+}
+
+impl TokenUsageCollector {
+    pub fn new(is_event_stream: bool) -> Self {
+        TokenUsageCollector {
+            body: BytesMut::new(),
+            is_event_stream,
+            is_too_large: false,
+        }
+    }
+
+    pub fn extend(&mut self, chunk: &[u8]) {
+        if self.is_too_large {
+            return;
+        }
+
+        self.body.extend_from_slice(chunk);
+
+        if self.is_event_stream {
+            if self.body.len() > MAX_STREAM_TAIL_SIZE {
+                // a cut off event at the start is not valid JSON, so it is skipped when parsing
+                let _ = self.body.split_to(self.body.len() - MAX_STREAM_TAIL_SIZE);
+            }
+        } else if self.body.len() > MAX_RESPONSE_SIZE {
+            self.body = BytesMut::new();
+            self.is_too_large = true;
+        }
+    }
+
+    /// Prompt and generated tokens together, None if the response does not say
+    pub fn total_tokens(&self) -> Option<u64> {
+        if self.is_too_large {
+            return None;
+        }
+
+        if !self.is_event_stream {
+            return serde_json::from_slice::<Value>(&self.body)
+                .ok()
+                .as_ref()
+                .and_then(total_tokens);
+        }
+
+        String::from_utf8_lossy(&self.body)
+            .lines()
+            .rev()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+            .find_map(|event| total_tokens(&event))
+    }
+}
+
+/// OpenAI-compatible `usage`, or the counters of the llama.cpp native endpoints
+fn total_tokens(response: &Value) -> Option<u64> {
+    if let Some(usage) = response.get("usage") {
+        if let Some(total_tokens) = usage.get("total_tokens").and_then(Value::as_u64) {
+            return Some(total_tokens);
+        }
+
+        return sum_counters(usage, "prompt_tokens", "completion_tokens");
+    }
+
+    sum_counters(response, "tokens_evaluated", "tokens_predicted")
+}
+
+fn sum_counters(object: &Value, prompt_field: &str, generated_field: &str) -> Option<u64> {
+    match (
+        object.get(prompt_field).and_then(Value::as_u64),
+        object.get(generated_field).and_then(Value::as_u64),
+    ) {
+        (None, None) => None,
+        (prompt, generated) => Some(prompt.unwrap_or(0) + generated.unwrap_or(0)),
+    }
+}
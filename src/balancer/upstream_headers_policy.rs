@@ -0,0 +1,96 @@
+use log::warn;
+use pingora::{http::RequestHeader, Result};
+use std::collections::BTreeMap;
+
+use crate::balancer::injected_header::InjectedHeader;
+
+/// Hop-by-hop headers only make sense for the connection with the client, and cookies belong
+/// to whatever sits in front of the balancer. `Transfer-Encoding` is left to the proxy, since
+/// it frames the request body.
+const DEFAULT_STRIPPED_HEADERS: [&str; 8] = [
+    "Connection",
+    "Cookie",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Upgrade",
+];
+
+/// Set by the balancer itself, per-agent headers cannot replace them
+const PROTECTED_HEADERS: [&str; 7] = [
+    "Authorization",
+    "Connection",
+    "Content-Length",
+    "Expect",
+    "Host",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+pub fn is_protected_header(name: &str) -> bool {
+    PROTECTED_HEADERS
+        .iter()
+        .any(|protected| protected.eq_ignore_ascii_case(name))
+}
+
+/// Adds the headers configured for a single agent, skipping the protected ones. Only the names
+/// are logged, since the values can be credentials.
+pub fn inject_peer_headers(
+    upstream_request: &mut RequestHeader,
+    upstream_headers: &BTreeMap<String, String>,
+) {
+    for (name, value) in upstream_headers {
+        if is_protected_header(name) {
+            warn!("Skipping protected upstream header {}", name);
+
+            continue;
+        }
+
+        if upstream_request
+            .insert_header(name.to_owned(), value.to_owned())
+            .is_err()
+        {
+            warn!("Skipping invalid upstream header {}", name);
+        }
+    }
+}
+
+/// Which of the client's headers reach llama.cpp
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamHeadersPolicy {
+    /// Headers from the default stripped set that are forwarded anyway
+    pub forward_headers: Vec<String>,
+    pub injected_headers: Vec<InjectedHeader>,
+    /// Stripped in addition to the default set, takes precedence over `forward_headers`
+    pub strip_headers: Vec<String>,
+}
+
+impl UpstreamHeadersPolicy {
+    pub fn apply(&self, upstream_request: &mut RequestHeader) -> Result<()> {
+        for header in DEFAULT_STRIPPED_HEADERS {
+            if !self.is_forwarded(header) {
+                upstream_request.remove_header(header);
+            }
+        }
+
+        for header in &self.strip_headers {
+            upstream_request.remove_header(header.as_str());
+        }
+
+        for injected_header in &self.injected_headers {
+            upstream_request
+                .insert_header(injected_header.name.to_owned(), injected_header.value.to_owned())?;
+        }
+
+        Ok(())
+    }
+
+    /// Header names are case-insensitive
+    fn is_forwarded(&self, header: &str) -> bool {
+        self.forward_headers
+            .iter()
+            .any(|forwarded| forwarded.eq_ignore_ascii_case(header))
+    }
+}
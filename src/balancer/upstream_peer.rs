@@ -1,38 +1,214 @@
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 use std::{
     cmp::{Eq, Ordering, PartialEq},
+    collections::{BTreeMap, VecDeque},
     net::SocketAddr,
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::sync::OwnedSemaphorePermit;
 
-use crate::balancer::status_update::StatusUpdate;
+use crate::{
+    balancer::{
+        admission_rate_policy::AdmissionRatePolicy, cooldown_policy::CooldownPolicy,
+        error_penalty_policy::ErrorPenaltyPolicy, flap_damping_policy::FlapDampingPolicy,
+        label::Label, peer_history::PeerHistory, pool_snapshot::PeerSnapshot,
+        response_status_counts::ResponseStatusCounts, static_peers_config::StaticPeerConfig,
+        status_update::StatusUpdate, upstream_protocol::UpstreamProtocol,
+    },
+    llamacpp::model_info::ModelInfo,
+};
+
+/// Reports an agent can miss before its peer is stale
+const MISSED_STATUS_UPDATES: u32 = 3;
+
+fn serialize_age_ms<S>(reported_at: &Option<Instant>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    reported_at
+        .map(|reported_at| reported_at.elapsed().as_millis() as u64)
+        .serialize(serializer)
+}
+
+/// Header values can carry credentials, so only the names are listed
+fn serialize_header_names<S>(
+    headers: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(headers.keys())
+}
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct UpstreamPeer {
+    /// Times the peer was skipped because it took its share of new requests, see
+    /// `AdmissionRatePolicy`
+    pub admission_deferred: usize,
+    /// Only tracked if the admission rate policy is enabled
+    #[serde(skip_serializing)]
+    pub admission_tokens: f64,
+    #[serde(skip_serializing)]
+    pub admission_tokens_refilled_at: Instant,
     pub agent_id: String,
+    /// Concurrency limit reported by the agent (`paddler agent --max-concurrency`)
+    pub agent_max_concurrency: Option<usize>,
     pub agent_name: Option<String>,
+    /// Headers reported by the agent (`paddler agent --upstream-header`)
+    #[serde(skip_serializing)]
+    pub agent_upstream_headers: BTreeMap<String, String>,
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+    /// Only tracked if the flap damping policy is enabled
+    #[serde(skip_serializing)]
+    pub capacity_changes: VecDeque<Instant>,
+    /// Identifies the status update connection the agent registered with, None for static peers
+    #[serde(skip_serializing)]
+    pub connection_id: Option<u64>,
+    /// Set while the peer is cooling down, see `CooldownPolicy`
+    pub cooldown_slots_factor: Option<f64>,
     pub error: Option<String>,
+    /// Set while the peer has recent errors, see `ErrorPenaltyPolicy`
+    pub error_penalty_factor: Option<f64>,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub external_llamacpp_addr: SocketAddr,
+    /// Set while the reported total slots keep changing, see `FlapDampingPolicy`
+    pub frozen_slots_total: Option<usize>,
+    /// Set by the operator with `paddler agent --hardware-class`, or in the static peers config
+    pub hardware_class: Option<String>,
+    /// Multiplies the idle slots when the peers are compared, see `--hardware-class-weight`
+    pub hardware_class_weight: Option<f64>,
+    /// Served at `/api/v1/agents/{agent_id}/history`
+    #[serde(skip_serializing)]
+    pub history: PeerHistory,
+    /// Sent in the `Host` header instead of the llama.cpp address, if the balancer rewrites it
+    pub host_header: Option<String>,
+    /// Slots the balancer took since the last status update, and did not release yet
+    pub in_flight_since_report: usize,
     /// None means undetermined, probably due to an error
     pub is_authorized: Option<bool>,
+    /// Set by the rolling drain, the peer gets no new requests until it restarts
+    pub is_draining: bool,
+    /// Discovered peers come from DNS, and are placeholders until their agent reports
+    pub is_discovered: bool,
     /// None means undetermined, probably due to an error
     pub is_slots_endpoint_enabled: Option<bool>,
+    /// Set when the agent missed a few reports in a row, stale peers are only used when no
+    /// fresh peer can take the request
+    pub is_stale: bool,
+    /// Set when another peer serves a newer version of the same model, and the balancer
+    /// prefers the newest versions
+    pub is_model_outdated: bool,
+    /// Static peers come from the config file, and are never removed from the pool
+    pub is_static: bool,
+    /// Set for the static peers the balancer could not connect to, their `error` says why,
+    /// until the probe connects or their agent reports
+    pub is_unreachable: bool,
+    pub labels: BTreeMap<String, String>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
     pub last_update: SystemTime,
+    /// Effective limit of requests the balancer sends to the peer at the same time
+    pub max_concurrency: Option<usize>,
+    /// Set through the management API, takes precedence over the agent's limit
+    pub max_concurrency_override: Option<usize>,
+    pub model: Option<String>,
+    /// None means the agent did not report it
+    pub model_info: Option<ModelInfo>,
+    /// Set when llama.cpp responded that it is still loading the model, the status updates
+    /// look fine by then, so the peer only gets requests again after that
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub model_loading_until: Option<SystemTime>,
+    /// Set by the operator with `paddler agent --model-version`
+    pub model_version: Option<String>,
+    /// Set when the operator quarantined the peer, the status updates do not lift the
+    /// quarantine before that
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub quarantine_held_until: Option<SystemTime>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
     pub quarantined_until: Option<SystemTime>,
+    /// Requests waiting in the llama.cpp queue, as reported by the agent
+    pub queued_requests_count: Option<usize>,
+    /// Only tracked if the error penalty policy is enabled
+    #[serde(skip_serializing)]
+    pub recent_errors: VecDeque<Instant>,
+    /// Only tracked if the cooldown policy is enabled
+    #[serde(skip_serializing)]
+    pub recent_requests: VecDeque<Instant>,
+    /// Idle slots as reported by the agent in the last status update
+    pub reported_slots_idle: usize,
+    /// Processing slots as reported by the agent in the last status update
+    pub reported_slots_processing: usize,
+    /// Total slots in the last status update, before the flap damping
+    #[serde(skip_serializing)]
+    pub reported_slots_total: usize,
+    /// Requests the balancer currently has in progress on this peer
+    pub requests_in_flight: usize,
+    /// Responses from llama.cpp since the peer was registered
+    pub response_status_counts: ResponseStatusCounts,
+    pub restart_epoch: u64,
+    /// Reported idle slots, minus the ones taken since the report
     pub slots_idle: usize,
+    /// Reported processing slots, plus the ones taken since the report
     pub slots_processing: usize,
     #[serde(skip_serializing)]
     pub slots_permissions: Option<OwnedSemaphorePermit>,
+    /// Total slots the peer reported before its capacity started to change
+    #[serde(skip_serializing)]
+    pub stable_slots_total: usize,
+    /// Set for peers restored from the state file until their agent reports again
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub stale_until: Option<SystemTime>,
+    /// The coalesced status updates only refresh `status_reported_at`
+    #[serde(skip_serializing)]
+    pub status_applied_at: Option<Instant>,
+    /// How often the agent reports, None for the peers without an agent and older agents
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub status_interval: Option<Duration>,
+    /// None for the peers without an agent, listed as the milliseconds since the last report
+    #[serde(rename = "age_ms", serialize_with = "serialize_age_ms")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<u64>))]
+    pub status_reported_at: Option<Instant>,
+    /// Status updates that arrived too soon after the previous one, and were not applied
+    pub status_updates_coalesced: usize,
+    /// Peers in higher tiers are only used when all the lower tiers are saturated
+    pub tier: usize,
+    /// Effective headers added to the requests forwarded to the peer, listed by name only
+    #[serde(serialize_with = "serialize_header_names")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub upstream_headers: BTreeMap<String, String>,
+    /// Set through the management API, takes precedence over the agent's headers
+    #[serde(skip_serializing)]
+    pub upstream_headers_override: Option<BTreeMap<String, String>>,
+    /// Set by the operator with `paddler agent --upstream-protocol`, or in the static peers
+    /// config, `--upstream-protocol` of the balancer is used if not set
+    pub upstream_protocol: Option<UpstreamProtocol>,
+    /// Limit of requests in progress while the peer is warming up, grows with time
+    pub warmup_max_concurrency: Option<usize>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub warmup_until: Option<SystemTime>,
+    /// False until the warm-up probe confirms the model is serving, if the probe is enabled
+    pub warmed_up: bool,
+    pub weight: usize,
+    pub zone: Option<String>,
 }
 
 pub struct UpstreamPeerInfo {
     pub agent_id: String,
+    pub api_key: Option<String>,
+    pub context_size: Option<usize>,
     pub external_llamacpp_addr: SocketAddr,
+    pub host_header: Option<String>,
+    pub is_slots_endpoint_enabled: Option<bool>,
     pub last_update: SystemTime,
+    pub restart_epoch: u64,
+    pub upstream_headers: BTreeMap<String, String>,
+    pub upstream_protocol: Option<UpstreamProtocol>,
 }
 
 impl UpstreamPeer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         agent_id: String,
         agent_name: Option<String>,
@@ -40,84 +216,674 @@ impl UpstreamPeer {
         external_llamacpp_addr: SocketAddr,
         is_authorized: Option<bool>,
         is_slots_endpoint_enabled: Option<bool>,
+        labels: BTreeMap<String, String>,
+        max_concurrency: Option<usize>,
+        model_info: Option<ModelInfo>,
+        restart_epoch: u64,
         slots_idle: usize,
         slots_processing: usize,
+        tier: usize,
     ) -> Self {
         UpstreamPeer {
+            admission_deferred: 0,
+            // the first refresh caps it at the burst
+            admission_tokens: f64::INFINITY,
+            admission_tokens_refilled_at: Instant::now(),
             agent_id,
+            agent_max_concurrency: max_concurrency,
             agent_name,
+            agent_upstream_headers: BTreeMap::new(),
+            api_key: None,
+            capacity_changes: VecDeque::new(),
+            connection_id: None,
+            cooldown_slots_factor: None,
             error,
+            error_penalty_factor: None,
             external_llamacpp_addr,
+            frozen_slots_total: None,
+            hardware_class: None,
+            hardware_class_weight: None,
+            history: PeerHistory::default(),
+            host_header: None,
+            in_flight_since_report: 0,
             is_authorized,
+            is_discovered: false,
+            is_draining: false,
             is_slots_endpoint_enabled,
+            is_model_outdated: false,
+            is_stale: false,
+            is_static: false,
+            is_unreachable: false,
+            labels,
             last_update: SystemTime::now(),
+            max_concurrency,
+            max_concurrency_override: None,
+            model: None,
+            model_info,
+            model_loading_until: None,
+            model_version: None,
+            quarantine_held_until: None,
             quarantined_until: None,
+            queued_requests_count: None,
+            recent_errors: VecDeque::new(),
+            recent_requests: VecDeque::new(),
+            reported_slots_idle: slots_idle,
+            reported_slots_processing: slots_processing,
+            reported_slots_total: slots_idle + slots_processing,
+            requests_in_flight: 0,
+            response_status_counts: ResponseStatusCounts::default(),
+            restart_epoch,
             slots_idle,
             slots_processing,
             slots_permissions: None,
+            stable_slots_total: slots_idle + slots_processing,
+            stale_until: None,
+            status_applied_at: None,
+            status_interval: None,
+            status_reported_at: None,
+            status_updates_coalesced: 0,
+            tier,
+            upstream_headers: BTreeMap::new(),
+            upstream_headers_override: None,
+            upstream_protocol: None,
+            warmup_max_concurrency: None,
+            warmup_until: None,
+            warmed_up: true,
+            weight: 1,
+            zone: None,
         }
     }
 
-    pub fn new_from_status_update(agent_id: String, status_update: StatusUpdate) -> Self {
-        Self::new(
+    pub fn new_from_static_peer_config(static_peer_config: StaticPeerConfig) -> Self {
+        let mut upstream_peer = Self::new(
+            static_peer_config.agent_id,
+            static_peer_config.name,
+            None,
+            static_peer_config.external_llamacpp_addr,
+            Some(true),
+            None,
+            static_peer_config.labels,
+            None,
+            None,
+            0,
+            static_peer_config.slots,
+            0,
+            static_peer_config.tier,
+        );
+
+        upstream_peer.api_key = static_peer_config.api_key;
+        upstream_peer.hardware_class = static_peer_config.hardware_class;
+        upstream_peer.host_header = static_peer_config.host_header;
+        upstream_peer.is_static = true;
+        upstream_peer.set_max_concurrency_override(static_peer_config.max_concurrency);
+        upstream_peer.set_agent_upstream_headers(static_peer_config.upstream_headers);
+        upstream_peer.model = static_peer_config.model;
+        upstream_peer.upstream_protocol = static_peer_config.upstream_protocol;
+        upstream_peer.weight = static_peer_config.weight;
+        upstream_peer.zone = static_peer_config.zone;
+
+        upstream_peer
+    }
+
+    /// There is no agent reporting yet, so the peer is not usable
+    pub fn new_from_discovered_addr(external_llamacpp_addr: SocketAddr) -> Self {
+        let mut upstream_peer = Self::new(
+            format!("dns:{}", external_llamacpp_addr),
+            None,
+            Some("Waiting for the agent to report".to_string()),
+            external_llamacpp_addr,
+            None,
+            None,
+            BTreeMap::new(),
+            None,
+            None,
+            0,
+            0,
+            0,
+            1,
+        );
+
+        upstream_peer.is_discovered = true;
+
+        upstream_peer
+    }
+
+    /// Restored peers are assumed to be usable, so the traffic can flow before the agents
+    /// report again
+    pub fn new_from_peer_snapshot(peer_snapshot: PeerSnapshot, stale_until: SystemTime) -> Self {
+        let mut upstream_peer = Self::new(
+            peer_snapshot.agent_id,
+            peer_snapshot.agent_name,
+            None,
+            peer_snapshot.external_llamacpp_addr,
+            Some(true),
+            peer_snapshot.is_slots_endpoint_enabled,
+            peer_snapshot.labels,
+            peer_snapshot.agent_max_concurrency,
+            peer_snapshot.model_info,
+            peer_snapshot.restart_epoch,
+            peer_snapshot.slots,
+            0,
+            peer_snapshot.tier,
+        );
+
+        upstream_peer.hardware_class = peer_snapshot.hardware_class;
+        upstream_peer.quarantined_until = peer_snapshot.quarantined_until;
+        upstream_peer.set_max_concurrency_override(peer_snapshot.max_concurrency_override);
+        upstream_peer.stale_until = Some(stale_until);
+        upstream_peer.upstream_protocol = peer_snapshot.upstream_protocol;
+
+        upstream_peer
+    }
+
+    pub fn new_from_status_update(
+        agent_id: String,
+        connection_id: u64,
+        status_update: StatusUpdate,
+    ) -> Self {
+        let mut upstream_peer = Self::new(
             agent_id,
             status_update.agent_name.to_owned(),
             status_update.error.to_owned(),
             status_update.external_llamacpp_addr,
             status_update.is_authorized,
             status_update.is_slots_endpoint_enabled,
+            status_update.labels.to_owned(),
+            status_update.max_concurrency,
+            status_update.model_info.to_owned(),
+            status_update.restart_epoch,
             status_update.idle_slots_count,
             status_update.processing_slots_count,
-        )
+            status_update.tier,
+        );
+
+        upstream_peer.connection_id = Some(connection_id);
+        upstream_peer.hardware_class = status_update.hardware_class;
+        upstream_peer.host_header = status_update.external_host;
+        upstream_peer.set_agent_upstream_headers(status_update.upstream_headers);
+        upstream_peer.model_version = status_update.model_version;
+        upstream_peer.queued_requests_count = status_update.queued_requests_count;
+        upstream_peer.status_applied_at = Some(Instant::now());
+        upstream_peer.status_interval = status_update.status_interval;
+        upstream_peer.status_reported_at = Some(Instant::now());
+        upstream_peer.upstream_protocol = status_update.upstream_protocol;
+
+        upstream_peer
     }
 
     pub fn info(&self) -> UpstreamPeerInfo {
         UpstreamPeerInfo {
             agent_id: self.agent_id.clone(),
+            api_key: self.api_key.clone(),
+            context_size: self.context_size(),
             external_llamacpp_addr: self.external_llamacpp_addr,
+            host_header: self.host_header.clone(),
+            is_slots_endpoint_enabled: self.is_slots_endpoint_enabled,
             last_update: self.last_update,
+            restart_epoch: self.restart_epoch,
+            upstream_headers: self.upstream_headers.clone(),
+            upstream_protocol: self.upstream_protocol,
         }
     }
 
+    /// How well suited the peer is for the next request, equal scores are tied
+    pub fn cmp_score(&self, other: &Self) -> Ordering {
+        other
+            .is_usable()
+            .cmp(&self.is_usable())
+            .then_with(|| self.is_stale.cmp(&other.is_stale))
+            .then_with(|| self.tier.cmp(&other.tier))
+            .then_with(|| self.is_model_outdated.cmp(&other.is_model_outdated))
+            .then_with(|| other.slots_idle_effective().cmp(&self.slots_idle_effective()))
+            .then_with(|| self.slots_processing.cmp(&other.slots_processing))
+            // peers that do not report their queue are assumed to have none
+            .then_with(|| {
+                self.queued_requests_count
+                    .unwrap_or(0)
+                    .cmp(&other.queued_requests_count.unwrap_or(0))
+            })
+    }
+
+    /// Like `cmp_score`, but the peers with the fewest idle slots come first. The cooldown and
+    /// error penalty would make the peers look busier, so the actual idle slots are compared.
+    pub fn cmp_pack_score(&self, other: &Self) -> Ordering {
+        other
+            .is_usable()
+            .cmp(&self.is_usable())
+            .then_with(|| self.is_stale.cmp(&other.is_stale))
+            .then_with(|| self.tier.cmp(&other.tier))
+            .then_with(|| self.is_model_outdated.cmp(&other.is_model_outdated))
+            .then_with(|| self.slots_idle.cmp(&other.slots_idle))
+            .then_with(|| other.slots_processing.cmp(&self.slots_processing))
+            .then_with(|| {
+                self.queued_requests_count
+                    .unwrap_or(0)
+                    .cmp(&other.queued_requests_count.unwrap_or(0))
+            })
+    }
+
+    pub fn context_size(&self) -> Option<usize> {
+        self.model_info
+            .as_ref()
+            .and_then(|model_info| model_info.context_size)
+    }
+
+    /// Unknown context size is assumed to fit, llama.cpp truncates the prompt at worst
+    pub fn fits_in_context(&self, prompt_tokens: usize) -> bool {
+        self.context_size()
+            .is_none_or(|context_size| prompt_tokens <= context_size)
+    }
+
+    /// Time until the peer can take another new request, zero if it can take one right now
+    pub fn admission_available_in(&self, admission_rate_policy: &AdmissionRatePolicy) -> Duration {
+        let admission_tokens = self.admission_tokens
+            + self.admission_tokens_refilled_at.elapsed().as_secs_f64()
+                * admission_rate_policy.per_second;
+
+        if admission_tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+
+        admission_rate_policy
+            .time_per_admission()
+            .mul_f64(1.0 - admission_tokens)
+    }
+
+    pub fn has_admission_token(&self) -> bool {
+        self.admission_tokens >= 1.0
+    }
+
+    pub fn refresh_admission_tokens(&mut self, admission_rate_policy: &AdmissionRatePolicy) {
+        let now = Instant::now();
+
+        self.admission_tokens = (self.admission_tokens
+            + now
+                .duration_since(self.admission_tokens_refilled_at)
+                .as_secs_f64()
+                * admission_rate_policy.per_second)
+            .min(admission_rate_policy.burst as f64);
+        self.admission_tokens_refilled_at = now;
+    }
+
+    pub fn take_admission_token(&mut self) {
+        self.admission_tokens = (self.admission_tokens - 1.0).max(0.0);
+    }
+
+    pub fn refresh_cooldown(&mut self, cooldown_policy: &CooldownPolicy) {
+        while self
+            .recent_requests
+            .front()
+            .is_some_and(|requested_at| requested_at.elapsed() > cooldown_policy.window)
+        {
+            self.recent_requests.pop_front();
+        }
+
+        self.cooldown_slots_factor = if self.recent_requests.len() >= cooldown_policy.after_requests
+        {
+            Some(cooldown_policy.slots_factor)
+        } else {
+            None
+        };
+    }
+
+    /// Peers without a class, or with a class that has no weight, keep their idle slots as
+    /// they are
+    pub fn refresh_hardware_class_weight(
+        &mut self,
+        hardware_class_weights: &BTreeMap<String, f64>,
+    ) {
+        self.hardware_class_weight = self
+            .hardware_class
+            .as_ref()
+            .and_then(|hardware_class| hardware_class_weights.get(hardware_class))
+            .copied();
+    }
+
+    pub fn refresh_model_loading(&mut self) {
+        if self
+            .model_loading_until
+            .is_some_and(|model_loading_until| model_loading_until <= SystemTime::now())
+        {
+            self.model_loading_until = None;
+        }
+    }
+
+    /// Agent that missed a few reports in a row might be stuck, even if its connection is still
+    /// open, so its slots are not up to date
+    pub fn refresh_staleness(&mut self) {
+        self.is_stale = match (self.status_interval, self.status_reported_at) {
+            (Some(status_interval), Some(status_reported_at)) => {
+                status_reported_at.elapsed() > status_interval * MISSED_STATUS_UPDATES
+            }
+            _ => false,
+        };
+    }
+
+    /// Allowed requests in progress ramp up linearly from one to all the slots
+    pub fn refresh_warmup(&mut self, warmup_period: Duration) {
+        let Some(warmup_until) = self.warmup_until else {
+            return;
+        };
+
+        match warmup_until.duration_since(SystemTime::now()) {
+            Ok(remaining) if !remaining.is_zero() => {
+                let progress = 1.0 - remaining.as_secs_f64() / warmup_period.as_secs_f64();
+
+                self.warmup_max_concurrency =
+                    Some(((self.slots_count() as f64 * progress).ceil() as usize).max(1));
+            }
+            _ => {
+                self.warmup_max_concurrency = None;
+                self.warmup_until = None;
+            }
+        }
+    }
+
+    pub fn start_warmup(&mut self, warmup_period: Duration) {
+        self.warmup_until = Some(SystemTime::now() + warmup_period);
+        self.refresh_warmup(warmup_period);
+    }
+
+    pub fn refresh_error_penalty(&mut self, error_penalty_policy: &ErrorPenaltyPolicy) {
+        while self
+            .recent_errors
+            .front()
+            .is_some_and(|failed_at| failed_at.elapsed() > error_penalty_policy.window)
+        {
+            self.recent_errors.pop_front();
+        }
+
+        self.error_penalty_factor = if self.recent_errors.is_empty() {
+            None
+        } else {
+            Some(1.0 / (1.0 + error_penalty_policy.weight * self.recent_errors.len() as f64))
+        };
+    }
+
+    pub fn register_error(&mut self, error_penalty_policy: &ErrorPenaltyPolicy) {
+        self.recent_errors.push_back(Instant::now());
+        self.refresh_error_penalty(error_penalty_policy);
+    }
+
+    pub fn register_request(&mut self, cooldown_policy: &CooldownPolicy) {
+        self.recent_requests.push_back(Instant::now());
+        self.refresh_cooldown(cooldown_policy);
+    }
+
+    /// A status update that arrives too soon after the last applied one is only a sign of
+    /// life. The next one after the interval carries the latest slots anyway. The restarts are
+    /// never coalesced, since they reset the slots.
+    pub fn can_coalesce_status_update(
+        &self,
+        status_update: &StatusUpdate,
+        status_update_min_interval: Duration,
+    ) -> bool {
+        self.restart_epoch == status_update.restart_epoch
+            && self.stale_until.is_none()
+            && self
+                .status_applied_at
+                .is_some_and(|applied_at| applied_at.elapsed() < status_update_min_interval)
+    }
+
+    pub fn coalesce_status_update(&mut self) {
+        self.is_stale = false;
+        self.status_reported_at = Some(Instant::now());
+        self.status_updates_coalesced += 1;
+    }
+
+    /// Rewrites the status update of a flapping peer, so it reports the stable total slots.
+    /// The processing slots are kept as reported, up to that total.
+    pub fn damp_capacity(
+        &mut self,
+        flap_damping_policy: &FlapDampingPolicy,
+        status_update: &mut StatusUpdate,
+    ) {
+        while self
+            .capacity_changes
+            .front()
+            .is_some_and(|changed_at| changed_at.elapsed() > flap_damping_policy.window)
+        {
+            self.capacity_changes.pop_front();
+        }
+
+        // the total did not change for the whole window
+        if self.capacity_changes.is_empty() {
+            self.stable_slots_total = self.reported_slots_total;
+        }
+
+        let slots_total = status_update.idle_slots_count + status_update.processing_slots_count;
+
+        if slots_total != self.reported_slots_total {
+            self.capacity_changes.push_back(Instant::now());
+            self.reported_slots_total = slots_total;
+        }
+
+        self.frozen_slots_total = (self.capacity_changes.len()
+            > flap_damping_policy.max_capacity_changes)
+            .then_some(self.stable_slots_total);
+
+        if let Some(frozen_slots_total) = self.frozen_slots_total {
+            status_update.processing_slots_count =
+                status_update.processing_slots_count.min(frozen_slots_total);
+            status_update.idle_slots_count =
+                frozen_slots_total - status_update.processing_slots_count;
+        }
+    }
+
+    /// Cooling down and recent errors only make the peer less preferred, it can still be used
+    /// if there is nothing better. The hardware class weight makes it more or less preferred
+    /// the same way.
+    pub fn slots_idle_effective(&self) -> usize {
+        let factor = self.cooldown_slots_factor.unwrap_or(1.0)
+            * self.error_penalty_factor.unwrap_or(1.0)
+            * self.hardware_class_weight.unwrap_or(1.0);
+
+        (self.slots_idle as f64 * factor) as usize
+    }
+
+    pub fn matches_labels(&self, label_selectors: &[Label]) -> bool {
+        label_selectors
+            .iter()
+            .all(|label_selector| label_selector.matches(&self.labels))
+    }
+
+    /// Alias reported by llama.cpp, or the model set in the static peers config
+    pub fn model_name(&self) -> Option<&str> {
+        self.model_info
+            .as_ref()
+            .and_then(|model_info| model_info.alias.as_deref())
+            .or(self.model.as_deref())
+    }
+
+    pub fn is_referred_to_as(&self, agent_id_or_name: &str) -> bool {
+        self.agent_id == agent_id_or_name || self.agent_name.as_deref() == Some(agent_id_or_name)
+    }
+
     pub fn is_usable(&self) -> bool {
+        self.is_usable_ignoring_warmup() && !self.is_warmup_saturated()
+    }
+
+    pub fn is_usable_ignoring_warmup(&self) -> bool {
         self.slots_idle > 0
+            && self
+                .max_concurrency
+                .is_none_or(|max_concurrency| self.requests_in_flight < max_concurrency)
             && self.quarantined_until.is_none()
+            && self.model_loading_until.is_none()
+            && !self.is_draining
             && self.error.is_none()
             && matches!(self.is_authorized, Some(true))
+            && self.warmed_up
+    }
+
+    /// Why `is_usable` is false, empty if the peer is usable
+    pub fn unusable_reasons(&self) -> Vec<&'static str> {
+        let mut unusable_reasons = vec![];
+
+        if self.slots_idle == 0 {
+            unusable_reasons.push("no_idle_slots");
+        }
+
+        if self
+            .max_concurrency
+            .is_some_and(|max_concurrency| self.requests_in_flight >= max_concurrency)
+        {
+            unusable_reasons.push("max_concurrency_reached");
+        }
+
+        if self.quarantined_until.is_some() {
+            unusable_reasons.push("quarantined");
+        }
+
+        if self.model_loading_until.is_some() {
+            unusable_reasons.push("loading_model");
+        }
+
+        if self.is_draining {
+            unusable_reasons.push("draining");
+        }
+
+        if self.error.is_some() {
+            unusable_reasons.push("error");
+        }
+
+        if !matches!(self.is_authorized, Some(true)) {
+            unusable_reasons.push("not_authorized");
+        }
+
+        if !self.warmed_up {
+            unusable_reasons.push("waiting_for_warmup_probe");
+        }
+
+        if self.is_warmup_saturated() {
+            unusable_reasons.push("warming_up");
+        }
+
+        unusable_reasons
+    }
+
+    pub fn is_warmup_saturated(&self) -> bool {
+        self.warmup_max_concurrency
+            .is_some_and(|warmup_max_concurrency| self.requests_in_flight >= warmup_max_concurrency)
     }
 
     pub fn release_slot(&mut self) {
         self.last_update = SystemTime::now();
-        self.slots_idle += 1;
-        self.slots_processing -= 1;
+        // slots taken before the last status update were already reported as processing, and
+        // the next report shows them as idle again
+        self.in_flight_since_report = self.in_flight_since_report.saturating_sub(1);
+        self.requests_in_flight = self.requests_in_flight.saturating_sub(1);
+        self.refresh_slots();
     }
 
     pub fn release_permits(&mut self, n: usize) {
-        self.slots_permissions.as_mut().unwrap().split(n);
+        // permits might have been already dropped if llama.cpp restarted
+        if let Some(permits) = self.slots_permissions.as_mut() {
+            permits.split(n);
+        }
+    }
+
+    /// Lowering the limit below the requests in flight does not interrupt them, the peer just
+    /// stops getting new ones
+    pub fn set_max_concurrency_override(&mut self, max_concurrency_override: Option<usize>) {
+        self.max_concurrency_override = max_concurrency_override;
+        self.max_concurrency = self.max_concurrency_override.or(self.agent_max_concurrency);
+    }
+
+    pub fn set_agent_upstream_headers(&mut self, agent_upstream_headers: BTreeMap<String, String>) {
+        self.agent_upstream_headers = agent_upstream_headers;
+        self.refresh_upstream_headers();
+    }
+
+    pub fn set_upstream_headers_override(
+        &mut self,
+        upstream_headers_override: Option<BTreeMap<String, String>>,
+    ) {
+        self.upstream_headers_override = upstream_headers_override;
+        self.refresh_upstream_headers();
+    }
+
+    fn refresh_upstream_headers(&mut self) {
+        self.upstream_headers = self
+            .upstream_headers_override
+            .as_ref()
+            .unwrap_or(&self.agent_upstream_headers)
+            .to_owned();
     }
 
     pub fn update_status(&mut self, status_update: StatusUpdate) {
+        self.agent_max_concurrency = status_update.max_concurrency;
         self.agent_name = status_update.agent_name.to_owned();
         self.error = status_update.error.to_owned();
+        // the agent reached llama.cpp, and its error replaced the one from the probe
+        self.is_unreachable = false;
         self.external_llamacpp_addr = status_update.external_llamacpp_addr;
         self.is_authorized = status_update.is_authorized;
         self.is_slots_endpoint_enabled = status_update.is_slots_endpoint_enabled;
+        self.labels = status_update.labels.to_owned();
         self.last_update = SystemTime::now();
-        self.quarantined_until = None;
+        self.max_concurrency = self.max_concurrency_override.or(self.agent_max_concurrency);
+        self.model_info = status_update.model_info.to_owned();
+        self.model_version = status_update.model_version.to_owned();
+        self.queued_requests_count = status_update.queued_requests_count;
+        self.is_stale = false;
+        self.status_applied_at = Some(Instant::now());
+        self.status_interval = status_update.status_interval;
+        self.status_reported_at = Some(Instant::now());
+        self.tier = status_update.tier;
+
+        if self
+            .quarantine_held_until
+            .is_none_or(|quarantine_held_until| quarantine_held_until <= SystemTime::now())
+        {
+            self.quarantine_held_until = None;
+            self.quarantined_until = None;
+        }
+
+        // static peers keep their configured host, unless the agent sets one
+        if !self.is_static || status_update.external_host.is_some() {
+            self.host_header = status_update.external_host.to_owned();
+        }
+
+        // same for the configured hardware class
+        if !self.is_static || status_update.hardware_class.is_some() {
+            self.hardware_class = status_update.hardware_class.to_owned();
+        }
+
+        // same for the configured headers
+        if !self.is_static || !status_update.upstream_headers.is_empty() {
+            self.set_agent_upstream_headers(status_update.upstream_headers.to_owned());
+        }
+
+        // same for the configured protocol
+        if !self.is_static || status_update.upstream_protocol.is_some() {
+            self.upstream_protocol = status_update.upstream_protocol;
+        }
+
+        if status_update.restart_epoch != self.restart_epoch {
+            // requests in progress are gone with the restart, so their permits can be reused
+            self.restart_epoch = status_update.restart_epoch;
+            self.requests_in_flight = 0;
+            self.slots_permissions = None;
+            self.slots_processing = 0;
+        }
 
         if status_update.processing_slots_count < self.slots_processing {
             let slots_to_release = self.slots_processing - status_update.processing_slots_count;
             self.release_permits(slots_to_release);
         }
 
-        self.slots_idle = status_update.idle_slots_count;
-        self.slots_processing = status_update.processing_slots_count;
+        self.in_flight_since_report = 0;
+        self.reported_slots_idle = status_update.idle_slots_count;
+        self.reported_slots_processing = status_update.processing_slots_count;
+        self.refresh_slots();
     }
 
     pub fn take_slot(&mut self) {
         self.last_update = SystemTime::now();
-        self.slots_idle -= 1;
-        self.slots_processing += 1;
+        self.requests_in_flight += 1;
+        self.in_flight_since_report += 1;
+        self.refresh_slots();
     }
 
     pub fn store_permit(&mut self, permit: OwnedSemaphorePermit) {
@@ -128,6 +894,13 @@ impl UpstreamPeer {
         }
     }
 
+    fn refresh_slots(&mut self) {
+        let taken_since_report = self.in_flight_since_report.min(self.reported_slots_idle);
+
+        self.slots_idle = self.reported_slots_idle - taken_since_report;
+        self.slots_processing = self.reported_slots_processing + taken_since_report;
+    }
+
     pub fn slots_count(&self) -> usize {
         self.slots_idle + self.slots_processing
     }
@@ -135,11 +908,7 @@ impl UpstreamPeer {
 
 impl Ord for UpstreamPeer {
     fn cmp(&self, other: &Self) -> Ordering {
-        other
-            .is_usable()
-            .cmp(&self.is_usable())
-            .then_with(|| other.slots_idle.cmp(&self.slots_idle))
-            .then_with(|| self.slots_processing.cmp(&other.slots_processing))
+        self.cmp_score(other)
             // compare by addr for stable sorting
             .then_with(|| {
                 self.external_llamacpp_addr
@@ -1,83 +1,485 @@
+use log::{info, warn};
 use serde::Serialize;
 use std::{
-    sync::{Arc, RwLock},
+    cmp::Ordering as CmpOrdering,
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
     time::{Duration, SystemTime},
 };
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{
+    broadcast::{self, Receiver, Sender},
+    Notify, OwnedSemaphorePermit, Semaphore,
+};
+
+#[cfg(any(test, feature = "pool_inspection"))]
+use crate::balancer::pool_inspection::PoolInspection;
 
 use crate::{
-    balancer::{status_update::StatusUpdate, upstream_peer::{UpstreamPeer, UpstreamPeerInfo}},
+    balancer::{
+        admission_rate_policy::AdmissionRatePolicy,
+        agent_name_pattern::AgentNamePattern,
+        cooldown_policy::CooldownPolicy,
+        duplicate_agent_id_policy::DuplicateAgentIdPolicy,
+        error_penalty_policy::ErrorPenaltyPolicy,
+        flap_damping_policy::FlapDampingPolicy,
+        label::Label,
+        model_version::cmp_model_versions,
+        peer_history::PeerHistorySample,
+        placement_strategy::PlacementStrategy,
+        pool_audit::{PeerInFlight, PoolAudit},
+        pool_event::PoolEvent,
+        pool_snapshot::{PeerSnapshot, PoolSnapshot},
+        request_priority::RequestPriority,
+        rolling_drain::RollingDrain,
+        routing_explanation::{
+            CandidateStatus, RoutingCandidate, RoutingExplanation, RoutingScore,
+        },
+        slots_endpoint_disabled_policy::SlotsEndpointDisabledPolicy,
+        static_peers_config::StaticPeersConfig,
+        status_update::StatusUpdate,
+        tie_break_strategy::TieBreakStrategy,
+        upstream_peer::{UpstreamPeer, UpstreamPeerInfo},
+    },
     errors::result::Result,
 };
 
+const POOL_EVENTS_CAPACITY: usize = 1024;
+
 #[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct UpstreamPeerPool {
+    #[serde(skip_serializing)]
+    admission_rate_policy: Option<AdmissionRatePolicy>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<UpstreamPeer>))]
     pub agents: RwLock<Vec<UpstreamPeer>>,
     #[serde(skip_serializing)]
+    cooldown_policy: Option<CooldownPolicy>,
+    #[serde(skip_serializing)]
+    duplicate_agent_id_policy: DuplicateAgentIdPolicy,
+    #[serde(skip_serializing)]
+    error_penalty_policy: Option<ErrorPenaltyPolicy>,
+    /// Peers with matching names keep reporting, but get no requests
+    #[serde(skip_serializing)]
+    excluded_agent_names: RwLock<Vec<AgentNamePattern>>,
+    #[serde(skip_serializing)]
+    flap_damping_policy: Option<FlapDampingPolicy>,
+    /// Classes that are not listed have the weight of 1
+    #[serde(skip_serializing)]
+    hardware_class_weights: BTreeMap<String, f64>,
+    /// How many times a request can pass on its permit, see `acquire_permit`
+    #[serde(skip_serializing)]
+    max_permit_handoffs: usize,
+    /// Responses of the peers that were still loading the model
+    #[serde(skip_serializing)]
+    model_loading_responses: AtomicUsize,
+    #[serde(skip_serializing)]
+    next_connection_id: AtomicU64,
+    /// Permits passed on by the requests that could not use them, see `acquire_permit`
+    #[serde(skip_serializing)]
+    permit_handoffs: AtomicUsize,
+    #[serde(skip_serializing)]
+    permit_waiters_changed: Notify,
+    /// Permits of the removed peers that were not available to forget, because requests were
+    /// holding them before storing them on a peer, see `settle_owed_permits`
+    #[serde(skip_serializing)]
+    permits_owed: AtomicUsize,
+    /// Notified when the slots are released, or the agents report more idle slots
+    #[serde(skip_serializing)]
+    permits_returned: Notify,
+    #[serde(skip_serializing)]
+    placement_strategy: PlacementStrategy,
+    #[serde(skip_serializing)]
+    prefer_newest_model_version: bool,
+    #[serde(skip_serializing)]
+    pool_events_tx: Sender<PoolEvent>,
+    #[cfg(feature = "statsd_reporter")]
+    #[serde(skip_serializing)]
+    requests_per_tier: RwLock<BTreeMap<usize, usize>>,
+    /// Requests that went to another peer because the best one was warming up
+    #[serde(skip_serializing)]
+    requests_deferred_by_warmup: AtomicUsize,
+    /// Retries given up because the request body did not fit the retry buffer
+    #[serde(skip_serializing)]
+    retries_skipped_by_body_size: AtomicUsize,
+    /// Indexed by the request priority
+    #[serde(skip_serializing)]
+    requests_waiting_for_permit: [AtomicUsize; RequestPriority::COUNT],
+    /// Kept after it finishes, so its progress can still be checked
+    #[serde(skip_serializing)]
+    rolling_drain: Mutex<Option<RollingDrain>>,
+    /// Requests whose peer became unusable between the selection and the connection
+    #[serde(skip_serializing)]
+    selections_invalidated: AtomicUsize,
+    #[serde(skip_serializing)]
+    slots_endpoint_disabled_policy: SlotsEndpointDisabledPolicy,
+    /// Status updates of a peer arriving sooner than this after the last applied one are
+    /// coalesced, see `UpstreamPeer::can_coalesce_status_update`
+    #[serde(skip_serializing)]
+    status_update_min_interval: Option<Duration>,
+    #[serde(skip_serializing)]
+    status_updates_coalesced: AtomicUsize,
+    #[serde(skip_serializing)]
+    tie_break_strategy: TieBreakStrategy,
+    /// Rotates the choice between peers with the same score
+    #[serde(skip_serializing)]
+    tie_breaker_cursor: AtomicUsize,
+    #[serde(skip_serializing)]
     pub upstream_slots_permits: Arc<Semaphore>,
+    #[serde(skip_serializing)]
+    warmup_period: Option<Duration>,
+    /// New peers stay unusable until `WarmupProbeService` confirms the model is serving
+    #[serde(skip_serializing)]
+    warmup_probe_enabled: bool,
 }
 
 impl UpstreamPeerPool {
-    pub fn new() -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        admission_rate_policy: Option<AdmissionRatePolicy>,
+        cooldown_policy: Option<CooldownPolicy>,
+        duplicate_agent_id_policy: DuplicateAgentIdPolicy,
+        error_penalty_policy: Option<ErrorPenaltyPolicy>,
+        flap_damping_policy: Option<FlapDampingPolicy>,
+        hardware_class_weights: BTreeMap<String, f64>,
+        max_permit_handoffs: usize,
+        placement_strategy: PlacementStrategy,
+        prefer_newest_model_version: bool,
+        slots_endpoint_disabled_policy: SlotsEndpointDisabledPolicy,
+        status_update_min_interval: Option<Duration>,
+        tie_break_strategy: TieBreakStrategy,
+        warmup_period: Option<Duration>,
+        warmup_probe_enabled: bool,
+    ) -> Self {
+        let (pool_events_tx, _pool_events_rx) = broadcast::channel(POOL_EVENTS_CAPACITY);
+
         UpstreamPeerPool {
+            admission_rate_policy,
             agents: RwLock::new(Vec::new()),
+            cooldown_policy,
+            duplicate_agent_id_policy,
+            error_penalty_policy,
+            excluded_agent_names: RwLock::new(Vec::new()),
+            flap_damping_policy,
+            hardware_class_weights,
+            max_permit_handoffs,
+            model_loading_responses: AtomicUsize::new(0),
+            next_connection_id: AtomicU64::new(0),
+            permit_handoffs: AtomicUsize::new(0),
+            permit_waiters_changed: Notify::new(),
+            permits_owed: AtomicUsize::new(0),
+            permits_returned: Notify::new(),
+            placement_strategy,
+            prefer_newest_model_version,
+            pool_events_tx,
+            #[cfg(feature = "statsd_reporter")]
+            requests_per_tier: RwLock::new(BTreeMap::new()),
+            requests_deferred_by_warmup: AtomicUsize::new(0),
+            requests_waiting_for_permit: Default::default(),
+            retries_skipped_by_body_size: AtomicUsize::new(0),
+            rolling_drain: Mutex::new(None),
+            selections_invalidated: AtomicUsize::new(0),
+            slots_endpoint_disabled_policy,
+            status_update_min_interval,
+            status_updates_coalesced: AtomicUsize::new(0),
+            tie_break_strategy,
+            tie_breaker_cursor: AtomicUsize::new(0),
             upstream_slots_permits: Arc::new(Semaphore::new(0)),
+            warmup_period,
+            warmup_probe_enabled,
         }
     }
 
-    pub fn quarantine_peer(&self, agent_id: &str) -> Result<bool> {
-        self.with_agents_write(|agents| {
-            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                peer.quarantined_until = Some(SystemTime::now() + Duration::from_secs(10));
+    /// Permits are handed out in the order the requests arrived, within the same priority. A
+    /// permit stands for an idle slot somewhere in the pool, not on a specific peer, so a
+    /// request that cannot use any of the idle slots (because of its labels or prompt size)
+    /// passes its permit to the next waiting request, and queues again once a slot is
+    /// released. It does that at most `max_permit_handoffs` times, so it is never starved:
+    /// after that it keeps the permit, and fails if there is still no peer for it.
+    pub async fn acquire_permit(
+        &self,
+        label_selectors: &[Label],
+        priority: RequestPriority,
+        prompt_tokens: Option<usize>,
+        slots: usize,
+        uses_slots: bool,
+    ) -> Result<OwnedSemaphorePermit> {
+        let _waiting_for_permit = WaitingForPermit::new(self, priority);
+        let mut handoffs = 0;
 
-                return Ok(true);
+        loop {
+            let permit = self.acquire_permit_in_order(priority, slots).await?;
+            let permits_returned = self.permits_returned.notified();
+
+            tokio::pin!(permits_returned);
+
+            // register before checking, so a release in between is not missed
+            permits_returned.as_mut().enable();
+
+            if handoffs >= self.max_permit_handoffs
+                || self.has_selectable_peer(label_selectors, prompt_tokens, uses_slots)?
+            {
+                return Ok(permit);
             }
 
-            Ok(false)
-        })
+            // dropping the permit hands it to the next request in the queue
+            drop(permit);
+            handoffs += 1;
+            self.permit_handoffs.fetch_add(1, Ordering::Relaxed);
+
+            permits_returned.await;
+        }
     }
 
-    pub fn register_status_update(
+    /// Semaphore hands out the permits in FIFO order, so the requests with lower priority stay
+    /// out of its queue while there are requests with higher priority waiting
+    async fn acquire_permit_in_order(
         &self,
-        agent_id: &str,
-        status_update: StatusUpdate,
-    ) -> Result<()> {
-        self.with_agents_write(|agents| {
-            if let Some(upstream_peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                let update_slots_count = status_update.idle_slots_count + status_update.processing_slots_count;
-                if update_slots_count > upstream_peer.slots_count() {
-                    let delta = update_slots_count  - upstream_peer.slots_count();
-                    self.upstream_slots_permits.add_permits(delta);
+        priority: RequestPriority,
+        slots: usize,
+    ) -> Result<OwnedSemaphorePermit> {
+        loop {
+            self.wait_for_higher_priority_requests(priority).await;
+
+            let permit = self
+                .upstream_slots_permits
+                .clone()
+                .acquire_many_owned(slots as u32);
+
+            tokio::pin!(permit);
+
+            loop {
+                let waiters_changed = self.permit_waiters_changed.notified();
+
+                tokio::pin!(waiters_changed);
+
+                waiters_changed.as_mut().enable();
+
+                if self.has_higher_priority_waiters(priority) {
+                    // give way to the requests that started waiting in the meantime
+                    break;
                 }
 
-                if upstream_peer.slots_idle > status_update.idle_slots_count {
-                    let delta = upstream_peer.slots_idle - status_update.idle_slots_count;
-                    self.upstream_slots_permits.forget_permits(delta);
+                tokio::select! {
+                    permit = &mut permit => return Ok(permit?),
+                    _ = &mut waiters_changed => {}
                 }
+            }
+        }
+    }
 
-                upstream_peer.update_status(status_update);
+    pub fn try_acquire_permit(&self, slots: usize) -> Option<OwnedSemaphorePermit> {
+        self.upstream_slots_permits
+            .clone()
+            .try_acquire_many_owned(slots as u32)
+            .ok()
+    }
+
+    /// Permits are only available for the idle slots that are not already promised to
+    /// other requests, so this does not count the same slot twice
+    pub fn is_saturated(&self, max_queued_requests: usize) -> bool {
+        self.upstream_slots_permits.available_permits() == 0
+            && self
+                .requests_waiting_for_permit
+                .iter()
+                .map(|waiting| waiting.load(Ordering::Relaxed))
+                .sum::<usize>()
+                >= max_queued_requests
+    }
+
+    pub fn subscribe_events(&self) -> Receiver<PoolEvent> {
+        self.pool_events_tx.subscribe()
+    }
+
+    pub fn has_usable_peers(&self) -> Result<bool> {
+        self.with_agents_read(|agents| Ok(agents.iter().any(|peer| peer.is_usable())))
+    }
+
+    /// Admission rate is not checked, requests wait for the admission after they get a permit
+    pub fn has_selectable_peer(
+        &self,
+        label_selectors: &[Label],
+        prompt_tokens: Option<usize>,
+        uses_slots: bool,
+    ) -> Result<bool> {
+        self.with_agents_read(|agents| {
+            Ok(agents.iter().any(|peer| {
+                Self::is_eligible(peer, label_selectors, prompt_tokens)
+                    && self.is_selectable(peer, uses_slots)
+            }))
+        })
+    }
+
+    pub fn agent_name(&self, agent_id: &str) -> Result<Option<String>> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .find(|peer| peer.agent_id == agent_id)
+                .and_then(|peer| peer.agent_name.to_owned()))
+        })
+    }
+
+    /// Returns the number of all the peers, and of the ones that can take a request right now
+    pub fn peers_count(&self) -> Result<(usize, usize)> {
+        self.with_agents_read(|agents| {
+            Ok((
+                agents.len(),
+                agents.iter().filter(|peer| peer.is_usable()).count(),
+            ))
+        })
+    }
+
+    pub fn requests_waiting_for_permit(&self) -> usize {
+        self.requests_waiting_for_permit
+            .iter()
+            .map(|waiting| waiting.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Checks all the agents, even the busy ones, since the request can wait for them
+    pub fn fits_in_context(&self, prompt_tokens: usize) -> Result<bool> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .any(|peer| peer.fits_in_context(prompt_tokens)))
+        })
+    }
+
+    /// Returns None if at least one agent matches all the selectors, otherwise the selectors
+    /// that no agent matches (or all of them, if only their combination is not matched)
+    pub fn unmatched_label_selectors(&self, label_selectors: &[Label]) -> Result<Option<Vec<Label>>> {
+        self.with_agents_read(|agents| {
+            if agents.iter().any(|peer| peer.matches_labels(label_selectors)) {
+                return Ok(None);
+            }
+
+            let unmatched: Vec<Label> = label_selectors
+                .iter()
+                .filter(|label_selector| {
+                    !agents
+                        .iter()
+                        .any(|peer| label_selector.matches(&peer.labels))
+                })
+                .cloned()
+                .collect();
+
+            if unmatched.is_empty() {
+                Ok(Some(label_selectors.to_vec()))
             } else {
-                let new_upstream_peer =
-                    UpstreamPeer::new_from_status_update(agent_id.to_string(), status_update);
-                self.upstream_slots_permits.add_permits(new_upstream_peer.slots_count());
-                agents.push(new_upstream_peer);
+                Ok(Some(unmatched))
             }
+        })
+    }
 
-            agents.sort();
+    pub fn audit(&self) -> Result<PoolAudit> {
+        self.with_agents_read(|agents| {
+            let mut pool_audit = PoolAudit {
+                available_permits: self.upstream_slots_permits.available_permits(),
+                expected_permits: 0,
+                peers_in_flight: vec![],
+            };
+            // a peer can hold more permits than it has slots, see `remove_peer_at`
+            let mut permits_held = 0;
 
-            Ok(())
+            for peer in agents.iter() {
+                permits_held += peer
+                    .slots_permissions
+                    .as_ref()
+                    .map_or(0, |permits| permits.num_permits());
+                pool_audit.expected_permits += peer.slots_count();
+
+                if peer.requests_in_flight > 0 || peer.slots_processing > 0 {
+                    pool_audit.peers_in_flight.push(PeerInFlight {
+                        agent_id: peer.agent_id.clone(),
+                        agent_name: peer.agent_name.clone(),
+                        requests_in_flight: peer.requests_in_flight,
+                        slots_processing: peer.slots_processing,
+                    });
+                }
+            }
+
+            pool_audit.expected_permits = pool_audit.expected_permits.saturating_sub(permits_held);
+
+            Ok(pool_audit)
         })
     }
 
-    pub fn release_slot(&self, agent_id: &str, last_update: SystemTime) -> Result<bool> {
-        self.with_agents_write(|agents| {
-            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                if peer.last_update < last_update {
-                    // edge case, but no need to update anything anyway
-                    return Ok(false);
+    #[cfg(any(test, feature = "pool_inspection"))]
+    pub fn inspect(&self) -> Result<PoolInspection> {
+        self.with_agents_read(|agents| {
+            let mut pool_inspection = PoolInspection {
+                available_permits: self.upstream_slots_permits.available_permits(),
+                peers_count: agents.len(),
+                quarantined_agent_ids: vec![],
+                requests_in_flight: 0,
+                requests_waiting_for_permit: self.requests_waiting_for_permit(),
+                slots_idle: 0,
+                slots_processing: 0,
+            };
+
+            for peer in agents.iter() {
+                if peer.quarantined_until.is_some() {
+                    pool_inspection
+                        .quarantined_agent_ids
+                        .push(peer.agent_id.clone());
                 }
 
-                peer.release_slot();
+                pool_inspection.requests_in_flight += peer.requests_in_flight;
+                pool_inspection.slots_idle += peer.slots_idle;
+                pool_inspection.slots_processing += peer.slots_processing;
+            }
+
+            Ok(pool_inspection)
+        })
+    }
+
+    /// The peer might have reported an error, or got quarantined, drained, or removed, after it
+    /// was selected for the request
+    pub fn is_selection_invalidated(&self, agent_id: &str) -> Result<bool> {
+        let is_selection_invalidated = self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .find(|peer| peer.agent_id == agent_id)
+                .is_none_or(|peer| {
+                    peer.error.is_some()
+                        || peer.quarantined_until.is_some()
+                        || peer.model_loading_until.is_some()
+                        || peer.is_draining
+                }))
+        })?;
+
+        if is_selection_invalidated {
+            self.selections_invalidated.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(is_selection_invalidated)
+    }
+
+    /// Agents can be referred to either by their id or their name
+    pub fn has_peer(&self, agent_id_or_name: &str) -> Result<bool> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .any(|peer| peer.is_referred_to_as(agent_id_or_name)))
+        })
+    }
+
+    /// Ignores the probes that were sent before llama.cpp restarted
+    pub fn mark_warmed_up(&self, agent_id: &str, restart_epoch: u64) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents
+                .iter_mut()
+                .find(|p| p.agent_id == agent_id && p.restart_epoch == restart_epoch)
+            {
+                if !peer.warmed_up {
+                    info!("Agent {} passed the warm-up probe", agent_id);
+
+                    peer.warmed_up = true;
+                    agents.sort();
+                }
 
                 return Ok(true);
             }
@@ -86,105 +488,2347 @@ impl UpstreamPeerPool {
         })
     }
 
-    pub fn remove_peer(&self, agent_id: &str) -> Result<()> {
-        self.with_agents_write(|agents| {
-            if let Some(pos) = agents.iter().position(|p| p.agent_id == agent_id) {
-                let slots_count = agents[pos].slots_count();
-                agents.remove(pos);
-                self.upstream_slots_permits.forget_permits(slots_count);
-            }
-            Ok(())
+    /// Peers quarantined after a timed out probe are not probed until the quarantine ends
+    pub fn peers_waiting_for_warmup_probe(&self) -> Result<Vec<UpstreamPeerInfo>> {
+        let now = SystemTime::now();
+
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .filter(|peer| {
+                    !peer.warmed_up
+                        && peer
+                            .quarantine_held_until
+                            .is_none_or(|quarantine_held_until| quarantine_held_until <= now)
+                })
+                .map(UpstreamPeer::info)
+                .collect())
         })
     }
 
-    pub fn restore_integrity(&self) -> Result<()> {
-        self.with_agents_write(|agents| {
+    pub fn advance_rolling_drain(&self) -> Result<()> {
+        let mut rolling_drain = self.lock_rolling_drain();
+
+        let Some(rolling_drain) = rolling_drain.as_mut() else {
+            return Ok(());
+        };
+
+        if rolling_drain.is_finished() {
+            return Ok(());
+        }
+
+        let just_drained = self.with_agents_write(|agents| {
+            let just_drained = rolling_drain.advance(agents);
+
             agents.sort();
 
-            Ok(())
+            Ok(just_drained)
+        })?;
+
+        for agent_id in just_drained {
+            info!("Agent {} is drained and can be restarted", agent_id);
+
+            self.emit(PoolEvent::PeerDrained { agent_id });
+        }
+
+        if rolling_drain.is_finished() {
+            info!("Rolling drain finished");
+        }
+
+        Ok(())
+    }
+
+    /// Returns false if there is no rolling drain in progress
+    pub fn cancel_rolling_drain(&self) -> Result<bool> {
+        let mut rolling_drain = self.lock_rolling_drain();
+
+        match rolling_drain.take() {
+            Some(cancelled) if !cancelled.is_finished() => {
+                self.with_agents_write(|agents| {
+                    cancelled.cancel(agents);
+                    agents.sort();
+
+                    Ok(())
+                })?;
+
+                Ok(true)
+            }
+            finished => {
+                *rolling_drain = finished;
+
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn rolling_drain(&self) -> Result<Option<RollingDrain>> {
+        Ok(self.lock_rolling_drain().to_owned())
+    }
+
+    /// Returns None if another rolling drain is still in progress. Unknown agents are skipped.
+    pub fn start_rolling_drain(
+        &self,
+        agent_ids: Option<Vec<String>>,
+        concurrency: usize,
+    ) -> Result<Option<RollingDrain>> {
+        let mut rolling_drain = self.lock_rolling_drain();
+
+        if rolling_drain
+            .as_ref()
+            .is_some_and(|rolling_drain| !rolling_drain.is_finished())
+        {
+            return Ok(None);
+        }
+
+        let agent_ids: Vec<String> = self.with_agents_read(|agents| {
+            Ok(match agent_ids {
+                Some(agent_ids) => agent_ids
+                    .into_iter()
+                    .filter(|agent_id| agents.iter().any(|peer| &peer.agent_id == agent_id))
+                    .collect(),
+                None => agents.iter().map(|peer| peer.agent_id.to_owned()).collect(),
+            })
+        })?;
+
+        info!("Starting rolling drain of {} agents", agent_ids.len());
+
+        *rolling_drain = Some(RollingDrain::new(agent_ids, concurrency));
+
+        Ok(rolling_drain.to_owned())
+    }
+
+    pub fn quarantine_peer(&self, agent_id: &str) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                peer.quarantined_until = Some(SystemTime::now() + Duration::from_secs(10));
+
+                self.emit(PoolEvent::PeerQuarantined {
+                    agent_id: agent_id.to_string(),
+                    reason: "connect_failed",
+                });
+
+                return Ok(true);
+            }
+
+            Ok(false)
         })
     }
 
-    pub fn take_slot(&self, agent_id: &str) -> Result<bool> {
+    /// Loading the model is expected after llama.cpp starts, so unlike the quarantine, the peer
+    /// is not considered broken, and the status updates do not lift it early
+    pub fn mark_model_loading(&self, agent_id: &str, duration: Duration) -> Result<bool> {
+        self.model_loading_responses.fetch_add(1, Ordering::Relaxed);
+
         self.with_agents_write(|agents| {
             if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                peer.take_slot();
+                info!(
+                    "Agent {} is still loading the model, skipping it for {:?}",
+                    agent_id, duration
+                );
 
-                Ok(true)
-            } else {
-                Ok(false)
+                peer.model_loading_until = Some(SystemTime::now() + duration);
+                agents.sort();
+
+                return Ok(true);
             }
+
+            Ok(false)
         })
     }
 
-    pub fn store_permit(&self, agent_id: &str, permit: OwnedSemaphorePermit) -> Result<bool> {
+    pub fn register_retry_skipped_by_body_size(&self) {
+        self.retries_skipped_by_body_size
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Unlike the quarantine after a failed request, the status updates do not lift it early
+    pub fn quarantine_peer_for(
+        &self,
+        agent_id: &str,
+        duration: Duration,
+        reason: &'static str,
+    ) -> Result<bool> {
         self.with_agents_write(|agents| {
             if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                peer.store_permit(permit);
-                Ok(true)
-            } else {
-                Ok(false)
+                let quarantined_until = SystemTime::now() + duration;
+
+                info!("Agent {} is quarantined for {:?}", agent_id, duration);
+
+                peer.quarantine_held_until = Some(quarantined_until);
+                peer.quarantined_until = Some(quarantined_until);
+
+                self.emit(PoolEvent::PeerQuarantined {
+                    agent_id: agent_id.to_string(),
+                    reason,
+                });
+
+                return Ok(true);
             }
+
+            Ok(false)
         })
     }
 
-    pub fn release_one_permit(&self, agent_id: &str) -> Result<()> {
+    pub fn register_error(&self, agent_id: &str) -> Result<()> {
         self.with_agents_write(|agents| {
             if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
-                peer.release_permits(1);
+                peer.history.register_error();
+
+                if let Some(error_penalty_policy) = &self.error_penalty_policy {
+                    peer.register_error(error_penalty_policy);
+                    agents.sort();
+                }
             }
+
             Ok(())
         })
     }
 
-    #[cfg(feature = "statsd_reporter")]
-    // returns (slots_idle, slots_processing) tuple
-    pub fn total_slots(&self) -> Result<(usize, usize)> {
+    /// None if there is no such peer
+    pub fn history(
+        &self,
+        agent_id: &str,
+        since: SystemTime,
+    ) -> Result<Option<Vec<PeerHistorySample>>> {
         self.with_agents_read(|agents| {
-            let mut slots_idle = 0;
-            let mut slots_processing = 0;
+            Ok(agents
+                .iter()
+                .find(|peer| peer.agent_id == agent_id)
+                .map(|peer| peer.history.samples_since(since)))
+        })
+    }
 
-            for peer in agents.iter() {
-                slots_idle += peer.slots_idle;
-                slots_processing += peer.slots_processing;
+    pub fn register_response_status(&self, agent_id: &str, status: u16) -> Result<()> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                peer.response_status_counts.register(status);
             }
 
-            Ok((slots_idle, slots_processing))
+            Ok(())
         })
     }
 
-    pub fn use_best_peer(&self) -> Result<Option<UpstreamPeerInfo>> {
+    /// Only counted, `register_error` is what makes the peer less preferred
+    pub fn register_stream_error(&self, agent_id: &str) -> Result<()> {
         self.with_agents_write(|agents| {
-            for peer in agents.iter_mut() {
-                if peer.is_usable() {
-                    return Ok(Some(peer.info()));
-                }
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                peer.response_status_counts.register_stream_error();
             }
 
-            return Ok(None);
+            Ok(())
         })
     }
 
-    #[cfg(feature = "statsd_reporter")]
-    #[inline]
-    fn with_agents_read<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
-    where
-        TCallback: FnOnce(&Vec<UpstreamPeer>) -> Result<TResult>,
-    {
-        match self.agents.read() {
-            Ok(agents) => cb(&agents),
-            Err(_) => Err("Failed to acquire read lock".into()),
-        }
+    /// Connection ids grow with every connection, so the most recent registration of the
+    /// agent always has the highest one
+    pub fn next_connection_id(&self) -> u64 {
+        self.next_connection_id.fetch_add(1, Ordering::Relaxed)
     }
 
-    #[inline]
-    fn with_agents_write<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
-    where
-        TCallback: FnOnce(&mut Vec<UpstreamPeer>) -> Result<TResult>,
-    {
-        match self.agents.write() {
-            Ok(mut agents) => cb(&mut agents),
-            Err(_) => Err("Failed to acquire write lock".into()),
+    /// Returns false if the status update is rejected because another connection registered
+    /// the same agent id
+    pub fn register_status_update(
+        &self,
+        agent_id: &str,
+        connection_id: u64,
+        mut status_update: StatusUpdate,
+    ) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(pos) = agents.iter().position(|p| {
+                p.agent_id == agent_id
+                    && !p.is_static
+                    && p.stale_until.is_none()
+                    && p.connection_id != Some(connection_id)
+            }) {
+                let is_newer = agents[pos]
+                    .connection_id
+                    .is_none_or(|existing_connection_id| connection_id > existing_connection_id);
+
+                if !is_newer || self.duplicate_agent_id_policy == DuplicateAgentIdPolicy::Reject {
+                    warn!(
+                        "Rejecting status update of agent {}, the id is registered by another connection",
+                        agent_id
+                    );
+
+                    return Ok(false);
+                }
+
+                warn!(
+                    "Agent {} registered again, replacing the previous registration",
+                    agent_id
+                );
+
+                self.remove_peer_at(agents, pos, "replaced");
+            }
+
+            if !agents.iter().any(|p| p.agent_id == agent_id) {
+                self.supersede_peers(agents, agent_id, &status_update);
+            }
+
+            if let Some(status_update_min_interval) = self.status_update_min_interval {
+                if let Some(upstream_peer) = agents.iter_mut().find(|p| {
+                    p.agent_id == agent_id
+                        && p.can_coalesce_status_update(&status_update, status_update_min_interval)
+                }) {
+                    upstream_peer.coalesce_status_update();
+                    self.status_updates_coalesced
+                        .fetch_add(1, Ordering::Relaxed);
+
+                    // nothing changed, so the pool does not need sorting
+                    return Ok(true);
+                }
+            }
+
+            let existing_peer = agents.iter_mut().find(|p| p.agent_id == agent_id);
+
+            if let SlotsEndpointDisabledPolicy::AssumeCapacity(assumed_capacity) =
+                self.slots_endpoint_disabled_policy
+            {
+                if matches!(status_update.is_slots_endpoint_enabled, Some(false)) {
+                    // prefer the capacity detected by the agent over the assumed one
+                    let capacity = status_update
+                        .model_info
+                        .as_ref()
+                        .and_then(|model_info| model_info.total_slots)
+                        .unwrap_or(assumed_capacity);
+                    // llama.cpp does not report the slots, so the requests the balancer has in
+                    // progress are all there is
+                    let slots_processing = existing_peer
+                        .as_ref()
+                        .map_or(0, |peer| peer.requests_in_flight.min(capacity));
+
+                    // llama.cpp only responds with 501 after the request passes authorization
+                    status_update.is_authorized.get_or_insert(true);
+                    status_update.idle_slots_count = capacity - slots_processing;
+                    status_update.processing_slots_count = slots_processing;
+                }
+            }
+
+            if let Some(upstream_peer) = existing_peer {
+                if let Some(flap_damping_policy) = &self.flap_damping_policy {
+                    let was_frozen = upstream_peer.frozen_slots_total.is_some();
+
+                    upstream_peer.damp_capacity(flap_damping_policy, &mut status_update);
+
+                    match (was_frozen, upstream_peer.frozen_slots_total) {
+                        (false, Some(frozen_slots_total)) => warn!(
+                            "Agent {} keeps changing its reported slots, holding them at {}",
+                            agent_id, frozen_slots_total
+                        ),
+                        (true, None) => info!("Agent {} reports stable slots again", agent_id),
+                        _ => {}
+                    }
+                }
+
+                let update_slots_count = status_update.idle_slots_count + status_update.processing_slots_count;
+                if update_slots_count > upstream_peer.slots_count() {
+                    let delta = update_slots_count  - upstream_peer.slots_count();
+                    self.upstream_slots_permits.add_permits(delta);
+                }
+
+                // Slots that only look busy keep their permits. A report from before the slot
+                // was taken gives its permit back early, and forgetting one when llama.cpp
+                // reports the slot processing would lose it for good.
+                if upstream_peer.slots_count() > update_slots_count {
+                    let delta = upstream_peer.slots_count() - update_slots_count;
+                    let permits_forgotten = self.upstream_slots_permits.forget_permits(delta);
+
+                    self.permits_owed
+                        .fetch_add(delta - permits_forgotten, Ordering::Relaxed);
+                }
+
+                let was_quarantined = upstream_peer.quarantined_until.is_some();
+
+                if upstream_peer.restart_epoch != status_update.restart_epoch {
+                    info!(
+                        "Agent {} reported llama.cpp restart, resetting its slots",
+                        agent_id
+                    );
+
+                    // the model is loading again
+                    if self.warmup_probe_enabled {
+                        upstream_peer.warmed_up = false;
+                    }
+                }
+
+                if upstream_peer.stale_until.is_some() {
+                    info!("Agent {} confirmed its restored registration", agent_id);
+
+                    upstream_peer.connection_id = Some(connection_id);
+                    upstream_peer.stale_until = None;
+                }
+
+                upstream_peer.update_status(status_update);
+
+                if was_quarantined && upstream_peer.quarantined_until.is_none() {
+                    if let Some(warmup_period) = self.warmup_period {
+                        upstream_peer.start_warmup(warmup_period);
+                    }
+
+                    self.emit(PoolEvent::PeerRecovered {
+                        agent_id: agent_id.to_string(),
+                    });
+                }
+            } else {
+                let mut new_upstream_peer = UpstreamPeer::new_from_status_update(
+                    agent_id.to_string(),
+                    connection_id,
+                    status_update,
+                );
+                if let Some(warmup_period) = self.warmup_period {
+                    new_upstream_peer.start_warmup(warmup_period);
+                }
+
+                new_upstream_peer.warmed_up = !self.warmup_probe_enabled;
+
+                self.upstream_slots_permits.add_permits(new_upstream_peer.slots_count());
+                agents.push(new_upstream_peer);
+
+                self.emit(PoolEvent::PeerAdded {
+                    agent_id: agent_id.to_string(),
+                });
+            }
+
+            agents.sort();
+            self.settle_owed_permits();
+            // the report might have freed the slots that the requests passing on their permits
+            // are waiting for
+            self.permits_returned.notify_waiters();
+
+            Ok(true)
+        })
+    }
+
+    pub fn release_slot(
+        &self,
+        agent_id: &str,
+        last_update: SystemTime,
+        restart_epoch: u64,
+        slots: usize,
+    ) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                peer.history.register_request_completed();
+
+                if peer.last_update < last_update {
+                    // edge case, but no need to update anything anyway
+                    return Ok(false);
+                }
+
+                if peer.restart_epoch != restart_epoch {
+                    // slot was already reset when llama.cpp restarted
+                    return Ok(false);
+                }
+
+                for _ in 0..slots {
+                    peer.release_slot();
+                }
+
+                self.emit(PoolEvent::SlotReleased {
+                    agent_id: agent_id.to_string(),
+                });
+
+                return Ok(true);
+            }
+
+            warn!(
+                "Agent {} left the pool while a request was in progress, its permits went with it",
+                agent_id
+            );
+
+            Ok(false)
+        })
+    }
+
+    /// Restored peers that no agent confirms until `ttl` passes get evicted
+    pub fn restore_snapshot(&self, pool_snapshot: PoolSnapshot, ttl: Duration) -> Result<()> {
+        let stale_until = SystemTime::now() + ttl;
+
+        self.with_agents_write(|agents| {
+            for peer_snapshot in pool_snapshot.peers {
+                if agents.iter().any(|p| {
+                    p.agent_id == peer_snapshot.agent_id
+                        || p.external_llamacpp_addr == peer_snapshot.external_llamacpp_addr
+                }) {
+                    continue;
+                }
+
+                let upstream_peer = UpstreamPeer::new_from_peer_snapshot(peer_snapshot, stale_until);
+
+                info!("Restoring agent: {}", upstream_peer.agent_id);
+
+                self.upstream_slots_permits
+                    .add_permits(upstream_peer.slots_count());
+                self.emit(PoolEvent::PeerAdded {
+                    agent_id: upstream_peer.agent_id.clone(),
+                });
+
+                agents.push(upstream_peer);
+            }
+
+            agents.sort();
+
+            Ok(())
+        })
+    }
+
+    /// Addresses that are already known are left alone, agents reporting from a discovered
+    /// address replace the placeholder (see `supersede_peers`)
+    pub fn reconcile_discovered_peers(&self, discovered_addrs: &[SocketAddr]) -> Result<()> {
+        self.with_agents_write(|agents| {
+            while let Some(pos) = agents.iter().position(|p| {
+                p.is_discovered && !discovered_addrs.contains(&p.external_llamacpp_addr)
+            }) {
+                info!(
+                    "Address {} disappeared from DNS, removing it",
+                    agents[pos].external_llamacpp_addr
+                );
+
+                self.remove_peer_at(agents, pos, "undiscovered");
+            }
+
+            for discovered_addr in discovered_addrs {
+                if agents
+                    .iter()
+                    .any(|p| p.external_llamacpp_addr == *discovered_addr)
+                {
+                    continue;
+                }
+
+                info!("Discovered address {} in DNS", discovered_addr);
+
+                let upstream_peer = UpstreamPeer::new_from_discovered_addr(*discovered_addr);
+
+                self.emit(PoolEvent::PeerAdded {
+                    agent_id: upstream_peer.agent_id.clone(),
+                });
+
+                agents.push(upstream_peer);
+            }
+
+            agents.sort();
+
+            Ok(())
+        })
+    }
+
+    pub fn evict_stale_peers(&self) -> Result<()> {
+        let now = SystemTime::now();
+
+        self.with_agents_write(|agents| {
+            while let Some(pos) = agents
+                .iter()
+                .position(|p| p.stale_until.is_some_and(|stale_until| stale_until < now))
+            {
+                warn!(
+                    "Agent {} did not report after the restart, evicting it",
+                    agents[pos].agent_id
+                );
+
+                self.remove_peer_at(agents, pos, "stale");
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The token quotas are not part of the pool, see `PoolSnapshotService`
+    pub fn snapshot(&self) -> Result<PoolSnapshot> {
+        self.with_agents_read(|agents| {
+            Ok(PoolSnapshot {
+                peers: agents
+                    .iter()
+                    .filter(|p| !p.is_static && !p.is_discovered)
+                    .map(PeerSnapshot::new_from_upstream_peer)
+                    .collect(),
+                token_quotas: BTreeMap::new(),
+            })
+        })
+    }
+
+    pub fn register_static_peers(&self, static_peers_config: StaticPeersConfig) -> Result<()> {
+        self.with_agents_write(|agents| {
+            for static_peer_config in static_peers_config.peers {
+                let upstream_peer = UpstreamPeer::new_from_static_peer_config(static_peer_config);
+
+                info!("Registering static agent: {}", upstream_peer.agent_id);
+
+                self.upstream_slots_permits
+                    .add_permits(upstream_peer.slots_count());
+                self.emit(PoolEvent::PeerAdded {
+                    agent_id: upstream_peer.agent_id.clone(),
+                });
+
+                agents.push(upstream_peer);
+            }
+
+            agents.sort();
+
+            Ok(())
+        })
+    }
+
+    /// Returns false if the peer was already reachable
+    pub fn mark_static_peer_reachable(&self, agent_id: &str) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents
+                .iter_mut()
+                .find(|p| p.agent_id == agent_id && p.is_static && p.is_unreachable)
+            {
+                peer.error = None;
+                peer.is_unreachable = false;
+                agents.sort();
+
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    /// Returns false if the peer was already unreachable
+    pub fn mark_static_peer_unreachable(&self, agent_id: &str, error: String) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents
+                .iter_mut()
+                .find(|p| p.agent_id == agent_id && p.is_static && !p.is_unreachable)
+            {
+                peer.error = Some(error);
+                peer.is_unreachable = true;
+                agents.sort();
+
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    /// All the static peers, or only the ones that were found unreachable
+    pub fn static_peers_to_probe(&self, only_unreachable: bool) -> Result<Vec<UpstreamPeerInfo>> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .filter(|peer| peer.is_static && (peer.is_unreachable || !only_unreachable))
+                .map(UpstreamPeer::info)
+                .collect())
+        })
+    }
+
+    /// Agent comes back with its next status update, unless it was stopped in the meantime;
+    /// static peers are never removed
+    pub fn evict_peer(&self, agent_id: &str) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(pos) = agents
+                .iter()
+                .position(|p| p.agent_id == agent_id && !p.is_static)
+            {
+                info!("Evicting agent {}", agent_id);
+
+                self.remove_peer_at(agents, pos, "evicted");
+
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    /// Only removes the peer if it is still registered by the given connection, it might have
+    /// been replaced by a newer one in the meantime
+    pub fn remove_peer(&self, agent_id: &str, connection_id: u64) -> Result<()> {
+        self.with_agents_write(|agents| {
+            if let Some(pos) = agents.iter().position(|p| {
+                p.agent_id == agent_id && !p.is_static && p.connection_id == Some(connection_id)
+            }) {
+                self.remove_peer_at(agents, pos, "disconnected");
+            }
+            Ok(())
+        })
+    }
+
+    pub fn restore_integrity(&self) -> Result<()> {
+        self.with_agents_write(|agents| {
+            agents.sort();
+
+            Ok(())
+        })
+    }
+
+    /// Takes effect for the next selection, the excluded peers keep their state
+    pub fn set_excluded_agent_names(&self, excluded_agent_names: Vec<AgentNamePattern>) {
+        match self.excluded_agent_names.write() {
+            Ok(mut current) => *current = excluded_agent_names,
+            Err(poisoned) => *poisoned.into_inner() = excluded_agent_names,
+        }
+    }
+
+    pub fn set_max_concurrency(
+        &self,
+        agent_id: &str,
+        max_concurrency: Option<usize>,
+    ) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                info!(
+                    "Setting max concurrency of agent {} to {:?}",
+                    agent_id, max_concurrency
+                );
+
+                peer.set_max_concurrency_override(max_concurrency);
+                agents.sort();
+
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    pub fn set_upstream_headers(
+        &self,
+        agent_id: &str,
+        upstream_headers: Option<BTreeMap<String, String>>,
+    ) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                // the values can be credentials, so only the names are logged
+                info!(
+                    "Setting upstream headers of agent {} to {:?}",
+                    agent_id,
+                    upstream_headers
+                        .as_ref()
+                        .map(|upstream_headers| upstream_headers.keys().collect::<Vec<_>>())
+                );
+
+                peer.set_upstream_headers_override(upstream_headers);
+
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    /// Returns false if the peer left the pool or llama.cpp restarted since `restart_epoch` was
+    /// selected, the request would never release the slot then
+    pub fn take_slot(&self, agent_id: &str, restart_epoch: u64, slots: usize) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents
+                .iter_mut()
+                .find(|p| p.agent_id == agent_id && p.restart_epoch == restart_epoch)
+            {
+                for _ in 0..slots {
+                    peer.take_slot();
+                }
+
+                if let Some(cooldown_policy) = &self.cooldown_policy {
+                    peer.register_request(cooldown_policy);
+                }
+
+                self.emit(PoolEvent::SlotTaken {
+                    agent_id: agent_id.to_string(),
+                });
+
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        })
+    }
+
+    pub fn store_permit(
+        &self,
+        agent_id: &str,
+        restart_epoch: u64,
+        permit: OwnedSemaphorePermit,
+    ) -> Result<bool> {
+        self.with_agents_write(|agents| {
+            if let Some(peer) = agents
+                .iter_mut()
+                .find(|p| p.agent_id == agent_id && p.restart_epoch == restart_epoch)
+            {
+                peer.store_permit(permit);
+                Ok(true)
+            } else {
+                // the peer left the pool with the permits of its idle slots, or dropped the
+                // permits of its requests when llama.cpp restarted, and the permit stands for a
+                // slot of the other peers then, unless the removal still owes it
+                drop(permit);
+                self.settle_owed_permits();
+
+                Ok(false)
+            }
+        })
+    }
+
+    pub fn release_permits(&self, agent_id: &str, restart_epoch: u64, slots: usize) -> Result<()> {
+        self.with_agents_write(|agents| {
+            // permits of the peers that left the pool went with them, see `remove_peer_at`
+            if let Some(peer) = agents.iter_mut().find(|p| p.agent_id == agent_id) {
+                // permits were already released when llama.cpp restarted
+                if peer.restart_epoch == restart_epoch {
+                    peer.release_permits(slots);
+                    self.permits_returned.notify_waiters();
+                }
+            }
+
+            self.settle_owed_permits();
+
+            Ok(())
+        })
+    }
+
+    /// Most slots of a single agent the request can go to, busy or not
+    pub fn max_peer_slots(
+        &self,
+        label_selectors: &[Label],
+        prompt_tokens: Option<usize>,
+    ) -> Result<usize> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .filter(|peer| Self::is_eligible(peer, label_selectors, prompt_tokens))
+                .map(UpstreamPeer::slots_count)
+                .max()
+                .unwrap_or(0))
+        })
+    }
+
+    // records the current slots of every agent for the dashboard sparklines
+    pub fn take_history_samples(&self) -> Result<()> {
+        self.with_agents_write(|agents| {
+            for peer in agents.iter_mut() {
+                peer.history
+                    .take_sample(peer.slots_idle, peer.slots_processing);
+            }
+
+            Ok(())
+        })
+    }
+
+    // returns (slots_idle, slots_processing) tuple
+    pub fn total_slots(&self) -> Result<(usize, usize)> {
+        self.with_agents_read(|agents| {
+            let mut slots_idle = 0;
+            let mut slots_processing = 0;
+
+            for peer in agents.iter() {
+                slots_idle += peer.slots_idle;
+                slots_processing += peer.slots_processing;
+            }
+
+            Ok((slots_idle, slots_processing))
+        })
+    }
+
+    /// Goes through the same steps as `use_best_peer`, but does not take the admission token,
+    /// or advance the round robin
+    pub fn explain_routing(
+        &self,
+        label_selectors: &[Label],
+        priority: RequestPriority,
+        prompt_tokens: Option<usize>,
+        request_hash: u64,
+        slots: usize,
+        uses_slots: bool,
+    ) -> Result<RoutingExplanation> {
+        self.with_agents_write(|agents| {
+            // only brings the states that depend on the time up to date, as any request would
+            self.refresh_for_selection(agents);
+
+            let selection = self.select(
+                agents,
+                &SelectionCriteria {
+                    label_selectors,
+                    prompt_tokens,
+                    skipped_agent_ids: &[],
+                    slots,
+                    uses_slots,
+                },
+                request_hash,
+                false,
+            );
+
+            Ok(self.explain_selection(agents, selection, priority))
+        })
+    }
+
+    fn explain_selection(
+        &self,
+        agents: &[UpstreamPeer],
+        selection: Selection,
+        priority: RequestPriority,
+    ) -> RoutingExplanation {
+        let candidates = selection
+            .selection_order
+            .iter()
+            .enumerate()
+            .map(|(rank, &pos)| {
+                let peer = &agents[pos];
+
+                RoutingCandidate {
+                    agent_id: peer.agent_id.to_owned(),
+                    agent_name: peer.agent_name.to_owned(),
+                    rank,
+                    score: RoutingScore::from(peer),
+                    status: selection.statuses[pos],
+                    unusable_reasons: peer.unusable_reasons(),
+                }
+            })
+            .collect();
+
+        RoutingExplanation {
+            candidates,
+            selected_agent_id: selection
+                .selected_pos
+                .map(|pos| agents[pos].agent_id.to_owned()),
+            waits_for_permit: self.upstream_slots_permits.available_permits() == 0
+                || self.has_higher_priority_waiters(priority),
+        }
+    }
+
+    /// Pure over the given peers, which are expected to be refreshed and sorted already, apart
+    /// from the round robin, which moves on if `advance` is set
+    fn select(
+        &self,
+        agents: &[UpstreamPeer],
+        criteria: &SelectionCriteria,
+        request_hash: u64,
+        advance: bool,
+    ) -> Selection {
+        let mut statuses: Vec<CandidateStatus> = agents
+            .iter()
+            .map(|peer| self.candidate_status(peer, criteria))
+            .collect();
+
+        // otherwise llama.cpp queues the prompts that do not fit on the best peer
+        if criteria.slots > 1
+            && agents.iter().zip(&statuses).any(|(peer, status)| {
+                matches!(
+                    status,
+                    CandidateStatus::AdmissionDeferred | CandidateStatus::Eligible
+                ) && peer.slots_idle >= criteria.slots
+            })
+        {
+            for (peer, status) in agents.iter().zip(statuses.iter_mut()) {
+                if matches!(
+                    status,
+                    CandidateStatus::AdmissionDeferred | CandidateStatus::Eligible
+                ) && peer.slots_idle < criteria.slots
+                {
+                    *status = CandidateStatus::NotEnoughIdleSlots;
+                }
+            }
+        }
+
+        let selection_order = self.selection_order(agents);
+        let best_rank = selection_order
+            .iter()
+            .position(|pos| statuses[*pos] == CandidateStatus::Eligible);
+        let mut selected_pos = None;
+
+        if let Some(best_rank) = best_rank {
+            let best_pos = selection_order[best_rank];
+            // peers are sorted, so the ones scoring the same as the best one are right after it;
+            // spread the requests between them, so the smallest address is not a hot spot
+            let tied_positions: Vec<usize> = selection_order[best_rank..]
+                .iter()
+                .copied()
+                .take_while(|other_pos| {
+                    self.cmp_placement(&agents[best_pos], &agents[*other_pos]) == CmpOrdering::Equal
+                })
+                .filter(|other_pos| statuses[*other_pos] == CandidateStatus::Eligible)
+                .collect();
+            let tied_pos =
+                tied_positions[self.tie_break_index(request_hash, tied_positions.len(), advance)];
+
+            for pos in &tied_positions {
+                statuses[*pos] = CandidateStatus::Tied;
+            }
+
+            statuses[tied_pos] = CandidateStatus::Selected;
+            selected_pos = Some(tied_pos);
+        }
+
+        // only the peers ranked before the selected one held the request back
+        let passed_over = &selection_order[..best_rank.unwrap_or(selection_order.len())];
+
+        Selection {
+            admission_deferred_positions: passed_over
+                .iter()
+                .copied()
+                .filter(|pos| statuses[*pos] == CandidateStatus::AdmissionDeferred)
+                .collect(),
+            is_deferred_by_warmup: passed_over
+                .iter()
+                .any(|pos| statuses[*pos] == CandidateStatus::WarmupSaturated),
+            selected_pos,
+            selection_order,
+            statuses,
+        }
+    }
+
+    fn candidate_status(
+        &self,
+        peer: &UpstreamPeer,
+        criteria: &SelectionCriteria,
+    ) -> CandidateStatus {
+        if !peer.matches_labels(criteria.label_selectors) {
+            CandidateStatus::LabelsNotMatched
+        } else if !criteria
+            .prompt_tokens
+            .is_none_or(|prompt_tokens| peer.fits_in_context(prompt_tokens))
+        {
+            CandidateStatus::ContextTooSmall
+        } else if criteria.skipped_agent_ids.contains(&peer.agent_id) {
+            CandidateStatus::Skipped
+        } else if self.is_agent_name_excluded(peer) {
+            CandidateStatus::AgentNameExcluded
+        } else if peer.is_warmup_saturated() && peer.is_usable_ignoring_warmup() {
+            CandidateStatus::WarmupSaturated
+        } else if !peer.is_usable() {
+            CandidateStatus::NotUsable
+        } else if !self.is_selectable(peer, criteria.uses_slots) {
+            CandidateStatus::SlotsEndpointDisabled
+        } else if self.admission_rate_policy.is_some() && !peer.has_admission_token() {
+            // took its share of new requests for now, so it leaves this one to the next best peer
+            CandidateStatus::AdmissionDeferred
+        } else {
+            CandidateStatus::Eligible
+        }
+    }
+
+    /// `skipped_agent_ids` are left out, for example because they turned out to be busy. Batches
+    /// go to the peers that have idle slots for all the prompts, if there are any.
+    pub fn use_best_peer(
+        &self,
+        label_selectors: &[Label],
+        prompt_tokens: Option<usize>,
+        request_hash: u64,
+        skipped_agent_ids: &[String],
+        slots: usize,
+        uses_slots: bool,
+    ) -> Result<Option<UpstreamPeerInfo>> {
+        self.with_agents_write(|agents| {
+            self.refresh_for_selection(agents);
+
+            let selection = self.select(
+                agents,
+                &SelectionCriteria {
+                    label_selectors,
+                    prompt_tokens,
+                    skipped_agent_ids,
+                    slots,
+                    uses_slots,
+                },
+                request_hash,
+                true,
+            );
+
+            for pos in selection.admission_deferred_positions {
+                agents[pos].admission_deferred += 1;
+            }
+
+            if selection.is_deferred_by_warmup {
+                self.requests_deferred_by_warmup
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+
+            let Some(selected_pos) = selection.selected_pos else {
+                return Ok(None);
+            };
+
+            let peer = &mut agents[selected_pos];
+
+            peer.take_admission_token();
+
+            #[cfg(feature = "statsd_reporter")]
+            self.register_request_in_tier(peer.tier)?;
+
+            Ok(Some(peer.info()))
+        })
+    }
+
+    /// Time until a peer that only held back because of the admission rate can take a new
+    /// request, None if there is no such peer
+    pub fn next_admission_in(&self, uses_slots: bool) -> Result<Option<Duration>> {
+        let Some(admission_rate_policy) = &self.admission_rate_policy else {
+            return Ok(None);
+        };
+
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .filter(|peer| self.is_selectable(peer, uses_slots) && !peer.has_admission_token())
+                .map(|peer| peer.admission_available_in(admission_rate_policy))
+                .min())
+        })
+    }
+
+    /// `/slots` takes no slot, so the idle slots do not matter, only whether llama.cpp serves
+    /// it. `skipped_agent_ids` are left out, for example because they already failed.
+    pub fn use_slots_endpoint_peer(
+        &self,
+        skipped_agent_ids: &[String],
+    ) -> Result<Option<UpstreamPeerInfo>> {
+        self.with_agents_read(|agents| {
+            Ok(self
+                .selection_order(agents)
+                .into_iter()
+                .map(|pos| &agents[pos])
+                .find(|peer| {
+                    matches!(peer.is_slots_endpoint_enabled, Some(true))
+                        && matches!(peer.is_authorized, Some(true))
+                        && peer.error.is_none()
+                        && peer.quarantined_until.is_none()
+                        && !self.is_agent_name_excluded(peer)
+                        && !skipped_agent_ids.contains(&peer.agent_id)
+                })
+                .map(UpstreamPeer::info))
+        })
+    }
+
+    /// Picks the reachable peers in turn, whether they have idle slots or not, for the
+    /// balancer that runs with the slot accounting disabled
+    pub fn use_reachable_peer(
+        &self,
+        request_hash: u64,
+        skipped_agent_ids: &[String],
+        target_agent: Option<&str>,
+    ) -> Result<Option<UpstreamPeerInfo>> {
+        self.with_agents_read(|agents| {
+            let reachable_peers: Vec<&UpstreamPeer> = agents
+                .iter()
+                .filter(|peer| {
+                    matches!(peer.is_authorized, Some(true))
+                        && peer.error.is_none()
+                        && peer.quarantined_until.is_none()
+                        && !peer.is_draining
+                        && !self.is_agent_name_excluded(peer)
+                        && !skipped_agent_ids.contains(&peer.agent_id)
+                        && target_agent
+                            .is_none_or(|agent_id_or_name| peer.is_referred_to_as(agent_id_or_name))
+                })
+                .collect();
+
+            if reachable_peers.is_empty() {
+                return Ok(None);
+            }
+
+            let peer_index = self.tie_break_index(request_hash, reachable_peers.len(), true);
+
+            Ok(Some(reachable_peers[peer_index].info()))
+        })
+    }
+
+    pub fn use_target_peer(
+        &self,
+        agent_id_or_name: &str,
+        uses_slots: bool,
+    ) -> Result<Option<UpstreamPeerInfo>> {
+        self.with_agents_read(|agents| {
+            Ok(agents
+                .iter()
+                .find(|peer| {
+                    peer.is_referred_to_as(agent_id_or_name) && self.is_selectable(peer, uses_slots)
+                })
+                .map(UpstreamPeer::info))
+        })
+    }
+
+    fn remove_peer_at(&self, agents: &mut Vec<UpstreamPeer>, pos: usize, reason: &'static str) {
+        let mut upstream_peer = agents.remove(pos);
+
+        // Permits of the requests in progress would go back to the semaphore when dropped, and
+        // some waiting request would get a permit for a slot that is gone. Only the idle ones
+        // are available to forget.
+        let slots_count = upstream_peer.slots_count();
+        let permits_in_use = match upstream_peer.slots_permissions.take() {
+            Some(mut permits) => {
+                // requests that picked the peer before taking their slots can hold more permits
+                // than it has slots, the extra ones stand for the slots of the other peers
+                if permits.num_permits() > slots_count {
+                    drop(permits.split(permits.num_permits() - slots_count));
+                }
+
+                let permits_in_use = permits.num_permits();
+
+                permits.forget();
+
+                permits_in_use
+            }
+            None => 0,
+        };
+
+        let permits_to_forget = slots_count - permits_in_use;
+        let permits_forgotten = self
+            .upstream_slots_permits
+            .forget_permits(permits_to_forget);
+
+        self.permits_owed
+            .fetch_add(permits_to_forget - permits_forgotten, Ordering::Relaxed);
+
+        self.emit(PoolEvent::PeerRemoved {
+            agent_id: upstream_peer.agent_id,
+            reason,
+        });
+    }
+
+    /// Permits dropped by the requests go back to the semaphore without the pool knowing, so
+    /// this is called whenever the pool gets the chance. Always under the write lock, so the
+    /// owed permits are not forgotten twice.
+    fn settle_owed_permits(&self) {
+        let permits_owed = self.permits_owed.load(Ordering::Relaxed);
+
+        if permits_owed > 0 {
+            let permits_forgotten = self.upstream_slots_permits.forget_permits(permits_owed);
+
+            self.permits_owed
+                .fetch_sub(permits_forgotten, Ordering::Relaxed);
+        }
+    }
+
+    /// A restarted agent can come back under a different id, there can only be one peer per
+    /// llama.cpp address
+    fn supersede_peers(
+        &self,
+        agents: &mut Vec<UpstreamPeer>,
+        agent_id: &str,
+        status_update: &StatusUpdate,
+    ) {
+        while let Some(pos) = agents.iter().position(|p| {
+            p.external_llamacpp_addr == status_update.external_llamacpp_addr && !p.is_static
+        }) {
+            info!(
+                "Agent {} superseded agent {} at {}",
+                agent_id, agents[pos].agent_id, status_update.external_llamacpp_addr
+            );
+
+            self.remove_peer_at(agents, pos, "superseded");
+        }
+
+        if let Some(static_peer) = agents.iter().find(|p| {
+            p.external_llamacpp_addr == status_update.external_llamacpp_addr && p.is_static
+        }) {
+            warn!(
+                "Agent {} reports the same llama.cpp address as static agent {}, start it with `--agent-id {}`",
+                agent_id, static_peer.agent_id, static_peer.agent_id
+            );
+        }
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_model_loading_responses(&self) -> usize {
+        self.model_loading_responses.swap(0, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_permit_handoffs(&self) -> usize {
+        self.permit_handoffs.swap(0, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_selections_invalidated(&self) -> usize {
+        self.selections_invalidated.swap(0, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_requests_deferred_by_warmup(&self) -> usize {
+        self.requests_deferred_by_warmup.swap(0, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_retries_skipped_by_body_size(&self) -> usize {
+        self.retries_skipped_by_body_size.swap(0, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_status_updates_coalesced(&self) -> usize {
+        self.status_updates_coalesced.swap(0, Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests sent to each tier since the last call
+    #[cfg(feature = "statsd_reporter")]
+    pub fn take_requests_per_tier(&self) -> Result<BTreeMap<usize, usize>> {
+        let mut requests_per_tier = self.requests_per_tier.write()?;
+        let taken = requests_per_tier.clone();
+
+        // keep the tiers, so they are reported as zero instead of disappearing
+        requests_per_tier.values_mut().for_each(|requests| *requests = 0);
+
+        Ok(taken)
+    }
+
+    #[cfg(feature = "statsd_reporter")]
+    fn register_request_in_tier(&self, tier: usize) -> Result<()> {
+        *self.requests_per_tier.write()?.entry(tier).or_insert(0) += 1;
+
+        Ok(())
+    }
+
+    fn has_higher_priority_waiters(&self, priority: RequestPriority) -> bool {
+        self.requests_waiting_for_permit[..priority.index()]
+            .iter()
+            .any(|waiting| waiting.load(Ordering::Relaxed) > 0)
+    }
+
+    async fn wait_for_higher_priority_requests(&self, priority: RequestPriority) {
+        loop {
+            let waiters_changed = self.permit_waiters_changed.notified();
+
+            tokio::pin!(waiters_changed);
+
+            // register before checking, so a change in between is not missed
+            waiters_changed.as_mut().enable();
+
+            if !self.has_higher_priority_waiters(priority) {
+                return;
+            }
+
+            waiters_changed.await;
+        }
+    }
+
+    #[inline]
+    fn is_eligible(
+        peer: &UpstreamPeer,
+        label_selectors: &[Label],
+        prompt_tokens: Option<usize>,
+    ) -> bool {
+        peer.matches_labels(label_selectors)
+            && prompt_tokens.is_none_or(|prompt_tokens| peer.fits_in_context(prompt_tokens))
+    }
+
+    fn cmp_placement(&self, peer: &UpstreamPeer, other: &UpstreamPeer) -> CmpOrdering {
+        match self.placement_strategy {
+            PlacementStrategy::Pack => peer.cmp_pack_score(other),
+            PlacementStrategy::Spread => peer.cmp_score(other),
+        }
+    }
+
+    fn is_agent_name_excluded(&self, peer: &UpstreamPeer) -> bool {
+        let Some(agent_name) = &peer.agent_name else {
+            return false;
+        };
+
+        let excluded_agent_names = match self.excluded_agent_names.read() {
+            Ok(excluded_agent_names) => excluded_agent_names,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        excluded_agent_names
+            .iter()
+            .any(|agent_name_pattern| agent_name_pattern.matches(agent_name))
+    }
+
+    #[inline]
+    fn is_selectable(&self, peer: &UpstreamPeer, uses_slots: bool) -> bool {
+        if !peer.is_usable() || self.is_agent_name_excluded(peer) {
+            return false;
+        }
+
+        !(uses_slots
+            && matches!(peer.is_slots_endpoint_enabled, Some(false))
+            && self.slots_endpoint_disabled_policy == SlotsEndpointDisabledPolicy::Exclude)
+    }
+
+    /// Peers that served requests or failed a while ago might have recovered already, peers
+    /// that are warming up can take more requests with time, and agents that stopped reporting
+    /// become stale
+    fn refresh_for_selection(&self, agents: &mut [UpstreamPeer]) {
+        if self.prefer_newest_model_version {
+            Self::refresh_outdated_models(agents);
+        }
+
+        for peer in agents.iter_mut() {
+            peer.refresh_model_loading();
+            peer.refresh_staleness();
+
+            if let Some(admission_rate_policy) = &self.admission_rate_policy {
+                peer.refresh_admission_tokens(admission_rate_policy);
+            }
+
+            if let Some(cooldown_policy) = &self.cooldown_policy {
+                peer.refresh_cooldown(cooldown_policy);
+            }
+
+            if let Some(error_penalty_policy) = &self.error_penalty_policy {
+                peer.refresh_error_penalty(error_penalty_policy);
+            }
+
+            if let Some(warmup_period) = self.warmup_period {
+                peer.refresh_warmup(warmup_period);
+            }
+
+            // set here, since the class can change with every status update
+            if !self.hardware_class_weights.is_empty() {
+                peer.refresh_hardware_class_weight(&self.hardware_class_weights);
+            }
+        }
+
+        agents.sort();
+    }
+
+    /// Positions of the peers, from the best to the worst. The peers are always kept sorted for
+    /// spreading the requests, packing only needs to reorder them here.
+    fn selection_order(&self, agents: &[UpstreamPeer]) -> Vec<usize> {
+        let mut selection_order: Vec<usize> = (0..agents.len()).collect();
+
+        if self.placement_strategy == PlacementStrategy::Pack {
+            // stable, so the tied peers keep their order
+            selection_order
+                .sort_by(|pos, other_pos| agents[*pos].cmp_pack_score(&agents[*other_pos]));
+        }
+
+        selection_order
+    }
+
+    /// Round robin only moves on to the next peer if `advance` is set, so the choice can be
+    /// previewed without affecting the next request
+    fn tie_break_index(&self, request_hash: u64, tied_count: usize, advance: bool) -> usize {
+        match self.tie_break_strategy {
+            TieBreakStrategy::Address => 0,
+            TieBreakStrategy::RequestHash => (request_hash % tied_count as u64) as usize,
+            TieBreakStrategy::RoundRobin if advance => {
+                self.tie_breaker_cursor.fetch_add(1, Ordering::Relaxed) % tied_count
+            }
+            TieBreakStrategy::RoundRobin => {
+                self.tie_breaker_cursor.load(Ordering::Relaxed) % tied_count
+            }
+        }
+    }
+
+    /// Peers without a version are outdated as soon as any peer serving the same model has one
+    fn refresh_outdated_models(agents: &mut [UpstreamPeer]) {
+        let mut newest_versions: BTreeMap<Option<String>, String> = BTreeMap::new();
+
+        for peer in agents.iter() {
+            if let Some(model_version) = &peer.model_version {
+                newest_versions
+                    .entry(peer.model_name().map(str::to_string))
+                    .and_modify(|newest_version| {
+                        if cmp_model_versions(model_version, newest_version) == CmpOrdering::Greater
+                        {
+                            *newest_version = model_version.to_owned();
+                        }
+                    })
+                    .or_insert_with(|| model_version.to_owned());
+            }
+        }
+
+        for peer in agents.iter_mut() {
+            peer.is_model_outdated = newest_versions
+                .get(&peer.model_name().map(str::to_string))
+                .is_some_and(|newest_version| {
+                    peer.model_version.as_deref().is_none_or(|model_version| {
+                        cmp_model_versions(model_version, newest_version) == CmpOrdering::Less
+                    })
+                });
+        }
+    }
+
+    #[inline]
+    fn emit(&self, pool_event: PoolEvent) {
+        // sending only fails if there are no subscribers, which is fine
+        let _ = self.pool_events_tx.send(pool_event);
+    }
+
+    #[inline]
+    fn lock_rolling_drain(&self) -> MutexGuard<'_, Option<RollingDrain>> {
+        // the rolling drain is only changed by its own methods, which leave it consistent
+        match self.rolling_drain.lock() {
+            Ok(rolling_drain) => rolling_drain,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    #[inline]
+    fn with_agents_read<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
+    where
+        TCallback: FnOnce(&Vec<UpstreamPeer>) -> Result<TResult>,
+    {
+        match self.agents.read() {
+            Ok(agents) => cb(&agents),
+            Err(_) => Err("Failed to acquire read lock".into()),
+        }
+    }
+
+    #[inline]
+    fn with_agents_write<TCallback, TResult>(&self, cb: TCallback) -> Result<TResult>
+    where
+        TCallback: FnOnce(&mut Vec<UpstreamPeer>) -> Result<TResult>,
+    {
+        match self.agents.write() {
+            Ok(mut agents) => cb(&mut agents),
+            Err(_) => Err("Failed to acquire write lock".into()),
+        }
+    }
+}
+
+/// Keeps the count right even if the request is dropped while it is waiting
+struct WaitingForPermit<'pool> {
+    counter: &'pool AtomicUsize,
+    waiters_changed: &'pool Notify,
+}
+
+impl<'pool> WaitingForPermit<'pool> {
+    fn new(upstream_peer_pool: &'pool UpstreamPeerPool, priority: RequestPriority) -> Self {
+        let counter = &upstream_peer_pool.requests_waiting_for_permit[priority.index()];
+
+        counter.fetch_add(1, Ordering::Relaxed);
+        upstream_peer_pool.permit_waiters_changed.notify_waiters();
+
+        Self {
+            counter,
+            waiters_changed: &upstream_peer_pool.permit_waiters_changed,
+        }
+    }
+}
+
+impl Drop for WaitingForPermit<'_> {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+        self.waiters_changed.notify_waiters();
+    }
+}
+
+/// What the request needs from the peer, see `UpstreamPeerPool::select`
+struct SelectionCriteria<'a> {
+    label_selectors: &'a [Label],
+    prompt_tokens: Option<usize>,
+    skipped_agent_ids: &'a [String],
+    slots: usize,
+    uses_slots: bool,
+}
+
+/// Positions refer to the peers the selection went through
+struct Selection {
+    /// Peers ranked before the selected one that only held back because of the admission rate
+    admission_deferred_positions: Vec<usize>,
+    /// Set if a peer ranked before the selected one only held back because of the warm-up
+    is_deferred_by_warmup: bool,
+    selected_pos: Option<usize>,
+    /// From the best peer to the worst
+    selection_order: Vec<usize>,
+    /// Parallel to the peers
+    statuses: Vec<CandidateStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        thread,
+    };
+
+    use super::*;
+    use crate::llamacpp::slot::Slot;
+
+    const AGENTS_COUNT: usize = 3;
+
+    fn upstream_peer_pool() -> UpstreamPeerPool {
+        upstream_peer_pool_with(DuplicateAgentIdPolicy::Replace)
+    }
+
+    fn upstream_peer_pool_with(
+        duplicate_agent_id_policy: DuplicateAgentIdPolicy,
+    ) -> UpstreamPeerPool {
+        UpstreamPeerPool::new(
+            None,
+            None,
+            duplicate_agent_id_policy,
+            None,
+            None,
+            BTreeMap::new(),
+            0,
+            PlacementStrategy::Spread,
+            false,
+            SlotsEndpointDisabledPolicy::Exclude,
+            None,
+            TieBreakStrategy::Address,
+            None,
+            false,
+        )
+    }
+
+    /// Status update of a llama.cpp instance listening on the given port
+    fn status_update(port: u16, slots_processing: usize, slots_count: usize) -> StatusUpdate {
+        StatusUpdate::new(
+            None,
+            None,
+            None,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            None,
+            Some(true),
+            Some(true),
+            BTreeMap::new(),
+            None,
+            None,
+            None,
+            None,
+            0,
+            (0..slots_count)
+                .map(|id| Slot {
+                    id,
+                    is_processing: id < slots_processing,
+                })
+                .collect(),
+            None,
+            1,
+            BTreeMap::new(),
+            None,
+        )
+    }
+
+    /// Goes through the same steps as `ProxyService`, until the request is connected to
+    /// llama.cpp
+    fn start_request(upstream_peer_pool: &UpstreamPeerPool) -> Option<UpstreamPeerInfo> {
+        let permit = upstream_peer_pool.try_acquire_permit(1)?;
+        let peer = upstream_peer_pool
+            .use_best_peer(&[], None, 0, &[], 1, true)
+            .unwrap()?;
+
+        assert!(upstream_peer_pool
+            .store_permit(&peer.agent_id, peer.restart_epoch, permit)
+            .unwrap());
+        assert!(upstream_peer_pool
+            .take_slot(&peer.agent_id, peer.restart_epoch, 1)
+            .unwrap());
+
+        upstream_peer_pool.restore_integrity().unwrap();
+
+        Some(peer)
+    }
+
+    fn finish_request(upstream_peer_pool: &UpstreamPeerPool, peer: &UpstreamPeerInfo) {
+        upstream_peer_pool
+            .release_slot(&peer.agent_id, peer.last_update, peer.restart_epoch, 1)
+            .unwrap();
+        upstream_peer_pool.restore_integrity().unwrap();
+        upstream_peer_pool
+            .release_permits(&peer.agent_id, peer.restart_epoch, 1)
+            .unwrap();
+    }
+
+    /// Permits that are neither available nor held by a peer went missing
+    fn assert_permits_accounted_for(upstream_peer_pool: &UpstreamPeerPool) {
+        let pool_audit = upstream_peer_pool.audit().unwrap();
+
+        assert_eq!(pool_audit.available_permits, pool_audit.expected_permits);
+    }
+
+    fn assert_slots(
+        upstream_peer_pool: &UpstreamPeerPool,
+        available_permits: usize,
+        slots_idle: usize,
+        slots_processing: usize,
+    ) {
+        let pool_inspection = upstream_peer_pool.inspect().unwrap();
+
+        assert_eq!(pool_inspection.available_permits, available_permits);
+        assert_eq!(pool_inspection.slots_idle, slots_idle);
+        assert_eq!(pool_inspection.slots_processing, slots_processing);
+        assert_permits_accounted_for(upstream_peer_pool);
+    }
+
+    #[test]
+    fn take_and_release_cycle_returns_the_permits_and_slots() {
+        let upstream_peer_pool = upstream_peer_pool();
+
+        upstream_peer_pool
+            .register_status_update(
+                "agent",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8081, 0, 2),
+            )
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+
+        let first = start_request(&upstream_peer_pool).unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 1, 1);
+
+        let second = start_request(&upstream_peer_pool).unwrap();
+
+        assert_slots(&upstream_peer_pool, 0, 0, 2);
+        assert_eq!(upstream_peer_pool.inspect().unwrap().requests_in_flight, 2);
+        assert!(start_request(&upstream_peer_pool).is_none());
+
+        finish_request(&upstream_peer_pool, &first);
+
+        assert_slots(&upstream_peer_pool, 1, 1, 1);
+
+        finish_request(&upstream_peer_pool, &second);
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+        assert_eq!(upstream_peer_pool.inspect().unwrap().requests_in_flight, 0);
+    }
+
+    #[test]
+    fn request_reported_by_the_agent_is_released_once() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 1, 2))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 1, 1);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        // the slot stays processing until the agent reports it idle
+        assert_slots(&upstream_peer_pool, 2, 1, 1);
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+    }
+
+    #[test]
+    fn report_from_before_the_slot_was_taken_does_not_lose_the_permit() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 1))
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+
+        // llama.cpp did not get the request yet when the agent checked the slots
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 1))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 1, 0);
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 1, 1))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 0, 1);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 1))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 1, 0);
+    }
+
+    #[test]
+    fn quarantined_peer_keeps_its_permits_until_the_next_status_update() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        assert!(upstream_peer_pool.quarantine_peer("agent").unwrap());
+        assert_eq!(
+            upstream_peer_pool.inspect().unwrap().quarantined_agent_ids,
+            vec!["agent".to_string()]
+        );
+        assert!(start_request(&upstream_peer_pool).is_none());
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        assert!(upstream_peer_pool
+            .inspect()
+            .unwrap()
+            .quarantined_agent_ids
+            .is_empty());
+        assert!(start_request(&upstream_peer_pool).is_some());
+        assert_slots(&upstream_peer_pool, 1, 1, 1);
+    }
+
+    #[test]
+    fn duplicate_agent_id_replacing_the_peer_does_not_leak_permits() {
+        let upstream_peer_pool = upstream_peer_pool_with(DuplicateAgentIdPolicy::Replace);
+
+        upstream_peer_pool
+            .register_status_update(
+                "agent",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8081, 0, 2),
+            )
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+
+        // the request is still in progress on llama.cpp, so the new agent reports it
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        assert!(upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 1, 2))
+            .unwrap());
+        assert_eq!(upstream_peer_pool.inspect().unwrap().peers_count, 1);
+        assert_permits_accounted_for(&upstream_peer_pool);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+    }
+
+    #[test]
+    fn duplicate_agent_id_rejected_keeps_the_peer_and_its_permits() {
+        let upstream_peer_pool = upstream_peer_pool_with(DuplicateAgentIdPolicy::Reject);
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+
+        assert!(!upstream_peer_pool
+            .register_status_update(
+                "agent",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 0, 4)
+            )
+            .unwrap());
+        assert_slots(&upstream_peer_pool, 1, 1, 1);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+    }
+
+    #[test]
+    fn peer_removed_mid_request_gives_the_permits_back_when_it_returns() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent-1", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+        upstream_peer_pool
+            .register_status_update(
+                "agent-2",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 0, 2),
+            )
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+
+        assert_eq!(peer.agent_id, "agent-1");
+
+        upstream_peer_pool
+            .remove_peer("agent-1", connection_id)
+            .unwrap();
+
+        // only the permits of the other peer are left
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+
+        upstream_peer_pool
+            .register_status_update(
+                "agent-1",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8081, 0, 2),
+            )
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 4, 4, 0);
+    }
+
+    #[test]
+    fn permit_taken_before_the_peer_is_removed_goes_back_to_the_other_peers() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent-1", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+        upstream_peer_pool
+            .register_status_update(
+                "agent-2",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 0, 2),
+            )
+            .unwrap();
+
+        let permit = upstream_peer_pool.try_acquire_permit(1).unwrap();
+
+        upstream_peer_pool
+            .remove_peer("agent-1", connection_id)
+            .unwrap();
+
+        assert!(!upstream_peer_pool
+            .store_permit("agent-1", 0, permit)
+            .unwrap());
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+    }
+
+    #[test]
+    fn permits_owed_by_the_removed_peer_are_forgotten_when_they_come_back() {
+        let upstream_peer_pool = upstream_peer_pool();
+
+        upstream_peer_pool
+            .register_status_update(
+                "agent-2",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 0, 1),
+            )
+            .unwrap();
+
+        let peer = start_request(&upstream_peer_pool).unwrap();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent-1", connection_id, status_update(8081, 0, 1))
+            .unwrap();
+
+        // the permit of the idle slot is taken, so the removal can't forget it yet
+        let permit = upstream_peer_pool.try_acquire_permit(1).unwrap();
+
+        upstream_peer_pool
+            .remove_peer("agent-1", connection_id)
+            .unwrap();
+
+        assert!(!upstream_peer_pool
+            .store_permit("agent-1", 0, permit)
+            .unwrap());
+        assert_slots(&upstream_peer_pool, 0, 0, 1);
+
+        finish_request(&upstream_peer_pool, &peer);
+
+        assert_slots(&upstream_peer_pool, 1, 1, 0);
+    }
+
+    #[test]
+    fn permits_held_beyond_the_slots_go_back_when_the_peer_is_removed() {
+        let upstream_peer_pool = upstream_peer_pool();
+
+        upstream_peer_pool
+            .register_status_update(
+                "agent-2",
+                upstream_peer_pool.next_connection_id(),
+                status_update(8082, 0, 1),
+            )
+            .unwrap();
+
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent-1", connection_id, status_update(8081, 0, 1))
+            .unwrap();
+
+        // both requests picked the peer before either of them took the slot
+        for _ in 0..2 {
+            let permit = upstream_peer_pool.try_acquire_permit(1).unwrap();
+
+            assert!(upstream_peer_pool
+                .store_permit("agent-1", 0, permit)
+                .unwrap());
+        }
+
+        assert_permits_accounted_for(&upstream_peer_pool);
+
+        upstream_peer_pool
+            .remove_peer("agent-1", connection_id)
+            .unwrap();
+
+        assert_slots(&upstream_peer_pool, 1, 1, 0);
+    }
+
+    #[test]
+    fn selection_from_before_the_restart_neither_stores_the_permit_nor_takes_the_slot() {
+        let upstream_peer_pool = upstream_peer_pool();
+        let connection_id = upstream_peer_pool.next_connection_id();
+
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, status_update(8081, 0, 2))
+            .unwrap();
+
+        let permit = upstream_peer_pool.try_acquire_permit(1).unwrap();
+        let peer = upstream_peer_pool
+            .use_best_peer(&[], None, 0, &[], 1, true)
+            .unwrap()
+            .unwrap();
+        let mut restarted = status_update(8081, 0, 2);
+
+        restarted.restart_epoch = 1;
+        upstream_peer_pool
+            .register_status_update("agent", connection_id, restarted)
+            .unwrap();
+
+        // the request would release them with the old epoch, which the peer ignores
+        assert!(!upstream_peer_pool
+            .store_permit(&peer.agent_id, peer.restart_epoch, permit)
+            .unwrap());
+        assert!(!upstream_peer_pool
+            .take_slot(&peer.agent_id, peer.restart_epoch, 1)
+            .unwrap());
+        assert_slots(&upstream_peer_pool, 2, 2, 0);
+        assert_eq!(upstream_peer_pool.inspect().unwrap().requests_in_flight, 0);
+    }
+
+    /// Indexes wrap around the agents and the requests in progress
+    #[derive(Clone, Debug)]
+    enum Operation {
+        CancelDrain,
+        Drain(usize),
+        FinishRequest(usize),
+        Quarantine(usize),
+        Remove(usize),
+        Report(usize),
+        Restart(usize),
+        StartRequest,
+    }
+
+    /// llama.cpp instance and its agent, as the agent reports them
+    #[derive(Debug)]
+    struct FakeAgent {
+        connection_id: Option<u64>,
+        restart_epoch: u64,
+        slots_count: usize,
+        slots_processing: usize,
+    }
+
+    struct Request {
+        agent_index: usize,
+        peer: UpstreamPeerInfo,
+        /// Of llama.cpp, the balancer might not know about the restart yet
+        restart_epoch: Option<u64>,
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            1 => Just(Operation::CancelDrain),
+            1 => (0..AGENTS_COUNT).prop_map(Operation::Drain),
+            1 => any::<usize>().prop_map(Operation::FinishRequest),
+            1 => (0..AGENTS_COUNT).prop_map(Operation::Quarantine),
+            1 => (0..AGENTS_COUNT).prop_map(Operation::Remove),
+            3 => (0..AGENTS_COUNT).prop_map(Operation::Report),
+            1 => (0..AGENTS_COUNT).prop_map(Operation::Restart),
+            4 => Just(Operation::StartRequest),
+        ]
+    }
+
+    fn fake_agents(slots_counts: &[usize]) -> Mutex<Vec<FakeAgent>> {
+        Mutex::new(
+            slots_counts
+                .iter()
+                .map(|slots_count| FakeAgent {
+                    connection_id: None,
+                    restart_epoch: 0,
+                    slots_count: *slots_count,
+                    slots_processing: 0,
+                })
+                .collect(),
+        )
+    }
+
+    fn agent_id(agent_index: usize) -> String {
+        format!("agent-{}", agent_index)
+    }
+
+    fn report(
+        upstream_peer_pool: &UpstreamPeerPool,
+        fake_agents: &Mutex<Vec<FakeAgent>>,
+        agent_index: usize,
+    ) {
+        // held while reporting, so the report is not older than the state of llama.cpp
+        let mut fake_agents = fake_agents.lock().unwrap();
+        let fake_agent = &mut fake_agents[agent_index];
+        let connection_id = *fake_agent
+            .connection_id
+            .get_or_insert_with(|| upstream_peer_pool.next_connection_id());
+        let mut status_update = status_update(
+            8080 + agent_index as u16,
+            fake_agent.slots_processing,
+            fake_agent.slots_count,
+        );
+
+        status_update.restart_epoch = fake_agent.restart_epoch;
+
+        assert!(upstream_peer_pool
+            .register_status_update(&agent_id(agent_index), connection_id, status_update)
+            .unwrap());
+
+        upstream_peer_pool.advance_rolling_drain().unwrap();
+    }
+
+    /// Same steps as `start_request`, but the peer can change between them when the
+    /// operations run in parallel
+    fn try_start_request(
+        upstream_peer_pool: &UpstreamPeerPool,
+        fake_agents: &Mutex<Vec<FakeAgent>>,
+        is_sequential: bool,
+    ) -> Option<Request> {
+        let permit = upstream_peer_pool.try_acquire_permit(1)?;
+        let peer = upstream_peer_pool
+            .use_best_peer(&[], None, 0, &[], 1, true)
+            .unwrap()?;
+
+        if is_sequential {
+            let agents = upstream_peer_pool.agents.read().unwrap();
+            let selected = agents
+                .iter()
+                .find(|upstream_peer| upstream_peer.agent_id == peer.agent_id)
+                .unwrap();
+
+            assert!(selected.quarantined_until.is_none());
+            assert!(!selected.is_draining);
+        }
+
+        if !upstream_peer_pool
+            .store_permit(&peer.agent_id, peer.restart_epoch, permit)
+            .unwrap()
+        {
+            return None;
+        }
+
+        let agent_index: usize = peer.agent_id["agent-".len()..].parse().unwrap();
+
+        let mut restart_epoch = None;
+
+        if upstream_peer_pool
+            .take_slot(&peer.agent_id, peer.restart_epoch, 1)
+            .unwrap()
+        {
+            let mut fake_agents = fake_agents.lock().unwrap();
+            let fake_agent = &mut fake_agents[agent_index];
+
+            // llama.cpp queues the request if the report was out of date
+            fake_agent.slots_processing =
+                (fake_agent.slots_processing + 1).min(fake_agent.slots_count);
+            restart_epoch = Some(fake_agent.restart_epoch);
+        }
+
+        upstream_peer_pool.restore_integrity().unwrap();
+
+        Some(Request {
+            agent_index,
+            peer,
+            restart_epoch,
+        })
+    }
+
+    fn run_operation(
+        upstream_peer_pool: &UpstreamPeerPool,
+        fake_agents: &Mutex<Vec<FakeAgent>>,
+        requests: &mut Vec<Request>,
+        operation: &Operation,
+        is_sequential: bool,
+    ) {
+        match operation {
+            Operation::CancelDrain => {
+                upstream_peer_pool.cancel_rolling_drain().unwrap();
+            }
+            Operation::Drain(agent_index) => {
+                upstream_peer_pool
+                    .start_rolling_drain(Some(vec![agent_id(*agent_index)]), 1)
+                    .unwrap();
+                upstream_peer_pool.advance_rolling_drain().unwrap();
+            }
+            Operation::FinishRequest(request_index) => {
+                if requests.is_empty() {
+                    return;
+                }
+
+                let request = requests.remove(request_index % requests.len());
+                let mut fake_agents = fake_agents.lock().unwrap();
+                let fake_agent = &mut fake_agents[request.agent_index];
+
+                // requests from before the restart are already gone from llama.cpp
+                if request.restart_epoch == Some(fake_agent.restart_epoch) {
+                    fake_agent.slots_processing = fake_agent.slots_processing.saturating_sub(1);
+                }
+
+                drop(fake_agents);
+                finish_request(upstream_peer_pool, &request.peer);
+            }
+            Operation::Quarantine(agent_index) => {
+                upstream_peer_pool
+                    .quarantine_peer(&agent_id(*agent_index))
+                    .unwrap();
+            }
+            Operation::Remove(agent_index) => {
+                let mut fake_agents = fake_agents.lock().unwrap();
+
+                if let Some(connection_id) = fake_agents[*agent_index].connection_id.take() {
+                    upstream_peer_pool
+                        .remove_peer(&agent_id(*agent_index), connection_id)
+                        .unwrap();
+                }
+            }
+            Operation::Report(agent_index) => report(upstream_peer_pool, fake_agents, *agent_index),
+            Operation::Restart(agent_index) => {
+                let mut fake_agents = fake_agents.lock().unwrap();
+                let fake_agent = &mut fake_agents[*agent_index];
+
+                fake_agent.restart_epoch += 1;
+                fake_agent.slots_processing = 0;
+            }
+            Operation::StartRequest => {
+                if let Some(request) =
+                    try_start_request(upstream_peer_pool, fake_agents, is_sequential)
+                {
+                    requests.push(request);
+                }
+            }
+        }
+    }
+
+    /// Holds after every operation, even when they run in parallel
+    fn assert_slots_consistent(
+        upstream_peer_pool: &UpstreamPeerPool,
+        fake_agents: &Mutex<Vec<FakeAgent>>,
+    ) {
+        // same order as in `report`
+        let fake_agents = fake_agents.lock().unwrap();
+        let agents = upstream_peer_pool.agents.read().unwrap();
+        let mut slots_count = 0;
+
+        for upstream_peer in agents.iter() {
+            let agent_index: usize = upstream_peer.agent_id["agent-".len()..].parse().unwrap();
+
+            assert_eq!(
+                upstream_peer.slots_count(),
+                fake_agents[agent_index].slots_count
+            );
+
+            slots_count += upstream_peer.slots_count();
+        }
+
+        // permits of the requests being selected are missing, but there are never extra ones
+        assert!(
+            upstream_peer_pool
+                .upstream_slots_permits
+                .available_permits()
+                <= slots_count
+        );
+    }
+
+    /// Once all the requests are finished and the agents reported that
+    fn assert_everything_released(
+        upstream_peer_pool: &UpstreamPeerPool,
+        fake_agents: &Mutex<Vec<FakeAgent>>,
+    ) {
+        for agent_index in 0..AGENTS_COUNT {
+            report(upstream_peer_pool, fake_agents, agent_index);
+        }
+
+        let agents = upstream_peer_pool.agents.read().unwrap();
+        let slots_count: usize = agents.iter().map(UpstreamPeer::slots_count).sum();
+
+        for upstream_peer in agents.iter() {
+            assert_eq!(upstream_peer.slots_idle, upstream_peer.slots_count());
+            assert!(
+                upstream_peer.slots_permissions.is_none()
+                    || upstream_peer
+                        .slots_permissions
+                        .as_ref()
+                        .is_some_and(|permits| permits.num_permits() == 0)
+            );
+        }
+
+        drop(agents);
+
+        assert_eq!(
+            upstream_peer_pool
+                .upstream_slots_permits
+                .available_permits(),
+            slots_count
+        );
+        assert_permits_accounted_for(upstream_peer_pool);
+    }
+
+    proptest! {
+        #[test]
+        fn sequential_operations_keep_the_slots_and_permits_consistent(
+            slots_counts in prop::collection::vec(1..4usize, AGENTS_COUNT),
+            operations in prop::collection::vec(operation(), 1..60),
+        ) {
+            let upstream_peer_pool = upstream_peer_pool();
+            let fake_agents = fake_agents(&slots_counts);
+            let mut requests = vec![];
+
+            for operation in &operations {
+                run_operation(&upstream_peer_pool, &fake_agents, &mut requests, operation, true);
+                assert_slots_consistent(&upstream_peer_pool, &fake_agents);
+                assert_permits_accounted_for(&upstream_peer_pool);
+            }
+
+            for request_index in (0..requests.len()).rev() {
+                run_operation(
+                    &upstream_peer_pool,
+                    &fake_agents,
+                    &mut requests,
+                    &Operation::FinishRequest(request_index),
+                    true,
+                );
+            }
+
+            assert_everything_released(&upstream_peer_pool, &fake_agents);
+        }
+
+        #[test]
+        fn parallel_operations_keep_the_slots_and_permits_consistent(
+            slots_counts in prop::collection::vec(1..4usize, AGENTS_COUNT),
+            threads_operations in prop::collection::vec(
+                prop::collection::vec(operation(), 1..40),
+                2..4,
+            ),
+        ) {
+            let upstream_peer_pool = upstream_peer_pool();
+            let fake_agents = fake_agents(&slots_counts);
+
+            thread::scope(|scope| {
+                for operations in &threads_operations {
+                    let upstream_peer_pool = &upstream_peer_pool;
+                    let fake_agents = &fake_agents;
+
+                    scope.spawn(move || {
+                        let mut requests = vec![];
+
+                        for operation in operations {
+                            run_operation(upstream_peer_pool, fake_agents, &mut requests, operation, false);
+                            assert_slots_consistent(upstream_peer_pool, fake_agents);
+                        }
+
+                        while !requests.is_empty() {
+                            run_operation(
+                                upstream_peer_pool,
+                                fake_agents,
+                                &mut requests,
+                                &Operation::FinishRequest(0),
+                                false,
+                            );
+                        }
+                    });
+                }
+            });
+
+            assert_everything_released(&upstream_peer_pool, &fake_agents);
         }
     }
 }
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::errors::app_error::AppError;
+
+/// Protocol the balancer speaks to llama.cpp, there is no TLS to negotiate it, so it has to be
+/// known up front
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    /// HTTP/1.1, with a connection per request in progress
+    #[default]
+    H1,
+    /// HTTP/2 without TLS (h2c, with prior knowledge), the requests in progress share a
+    /// connection
+    H2,
+}
+
+impl FromStr for UpstreamProtocol {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "h1" => Ok(UpstreamProtocol::H1),
+            "h2" => Ok(UpstreamProtocol::H2),
+            _ => Err(AppError::UnexpectedError(format!(
+                "Invalid upstream protocol: {} (expected \"h1\" or \"h2\")",
+                arg
+            ))),
+        }
+    }
+}
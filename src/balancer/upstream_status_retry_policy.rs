@@ -0,0 +1,30 @@
+use serde::Deserialize;
+
+use crate::balancer::response_status_counts::ResponseStatusCounts;
+
+/// Which error responses from llama.cpp are retried on a different agent. Completions are not
+/// strictly idempotent, so nothing is retried by default.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStatusRetryPolicy {
+    #[default]
+    Never,
+    /// `429` and `503`, the agent did not start processing the request
+    Overloaded,
+    /// `429` and all the `5xx` responses
+    ServerError,
+}
+
+impl UpstreamStatusRetryPolicy {
+    pub fn allows_retry(&self, status: u16) -> bool {
+        match self {
+            UpstreamStatusRetryPolicy::Never => false,
+            UpstreamStatusRetryPolicy::Overloaded => {
+                ResponseStatusCounts::is_overloaded_status(status)
+            }
+            UpstreamStatusRetryPolicy::ServerError => {
+                status == 429 || (500..=599).contains(&status)
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{debug, error};
+use pingora::{server::ShutdownWatch, services::Service};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{
+    balancer::{upstream_peer::UpstreamPeerInfo, upstream_peer_pool::UpstreamPeerPool},
+    errors::{app_error::AppError, result::Result},
+};
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A loading model fails the probe right away with 503, a probe that times out points to a
+/// llama.cpp that hangs, so it is not probed again for a while
+const TIMED_OUT_PROBE_QUARANTINE: Duration = Duration::from_secs(30);
+
+/// Sends a tiny completion to the peers that are not warmed up yet, llama.cpp can report its
+/// slots while the model is still loading
+pub struct WarmupProbeService {
+    client: reqwest::Client,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+    warmup_probe_payload: Value,
+}
+
+impl WarmupProbeService {
+    pub fn new(
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+        warmup_probe_payload: Value,
+        warmup_probe_timeout: Duration,
+    ) -> Result<Self> {
+        Ok(WarmupProbeService {
+            client: reqwest::Client::builder()
+                .timeout(warmup_probe_timeout)
+                .build()?,
+            upstream_peer_pool,
+            warmup_probe_payload,
+        })
+    }
+
+    async fn probe_peer(&self, peer: UpstreamPeerInfo) -> Result<()> {
+        let mut request = self
+            .client
+            .post(format!("http://{}/completion", peer.external_llamacpp_addr))
+            .json(&self.warmup_probe_payload);
+
+        if let Some(api_key) = &peer.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = match request.send().await {
+            Err(err) if err.is_timeout() => {
+                self.upstream_peer_pool.quarantine_peer_for(
+                    &peer.agent_id,
+                    TIMED_OUT_PROBE_QUARANTINE,
+                    "warmup_probe_timeout",
+                )?;
+
+                return Err(err.into());
+            }
+            response => response?,
+        };
+
+        if !response.status().is_success() {
+            return Err(AppError::UnexpectedError(format!(
+                "Unexpected response status {}",
+                response.status()
+            )));
+        }
+
+        self.upstream_peer_pool
+            .mark_warmed_up(&peer.agent_id, peer.restart_epoch)?;
+
+        Ok(())
+    }
+
+    async fn probe_peers(&self) -> Result<()> {
+        let peers = self.upstream_peer_pool.peers_waiting_for_warmup_probe()?;
+
+        // a probe can take a while, so one loading model does not hold the others back
+        join_all(peers.into_iter().map(|peer| async move {
+            let agent_id = peer.agent_id.clone();
+
+            // loading a model takes a while, so failed probes are expected
+            if let Err(err) = self.probe_peer(peer).await {
+                debug!("Agent {} did not pass the warm-up probe yet: {}", agent_id, err);
+            }
+        }))
+        .await;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Service for WarmupProbeService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        // the probes of a tick are awaited before the next tick, so each peer has at most one
+        // probe in flight
+        let mut ticker = interval(PROBE_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down warm-up probe service");
+                    return;
+                },
+                _ = ticker.tick() => {
+                    if let Err(err) = self.probe_peers().await {
+                        error!("Failed to probe peers: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "warmup_probe"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
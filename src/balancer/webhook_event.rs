@@ -0,0 +1,81 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+use crate::errors::app_error::AppError;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    /// The number of usable agents dropped below `--webhook-min-usable-peers`
+    CapacityLow,
+    /// The number of usable agents is back at `--webhook-min-usable-peers` or above
+    CapacityRestored,
+    /// The agent finished its requests during a rolling drain and can be restarted
+    PeerDrained,
+    PeerQuarantined,
+    PeerRegistered,
+    PeerRemoved,
+    PeerUnquarantined,
+}
+
+impl WebhookEventType {
+    const ALL: [WebhookEventType; 7] = [
+        WebhookEventType::CapacityLow,
+        WebhookEventType::CapacityRestored,
+        WebhookEventType::PeerDrained,
+        WebhookEventType::PeerQuarantined,
+        WebhookEventType::PeerRegistered,
+        WebhookEventType::PeerRemoved,
+        WebhookEventType::PeerUnquarantined,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::CapacityLow => "capacity_low",
+            WebhookEventType::CapacityRestored => "capacity_restored",
+            WebhookEventType::PeerDrained => "peer_drained",
+            WebhookEventType::PeerQuarantined => "peer_quarantined",
+            WebhookEventType::PeerRegistered => "peer_registered",
+            WebhookEventType::PeerRemoved => "peer_removed",
+            WebhookEventType::PeerUnquarantined => "peer_unquarantined",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEventType {
+    type Err = AppError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        WebhookEventType::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == arg)
+            .ok_or_else(|| {
+                AppError::UnexpectedError(format!(
+                    "Invalid webhook event: {} (expected one of: {})",
+                    arg,
+                    WebhookEventType::ALL
+                        .map(|event_type| event_type.as_str())
+                        .join(", ")
+                ))
+            })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookEvent {
+    pub agent_id: Option<String>,
+    pub agent_name: Option<String>,
+    pub details: Value,
+    pub event: WebhookEventType,
+    /// Why the agent was quarantined or removed, see `PoolEvent`
+    pub reason: Option<&'static str>,
+    /// RFC 3339, in UTC
+    pub timestamp: String,
+}
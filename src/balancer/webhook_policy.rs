@@ -0,0 +1,26 @@
+use std::time::Duration;
+use url::Url;
+
+use crate::balancer::webhook_event::WebhookEventType;
+
+#[derive(Clone, Debug)]
+pub struct WebhookPolicy {
+    /// Events that are sent, all of them if empty
+    pub event_types: Vec<WebhookEventType>,
+    pub max_retries: usize,
+    /// Usable agents below this count trigger `capacity_low` (optional)
+    pub min_usable_peers: Option<usize>,
+    /// Events waiting for the delivery above this count are dropped, so a dead receiver can't
+    /// hold back the pool
+    pub queue_size: usize,
+    /// Key of the HMAC-SHA256 signature of the payload (optional)
+    pub secret: Option<String>,
+    pub timeout: Duration,
+    pub url: Url,
+}
+
+impl WebhookPolicy {
+    pub fn accepts(&self, event_type: WebhookEventType) -> bool {
+        self.event_types.is_empty() || self.event_types.contains(&event_type)
+    }
+}
@@ -0,0 +1,316 @@
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, error, warn};
+use pingora::{server::ShutdownWatch, services::Service};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::{
+        broadcast::error::RecvError,
+        mpsc::{self, error::TrySendError, Receiver, Sender},
+    },
+    time::{interval, sleep, Duration, MissedTickBehavior},
+};
+
+#[cfg(unix)]
+use pingora::server::ListenFds;
+
+use crate::{
+    balancer::{
+        pool_event::PoolEvent,
+        upstream_peer_pool::UpstreamPeerPool,
+        webhook_event::{WebhookEvent, WebhookEventType},
+        webhook_policy::WebhookPolicy,
+        webhook_stats::WebhookStats,
+    },
+    errors::{app_error::AppError, result::Result},
+};
+
+const CAPACITY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+const EVENT_HEADER: &str = "X-Paddler-Event";
+
+/// Doubled after each failed attempt
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+const SIGNATURE_HEADER: &str = "X-Paddler-Signature";
+
+/// POSTs the peer lifecycle events to `--webhook-url`. The events are queued and delivered in
+/// the background, so a slow or dead receiver only drops events and never slows down the pool
+pub struct WebhookService {
+    /// Names are gone from the pool by the time the peer is removed
+    agent_names: HashMap<String, Option<String>>,
+    client: reqwest::Client,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+    webhook_policy: Arc<WebhookPolicy>,
+    webhook_stats: Arc<WebhookStats>,
+}
+
+impl WebhookService {
+    pub fn new(
+        upstream_peer_pool: Arc<UpstreamPeerPool>,
+        webhook_policy: WebhookPolicy,
+        webhook_stats: Arc<WebhookStats>,
+    ) -> Result<Self> {
+        Ok(WebhookService {
+            agent_names: HashMap::new(),
+            client: reqwest::Client::builder()
+                .timeout(webhook_policy.timeout)
+                .build()?,
+            upstream_peer_pool,
+            webhook_policy: Arc::new(webhook_policy),
+            webhook_stats,
+        })
+    }
+
+    fn capacity_event(&self, is_capacity_low: &mut Option<bool>) -> Result<Option<WebhookEvent>> {
+        let min_usable_peers = match self.webhook_policy.min_usable_peers {
+            Some(min_usable_peers) => min_usable_peers,
+            None => return Ok(None),
+        };
+
+        let (peers, usable_peers) = self.upstream_peer_pool.peers_count()?;
+        let is_low = usable_peers < min_usable_peers;
+
+        // the agents need a moment to report after the balancer starts, so only the changes
+        // after the first check are sent
+        let was_low = match is_capacity_low.replace(is_low) {
+            Some(was_low) => was_low,
+            None => return Ok(None),
+        };
+
+        if is_low == was_low {
+            return Ok(None);
+        }
+
+        Ok(Some(WebhookEvent {
+            agent_id: None,
+            agent_name: None,
+            details: json!({
+                "min_usable_peers": min_usable_peers,
+                "peers": peers,
+                "usable_peers": usable_peers,
+            }),
+            event: if is_low {
+                WebhookEventType::CapacityLow
+            } else {
+                WebhookEventType::CapacityRestored
+            },
+            reason: None,
+            timestamp: now(),
+        }))
+    }
+
+    fn enqueue(&self, events_tx: &Sender<WebhookEvent>, webhook_event: WebhookEvent) {
+        if !self.webhook_policy.accepts(webhook_event.event) {
+            return;
+        }
+
+        match events_tx.try_send(webhook_event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(webhook_event)) => {
+                warn!(
+                    "Webhook queue is full, dropping {} event",
+                    webhook_event.event
+                );
+
+                self.webhook_stats.register_dropped(1);
+            }
+            Err(TrySendError::Closed(_)) => error!("Webhook delivery stopped unexpectedly"),
+        }
+    }
+
+    fn peer_event(&mut self, pool_event: PoolEvent) -> Option<WebhookEvent> {
+        let (event, agent_id, reason) = match pool_event {
+            PoolEvent::PeerAdded { agent_id } => (WebhookEventType::PeerRegistered, agent_id, None),
+            PoolEvent::PeerDrained { agent_id } => (WebhookEventType::PeerDrained, agent_id, None),
+            PoolEvent::PeerQuarantined { agent_id, reason } => {
+                (WebhookEventType::PeerQuarantined, agent_id, Some(reason))
+            }
+            PoolEvent::PeerRecovered { agent_id } => {
+                (WebhookEventType::PeerUnquarantined, agent_id, None)
+            }
+            PoolEvent::PeerRemoved { agent_id, reason } => {
+                (WebhookEventType::PeerRemoved, agent_id, Some(reason))
+            }
+            PoolEvent::SlotReleased { .. }
+            | PoolEvent::SlotTaken { .. }
+            | PoolEvent::Utilization { .. } => return None,
+        };
+
+        let agent_name = if event == WebhookEventType::PeerRemoved {
+            self.agent_names.remove(&agent_id).flatten()
+        } else {
+            match self.upstream_peer_pool.agent_name(&agent_id) {
+                Ok(agent_name) => {
+                    self.agent_names
+                        .insert(agent_id.clone(), agent_name.clone());
+
+                    agent_name
+                }
+                Err(err) => {
+                    error!("Failed to get the name of agent {}: {}", agent_id, err);
+
+                    self.agent_names.get(&agent_id).cloned().flatten()
+                }
+            }
+        };
+
+        Some(WebhookEvent {
+            agent_id: Some(agent_id),
+            agent_name,
+            details: Value::Object(Default::default()),
+            event,
+            reason,
+            timestamp: now(),
+        })
+    }
+}
+
+#[async_trait]
+impl Service for WebhookService {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] _fds: Option<ListenFds>,
+        mut shutdown: ShutdownWatch,
+    ) {
+        let (events_tx, events_rx) = mpsc::channel(self.webhook_policy.queue_size);
+        let delivery = tokio::spawn(deliver_events(
+            self.client.clone(),
+            events_rx,
+            self.webhook_policy.clone(),
+            self.webhook_stats.clone(),
+        ));
+        let mut is_capacity_low = None;
+        let mut pool_events_rx = self.upstream_peer_pool.subscribe_events();
+        let mut ticker = interval(CAPACITY_CHECK_INTERVAL);
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down webhook service");
+                    delivery.abort();
+                    return;
+                },
+                pool_event = pool_events_rx.recv() => match pool_event {
+                    Ok(pool_event) => {
+                        if let Some(webhook_event) = self.peer_event(pool_event) {
+                            self.enqueue(&events_tx, webhook_event);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhook service lagged behind, skipped {} pool events", skipped);
+
+                        self.webhook_stats.register_dropped(skipped as usize);
+                    }
+                    Err(RecvError::Closed) => {
+                        error!("Pool events stopped unexpectedly");
+                        delivery.abort();
+                        return;
+                    }
+                },
+                _ = ticker.tick() => match self.capacity_event(&mut is_capacity_low) {
+                    Ok(Some(webhook_event)) => self.enqueue(&events_tx, webhook_event),
+                    Ok(None) => {}
+                    Err(err) => error!("Failed to check the cluster capacity: {}", err),
+                },
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+async fn deliver_event(
+    client: &reqwest::Client,
+    webhook_event: &WebhookEvent,
+    webhook_policy: &WebhookPolicy,
+) -> Result<()> {
+    let body = serde_json::to_vec(webhook_event)?;
+    let mut request = client
+        .post(webhook_policy.url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, webhook_event.event.as_str());
+
+    if let Some(secret) = &webhook_policy.secret {
+        request = request.header(SIGNATURE_HEADER, sign(secret, &body)?);
+    }
+
+    let response = request.body(body).send().await?;
+
+    if !response.status().is_success() {
+        return Err(AppError::UnexpectedError(format!(
+            "Unexpected response status {}",
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
+async fn deliver_events(
+    client: reqwest::Client,
+    mut events_rx: Receiver<WebhookEvent>,
+    webhook_policy: Arc<WebhookPolicy>,
+    webhook_stats: Arc<WebhookStats>,
+) {
+    while let Some(webhook_event) = events_rx.recv().await {
+        let mut attempt = 0;
+
+        loop {
+            match deliver_event(&client, &webhook_event, &webhook_policy).await {
+                Ok(()) => {
+                    webhook_stats.register_delivered();
+
+                    break;
+                }
+                Err(err) if attempt < webhook_policy.max_retries => {
+                    debug!(
+                        "Failed to deliver {} event, retrying: {}",
+                        webhook_event.event, err
+                    );
+
+                    webhook_stats.register_retried();
+
+                    sleep(RETRY_BACKOFF * 2u32.saturating_pow(attempt as u32)).await;
+
+                    attempt += 1;
+                }
+                Err(err) => {
+                    warn!("Failed to deliver {} event: {}", webhook_event.event, err);
+
+                    webhook_stats.register_failed();
+
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Receivers verify the payload by computing the same HMAC with the shared secret
+fn sign(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|err| AppError::UnexpectedError(err.to_string()))?;
+
+    mac.update(body);
+
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
@@ -0,0 +1,52 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counters of the webhook deliveries, so a dead receiver is visible without digging through
+/// the logs
+#[derive(Debug, Default)]
+pub struct WebhookStats {
+    delivered: AtomicUsize,
+    dropped: AtomicUsize,
+    failed: AtomicUsize,
+    retried: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WebhookStatsSummary {
+    /// Events accepted by the receiver
+    pub delivered: usize,
+    /// Events that did not fit in the queue
+    pub dropped: usize,
+    /// Events that were not accepted after all the retries
+    pub failed: usize,
+    /// Failed attempts that were retried
+    pub retried: usize,
+}
+
+impl WebhookStats {
+    pub fn register_delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn register_dropped(&self, count: usize) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn register_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn register_retried(&self) {
+        self.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> WebhookStatsSummary {
+        WebhookStatsSummary {
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+        }
+    }
+}
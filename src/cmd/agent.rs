@@ -1,34 +1,111 @@
 use actix_web::web::Bytes;
-use pingora::server::{configuration::Opt, Server};
-use std::{net::SocketAddr, time::Duration};
-use tokio::sync::broadcast::channel;
+use pingora::{
+    server::{configuration::Opt, Server},
+    services::Service,
+};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{broadcast::channel, watch};
 
+use crate::agent::agent_id::resolve_agent_id;
+use crate::agent::agent_status::AgentStatus;
 use crate::agent::monitoring_service::MonitoringService;
 use crate::agent::reporting_service::ReportingService;
+use crate::agent::status_service::StatusService;
+use crate::agent::supervisor_service::SupervisorService;
+use crate::balancer::injected_header::InjectedHeader;
+use crate::balancer::label::Label;
+use crate::balancer::upstream_protocol::UpstreamProtocol;
 use crate::errors::result::Result;
 use crate::llamacpp::llamacpp_client::LlamacppClient;
+use crate::service_runner::run_forever;
 
+#[cfg(all(unix, feature = "systemd"))]
+use crate::systemd::systemd_service::SystemdService;
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle(
+    agent_id: Option<String>,
+    external_host: Option<String>,
     external_llamacpp_addr: SocketAddr,
+    hardware_class: Option<String>,
+    labels: Vec<Label>,
     local_llamacpp_addr: SocketAddr,
     llamacpp_api_key: Option<String>,
     management_addr: SocketAddr,
-    monitoring_interval: Duration,
+    max_concurrency: Option<usize>,
+    model_version: Option<String>,
     name: Option<String>,
+    spawn_llamacpp: Option<String>,
+    spawn_llamacpp_grace_period: Duration,
+    state_dir: Option<PathBuf>,
+    status_addr: Option<SocketAddr>,
+    status_interval: Duration,
+    tier: usize,
+    upstream_headers: Vec<InjectedHeader>,
+    upstream_protocol: Option<UpstreamProtocol>,
 ) -> Result<()> {
+    let agent_id = resolve_agent_id(
+        agent_id,
+        external_llamacpp_addr,
+        name.as_deref(),
+        state_dir.as_deref(),
+    )?;
+
     let (status_update_tx, _status_update_rx) = channel::<Bytes>(1);
 
-    let llamacpp_client = LlamacppClient::new(local_llamacpp_addr, llamacpp_api_key)?;
+    let agent_status = Arc::new(AgentStatus::new(status_interval));
+
+    let llamacpp_client = LlamacppClient::new(local_llamacpp_addr, llamacpp_api_key.clone())?;
+
+    let supervisor_service = match spawn_llamacpp {
+        Some(command_line) => {
+            let (llamacpp_error_tx, _llamacpp_error_rx) =
+                watch::channel(Some("llama.cpp has not started yet".to_string()));
+
+            Some(SupervisorService::new(
+                &command_line,
+                spawn_llamacpp_grace_period,
+                LlamacppClient::new(local_llamacpp_addr, llamacpp_api_key)?,
+                llamacpp_error_tx,
+            )?)
+        }
+        None => None,
+    };
+    let llamacpp_error_rx = supervisor_service
+        .as_ref()
+        .map(|supervisor_service| supervisor_service.subscribe());
 
     let monitoring_service = MonitoringService::new(
+        agent_status.clone(),
+        external_host,
         external_llamacpp_addr,
+        hardware_class,
+        labels
+            .into_iter()
+            .map(|label| (label.key, label.value))
+            .collect(),
         llamacpp_client,
-        monitoring_interval,
+        llamacpp_error_rx.clone(),
+        max_concurrency,
+        model_version,
+        status_interval,
         name,
         status_update_tx.clone(),
+        tier,
+        upstream_headers
+            .into_iter()
+            .map(|upstream_header| (upstream_header.name, upstream_header.value))
+            .collect(),
+        upstream_protocol,
     )?;
 
-    let reporting_service = ReportingService::new(management_addr, status_update_tx)?;
+    let reporting_service = ReportingService::new(
+        agent_id,
+        agent_status.clone(),
+        llamacpp_error_rx,
+        management_addr,
+        status_update_tx,
+    )?;
 
     let mut pingora_server = Server::new(Opt {
         upgrade: false,
@@ -39,7 +116,22 @@ pub fn handle(
     })?;
 
     pingora_server.bootstrap();
-    pingora_server.add_service(monitoring_service);
-    pingora_server.add_service(reporting_service);
-    pingora_server.run_forever();
+
+    let mut services: Vec<Box<dyn Service>> = vec![];
+
+    if let Some(supervisor_service) = supervisor_service {
+        services.push(Box::new(supervisor_service));
+    }
+
+    services.push(Box::new(monitoring_service));
+    services.push(Box::new(reporting_service));
+
+    #[cfg(all(unix, feature = "systemd"))]
+    services.push(Box::new(SystemdService::new(agent_status.clone())));
+
+    if let Some(status_addr) = status_addr {
+        services.push(Box::new(StatusService::new(status_addr, agent_status)));
+    }
+
+    run_forever(pingora_server, services)
 }
@@ -1,72 +1,576 @@
 use pingora::{
+    listeners::TcpSocketOptions,
+    protocols::TcpKeepalive,
     proxy::http_proxy_service,
-    server::{configuration::Opt, Server},
+    server::{
+        configuration::{Opt, ServerConf},
+        Server,
+    },
+    services::Service,
 };
-use std::{net::SocketAddr, sync::Arc};
-
-#[cfg(feature = "statsd_reporter")]
-use std::time::Duration;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use url::Url;
 
+use crate::balancer::admission_rate_policy::AdmissionRatePolicy;
+use crate::balancer::agent_name_pattern::AgentNamePattern;
+use crate::balancer::client_connection_limiter::ClientConnectionLimiter;
+use crate::balancer::cluster_stats::ClusterStats;
+use crate::balancer::config_file::ConfigFile;
+use crate::balancer::config_reloader::ConfigReloader;
+use crate::balancer::cooldown_policy::CooldownPolicy;
+use crate::balancer::dns_discovery_service::DnsDiscoveryService;
+use crate::balancer::downstream_connections::DownstreamConnections;
+use crate::balancer::downstream_connections_service::DownstreamConnectionsService;
+use crate::balancer::duplicate_agent_id_policy::DuplicateAgentIdPolicy;
+use crate::balancer::error_penalty_policy::ErrorPenaltyPolicy;
+use crate::balancer::excess_connection_policy::ExcessConnectionPolicy;
+use crate::balancer::flap_damping_policy::FlapDampingPolicy;
+use crate::balancer::hardware_class_weight::HardwareClassWeight;
+use crate::balancer::injected_header::InjectedHeader;
+use crate::balancer::listener::{Listener, ListenerPaths};
 use crate::balancer::management_service::ManagementService;
-use crate::balancer::proxy_service::ProxyService;
+use crate::balancer::method_policy::MethodPolicy;
+use crate::balancer::model_loading_probe::ModelLoadingProbe;
+use crate::balancer::oversized_batch_policy::OversizedBatchPolicy;
+use crate::balancer::path_rewrite_policy::{PathRewrite, PathRewritePolicy};
+use crate::balancer::peer_history_service::PeerHistoryService;
+use crate::balancer::placement_strategy::PlacementStrategy;
+use crate::balancer::pool_snapshot::PoolSnapshot;
+use crate::balancer::pool_snapshot_service::PoolSnapshotService;
+use crate::balancer::priority_policy::PriorityPolicy;
+use crate::balancer::proxy_service_builder::ProxyServiceBuilder;
+use crate::balancer::proxy_settings::{ProxySettings, ProxySettingsStore};
+use crate::balancer::request_coalescer::RequestCoalescer;
+use crate::balancer::response_cache::ResponseCache;
+use crate::balancer::response_compression_policy::ResponseCompressionPolicy;
+use crate::balancer::retry_budget::RetryBudget;
+use crate::balancer::rolling_drain_service::RollingDrainService;
+use crate::balancer::shutdown_audit_service::ShutdownAuditService;
+use crate::balancer::slots_endpoint_disabled_policy::SlotsEndpointDisabledPolicy;
+use crate::balancer::slots_probe::SlotsProbe;
+use crate::balancer::static_peers_config::StaticPeersConfig;
+use crate::balancer::static_peers_probe_service::StaticPeersProbeService;
+use crate::balancer::tie_break_strategy::TieBreakStrategy;
+use crate::balancer::token_quota::TokenQuota;
+use crate::balancer::token_quotas::TokenQuotas;
+use crate::balancer::upstream_headers_policy::UpstreamHeadersPolicy;
 use crate::balancer::upstream_peer_pool::UpstreamPeerPool;
-use crate::errors::result::Result;
+use crate::balancer::upstream_protocol::UpstreamProtocol;
+use crate::balancer::upstream_status_retry_policy::UpstreamStatusRetryPolicy;
+use crate::balancer::warmup_probe_service::WarmupProbeService;
+use crate::balancer::webhook_event::WebhookEventType;
+use crate::balancer::webhook_policy::WebhookPolicy;
+use crate::balancer::webhook_service::WebhookService;
+use crate::balancer::webhook_stats::WebhookStats;
+use crate::errors::{app_error::AppError, result::Result};
+use crate::service_runner::run_forever;
+
+#[cfg(unix)]
+use crate::balancer::config_reload_service::ConfigReloadService;
+
+#[cfg(feature = "grpc_health")]
+use crate::balancer::grpc_health_service::GrpcHealthService;
 
 #[cfg(feature = "statsd_reporter")]
-use crate::balancer::statsd_service::StatsdService;
+use crate::balancer::{endpoint_metrics::EndpointMetrics, statsd_service::StatsdService};
 
+#[cfg(all(unix, feature = "systemd"))]
+use crate::systemd::{
+    balancer_health_check::BalancerHealthCheck, systemd_service::SystemdService,
+};
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle(
+    compression_content_types: Vec<String>,
+    compression_level: Option<u32>,
+    compression_min_size: usize,
+    config_file: Option<PathBuf>,
+    context_chars_per_token: Option<f64>,
+    cooldown_after_requests: Option<usize>,
+    cooldown_slots_factor: f64,
+    cooldown_window: Duration,
+    disable_slot_accounting: bool,
+    discovery_dns_interval: Duration,
+    discovery_dns_name: Option<String>,
+    downstream_idle_timeout: Option<Duration>,
+    duplicate_agent_id_policy: DuplicateAgentIdPolicy,
+    error_penalty_weight: f64,
+    error_penalty_window: Duration,
+    excluded_agent_names: Vec<AgentNamePattern>,
+    flap_damping_max_changes: Option<usize>,
+    flap_damping_window: Duration,
+    forward_headers: Vec<String>,
+    #[cfg(feature = "grpc_health")] grpc_health_addr: Option<SocketAddr>,
+    hardware_class_weights: Vec<HardwareClassWeight>,
+    head_request_policy: MethodPolicy,
+    listeners: Vec<Listener>,
     management_addr: &SocketAddr,
     #[cfg(feature = "web_dashboard")] management_dashboard_enable: bool,
+    management_events_enable: bool,
+    max_connections_per_client: Option<usize>,
+    max_connections_per_client_exempt: Vec<IpAddr>,
+    max_downstream_connections: Option<usize>,
+    max_downstream_connections_policy: ExcessConnectionPolicy,
+    max_permit_handoffs: usize,
+    max_queued_requests: Option<usize>,
+    max_response_bytes: Option<usize>,
+    max_retries_per_request: usize,
+    model_loading_period: Duration,
+    no_capacity_retry_after: Duration,
+    no_capacity_status: u16,
+    options_request_policy: MethodPolicy,
+    path_prefix: Option<String>,
+    path_rewrites: Vec<PathRewrite>,
+    per_peer_admission_burst: usize,
+    per_peer_admission_rate: Option<f64>,
+    placement_strategy: PlacementStrategy,
+    prefer_newest_model_version: bool,
+    request_coalescing_enable: bool,
+    response_cache_max_entries: Option<usize>,
+    response_cache_max_response_size: usize,
+    response_cache_ttl: Duration,
+    retry_budget_min_retries: usize,
+    retry_budget_ratio: Option<f64>,
+    retry_budget_window: Duration,
+    retry_buffer_bytes: usize,
     reverseproxy_addr: &SocketAddr,
+    rewrite_content_type: bool,
     rewrite_host_header: bool,
+    rewrite_host_header_value: Option<String>,
+    shutdown_drain_timeout: Duration,
     slots_endpoint_enable: bool,
+    slots_endpoint_disabled_policy: SlotsEndpointDisabledPolicy,
+    slots_endpoint_token: Option<String>,
+    slots_probe_enable: bool,
+    slots_probe_timeout: Duration,
+    state_file: Option<PathBuf>,
+    state_file_ttl: Duration,
     #[cfg(feature = "statsd_reporter")] statsd_addr: Option<SocketAddr>,
     #[cfg(feature = "statsd_reporter")] statsd_prefix: String,
     #[cfg(feature = "statsd_reporter")] statsd_reporting_interval: Duration,
+    static_peers_file: Option<PathBuf>,
+    status_update_min_interval: Option<Duration>,
+    stream_error_event: bool,
+    strip_headers: Vec<String>,
+    target_agent_token: Option<String>,
+    tcp_keepalive_count: usize,
+    tcp_keepalive_idle: Option<Duration>,
+    tcp_keepalive_interval: Duration,
+    tie_break_strategy: TieBreakStrategy,
+    token_quota_fallback_cost: u64,
+    token_quotas: Vec<TokenQuota>,
+    upgrade: bool,
+    upgrade_sock: PathBuf,
+    upstream_connect_timeout: Duration,
+    upstream_connection_max_lifetime: Option<Duration>,
+    upstream_headers: Vec<InjectedHeader>,
+    upstream_protocol: UpstreamProtocol,
+    warmup_period: Option<Duration>,
+    warmup_probe_payload: Option<Value>,
+    warmup_probe_timeout: Duration,
+    webhook_events: Vec<WebhookEventType>,
+    webhook_max_retries: usize,
+    webhook_min_usable_peers: Option<usize>,
+    webhook_queue_size: usize,
+    webhook_secret: Option<String>,
+    webhook_timeout: Duration,
+    webhook_url: Option<Url>,
+    worker_threads: usize,
 ) -> Result<()> {
-    let mut pingora_server = Server::new(Opt {
-        upgrade: false,
-        daemon: false,
-        nocapture: false,
-        test: false,
-        conf: None,
-    })?;
+    let mut pingora_server = Server::new_with_opt_and_conf(
+        Opt {
+            upgrade,
+            daemon: false,
+            nocapture: false,
+            test: false,
+            conf: None,
+        },
+        ServerConf {
+            // pingora does not start a listener without threads
+            threads: worker_threads.max(1),
+            upgrade_sock: upgrade_sock.to_string_lossy().to_string(),
+            ..Default::default()
+        },
+    );
 
     pingora_server.bootstrap();
 
-    let upstream_peer_pool = Arc::new(UpstreamPeerPool::new());
+    let mut services: Vec<Box<dyn Service>> = vec![];
+
+    let admission_rate_policy = per_peer_admission_rate.map(|per_second| AdmissionRatePolicy {
+        // a burst of zero would never let a request through
+        burst: per_peer_admission_burst.max(1),
+        per_second,
+    });
+
+    let cooldown_policy = cooldown_after_requests.map(|after_requests| CooldownPolicy {
+        after_requests,
+        slots_factor: cooldown_slots_factor,
+        window: cooldown_window,
+    });
+
+    let error_penalty_policy = (error_penalty_weight > 0.0).then_some(ErrorPenaltyPolicy {
+        weight: error_penalty_weight,
+        window: error_penalty_window,
+    });
+
+    let flap_damping_policy =
+        flap_damping_max_changes.map(|max_capacity_changes| FlapDampingPolicy {
+            max_capacity_changes,
+            window: flap_damping_window,
+        });
+
+    let upstream_peer_pool = Arc::new(UpstreamPeerPool::new(
+        admission_rate_policy,
+        cooldown_policy,
+        duplicate_agent_id_policy,
+        error_penalty_policy,
+        flap_damping_policy,
+        hardware_class_weights
+            .into_iter()
+            .map(|hardware_class_weight| {
+                (
+                    hardware_class_weight.hardware_class,
+                    hardware_class_weight.weight,
+                )
+            })
+            .collect(),
+        max_permit_handoffs,
+        placement_strategy,
+        prefer_newest_model_version,
+        slots_endpoint_disabled_policy,
+        status_update_min_interval,
+        tie_break_strategy,
+        warmup_period,
+        warmup_probe_payload.is_some(),
+    ));
+
+    // unreachable static peers are excluded by the probe, the others are used right away
+    if let Some(static_peers_file) = &static_peers_file {
+        upstream_peer_pool.register_static_peers(StaticPeersConfig::load(static_peers_file)?)?;
+    }
+
+    if let Some(token_quota) = token_quotas.iter().find(|token_quota| {
+        token_quota.listener != "default"
+            && !listeners
+                .iter()
+                .any(|listener| listener.name == token_quota.listener)
+    }) {
+        return Err(AppError::UnexpectedError(format!(
+            "Token quota is set for an unknown listener: {}",
+            token_quota.listener
+        )));
+    }
+
+    let token_quotas = (!token_quotas.is_empty())
+        .then(|| Arc::new(TokenQuotas::new(token_quota_fallback_cost, token_quotas)));
+
+    if let Some(state_file) = &state_file {
+        // there is nothing to restore on the first start
+        if state_file.exists() {
+            let pool_snapshot = PoolSnapshot::load(state_file)?;
 
-    let mut proxy_service = http_proxy_service(
-        &pingora_server.configuration,
-        ProxyService::new(
-            rewrite_host_header,
-            slots_endpoint_enable,
+            if let Some(token_quotas) = &token_quotas {
+                token_quotas.restore(&pool_snapshot.token_quotas);
+            }
+
+            upstream_peer_pool.restore_snapshot(pool_snapshot, state_file_ttl)?;
+        }
+    }
+
+    let flag_settings = ProxySettings {
+        context_chars_per_token,
+        excluded_agent_names,
+        head_request_policy,
+        max_queued_requests,
+        max_response_bytes,
+        max_retries_per_request,
+        // mapping models to priorities only makes sense in the config file
+        model_priorities: BTreeMap::new(),
+        no_capacity_retry_after,
+        no_capacity_status,
+        options_request_policy,
+        oversized_batch_policy: OversizedBatchPolicy::default(),
+        // overrides are too structured for the command line flags
+        parameter_overrides: None,
+        path_rewrite_policy: PathRewritePolicy {
+            path_prefix,
+            path_rewrites,
+        },
+        priority_header: None,
+        priority_policy: PriorityPolicy::default(),
+        response_compression_policy: compression_level.map(|level| ResponseCompressionPolicy {
+            content_types: compression_content_types,
+            level,
+            min_size: compression_min_size,
+        }),
+        retry_buffer_bytes,
+        rewrite_content_type,
+        rewrite_host_header,
+        rewrite_host_header_value,
+        // rules are too structured for the command line flags
+        routing_rules: Vec::new(),
+        slots_endpoint_token,
+        stream_error_event,
+        target_agent_token,
+        upstream_connect_timeout,
+        upstream_connection_max_lifetime,
+        upstream_headers_policy: UpstreamHeadersPolicy {
+            forward_headers,
+            injected_headers: upstream_headers,
+            strip_headers,
+        },
+        upstream_protocol,
+        // retrying non-idempotent requests only makes sense to set in the config file
+        upstream_status_retry_policy: UpstreamStatusRetryPolicy::default(),
+    };
+
+    let proxy_settings = Arc::new(ProxySettingsStore::new(match &config_file {
+        Some(config_file) => ConfigFile::load(config_file)?.apply_to(&flag_settings),
+        None => flag_settings.clone(),
+    }));
+
+    upstream_peer_pool
+        .set_excluded_agent_names(proxy_settings.load().excluded_agent_names.to_owned());
+
+    let config_reloader = config_file.map(|config_file| {
+        Arc::new(ConfigReloader::new(
+            config_file,
+            flag_settings,
+            proxy_settings.clone(),
             upstream_peer_pool.clone(),
-        ),
-    );
+        ))
+    });
+
+    let client_connection_limiter = max_connections_per_client.map(|max_connections_per_client| {
+        Arc::new(ClientConnectionLimiter::new(
+            max_connections_per_client_exempt,
+            max_connections_per_client,
+        ))
+    });
+
+    let downstream_connections = Arc::new(DownstreamConnections::new(
+        max_downstream_connections_policy,
+        downstream_idle_timeout,
+        max_downstream_connections,
+    ));
+
+    let request_coalescer = request_coalescing_enable
+        .then(|| Arc::new(RequestCoalescer::new(response_cache_max_response_size)));
+
+    let response_cache = response_cache_max_entries.map(|max_entries| {
+        Arc::new(ResponseCache::new(
+            max_entries,
+            response_cache_max_response_size,
+            response_cache_ttl,
+        ))
+    });
+
+    let retry_budget = retry_budget_ratio.map(|ratio| {
+        Arc::new(RetryBudget::new(
+            retry_budget_min_retries,
+            ratio,
+            retry_budget_window,
+        ))
+    });
+
+    let model_loading_probe = Arc::new(ModelLoadingProbe::new(model_loading_period)?);
+    let slots_probe = Arc::new(SlotsProbe::new(slots_probe_enable, slots_probe_timeout)?);
+
+    let cluster_stats = Arc::new(ClusterStats::default());
+    let webhook_stats = Arc::new(WebhookStats::default());
+
+    #[cfg(feature = "statsd_reporter")]
+    let endpoint_metrics = Arc::new(EndpointMetrics::default());
+
+    let default_listener = Listener {
+        addr: *reverseproxy_addr,
+        api_key: None,
+        name: "default".to_string(),
+        paths: ListenerPaths::All,
+        slots_endpoint_enable,
+    };
+
+    #[cfg(all(unix, feature = "systemd"))]
+    let mut ready_addrs = vec![*management_addr];
+
+    let mut tcp_socket_options = TcpSocketOptions::default();
 
-    proxy_service.add_tcp(&reverseproxy_addr.clone().to_string());
+    tcp_socket_options.tcp_keepalive = tcp_keepalive_idle.map(|idle| TcpKeepalive {
+        count: tcp_keepalive_count,
+        idle,
+        interval: tcp_keepalive_interval,
+    });
 
-    pingora_server.add_service(proxy_service);
-    pingora_server.add_service(ManagementService::new(
+    for listener in std::iter::once(default_listener).chain(listeners) {
+        let listener_addr = listener.addr.to_string();
+
+        #[cfg(all(unix, feature = "systemd"))]
+        ready_addrs.push(listener.addr);
+
+        let mut proxy_service_builder = ProxyServiceBuilder::new()
+            .cluster_stats(cluster_stats.clone())
+            .downstream_connections(downstream_connections.clone())
+            .listener(listener)
+            .model_loading_probe(model_loading_probe.clone())
+            .proxy_settings(proxy_settings.clone())
+            .slots_probe(slots_probe.clone())
+            .upstream_peer_pool(upstream_peer_pool.clone());
+
+        if disable_slot_accounting {
+            proxy_service_builder = proxy_service_builder.disable_slot_accounting();
+        }
+
+        if let Some(client_connection_limiter) = &client_connection_limiter {
+            proxy_service_builder =
+                proxy_service_builder.client_connection_limiter(client_connection_limiter.clone());
+        }
+
+        if let Some(request_coalescer) = &request_coalescer {
+            proxy_service_builder =
+                proxy_service_builder.request_coalescer(request_coalescer.clone());
+        }
+
+        if let Some(response_cache) = &response_cache {
+            proxy_service_builder = proxy_service_builder.response_cache(response_cache.clone());
+        }
+
+        if let Some(retry_budget) = &retry_budget {
+            proxy_service_builder = proxy_service_builder.retry_budget(retry_budget.clone());
+        }
+
+        if let Some(token_quotas) = &token_quotas {
+            proxy_service_builder = proxy_service_builder.token_quotas(token_quotas.clone());
+        }
+
+        #[cfg(feature = "statsd_reporter")]
+        if statsd_addr.is_some() {
+            proxy_service_builder = proxy_service_builder.endpoint_metrics(endpoint_metrics.clone());
+        }
+
+        let mut proxy_service = http_proxy_service(
+            &pingora_server.configuration,
+            proxy_service_builder.build()?,
+        );
+
+        proxy_service.add_tcp_with_settings(&listener_addr, tcp_socket_options.clone());
+
+        services.push(Box::new(proxy_service));
+    }
+
+    services.push(Box::new(ManagementService::new(
         *management_addr,
+        cluster_stats,
+        config_reloader.clone(),
+        downstream_connections.clone(),
         #[cfg(feature = "web_dashboard")]
         management_dashboard_enable,
+        management_events_enable,
+        proxy_settings.clone(),
+        token_quotas.clone(),
         upstream_peer_pool.clone(),
-    ));
+        webhook_stats.clone(),
+    )));
+
+    services.push(Box::new(DownstreamConnectionsService::new(
+        downstream_connections.clone(),
+    )));
+    services.push(Box::new(PeerHistoryService::new(
+        upstream_peer_pool.clone(),
+    )));
+    services.push(Box::new(RollingDrainService::new(
+        upstream_peer_pool.clone(),
+    )));
+    services.push(Box::new(ShutdownAuditService::new(
+        shutdown_drain_timeout,
+        upstream_peer_pool.clone(),
+    )));
+
+    if let Some(discovery_dns_name) = discovery_dns_name {
+        services.push(Box::new(DnsDiscoveryService::new(
+            discovery_dns_name,
+            discovery_dns_interval,
+            upstream_peer_pool.clone(),
+        )));
+    }
+
+    if static_peers_file.is_some() {
+        services.push(Box::new(StaticPeersProbeService::new(
+            upstream_peer_pool.clone(),
+        )));
+    }
+
+    if let Some(warmup_probe_payload) = warmup_probe_payload {
+        services.push(Box::new(WarmupProbeService::new(
+            upstream_peer_pool.clone(),
+            warmup_probe_payload,
+            warmup_probe_timeout,
+        )?));
+    }
+
+    if let Some(webhook_url) = webhook_url {
+        services.push(Box::new(WebhookService::new(
+            upstream_peer_pool.clone(),
+            WebhookPolicy {
+                event_types: webhook_events,
+                max_retries: webhook_max_retries,
+                min_usable_peers: webhook_min_usable_peers,
+                queue_size: webhook_queue_size,
+                secret: webhook_secret,
+                timeout: webhook_timeout,
+                url: webhook_url,
+            },
+            webhook_stats,
+        )?));
+    }
+
+    if let Some(state_file) = state_file {
+        services.push(Box::new(PoolSnapshotService::new(
+            state_file,
+            token_quotas,
+            upstream_peer_pool.clone(),
+        )));
+    }
+
+    #[cfg(feature = "grpc_health")]
+    if let Some(grpc_health_addr) = grpc_health_addr {
+        services.push(Box::new(GrpcHealthService::new(
+            grpc_health_addr,
+            upstream_peer_pool.clone(),
+        )));
+    }
 
     #[cfg(feature = "statsd_reporter")]
     if let Some(statsd_addr) = statsd_addr {
         let statsd_service = StatsdService::new(
+            downstream_connections,
+            endpoint_metrics,
+            retry_budget,
             statsd_addr,
             statsd_prefix,
             statsd_reporting_interval,
             upstream_peer_pool.clone(),
         )?;
 
-        pingora_server.add_service(statsd_service);
+        services.push(Box::new(statsd_service));
     }
 
-    pingora_server.run_forever();
+    #[cfg(unix)]
+    if let Some(config_reloader) = config_reloader {
+        services.push(Box::new(ConfigReloadService::new(config_reloader)));
+    }
+
+    #[cfg(all(unix, feature = "systemd"))]
+    services.push(Box::new(SystemdService::new(Arc::new(
+        BalancerHealthCheck::new(ready_addrs, upstream_peer_pool.clone()),
+    ))));
+
+    run_forever(pingora_server, services)
 }
@@ -0,0 +1,292 @@
+use clap::Subcommand;
+use reqwest::{Method, RequestBuilder};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, time::Duration};
+use tokio::runtime::Runtime;
+
+use crate::errors::{app_error::AppError, result::Result};
+
+const WINDOWS: [&str; 3] = ["1m", "5m", "15m"];
+
+#[derive(Subcommand)]
+pub enum CtlCommand {
+    /// Lists and manages the registered agents
+    Agents {
+        #[command(subcommand)]
+        command: CtlAgentsCommand,
+    },
+    /// Reloads the balancer's config file
+    Reload,
+    /// Shows how busy the cluster is
+    Stats,
+}
+
+#[derive(Subcommand)]
+pub enum CtlAgentsCommand {
+    /// Stops sending new requests to the agent, so it can be restarted once its requests finish
+    Drain { agent_id: String },
+    /// Lists the registered agents
+    List,
+    /// Stops sending requests to the agent for a while
+    Quarantine {
+        agent_id: String,
+
+        #[arg(long = "for", default_value = "5m", value_parser = parse_quarantine_duration)]
+        /// How long the agent stays quarantined, for example `30s`, `5m`, or `1h`
+        duration: Duration,
+    },
+    /// Removes the agent from the pool, until it reports again
+    Remove { agent_id: String },
+}
+
+/// Accepts `30s`, `5m`, `1h`, or a number of seconds
+fn parse_quarantine_duration(arg: &str) -> Result<Duration> {
+    let (seconds, multiplier) = match arg.char_indices().last() {
+        Some((pos, 's')) => (&arg[..pos], 1),
+        Some((pos, 'm')) => (&arg[..pos], 60),
+        Some((pos, 'h')) => (&arg[..pos], 3600),
+        _ => (arg, 1),
+    };
+
+    Ok(Duration::from_secs(seconds.parse::<u64>()? * multiplier))
+}
+
+struct ManagementClient {
+    client: reqwest::Client,
+    management_addr: SocketAddr,
+    management_token: Option<String>,
+}
+
+impl ManagementClient {
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("http://{}{}", self.management_addr, path));
+
+        match &self.management_token {
+            Some(management_token) => request.bearer_auth(management_token),
+            None => request,
+        }
+    }
+
+    /// Returns None if the response has no body
+    async fn send(&self, request: RequestBuilder) -> Result<Option<Value>> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(AppError::UnexpectedError(format!(
+                "Management server responded with {}: {}",
+                status, body
+            )));
+        }
+
+        if body.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_str(&body)?))
+    }
+}
+
+fn agent_state(agent: &Value) -> &'static str {
+    let is_set = |field: &str| !agent[field].is_null() && agent[field] != false;
+
+    if is_set("error") {
+        "error"
+    } else if is_set("quarantined_until") {
+        "quarantined"
+    } else if is_set("is_draining") {
+        "draining"
+    } else if agent["warmed_up"] == false || is_set("model_loading_until") {
+        "loading"
+    } else if is_set("is_stale") {
+        "stale"
+    } else if agent["slots_idle"] == 0 {
+        "busy"
+    } else {
+        "ready"
+    }
+}
+
+fn print_agents(agents: &Value) {
+    let rows: Vec<[String; 5]> = agents["agents"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|agent| {
+            let slots_idle = agent["slots_idle"].as_u64().unwrap_or(0);
+            let slots_processing = agent["slots_processing"].as_u64().unwrap_or(0);
+
+            [
+                agent["agent_name"].as_str().unwrap_or("-").to_string(),
+                agent["agent_id"].as_str().unwrap_or("-").to_string(),
+                agent["external_llamacpp_addr"]
+                    .as_str()
+                    .unwrap_or("-")
+                    .to_string(),
+                format!("{}/{}", slots_idle, slots_idle + slots_processing),
+                agent_state(agent).to_string(),
+            ]
+        })
+        .collect();
+
+    print_table(["NAME", "ID", "ADDRESS", "IDLE SLOTS", "STATE"], &rows);
+}
+
+fn print_stats(stats: &Value) {
+    println!(
+        "Agents: {} ({} usable)",
+        stats["peers"], stats["usable_peers"]
+    );
+    println!(
+        "Slots: {} idle, {} processing, {} total",
+        stats["slots_idle"], stats["slots_processing"], stats["slots_total"]
+    );
+    println!(
+        "Requests waiting for a slot: {}",
+        stats["requests_waiting_for_permit"]
+    );
+    println!("Client connections: {}", stats["downstream_connections"]);
+
+    for window in WINDOWS {
+        let window_stats = &stats["windows"][window];
+
+        println!(
+            "Last {}: {:.2} requests/s, p50 {} ms, p95 {} ms, p99 {} ms, rejections {}",
+            window,
+            window_stats["requests_per_second"].as_f64().unwrap_or(0.0),
+            window_stats["p50_ms"],
+            window_stats["p95_ms"],
+            window_stats["p99_ms"],
+            window_stats["rejections"]
+        );
+    }
+}
+
+fn print_table<const COLUMNS: usize>(headers: [&str; COLUMNS], rows: &[[String; COLUMNS]]) {
+    let mut widths = headers.map(str::len);
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |cells: [&str; COLUMNS]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers));
+
+    for row in rows {
+        println!("{}", format_row(row.each_ref().map(String::as_str)));
+    }
+}
+
+async fn run(command: &CtlCommand, json: bool, management_client: ManagementClient) -> Result<()> {
+    let (response, message) = match command {
+        CtlCommand::Agents { command } => match command {
+            CtlAgentsCommand::Drain { agent_id } => (
+                management_client
+                    .send(
+                        management_client
+                            .request(Method::POST, "/api/v1/rolling_drain")
+                            .json(&json!({ "agent_ids": [agent_id] })),
+                    )
+                    .await?,
+                format!("Agent {} is draining", agent_id),
+            ),
+            CtlAgentsCommand::List => {
+                let agents = management_client
+                    .send(management_client.request(Method::GET, "/api/v1/agents"))
+                    .await?
+                    .unwrap_or_default();
+
+                if !json {
+                    print_agents(&agents);
+
+                    return Ok(());
+                }
+
+                (Some(agents), String::new())
+            }
+            CtlAgentsCommand::Quarantine { agent_id, duration } => (
+                management_client
+                    .send(
+                        management_client
+                            .request(
+                                Method::POST,
+                                &format!("/api/v1/agents/{}/quarantine", agent_id),
+                            )
+                            .json(&json!({ "duration_secs": duration.as_secs() })),
+                    )
+                    .await?,
+                format!("Agent {} is quarantined for {:?}", agent_id, duration),
+            ),
+            CtlAgentsCommand::Remove { agent_id } => (
+                management_client
+                    .send(
+                        management_client
+                            .request(Method::DELETE, &format!("/api/v1/agents/{}", agent_id)),
+                    )
+                    .await?,
+                format!("Agent {} is removed", agent_id),
+            ),
+        },
+        CtlCommand::Reload => (
+            management_client
+                .send(management_client.request(Method::POST, "/api/v1/config/reload"))
+                .await?,
+            "Config file is reloaded".to_string(),
+        ),
+        CtlCommand::Stats => {
+            let stats = management_client
+                .send(management_client.request(Method::GET, "/api/v1/stats"))
+                .await?
+                .unwrap_or_default();
+
+            if !json {
+                print_stats(&stats);
+
+                return Ok(());
+            }
+
+            (Some(stats), String::new())
+        }
+    };
+
+    if !json {
+        println!("{}", message);
+    } else if let Some(response) = response {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    }
+
+    Ok(())
+}
+
+pub fn handle(
+    command: &CtlCommand,
+    json: bool,
+    management_addr: SocketAddr,
+    management_token: Option<String>,
+) -> Result<()> {
+    Runtime::new()?.block_on(run(
+        command,
+        json,
+        ManagementClient {
+            client: reqwest::Client::new(),
+            management_addr,
+            management_token,
+        },
+    ))
+}
@@ -1,5 +1,14 @@
+pub mod ctl;
+pub mod testserver;
+
+#[cfg(feature = "agent")]
 pub mod agent;
+
+#[cfg(feature = "balancer")]
 pub mod balancer;
 
 #[cfg(feature = "ratatui_dashboard")]
 pub mod dashboard;
+
+#[cfg(feature = "balancer")]
+pub mod print_config;
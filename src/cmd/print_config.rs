@@ -0,0 +1,71 @@
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Command};
+use serde_json::{json, Map, Value};
+
+use crate::errors::{app_error::AppError, result::Result};
+
+const REDACTED: &str = "<redacted>";
+
+fn value_source_name(value_source: Option<ValueSource>) -> Option<&'static str> {
+    match value_source? {
+        ValueSource::CommandLine => Some("command_line"),
+        ValueSource::DefaultValue => Some("default"),
+        ValueSource::EnvVariable => Some("env"),
+        _ => None,
+    }
+}
+
+/// Values are listed as they were received, before they are parsed, so it is clear what the
+/// container actually got. Secrets are redacted.
+pub fn handle(command: &Command, matches: &ArgMatches, subcommand_name: &str) -> Result<()> {
+    let (Some(subcommand), Some(subcommand_matches)) = (
+        command.find_subcommand(subcommand_name),
+        matches.subcommand_matches(subcommand_name),
+    ) else {
+        return Err(AppError::UnexpectedError(format!(
+            "Unknown subcommand: {}",
+            subcommand_name
+        )));
+    };
+
+    let mut config = Map::new();
+
+    for arg in subcommand.get_arguments() {
+        let (Some(long), Some(env)) = (arg.get_long(), arg.get_env()) else {
+            // only the settings can be set through the environment
+            continue;
+        };
+
+        let id = arg.get_id().as_str();
+        let raw_values: Vec<String> = match subcommand_matches.try_get_raw(id) {
+            Ok(Some(raw_values)) => raw_values
+                .map(|raw_value| raw_value.to_string_lossy().into_owned())
+                .collect(),
+            _ => vec![],
+        };
+
+        let value = if raw_values.is_empty() {
+            Value::Null
+        } else if arg.is_hide_env_values_set() {
+            json!(REDACTED)
+        } else {
+            match arg.get_action() {
+                ArgAction::Append => json!(raw_values),
+                ArgAction::SetTrue => json!(subcommand_matches.get_flag(id)),
+                _ => json!(raw_values[0]),
+            }
+        };
+
+        config.insert(
+            long.to_string(),
+            json!({
+                "env": env.to_string_lossy(),
+                "source": value_source_name(subcommand_matches.value_source(id)),
+                "value": value,
+            }),
+        );
+    }
+
+    println!("{}", serde_json::to_string_pretty(&config)?);
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use actix_web::{rt::System, web::Data, App, HttpServer};
+use log::info;
+use std::net::{SocketAddr, TcpListener};
+
+use crate::{
+    errors::result::Result,
+    testserver::{
+        fake_llamacpp::{FakeLlamacpp, FakeLlamacppConfig},
+        http_route,
+    },
+};
+
+pub fn handle(
+    addr: SocketAddr,
+    config: FakeLlamacppConfig,
+    refuse_connections: bool,
+) -> Result<()> {
+    if refuse_connections {
+        return refuse(addr);
+    }
+
+    let h2c = config.h2c;
+    let fake_llamacpp = Data::new(FakeLlamacpp::new(config));
+
+    info!("Fake llama.cpp is listening on {}", addr);
+
+    let http_server = HttpServer::new(move || {
+        App::new()
+            .app_data(fake_llamacpp.clone())
+            .configure(http_route::completion::register)
+            .configure(http_route::health::register)
+            .configure(http_route::props::register)
+            .configure(http_route::slots::register)
+    });
+
+    // the agent still talks HTTP/1.1 to it
+    let http_server = if h2c {
+        http_server.bind_auto_h2c(addr)?
+    } else {
+        http_server.bind(addr)?
+    };
+
+    System::new().block_on(http_server.run())?;
+
+    Ok(())
+}
+
+/// Accepts and immediately drops connections, simulating a crashed llama.cpp
+fn refuse(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    info!("Fake llama.cpp is refusing connections on {}", addr);
+
+    for stream in listener.incoming() {
+        drop(stream?);
+    }
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
     #[error("Address parse error: {0}")]
@@ -13,6 +14,9 @@ pub enum AppError {
     #[error("Invalid request header: {0}")]
     InvalidHeaderError(#[from] reqwest::header::InvalidHeaderValue),
 
+    #[error("Semaphore acquire error: {0}")]
+    AcquireError(#[from] tokio::sync::AcquireError),
+
     #[error("IO Error: {0}")]
     IoError(#[from] std::io::Error),
 
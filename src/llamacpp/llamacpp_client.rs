@@ -4,11 +4,14 @@ use url::Url;
 
 use crate::{
     errors::result::Result,
-    llamacpp::{slot::Slot, slots_response::SlotsResponse},
+    llamacpp::{props::Props, slot::Slot, slots_response::SlotsResponse},
 };
 
 pub struct LlamacppClient {
     client: reqwest::Client,
+    health_endpoint_url: String,
+    metrics_endpoint_url: String,
+    props_endpoint_url: String,
     slots_endpoint_url: String,
 }
 
@@ -35,10 +38,63 @@ impl LlamacppClient {
 
         Ok(Self {
             client: builder.build()?,
+            health_endpoint_url: Url::parse(&format!("http://{}/health", addr))?.to_string(),
+            metrics_endpoint_url: Url::parse(&format!("http://{}/metrics", addr))?.to_string(),
+            props_endpoint_url: Url::parse(&format!("http://{}/props", addr))?.to_string(),
             slots_endpoint_url: Url::parse(&format!("http://{}/slots", addr))?.to_string(),
         })
     }
 
+    pub async fn is_healthy(&self) -> Result<bool> {
+        let response = self
+            .client
+            .get(self.health_endpoint_url.to_owned())
+            .send()
+            .await?;
+
+        Ok(response.status() == reqwest::StatusCode::OK)
+    }
+
+    /// Returns None if llama.cpp does not expose the props endpoint (older versions)
+    pub async fn get_props(&self) -> Result<Option<Props>> {
+        let response = self
+            .client
+            .get(self.props_endpoint_url.to_owned())
+            .send()
+            .await?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(Some(response.json::<Props>().await?)),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::NOT_IMPLEMENTED => Ok(None),
+            _ => Err("Unexpected response status".into()),
+        }
+    }
+
+    /// Requests waiting in the llama.cpp queue, because all the slots are busy. Returns None if
+    /// the metrics endpoint is not enabled (`llama-server --metrics`).
+    pub async fn get_requests_deferred(&self) -> Result<Option<usize>> {
+        let response = self
+            .client
+            .get(self.metrics_endpoint_url.to_owned())
+            .send()
+            .await?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let metrics = response.text().await?;
+
+                // Prometheus text format, for example `llamacpp:requests_deferred 3`
+                Ok(metrics
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("llamacpp:requests_deferred "))
+                    .find_map(|value| value.trim().parse::<f64>().ok())
+                    .map(|requests_deferred| requests_deferred as usize))
+            }
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::NOT_IMPLEMENTED => Ok(None),
+            _ => Err("Unexpected response status".into()),
+        }
+    }
+
     pub async fn get_available_slots(&self) -> Result<SlotsResponse> {
         let response = self
             .client
@@ -1,3 +1,13 @@
-pub mod llamacpp_client;
+#[cfg(any(feature = "agent", feature = "balancer"))]
+pub mod model_info;
+
 pub mod slot;
+
+#[cfg(feature = "agent")]
+pub mod llamacpp_client;
+
+#[cfg(feature = "agent")]
+pub mod props;
+
+#[cfg(feature = "agent")]
 pub mod slots_response;
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "agent")]
+use std::path::Path;
+
+#[cfg(feature = "agent")]
+use crate::llamacpp::props::Props;
+
+/// Capacity and capabilities of a llama.cpp instance, as detected by the agent
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ModelInfo {
+    pub alias: Option<String>,
+    pub context_size: Option<usize>,
+    /// None means llama.cpp did not report the capability
+    pub supports_embedding: Option<bool>,
+    /// None means llama.cpp did not report the capability
+    pub supports_infill: Option<bool>,
+    /// None means llama.cpp did not report the capability
+    pub supports_reranking: Option<bool>,
+    pub total_slots: Option<usize>,
+}
+
+#[cfg(feature = "agent")]
+impl ModelInfo {
+    pub fn new_from_props(props: Props) -> Self {
+        let alias = props.model_alias.or_else(|| {
+            props.model_path.as_ref().and_then(|model_path| {
+                Path::new(model_path)
+                    .file_stem()
+                    .map(|file_stem| file_stem.to_string_lossy().to_string())
+            })
+        });
+
+        Self {
+            alias,
+            context_size: props
+                .default_generation_settings
+                .and_then(|settings| settings.n_ctx),
+            supports_embedding: props.embedding,
+            supports_infill: props.infill,
+            supports_reranking: props.reranking,
+            total_slots: props.total_slots,
+        }
+    }
+}
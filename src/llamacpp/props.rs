@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DefaultGenerationSettings {
+    pub n_ctx: Option<usize>,
+}
+
+/// Subset of the llama.cpp `/props` response. Fields differ between llama.cpp versions, so
+/// everything is optional
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Props {
+    pub build_info: Option<String>,
+    pub default_generation_settings: Option<DefaultGenerationSettings>,
+    pub embedding: Option<bool>,
+    pub infill: Option<bool>,
+    pub model_alias: Option<String>,
+    pub model_path: Option<String>,
+    pub reranking: Option<bool>,
+    pub total_slots: Option<usize>,
+}
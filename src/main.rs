@@ -1,16 +1,69 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use std::{
     net::{SocketAddr, ToSocketAddrs},
     time::Duration,
 };
 
-use crate::errors::result::Result;
+use crate::{
+    cmd::ctl::CtlCommand,
+    errors::result::Result,
+    testserver::fake_llamacpp::FakeLlamacppConfig,
+};
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+use std::path::PathBuf;
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+use crate::{
+    balancer::{injected_header::InjectedHeader, upstream_protocol::UpstreamProtocol},
+    errors::app_error::AppError,
+};
+
+#[cfg(feature = "agent")]
+use crate::balancer::label::Label;
+
+#[cfg(feature = "balancer")]
+use serde_json::Value;
+
+#[cfg(feature = "balancer")]
+use std::net::IpAddr;
+
+#[cfg(feature = "balancer")]
+use url::Url;
+
+#[cfg(feature = "balancer")]
+use crate::balancer::{
+    agent_name_pattern::AgentNamePattern,
+    duplicate_agent_id_policy::DuplicateAgentIdPolicy,
+    excess_connection_policy::ExcessConnectionPolicy,
+    hardware_class_weight::HardwareClassWeight,
+    listener::Listener,
+    method_policy::MethodPolicy,
+    path_rewrite_policy::{PathRewrite, PathRewritePolicy},
+    placement_strategy::PlacementStrategy,
+    slots_endpoint_disabled_policy::SlotsEndpointDisabledPolicy,
+    tie_break_strategy::TieBreakStrategy,
+    token_quota::TokenQuota,
+    webhook_event::WebhookEventType,
+};
 
-mod agent;
 mod balancer;
 mod cmd;
 mod errors;
 mod llamacpp;
+mod testserver;
+
+#[cfg(feature = "agent")]
+mod agent;
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+mod service_runner;
+
+#[cfg(all(unix, feature = "systemd"))]
+mod systemd;
+
+#[cfg(feature = "agent")]
+const MIN_STATUS_INTERVAL: Duration = Duration::from_millis(100);
 
 fn resolve_socket_addr(s: &str) -> Result<SocketAddr> {
     let addrs: Vec<SocketAddr> = s.to_socket_addrs()?.collect();
@@ -30,12 +83,114 @@ fn resolve_socket_addr(s: &str) -> Result<SocketAddr> {
     Err("Failed to resolve socket address".into())
 }
 
+/// Accepts `4/s`, `120/m`, or a number per second
+#[cfg(feature = "balancer")]
+fn parse_admission_rate(arg: &str) -> Result<f64> {
+    let (count, per_seconds) = match arg.split_once('/') {
+        Some((count, "s")) => (count, 1.0),
+        Some((count, "m")) => (count, 60.0),
+        Some(_) => {
+            return Err(AppError::UnexpectedError(format!(
+                "Invalid admission rate: {} (expected a unit of \"s\" or \"m\")",
+                arg
+            )))
+        }
+        None => (arg, 1.0),
+    };
+
+    match count.parse::<f64>() {
+        Ok(count) if count > 0.0 && count.is_finite() => Ok(count / per_seconds),
+        _ => Err(AppError::UnexpectedError(format!(
+            "Invalid admission rate: {} (expected a positive number)",
+            arg
+        ))),
+    }
+}
+
+#[cfg(feature = "balancer")]
+fn parse_agent_name_pattern(arg: &str) -> Result<AgentNamePattern> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_duplicate_agent_id_policy(arg: &str) -> Result<DuplicateAgentIdPolicy> {
+    arg.parse()
+}
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
 fn parse_duration(arg: &str) -> Result<Duration> {
     let seconds = arg.parse()?;
 
     Ok(std::time::Duration::from_secs(seconds))
 }
 
+fn parse_duration_millis(arg: &str) -> Result<Duration> {
+    let millis = arg.parse()?;
+
+    Ok(std::time::Duration::from_millis(millis))
+}
+
+#[cfg(feature = "balancer")]
+fn parse_error_status(arg: &str) -> Result<u16> {
+    match arg.parse::<u16>()? {
+        status @ 400..=599 => Ok(status),
+        status => Err(AppError::UnexpectedError(format!(
+            "Invalid status: {} (expected 4xx or 5xx)",
+            status
+        ))),
+    }
+}
+
+#[cfg(feature = "balancer")]
+fn parse_excess_connection_policy(arg: &str) -> Result<ExcessConnectionPolicy> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_hardware_class_weight(arg: &str) -> Result<HardwareClassWeight> {
+    arg.parse()
+}
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+fn parse_injected_header(arg: &str) -> Result<InjectedHeader> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_json(arg: &str) -> Result<Value> {
+    Ok(serde_json::from_str(arg)?)
+}
+
+#[cfg(feature = "agent")]
+fn parse_label(arg: &str) -> Result<Label> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_listener(arg: &str) -> Result<Listener> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_method_policy(arg: &str) -> Result<MethodPolicy> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_path_prefix(arg: &str) -> Result<String> {
+    PathRewritePolicy::parse_path_prefix(arg)
+}
+
+#[cfg(feature = "balancer")]
+fn parse_path_rewrite(arg: &str) -> Result<PathRewrite> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_slots_endpoint_disabled_policy(arg: &str) -> Result<SlotsEndpointDisabledPolicy> {
+    arg.parse()
+}
+
 fn parse_socket_addr(arg: &str) -> Result<SocketAddr> {
     match arg.parse() {
         Ok(socketaddr) => Ok(socketaddr),
@@ -43,6 +198,55 @@ fn parse_socket_addr(arg: &str) -> Result<SocketAddr> {
     }
 }
 
+/// Accepts `250ms`, `5s`, or a number of seconds
+#[cfg(feature = "agent")]
+fn parse_status_interval(arg: &str) -> Result<Duration> {
+    let status_interval = match arg.strip_suffix("ms") {
+        Some(millis) => Duration::from_millis(millis.parse()?),
+        None => Duration::from_secs(arg.strip_suffix('s').unwrap_or(arg).parse()?),
+    };
+
+    // reports more often than that would mostly measure the reporting itself
+    if status_interval < MIN_STATUS_INTERVAL {
+        return Err(AppError::UnexpectedError(format!(
+            "Status interval has to be at least {}ms",
+            MIN_STATUS_INTERVAL.as_millis()
+        )));
+    }
+
+    Ok(status_interval)
+}
+
+#[cfg(feature = "balancer")]
+fn parse_placement_strategy(arg: &str) -> Result<PlacementStrategy> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_tie_break_strategy(arg: &str) -> Result<TieBreakStrategy> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_token_quota(arg: &str) -> Result<TokenQuota> {
+    arg.parse()
+}
+
+#[cfg(any(feature = "agent", feature = "balancer"))]
+fn parse_upstream_protocol(arg: &str) -> Result<UpstreamProtocol> {
+    arg.parse()
+}
+
+#[cfg(feature = "balancer")]
+fn parse_url(arg: &str) -> Result<Url> {
+    Ok(arg.parse()?)
+}
+
+#[cfg(feature = "balancer")]
+fn parse_webhook_event_type(arg: &str) -> Result<WebhookEventType> {
+    arg.parse()
+}
+
 #[derive(Parser)]
 #[command(arg_required_else_help(true), version, about, long_about = None)]
 /// Stateful load balancer for llama.cpp
@@ -51,73 +255,817 @@ struct Cli {
     command: Option<Commands>,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
+    #[cfg(feature = "agent")]
     /// Monitors llama.cpp instance and reports their status to the balancer
     Agent {
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(long, env = "PADDLER_AGENT_ID")]
+        /// Identifier the agent registers with in the balancer. If not provided, it is read from
+        /// `--state-dir`, or derived from the agent name and the external llama.cpp address
+        agent_id: Option<String>,
+
+        #[arg(long, env = "PADDLER_EXTERNAL_HOST")]
+        /// Host name the balancer sends in the `Host` header when forwarding requests to this
+        /// agent, if it rewrites the header (optional)
+        external_host: Option<String>,
+
+        #[arg(long, env = "PADDLER_EXTERNAL_LLAMACPP_ADDR", value_parser = parse_socket_addr)]
         /// Address of llama.cpp instance that the balancer will forward requests to. If not
         /// provided, then `--local-llamacpp-addr` will be used
         external_llamacpp_addr: Option<SocketAddr>,
 
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(long, env = "PADDLER_HARDWARE_CLASS")]
+        /// Hardware class of the llama.cpp instance, like `gpu` or `cpu`, that the balancer can
+        /// weight the instances by (optional)
+        hardware_class: Option<String>,
+
+        #[arg(
+            long = "label",
+            env = "PADDLER_LABEL",
+            value_parser = parse_label,
+            value_delimiter = ','
+        )]
+        /// Label of the agent in the `key=value` format, that clients can select the agents by
+        /// (can be repeated or comma separated)
+        labels: Vec<Label>,
+
+        #[arg(long, env = "PADDLER_LOCAL_LLAMACPP_ADDR", value_parser = parse_socket_addr)]
         /// Address of the local llama.cpp instance that the agent will monitor
         local_llamacpp_addr: SocketAddr,
 
-        #[arg(long)]
+        #[arg(long, env = "PADDLER_LLAMACPP_API_KEY", hide_env_values = true)]
         /// API key for the llama.cpp instance (optional)
         llamacpp_api_key: Option<String>,
 
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(long, env = "PADDLER_MANAGEMENT_ADDR", value_parser = parse_socket_addr)]
         /// Address of the management server that the agent will report to
         management_addr: SocketAddr,
 
-        #[arg(long, default_value = "10", value_parser = parse_duration)]
-        /// Interval (in seconds) at which the agent will report the status of the llama.cpp instance
-        monitoring_interval: Duration,
+        #[arg(long, env = "PADDLER_MAX_CONCURRENCY")]
+        /// Maximum number of requests the balancer sends to this llama.cpp instance at the same
+        /// time, even if it reports more slots (optional)
+        max_concurrency: Option<usize>,
 
-        #[arg(long)]
+        #[arg(long, env = "PADDLER_MODEL_VERSION")]
+        /// Version of the model served by llama.cpp, like `1.2` or `2024-06-01`, so the
+        /// balancer can prefer the newest one (optional)
+        model_version: Option<String>,
+
+        #[arg(long, env = "PADDLER_NAME")]
         /// Name of the agent (optional)
         name: Option<String>,
+
+        #[arg(long, env = "PADDLER_SPAWN_LLAMACPP")]
+        /// Command line used to launch and supervise llama.cpp as a child process of the agent
+        /// (optional)
+        spawn_llamacpp: Option<String>,
+
+        #[arg(
+            long,
+            env = "PADDLER_SPAWN_LLAMACPP_GRACE_PERIOD",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) the supervised llama.cpp is given to exit after SIGTERM before it
+        /// is killed (on Windows, it is killed right away)
+        spawn_llamacpp_grace_period: Duration,
+
+        #[arg(long, env = "PADDLER_STATE_DIR")]
+        /// Directory where the agent persists its generated id across restarts (optional)
+        state_dir: Option<PathBuf>,
+
+        #[arg(long, env = "PADDLER_STATUS_ADDR", value_parser = parse_socket_addr)]
+        /// Address of the agent's local status server, exposing `/status` and `/healthz`
+        /// (optional)
+        status_addr: Option<SocketAddr>,
+
+        #[arg(
+            long,
+            env = "PADDLER_STATUS_INTERVAL",
+            alias = "monitoring-interval",
+            default_value = "10s",
+            value_parser = parse_status_interval
+        )]
+        /// Interval at which the agent will report the status of the llama.cpp instance, like
+        /// `250ms` or `5s` (at least 100ms)
+        status_interval: Duration,
+
+        #[arg(long, env = "PADDLER_TIER", default_value = "1")]
+        /// Tier of the llama.cpp instance. The balancer only uses higher tiers when all the
+        /// instances in lower tiers are busy
+        tier: usize,
+
+        #[arg(
+            long = "upstream-header",
+            env = "PADDLER_UPSTREAM_HEADER",
+            value_parser = parse_injected_header,
+            value_delimiter = '\n'
+        )]
+        /// Header the balancer adds to the requests it forwards to this llama.cpp instance, for
+        /// example `X-Tenant-Id: acme` (can be repeated or newline separated)
+        upstream_headers: Vec<InjectedHeader>,
+
+        #[arg(long, env = "PADDLER_UPSTREAM_PROTOCOL", value_parser = parse_upstream_protocol)]
+        /// Protocol the balancer speaks to this llama.cpp instance: `h1`, or `h2` for HTTP/2
+        /// without TLS, overrides the balancer's `--upstream-protocol` (optional)
+        upstream_protocol: Option<UpstreamProtocol>,
     },
+    #[cfg(feature = "balancer")]
     /// Balances incoming requests to llama.cpp instances and optionally provides a web dashboard
     Balancer {
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(
+            long = "compression-content-type",
+            env = "PADDLER_COMPRESSION_CONTENT_TYPE",
+            default_value = "application/json",
+            value_delimiter = ','
+        )]
+        /// Content types of the responses that are compressed, if the compression is enabled
+        /// (can be repeated or comma separated)
+        compression_content_types: Vec<String>,
+
+        #[arg(
+            long,
+            env = "PADDLER_COMPRESSION_LEVEL",
+            value_parser = clap::value_parser!(u32).range(1..=9)
+        )]
+        /// Compress the responses for the clients that accept it (gzip, brotli, or zstd) at this
+        /// level, from 1 (fastest) to 9 (smallest) (optional)
+        compression_level: Option<u32>,
+
+        #[arg(long, env = "PADDLER_COMPRESSION_MIN_SIZE", default_value = "1024")]
+        /// Responses smaller than this many bytes are not compressed
+        compression_min_size: usize,
+
+        #[arg(long, env = "PADDLER_CONFIG_FILE")]
+        /// Path to a JSON config file with hot-reloadable settings, re-read on SIGHUP (optional)
+        config_file: Option<PathBuf>,
+
+        #[arg(long, env = "PADDLER_CONTEXT_CHARS_PER_TOKEN")]
+        /// Estimate the prompt length with this many characters per token, and only send
+        /// requests to agents whose context can fit the prompt (optional)
+        context_chars_per_token: Option<f64>,
+
+        #[arg(long, env = "PADDLER_COOLDOWN_AFTER_REQUESTS")]
+        /// Agents that served this many requests within `--cooldown-window` are preferred less,
+        /// to give them some breathing room (optional)
+        cooldown_after_requests: Option<usize>,
+
+        #[arg(long, env = "PADDLER_COOLDOWN_SLOTS_FACTOR", default_value = "0.5")]
+        /// Idle slots of an agent that is cooling down are multiplied by this factor when
+        /// picking the agent
+        cooldown_slots_factor: f64,
+
+        #[arg(
+            long,
+            env = "PADDLER_COOLDOWN_WINDOW",
+            default_value = "60",
+            value_parser = parse_duration
+        )]
+        /// Sliding window (in seconds) in which the requests are counted for the cooldown
+        cooldown_window: Duration,
+
+        #[arg(long, env = "PADDLER_DISCOVERY_DNS_NAME")]
+        /// DNS name (`host:port`) whose records are the llama.cpp addresses of the agents, for
+        /// example a headless Kubernetes service (optional)
+        discovery_dns_name: Option<String>,
+
+        #[arg(long, env = "PADDLER_DISABLE_SLOT_ACCOUNTING")]
+        /// Observe mode: forward the requests to the reachable agents in turn, without waiting
+        /// for their idle slots, to check the connectivity before relying on the slots
+        disable_slot_accounting: bool,
+
+        #[arg(
+            long,
+            env = "PADDLER_DISCOVERY_DNS_INTERVAL",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// How often (in seconds) to resolve `--discovery-dns-name`
+        discovery_dns_interval: Duration,
+
+        #[arg(long, env = "PADDLER_DOWNSTREAM_IDLE_TIMEOUT", value_parser = parse_duration)]
+        /// Close the client keep-alive connections that sent no request for this many seconds;
+        /// connections with a request in progress are never closed (optional, unix only)
+        downstream_idle_timeout: Option<Duration>,
+
+        #[arg(
+            long,
+            env = "PADDLER_DUPLICATE_AGENT_ID_POLICY",
+            default_value = "replace",
+            value_parser = parse_duplicate_agent_id_policy
+        )]
+        /// What to do when an agent registers with the id of an already connected agent:
+        /// `replace` the previous registration, or `reject` the new one
+        duplicate_agent_id_policy: DuplicateAgentIdPolicy,
+
+        #[arg(long, env = "PADDLER_ERROR_PENALTY_WEIGHT", default_value = "0")]
+        /// How much each recent error (5xx response or a broken connection) makes an agent less
+        /// preferred, zero disables the penalty
+        error_penalty_weight: f64,
+
+        #[arg(
+            long,
+            env = "PADDLER_ERROR_PENALTY_WINDOW",
+            default_value = "60",
+            value_parser = parse_duration
+        )]
+        /// Sliding window (in seconds) in which the errors are counted for the penalty
+        error_penalty_window: Duration,
+
+        #[arg(
+            long = "exclude-agent-name",
+            env = "PADDLER_EXCLUDE_AGENT_NAME",
+            value_parser = parse_agent_name_pattern,
+            value_delimiter = ','
+        )]
+        /// Agents whose names match the pattern (`*` matches any characters, a pattern without it
+        /// any part of the name) get no requests, but stay registered (can be repeated or comma
+        /// separated)
+        excluded_agent_names: Vec<AgentNamePattern>,
+
+        #[arg(long, env = "PADDLER_FLAP_DAMPING_MAX_CHANGES")]
+        /// Agents whose reported total slots changed more than this many times within
+        /// `--flap-damping-window` are held at their last stable total until the reports settle
+        /// (optional)
+        flap_damping_max_changes: Option<usize>,
+
+        #[arg(
+            long,
+            env = "PADDLER_FLAP_DAMPING_WINDOW",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// Sliding window (in seconds) in which the changes of the reported slots are counted
+        flap_damping_window: Duration,
+
+        #[arg(long, env = "PADDLER_FORWARD_HEADERS", value_delimiter = ',')]
+        /// Headers that are stripped by default (hop-by-hop headers and `Cookie`), but should be
+        /// forwarded to llama.cpp anyway (can be repeated or comma separated)
+        forward_headers: Vec<String>,
+
+        #[cfg(feature = "grpc_health")]
+        #[arg(long, env = "PADDLER_GRPC_HEALTH_ADDR", value_parser = parse_socket_addr)]
+        /// Address of the gRPC server exposing the `grpc.health.v1.Health` service (optional)
+        grpc_health_addr: Option<SocketAddr>,
+
+        #[arg(
+            long = "hardware-class-weight",
+            env = "PADDLER_HARDWARE_CLASS_WEIGHT",
+            value_parser = parse_hardware_class_weight,
+            value_delimiter = ','
+        )]
+        /// Multiplier of the idle slots of the agents in a hardware class when they are
+        /// compared, for example `gpu=4` or `cpu=0.25`. The classes that are not listed have the
+        /// weight of 1 (can be repeated or comma separated)
+        hardware_class_weights: Vec<HardwareClassWeight>,
+
+        #[arg(
+            long,
+            env = "PADDLER_HEAD_REQUEST_POLICY",
+            default_value = "forward",
+            value_parser = parse_method_policy
+        )]
+        /// What to do with `HEAD` requests: `forward` them to an agent (without taking a slot),
+        /// `respond` from the balancer (`200` if any agent is usable, `503` otherwise), or
+        /// `reject` them with `405`
+        head_request_policy: MethodPolicy,
+
+        #[arg(
+            long = "listener",
+            env = "PADDLER_LISTENER",
+            value_parser = parse_listener,
+            value_delimiter = '\n'
+        )]
+        /// Additional inference listener with its own policy, for example
+        /// `name=public,addr=0.0.0.0:8080,paths=public,api_key=secret` (can be repeated or
+        /// newline separated)
+        listeners: Vec<Listener>,
+
+        #[arg(long, env = "PADDLER_MANAGEMENT_ADDR", value_parser = parse_socket_addr)]
         /// Address of the management server that the balancer will report to
         management_addr: SocketAddr,
 
         #[cfg(feature = "web_dashboard")]
-        #[arg(long)]
+        #[arg(long, env = "PADDLER_MANAGEMENT_DASHBOARD_ENABLE")]
         /// Enable the web management dashboard
         management_dashboard_enable: bool,
 
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(long, env = "PADDLER_MANAGEMENT_EVENTS_ENABLE")]
+        /// Enable the websocket endpoint that streams pool events (`/api/v1/events`)
+        management_events_enable: bool,
+
+        #[arg(long, env = "PADDLER_MAX_CONNECTIONS_PER_CLIENT")]
+        /// Reject the requests on new connections with 429 when the client IP already has this
+        /// many connections open (optional)
+        max_connections_per_client: Option<usize>,
+
+        #[arg(
+            long = "max-connections-per-client-exempt",
+            env = "PADDLER_MAX_CONNECTIONS_PER_CLIENT_EXEMPT",
+            value_delimiter = ','
+        )]
+        /// Client IP that is not limited by `--max-connections-per-client` (can be repeated or
+        /// comma separated)
+        max_connections_per_client_exempt: Vec<IpAddr>,
+
+        #[arg(long, env = "PADDLER_MAX_DOWNSTREAM_CONNECTIONS")]
+        /// How many client connections can be open at once across all the listeners; the open
+        /// connections are never closed to make room (optional)
+        max_downstream_connections: Option<usize>,
+
+        #[arg(
+            long,
+            env = "PADDLER_MAX_DOWNSTREAM_CONNECTIONS_POLICY",
+            default_value = "reject",
+            value_parser = parse_excess_connection_policy
+        )]
+        /// What to do with the new connections over `--max-downstream-connections`: `reject`
+        /// their first request with 503 and close them, or make it `wait` for a free connection
+        max_downstream_connections_policy: ExcessConnectionPolicy,
+
+        #[arg(long, env = "PADDLER_MAX_PERMIT_HANDOFFS", default_value = "3")]
+        /// How many times a request with requirements (labels or context size) can pass a freed
+        /// slot it cannot use to the next waiting request, before it gives up with 503
+        max_permit_handoffs: usize,
+
+        #[arg(long, env = "PADDLER_MAX_QUEUED_REQUESTS")]
+        /// Reject requests with 503 instead of queueing them when there are no idle slots and at
+        /// least this many requests are already waiting (optional)
+        max_queued_requests: Option<usize>,
+
+        #[arg(long, env = "PADDLER_MAX_RESPONSE_BYTES")]
+        /// Cut off the responses to the clients once they get larger than this many bytes, as a
+        /// safety valve against runaway generations (optional)
+        max_response_bytes: Option<usize>,
+
+        #[arg(long, env = "PADDLER_MAX_RETRIES_PER_REQUEST", default_value = "3")]
+        /// Maximum number of times a single request can be retried, across all the retry paths
+        max_retries_per_request: usize,
+
+        #[arg(
+            long,
+            env = "PADDLER_MODEL_LOADING_PERIOD",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// How long (in seconds) an agent gets no requests after its llama.cpp responded that it
+        /// is still loading the model
+        model_loading_period: Duration,
+
+        #[arg(
+            long,
+            env = "PADDLER_NO_CAPACITY_RETRY_AFTER",
+            default_value = "1",
+            value_parser = parse_duration
+        )]
+        /// `Retry-After` (in seconds) of the responses to the requests that no agent can take
+        no_capacity_retry_after: Duration,
+
+        #[arg(
+            long,
+            env = "PADDLER_NO_CAPACITY_STATUS",
+            default_value = "503",
+            value_parser = parse_error_status
+        )]
+        /// Status of the responses to the requests that no agent can take (for example `429`,
+        /// for the clients that back off only on that)
+        no_capacity_status: u16,
+
+        #[arg(
+            long,
+            env = "PADDLER_OPTIONS_REQUEST_POLICY",
+            default_value = "forward",
+            value_parser = parse_method_policy
+        )]
+        /// What to do with `OPTIONS` requests: `forward` them to an agent (without taking a
+        /// slot), `respond` from the balancer (including the CORS preflight requests), or
+        /// `reject` them with `405`
+        options_request_policy: MethodPolicy,
+
+        #[arg(long, env = "PADDLER_PATH_PREFIX", value_parser = parse_path_prefix)]
+        /// Prefix under which the balancer is mounted (for example `/llm`), stripped before the
+        /// requests are forwarded to llama.cpp (optional)
+        path_prefix: Option<String>,
+
+        #[arg(
+            long = "path-rewrite",
+            env = "PADDLER_PATH_REWRITE",
+            value_parser = parse_path_rewrite,
+            value_delimiter = ','
+        )]
+        /// Path forwarded to llama.cpp under a different name, for example
+        /// `/legacy/complete=/completion` (can be repeated or comma separated)
+        path_rewrites: Vec<PathRewrite>,
+
+        #[arg(long, env = "PADDLER_PER_PEER_ADMISSION_BURST", default_value = "1")]
+        /// New requests a single agent can take at once when `--per-peer-admission-rate` is set
+        per_peer_admission_burst: usize,
+
+        #[arg(long, env = "PADDLER_PER_PEER_ADMISSION_RATE", value_parser = parse_admission_rate)]
+        /// Maximum rate of new requests sent to a single agent, for example `4/s` or `120/m`;
+        /// the rest goes to the other agents or waits (optional, unlimited by default)
+        per_peer_admission_rate: Option<f64>,
+
+        #[arg(
+            long,
+            env = "PADDLER_PLACEMENT_STRATEGY",
+            default_value = "spread",
+            value_parser = parse_placement_strategy
+        )]
+        /// How to distribute the requests: `spread` (to the agents with the most idle slots),
+        /// or `pack` (to the busiest agents that still have an idle slot, so the others can be
+        /// powered down)
+        placement_strategy: PlacementStrategy,
+
+        #[arg(long, env = "PADDLER_PREFER_NEWEST_MODEL_VERSION")]
+        /// Prefer the agents with the newest `--model-version` among the agents serving the
+        /// same model, for example to shift the traffic to the upgraded agents
+        prefer_newest_model_version: bool,
+
+        #[arg(long)]
+        /// Print the effective configuration (from the flags, the `PADDLER_*` environment
+        /// variables, and the defaults) as JSON, and exit without starting the balancer
+        print_config: bool,
+
+        #[arg(long, env = "PADDLER_REQUEST_COALESCING_ENABLE")]
+        /// Make the deterministic (`temperature` set to zero, not streamed) completion requests
+        /// wait for the response of an identical request that is already in progress, instead
+        /// of taking a slot each
+        request_coalescing_enable: bool,
+
+        #[arg(long, env = "PADDLER_RESPONSE_CACHE_MAX_ENTRIES")]
+        /// Cache up to this many responses to deterministic (`temperature` set to zero, not
+        /// streamed) completion requests in memory (optional)
+        response_cache_max_entries: Option<usize>,
+
+        #[arg(
+            long,
+            env = "PADDLER_RESPONSE_CACHE_MAX_RESPONSE_SIZE",
+            default_value = "1048576"
+        )]
+        /// Responses larger than this (in bytes) are not cached, nor shared with the coalesced
+        /// requests
+        response_cache_max_response_size: usize,
+
+        #[arg(
+            long,
+            env = "PADDLER_RESPONSE_CACHE_TTL",
+            default_value = "60",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) the cached responses are served for
+        response_cache_ttl: Duration,
+
+        #[arg(long, env = "PADDLER_RETRY_BUDGET_MIN_RETRIES", default_value = "10")]
+        /// Retries allowed within the retry budget window regardless of the ratio
+        retry_budget_min_retries: usize,
+
+        #[arg(long, env = "PADDLER_RETRY_BUDGET_RATIO")]
+        /// Cap the retries of all the requests together to this fraction (for example `0.2`) of
+        /// the requests in the retry budget window (optional)
+        retry_budget_ratio: Option<f64>,
+
+        #[arg(
+            long,
+            env = "PADDLER_RETRY_BUDGET_WINDOW",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// Sliding window (in seconds) in which the requests and retries are counted for the
+        /// retry budget
+        retry_budget_window: Duration,
+
+        #[arg(long, env = "PADDLER_RETRY_BUFFER_BYTES", default_value = "65536")]
+        /// Requests with larger bodies are not retried on a different agent, since their body
+        /// can't be sent again (at most 65536, the size of the buffer the proxy keeps)
+        retry_buffer_bytes: usize,
+
+        #[arg(long, env = "PADDLER_REVERSEPROXY_ADDR", value_parser = parse_socket_addr)]
         /// Address of the reverse proxy server
         reverseproxy_addr: SocketAddr,
 
-        #[arg(long)]
+        #[arg(long, env = "PADDLER_REWRITE_CONTENT_TYPE")]
+        /// Set `Content-Type: application/json` on the completion requests that are sent
+        /// without it, for the clients that forget it
+        rewrite_content_type: bool,
+
+        #[arg(long, env = "PADDLER_REWRITE_HOST_HEADER")]
         /// Rewrite the host header of incoming requests so that it matches the upstream server
         /// instead of the reverse client server
         rewrite_host_header: bool,
 
-        #[arg(long)]
+        #[arg(long, env = "PADDLER_REWRITE_HOST_HEADER_VALUE")]
+        /// Value of the rewritten host header for the agents that do not set their own with
+        /// `--external-host` (optional, defaults to the llama.cpp address)
+        rewrite_host_header_value: Option<String>,
+
+        #[arg(
+            long,
+            env = "PADDLER_SHUTDOWN_DRAIN_TIMEOUT",
+            default_value = "30",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) to wait for the requests in progress to finish on shutdown, the
+        /// balancer exits with an error if some are still in progress after that
+        shutdown_drain_timeout: Duration,
+
+        #[arg(long, env = "PADDLER_SLOTS_ENDPOINT_ENABLE")]
         /// Enable the slots endpoint (not recommended)
         slots_endpoint_enable: bool,
 
+        #[arg(
+            long,
+            env = "PADDLER_SLOTS_ENDPOINT_DISABLED_POLICY",
+            default_value = "exclude",
+            value_parser = parse_slots_endpoint_disabled_policy
+        )]
+        /// How to treat agents whose llama.cpp has the slots endpoint disabled: `exclude` them
+        /// from requests that consume slots, or `assume-capacity:N` to assume N slots
+        slots_endpoint_disabled_policy: SlotsEndpointDisabledPolicy,
+
+        #[arg(long, env = "PADDLER_SLOTS_ENDPOINT_TOKEN", hide_env_values = true)]
+        /// Token clients need to send in the `X-Paddler-Slots-Token` header to access the slots
+        /// endpoint, on top of the listener API key (optional)
+        slots_endpoint_token: Option<String>,
+
+        #[arg(long, env = "PADDLER_SLOTS_PROBE_ENABLE")]
+        /// Confirm the idle slots of the selected agent with its llama.cpp before every request
+        /// that takes a slot, instead of only the ones with the `X-Paddler-Probe-Slots: 1`
+        /// header
+        slots_probe_enable: bool,
+
+        #[arg(
+            long,
+            env = "PADDLER_SLOTS_PROBE_TIMEOUT",
+            default_value = "200",
+            value_parser = parse_duration_millis
+        )]
+        /// Time (in milliseconds) to wait for the slots of llama.cpp, the last reported state
+        /// is used after that
+        slots_probe_timeout: Duration,
+
+        #[arg(long, env = "PADDLER_STATE_FILE")]
+        /// Path to a file where the balancer keeps the registered agents, to restore them after
+        /// a restart (optional)
+        state_file: Option<PathBuf>,
+
+        #[arg(
+            long,
+            env = "PADDLER_STATE_FILE_TTL",
+            default_value = "30",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) the agents restored from the state file have to report before they
+        /// are evicted
+        state_file_ttl: Duration,
+
         #[cfg(feature = "statsd_reporter")]
-        #[arg(long, value_parser = parse_socket_addr)]
+        #[arg(long, env = "PADDLER_STATSD_ADDR", value_parser = parse_socket_addr)]
         /// Address of the statsd server to report metrics to
         statsd_addr: Option<SocketAddr>,
 
         #[cfg(feature = "statsd_reporter")]
-        #[arg(long, default_value = "paddler")]
+        #[arg(long, env = "PADDLER_STATSD_PREFIX", default_value = "paddler")]
         /// Prefix for statsd metrics
         statsd_prefix: String,
 
         #[cfg(feature = "statsd_reporter")]
-        #[arg(long, default_value = "10", value_parser = parse_duration)]
+        #[arg(
+            long,
+            env = "PADDLER_STATSD_REPORTING_INTERVAL",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
         /// Interval (in seconds) at which the balancer will report metrics to statsd
         statsd_reporting_interval: Duration,
+
+        #[arg(long, env = "PADDLER_STATIC_PEERS_FILE")]
+        /// Path to a JSON file with statically configured agents (optional)
+        static_peers_file: Option<PathBuf>,
+
+        #[arg(
+            long,
+            env = "PADDLER_STATUS_UPDATE_MIN_INTERVAL",
+            value_parser = parse_duration_millis
+        )]
+        /// Time (in milliseconds) within which the status updates of an agent after the last
+        /// applied one only count as a sign of life, to keep agents that report too often from
+        /// reshuffling the pool (optional)
+        status_update_min_interval: Option<Duration>,
+
+        #[arg(long, env = "PADDLER_STREAM_ERROR_EVENT")]
+        /// End the streams that llama.cpp aborted with an error with an OpenAI-style `error`
+        /// event, so the OpenAI SDKs raise an exception instead of returning a cut off answer
+        stream_error_event: bool,
+
+        #[arg(long, env = "PADDLER_STRIP_HEADERS", value_delimiter = ',')]
+        /// Headers that should not be forwarded to llama.cpp, in addition to the ones stripped
+        /// by default (can be repeated or comma separated)
+        strip_headers: Vec<String>,
+
+        #[arg(long, env = "PADDLER_TARGET_AGENT_TOKEN", hide_env_values = true)]
+        /// Token clients need to send in the `X-Paddler-Target-Agent-Token` header to force the
+        /// agent with `X-Paddler-Target-Agent` (optional)
+        target_agent_token: Option<String>,
+
+        #[arg(
+            long,
+            env = "PADDLER_TCP_KEEPALIVE_COUNT",
+            default_value = "5",
+            requires = "tcp_keepalive_idle"
+        )]
+        /// Unanswered keepalive probes after which a client connection is dropped
+        tcp_keepalive_count: usize,
+
+        #[arg(long, env = "PADDLER_TCP_KEEPALIVE_IDLE", value_parser = parse_duration)]
+        /// Time (in seconds) a client connection has to be idle before the keepalive probes
+        /// start, the connections have no keepalive if it is not set (optional)
+        tcp_keepalive_idle: Option<Duration>,
+
+        #[arg(
+            long,
+            env = "PADDLER_TCP_KEEPALIVE_INTERVAL",
+            default_value = "10",
+            requires = "tcp_keepalive_idle",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) between the keepalive probes
+        tcp_keepalive_interval: Duration,
+
+        #[arg(
+            long,
+            env = "PADDLER_TIE_BREAK_STRATEGY",
+            default_value = "round-robin",
+            value_parser = parse_tie_break_strategy
+        )]
+        /// How to pick between equally good agents: `round-robin`, `request-hash` (of the
+        /// `X-Request-Id` header or the client IP), or `address` (always the smallest one)
+        tie_break_strategy: TieBreakStrategy,
+
+        #[arg(
+            long,
+            env = "PADDLER_TOKEN_QUOTA_FALLBACK_COST",
+            default_value = "1000"
+        )]
+        /// Tokens charged against the quota for a response that does not report its usage, for
+        /// example an interrupted stream
+        token_quota_fallback_cost: u64,
+
+        #[arg(
+            long = "token-quota",
+            env = "PADDLER_TOKEN_QUOTA",
+            value_parser = parse_token_quota,
+            value_delimiter = '\n'
+        )]
+        /// Tokens the clients of a listener can use per UTC day or month, for example
+        /// `listener=public,tokens=1000000,period=day` (can be repeated or newline separated)
+        token_quotas: Vec<TokenQuota>,
+
+        #[arg(long, env = "PADDLER_UPGRADE")]
+        /// Take over the listening sockets of a running balancer through `--upgrade-sock`
+        /// instead of binding them, for upgrades without refusing any connection
+        upgrade: bool,
+
+        #[arg(
+            long,
+            env = "PADDLER_UPGRADE_SOCK",
+            default_value = "/tmp/pingora_upgrade.sock"
+        )]
+        /// Path of the Unix socket the listening sockets are handed over through on upgrade
+        upgrade_sock: PathBuf,
+
+        #[arg(
+            long,
+            env = "PADDLER_UPSTREAM_CONNECT_TIMEOUT",
+            default_value = "5",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) to wait for the connection with llama.cpp to be established before
+        /// the agent is quarantined and the request is retried
+        upstream_connect_timeout: Duration,
+
+        #[arg(
+            long,
+            env = "PADDLER_UPSTREAM_CONNECTION_MAX_LIFETIME",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) after which a kept-alive connection with llama.cpp is closed once
+        /// the request using it finishes, instead of being reused (optional)
+        upstream_connection_max_lifetime: Option<Duration>,
+
+        #[arg(
+            long = "upstream-header",
+            env = "PADDLER_UPSTREAM_HEADER",
+            value_parser = parse_injected_header,
+            value_delimiter = '\n'
+        )]
+        /// Header added to every request forwarded to llama.cpp, for example
+        /// `X-Paddler-Version: 1.0.0` (can be repeated or newline separated)
+        upstream_headers: Vec<InjectedHeader>,
+
+        #[arg(
+            long,
+            env = "PADDLER_UPSTREAM_PROTOCOL",
+            default_value = "h1",
+            value_parser = parse_upstream_protocol
+        )]
+        /// Protocol to speak to llama.cpp: `h1`, or `h2` for HTTP/2 without TLS (h2c), so the
+        /// requests to an agent share a connection; the agents can override it
+        upstream_protocol: UpstreamProtocol,
+
+        #[arg(long, env = "PADDLER_WARMUP_PERIOD", value_parser = parse_duration)]
+        /// Time (in seconds) during which a newly registered or recovered agent gets gradually
+        /// more requests, up to all of its slots (optional)
+        warmup_period: Option<Duration>,
+
+        #[arg(long, env = "PADDLER_WARMUP_PROBE_PAYLOAD", value_parser = parse_json)]
+        /// JSON body of a completion request (for example `{"prompt":"Hi","n_predict":1}`) that
+        /// new agents have to answer before they get any traffic (optional)
+        warmup_probe_payload: Option<Value>,
+
+        #[arg(
+            long,
+            env = "PADDLER_WARMUP_PROBE_TIMEOUT",
+            default_value = "10",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) to wait for the response to the warm-up probe
+        warmup_probe_timeout: Duration,
+
+        #[arg(
+            long = "webhook-event",
+            env = "PADDLER_WEBHOOK_EVENT",
+            value_delimiter = ',',
+            value_parser = parse_webhook_event_type
+        )]
+        /// Events sent to `--webhook-url`: `peer_registered`, `peer_removed`,
+        /// `peer_quarantined`, `peer_unquarantined`, `peer_drained`, `capacity_low`, or
+        /// `capacity_restored` (can be repeated or comma separated, defaults to all of them)
+        webhook_events: Vec<WebhookEventType>,
+
+        #[arg(long, env = "PADDLER_WEBHOOK_MAX_RETRIES", default_value = "3")]
+        /// How many times to retry a webhook delivery that failed
+        webhook_max_retries: usize,
+
+        #[arg(long, env = "PADDLER_WEBHOOK_MIN_USABLE_PEERS")]
+        /// Send `capacity_low` when the number of usable agents drops below this (optional)
+        webhook_min_usable_peers: Option<usize>,
+
+        #[arg(long, env = "PADDLER_WEBHOOK_QUEUE_SIZE", default_value = "1000")]
+        /// Maximum number of webhook events waiting for the delivery, the newer ones are dropped
+        webhook_queue_size: usize,
+
+        #[arg(long, env = "PADDLER_WEBHOOK_SECRET", hide_env_values = true)]
+        /// Secret to sign the webhook payloads with, in the `X-Paddler-Signature` header
+        /// (optional)
+        webhook_secret: Option<String>,
+
+        #[arg(
+            long,
+            env = "PADDLER_WEBHOOK_TIMEOUT",
+            default_value = "5",
+            value_parser = parse_duration
+        )]
+        /// Time (in seconds) to wait for the webhook receiver to respond
+        webhook_timeout: Duration,
+
+        #[arg(long, env = "PADDLER_WEBHOOK_URL", value_parser = parse_url)]
+        /// URL to POST the agent lifecycle events to (optional)
+        webhook_url: Option<Url>,
+
+        #[arg(long, env = "PADDLER_WORKER_THREADS", default_value = "1")]
+        /// Threads each proxy listener handles the requests with
+        worker_threads: usize,
+    },
+    /// Operates a running balancer through its management API
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+
+        #[arg(long, global = true)]
+        /// Print the responses of the management API as JSON, for scripting
+        json: bool,
+
+        #[arg(
+            long,
+            env = "PADDLER_MANAGEMENT_ADDR",
+            default_value = "127.0.0.1:8085",
+            global = true,
+            value_parser = parse_socket_addr
+        )]
+        /// Address of the balancer's management server
+        management_addr: SocketAddr,
+
+        #[arg(
+            long,
+            env = "PADDLER_MANAGEMENT_TOKEN",
+            global = true,
+            hide_env_values = true
+        )]
+        /// Bearer token for a management server behind an authenticating proxy (optional)
+        management_token: Option<String>,
     },
     #[cfg(feature = "ratatui_dashboard")]
     /// Command-line dashboard for monitoring the balancer
@@ -126,61 +1074,339 @@ enum Commands {
         /// Address of the management server that the dashboard will connect to
         management_addr: SocketAddr,
     },
+    #[command(hide = true)]
+    /// Fake llama.cpp instance with failure injection, for testing the balancer and agents
+    Testserver {
+        #[arg(long, value_parser = parse_socket_addr)]
+        /// Address the fake llama.cpp instance will listen on
+        addr: SocketAddr,
+
+        #[arg(long)]
+        /// Abort the completion response stream after this many bytes were sent
+        fail_after_bytes: Option<usize>,
+
+        #[arg(long)]
+        /// Accept HTTP/2 without TLS (h2c, with prior knowledge), and reject the completion
+        /// requests sent over HTTP/1.1
+        h2c: bool,
+
+        #[arg(long)]
+        /// Accept and immediately drop every connection
+        refuse_connections: bool,
+
+        #[arg(long, default_value = "4")]
+        /// Number of slots
+        slots: usize,
+
+        #[arg(long)]
+        /// Stop sending completion tokens (without closing the connection) after this many
+        stall_after_tokens: Option<usize>,
+
+        #[arg(long, default_value = "50", value_parser = parse_duration_millis)]
+        /// Delay (in milliseconds) before each completion token
+        token_latency: Duration,
+
+        #[arg(long, default_value = "16")]
+        /// Number of tokens in each completion
+        tokens: usize,
+    },
 }
 
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
     match &cli.command {
+        #[cfg(feature = "agent")]
         Some(Commands::Agent {
+            agent_id,
+            external_host,
             external_llamacpp_addr,
+            hardware_class,
+            labels,
             local_llamacpp_addr,
             llamacpp_api_key,
             management_addr,
-            monitoring_interval,
+            max_concurrency,
+            model_version,
             name,
+            spawn_llamacpp,
+            spawn_llamacpp_grace_period,
+            state_dir,
+            status_addr,
+            status_interval,
+            tier,
+            upstream_headers,
+            upstream_protocol,
         }) => cmd::agent::handle(
+            agent_id.to_owned(),
+            external_host.to_owned(),
             match external_llamacpp_addr {
                 Some(addr) => addr.to_owned(),
                 None => local_llamacpp_addr.to_owned(),
             },
+            hardware_class.to_owned(),
+            labels.to_owned(),
             local_llamacpp_addr.to_owned(),
             llamacpp_api_key.to_owned(),
             management_addr.to_owned(),
-            monitoring_interval.to_owned(),
+            max_concurrency.to_owned(),
+            model_version.to_owned(),
             name.to_owned(),
+            spawn_llamacpp.to_owned(),
+            spawn_llamacpp_grace_period.to_owned(),
+            state_dir.to_owned(),
+            status_addr.to_owned(),
+            status_interval.to_owned(),
+            tier.to_owned(),
+            upstream_headers.to_owned(),
+            upstream_protocol.to_owned(),
         ),
+        #[cfg(feature = "balancer")]
         Some(Commands::Balancer {
+            print_config: true, ..
+        }) => cmd::print_config::handle(&Cli::command(), &matches, "balancer"),
+        #[cfg(feature = "balancer")]
+        Some(Commands::Balancer {
+            compression_content_types,
+            compression_level,
+            compression_min_size,
+            config_file,
+            context_chars_per_token,
+            cooldown_after_requests,
+            cooldown_slots_factor,
+            cooldown_window,
+            disable_slot_accounting,
+            discovery_dns_interval,
+            discovery_dns_name,
+            downstream_idle_timeout,
+            duplicate_agent_id_policy,
+            error_penalty_weight,
+            error_penalty_window,
+            excluded_agent_names,
+            flap_damping_max_changes,
+            flap_damping_window,
+            forward_headers,
+            #[cfg(feature = "grpc_health")]
+            grpc_health_addr,
+            hardware_class_weights,
+            head_request_policy,
+            listeners,
             management_addr,
             #[cfg(feature = "web_dashboard")]
             management_dashboard_enable,
+            management_events_enable,
+            max_connections_per_client,
+            max_connections_per_client_exempt,
+            max_downstream_connections,
+            max_downstream_connections_policy,
+            max_permit_handoffs,
+            max_queued_requests,
+            max_response_bytes,
+            max_retries_per_request,
+            model_loading_period,
+            no_capacity_retry_after,
+            no_capacity_status,
+            options_request_policy,
+            path_prefix,
+            path_rewrites,
+            per_peer_admission_burst,
+            per_peer_admission_rate,
+            placement_strategy,
+            prefer_newest_model_version,
+            print_config: _,
+            request_coalescing_enable,
+            response_cache_max_entries,
+            response_cache_max_response_size,
+            response_cache_ttl,
+            retry_budget_min_retries,
+            retry_budget_ratio,
+            retry_budget_window,
+            retry_buffer_bytes,
             reverseproxy_addr,
+            rewrite_content_type,
             rewrite_host_header,
+            rewrite_host_header_value,
+            shutdown_drain_timeout,
             slots_endpoint_enable,
+            slots_endpoint_disabled_policy,
+            slots_endpoint_token,
+            slots_probe_enable,
+            slots_probe_timeout,
+            state_file,
+            state_file_ttl,
             #[cfg(feature = "statsd_reporter")]
             statsd_addr,
             #[cfg(feature = "statsd_reporter")]
             statsd_prefix,
             #[cfg(feature = "statsd_reporter")]
             statsd_reporting_interval,
+            static_peers_file,
+            status_update_min_interval,
+            stream_error_event,
+            strip_headers,
+            target_agent_token,
+            tcp_keepalive_count,
+            tcp_keepalive_idle,
+            tcp_keepalive_interval,
+            tie_break_strategy,
+            token_quota_fallback_cost,
+            token_quotas,
+            upgrade,
+            upgrade_sock,
+            upstream_connect_timeout,
+            upstream_connection_max_lifetime,
+            upstream_headers,
+            upstream_protocol,
+            warmup_period,
+            warmup_probe_payload,
+            warmup_probe_timeout,
+            webhook_events,
+            webhook_max_retries,
+            webhook_min_usable_peers,
+            webhook_queue_size,
+            webhook_secret,
+            webhook_timeout,
+            webhook_url,
+            worker_threads,
         }) => cmd::balancer::handle(
+            compression_content_types.to_owned(),
+            compression_level.to_owned(),
+            compression_min_size.to_owned(),
+            config_file.to_owned(),
+            context_chars_per_token.to_owned(),
+            cooldown_after_requests.to_owned(),
+            cooldown_slots_factor.to_owned(),
+            cooldown_window.to_owned(),
+            disable_slot_accounting.to_owned(),
+            discovery_dns_interval.to_owned(),
+            discovery_dns_name.to_owned(),
+            downstream_idle_timeout.to_owned(),
+            duplicate_agent_id_policy.to_owned(),
+            error_penalty_weight.to_owned(),
+            error_penalty_window.to_owned(),
+            excluded_agent_names.to_owned(),
+            flap_damping_max_changes.to_owned(),
+            flap_damping_window.to_owned(),
+            forward_headers.to_owned(),
+            #[cfg(feature = "grpc_health")]
+            grpc_health_addr.to_owned(),
+            hardware_class_weights.to_owned(),
+            head_request_policy.to_owned(),
+            listeners.to_owned(),
             management_addr,
             #[cfg(feature = "web_dashboard")]
             management_dashboard_enable.to_owned(),
+            management_events_enable.to_owned(),
+            max_connections_per_client.to_owned(),
+            max_connections_per_client_exempt.to_owned(),
+            max_downstream_connections.to_owned(),
+            max_downstream_connections_policy.to_owned(),
+            max_permit_handoffs.to_owned(),
+            max_queued_requests.to_owned(),
+            max_response_bytes.to_owned(),
+            max_retries_per_request.to_owned(),
+            model_loading_period.to_owned(),
+            no_capacity_retry_after.to_owned(),
+            no_capacity_status.to_owned(),
+            options_request_policy.to_owned(),
+            path_prefix.to_owned(),
+            path_rewrites.to_owned(),
+            per_peer_admission_burst.to_owned(),
+            per_peer_admission_rate.to_owned(),
+            placement_strategy.to_owned(),
+            prefer_newest_model_version.to_owned(),
+            request_coalescing_enable.to_owned(),
+            response_cache_max_entries.to_owned(),
+            response_cache_max_response_size.to_owned(),
+            response_cache_ttl.to_owned(),
+            retry_budget_min_retries.to_owned(),
+            retry_budget_ratio.to_owned(),
+            retry_budget_window.to_owned(),
+            retry_buffer_bytes.to_owned(),
             reverseproxy_addr,
+            rewrite_content_type.to_owned(),
             rewrite_host_header.to_owned(),
+            rewrite_host_header_value.to_owned(),
+            shutdown_drain_timeout.to_owned(),
             slots_endpoint_enable.to_owned(),
+            slots_endpoint_disabled_policy.to_owned(),
+            slots_endpoint_token.to_owned(),
+            slots_probe_enable.to_owned(),
+            slots_probe_timeout.to_owned(),
+            state_file.to_owned(),
+            state_file_ttl.to_owned(),
             #[cfg(feature = "statsd_reporter")]
             statsd_addr.to_owned(),
             #[cfg(feature = "statsd_reporter")]
             statsd_prefix.to_owned(),
             #[cfg(feature = "statsd_reporter")]
             statsd_reporting_interval.to_owned(),
+            static_peers_file.to_owned(),
+            status_update_min_interval.to_owned(),
+            stream_error_event.to_owned(),
+            strip_headers.to_owned(),
+            target_agent_token.to_owned(),
+            tcp_keepalive_count.to_owned(),
+            tcp_keepalive_idle.to_owned(),
+            tcp_keepalive_interval.to_owned(),
+            tie_break_strategy.to_owned(),
+            token_quota_fallback_cost.to_owned(),
+            token_quotas.to_owned(),
+            upgrade.to_owned(),
+            upgrade_sock.to_owned(),
+            upstream_connect_timeout.to_owned(),
+            upstream_connection_max_lifetime.to_owned(),
+            upstream_headers.to_owned(),
+            upstream_protocol.to_owned(),
+            warmup_period.to_owned(),
+            warmup_probe_payload.to_owned(),
+            warmup_probe_timeout.to_owned(),
+            webhook_events.to_owned(),
+            webhook_max_retries.to_owned(),
+            webhook_min_usable_peers.to_owned(),
+            webhook_queue_size.to_owned(),
+            webhook_secret.to_owned(),
+            webhook_timeout.to_owned(),
+            webhook_url.to_owned(),
+            worker_threads.to_owned(),
+        ),
+        Some(Commands::Ctl {
+            command,
+            json,
+            management_addr,
+            management_token,
+        }) => cmd::ctl::handle(
+            command,
+            json.to_owned(),
+            management_addr.to_owned(),
+            management_token.to_owned(),
         ),
         #[cfg(feature = "ratatui_dashboard")]
         Some(Commands::Dashboard { management_addr }) => cmd::dashboard::handle(management_addr),
+        Some(Commands::Testserver {
+            addr,
+            fail_after_bytes,
+            h2c,
+            refuse_connections,
+            slots,
+            stall_after_tokens,
+            token_latency,
+            tokens,
+        }) => cmd::testserver::handle(
+            addr.to_owned(),
+            FakeLlamacppConfig {
+                fail_after_bytes: fail_after_bytes.to_owned(),
+                h2c: h2c.to_owned(),
+                slots: slots.to_owned(),
+                stall_after_tokens: stall_after_tokens.to_owned(),
+                token_latency: token_latency.to_owned(),
+                tokens: tokens.to_owned(),
+            },
+            refuse_connections.to_owned(),
+        ),
         None => Ok(()),
     }
 }
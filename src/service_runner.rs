@@ -0,0 +1,85 @@
+use pingora::{server::Server, services::Service};
+
+use crate::errors::result::Result;
+
+#[cfg(windows)]
+use futures::future::join_all;
+#[cfg(windows)]
+use log::{info, warn};
+#[cfg(windows)]
+use tokio::{
+    runtime::Builder,
+    signal::windows::{ctrl_break, ctrl_c, ctrl_close, ctrl_shutdown},
+    sync::watch,
+    time::{timeout, Duration},
+};
+
+/// Matches the grace period pingora uses when it is not configured
+#[cfg(windows)]
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60 * 5);
+
+/// Blocks until the process is asked to stop
+#[cfg(unix)]
+pub fn run_forever(mut pingora_server: Server, services: Vec<Box<dyn Service>>) -> Result<()> {
+    pingora_server.add_services(services);
+    pingora_server.run_forever();
+}
+
+/// pingora only waits for the shutdown signals on Unix, on Windows its `run_forever` stops the
+/// services once the grace period passes. The services are run here instead.
+#[cfg(windows)]
+pub fn run_forever(pingora_server: Server, services: Vec<Box<dyn Service>>) -> Result<()> {
+    let grace_period = pingora_server
+        .configuration
+        .grace_period_seconds
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_GRACE_PERIOD);
+
+    Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(pingora_server.configuration.threads.max(1))
+        .build()?
+        .block_on(run_until_console_event(grace_period, services))
+}
+
+/// Ctrl+C, Ctrl+Break, closing the console window, or the system shutting down
+#[cfg(windows)]
+async fn run_until_console_event(
+    grace_period: Duration,
+    services: Vec<Box<dyn Service>>,
+) -> Result<()> {
+    let mut ctrl_break = ctrl_break()?;
+    let mut ctrl_c = ctrl_c()?;
+    let mut ctrl_close = ctrl_close()?;
+    let mut ctrl_shutdown = ctrl_shutdown()?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let service_handles: Vec<_> = services
+        .into_iter()
+        .map(|mut service| {
+            let shutdown = shutdown_rx.clone();
+
+            tokio::spawn(async move { service.start_service(shutdown).await })
+        })
+        .collect();
+
+    tokio::select! {
+        _ = ctrl_break.recv() => {},
+        _ = ctrl_c.recv() => {},
+        _ = ctrl_close.recv() => {},
+        _ = ctrl_shutdown.recv() => {},
+    }
+
+    info!("Shutting down, grace period {:?} starts", grace_period);
+
+    shutdown_tx.send_replace(true);
+
+    if timeout(grace_period, join_all(service_handles))
+        .await
+        .is_err()
+    {
+        warn!("Some of the services did not stop within the grace period");
+    }
+
+    Ok(())
+}
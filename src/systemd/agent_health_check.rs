@@ -0,0 +1,17 @@
+use async_trait::async_trait;
+
+use crate::{agent::agent_status::AgentStatus, systemd::health_check::HealthCheck};
+
+#[async_trait]
+impl HealthCheck for AgentStatus {
+    async fn is_ready(&self) -> bool {
+        matches!(
+            self.snapshot(),
+            Ok(snapshot) if snapshot.last_successful_scrape.is_some()
+        )
+    }
+
+    async fn is_alive(&self) -> bool {
+        self.snapshot().is_ok()
+    }
+}
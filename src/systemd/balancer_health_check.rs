@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::TcpStream,
+    task::spawn_blocking,
+    time::{timeout, Duration},
+};
+
+use crate::{balancer::upstream_peer_pool::UpstreamPeerPool, systemd::health_check::HealthCheck};
+
+const POOL_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct BalancerHealthCheck {
+    /// Balancer is ready once all of these accept connections
+    addrs: Vec<SocketAddr>,
+    upstream_peer_pool: Arc<UpstreamPeerPool>,
+}
+
+impl BalancerHealthCheck {
+    pub fn new(addrs: Vec<SocketAddr>, upstream_peer_pool: Arc<UpstreamPeerPool>) -> Self {
+        BalancerHealthCheck {
+            addrs,
+            upstream_peer_pool,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for BalancerHealthCheck {
+    async fn is_ready(&self) -> bool {
+        for addr in &self.addrs {
+            if TcpStream::connect(addr).await.is_err() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    async fn is_alive(&self) -> bool {
+        let upstream_peer_pool = self.upstream_peer_pool.clone();
+
+        // pool uses a blocking lock, so a wedged lock would block the runtime thread
+        matches!(
+            timeout(
+                POOL_LOCK_TIMEOUT,
+                spawn_blocking(move || upstream_peer_pool.total_slots()),
+            )
+            .await,
+            Ok(Ok(Ok(_)))
+        )
+    }
+}
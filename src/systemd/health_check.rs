@@ -0,0 +1,10 @@
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Checked until it returns true for the first time, then systemd is notified
+    async fn is_ready(&self) -> bool;
+
+    /// Checked before every watchdog ping
+    async fn is_alive(&self) -> bool;
+}
@@ -0,0 +1,9 @@
+pub mod health_check;
+pub mod sd_notify;
+pub mod systemd_service;
+
+#[cfg(feature = "agent")]
+pub mod agent_health_check;
+
+#[cfg(feature = "balancer")]
+pub mod balancer_health_check;
@@ -0,0 +1,47 @@
+use std::{env, os::unix::ffi::OsStrExt, os::unix::net::UnixDatagram, path::Path, time::Duration};
+
+#[cfg(target_os = "linux")]
+use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+use crate::errors::result::Result;
+
+/// Sends the state to systemd. Returns false if the process is not supervised by systemd
+/// (`NOTIFY_SOCKET` is not set).
+pub fn notify(state: &str) -> Result<bool> {
+    let notify_socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(notify_socket) => notify_socket,
+        None => return Ok(false),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    match notify_socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(abstract_name) => {
+            socket.send_to_addr(
+                state.as_bytes(),
+                &SocketAddr::from_abstract_name(abstract_name)?,
+            )?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err("Abstract notify sockets are only supported on Linux".into()),
+        None => {
+            socket.send_to(state.as_bytes(), Path::new(&notify_socket))?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// systemd recommends pinging the watchdog at half of the configured interval
+pub fn watchdog_interval() -> Option<Duration> {
+    let watchdog_usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    if let Ok(watchdog_pid) = env::var("WATCHDOG_PID") {
+        if watchdog_pid != std::process::id().to_string() {
+            return None;
+        }
+    }
+
+    Some(Duration::from_micros(watchdog_usec) / 2)
+}
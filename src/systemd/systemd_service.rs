@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use pingora::{
+    server::{ListenFds, ShutdownWatch},
+    services::Service,
+};
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::systemd::{
+    health_check::HealthCheck,
+    sd_notify::{notify, watchdog_interval},
+};
+
+const READINESS_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct SystemdService {
+    health_check: Arc<dyn HealthCheck>,
+}
+
+impl SystemdService {
+    pub fn new(health_check: Arc<dyn HealthCheck>) -> Self {
+        SystemdService { health_check }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(err) = notify(state) {
+            error!("Failed to notify systemd ({}): {}", state, err);
+        }
+    }
+}
+
+#[async_trait]
+impl Service for SystemdService {
+    async fn start_service(&mut self, _fds: Option<ListenFds>, mut shutdown: ShutdownWatch) {
+        let watchdog_interval = watchdog_interval();
+        let mut is_ready = false;
+        let mut readiness_ticker = interval(READINESS_CHECK_INTERVAL);
+        let mut watchdog_ticker =
+            interval(watchdog_interval.unwrap_or(READINESS_CHECK_INTERVAL));
+
+        readiness_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        watchdog_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    debug!("Shutting down systemd service");
+                    self.notify("STOPPING=1");
+                    return;
+                },
+                _ = readiness_ticker.tick(), if !is_ready => {
+                    if self.health_check.is_ready().await {
+                        info!("Service is ready");
+                        self.notify("READY=1");
+
+                        is_ready = true;
+                    }
+                },
+                _ = watchdog_ticker.tick(), if is_ready && watchdog_interval.is_some() => {
+                    if self.health_check.is_alive().await {
+                        self.notify("WATCHDOG=1");
+                    } else {
+                        warn!("Health check failed, skipping the watchdog ping");
+                    }
+                }
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "systemd"
+    }
+
+    fn threads(&self) -> Option<usize> {
+        Some(1)
+    }
+}
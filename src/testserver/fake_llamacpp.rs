@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::time::Duration;
+
+/// Failure injection and timing of the fake llama.cpp instance
+#[derive(Clone, Debug)]
+pub struct FakeLlamacppConfig {
+    /// Abort the response stream after this many bytes were sent
+    pub fail_after_bytes: Option<usize>,
+    /// Serve the completions only over HTTP/2 without TLS (h2c, with prior knowledge)
+    pub h2c: bool,
+    pub slots: usize,
+    /// Stop sending tokens (without closing the connection) after this many were sent
+    pub stall_after_tokens: Option<usize>,
+    pub token_latency: Duration,
+    pub tokens: usize,
+}
+
+pub struct FakeLlamacpp {
+    pub config: FakeLlamacppConfig,
+    slots_processing: AtomicUsize,
+}
+
+impl FakeLlamacpp {
+    pub fn new(config: FakeLlamacppConfig) -> Self {
+        FakeLlamacpp {
+            config,
+            slots_processing: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn release_slot(&self) {
+        self.slots_processing.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn slots_processing(&self) -> usize {
+        self.slots_processing.load(Ordering::SeqCst)
+    }
+
+    /// Returns false if all the slots are busy
+    pub fn take_slot(&self) -> bool {
+        self.slots_processing
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |slots_processing| {
+                if slots_processing < self.config.slots {
+                    Some(slots_processing + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
@@ -0,0 +1,101 @@
+use actix_web::{http::Version, post, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use futures::{future, stream};
+use serde_json::{json, Value};
+use std::io;
+use tokio::time::sleep;
+
+use crate::testserver::fake_llamacpp::FakeLlamacpp;
+
+/// Large enough for the prompts the balancer tests send, the default limit is 256 KiB
+const MAX_REQUEST_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(MAX_REQUEST_BODY_SIZE))
+        .service(respond);
+}
+
+/// Frees the slot when the response stream ends or the client disconnects
+struct SlotGuard {
+    fake_llamacpp: web::Data<FakeLlamacpp>,
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.fake_llamacpp.release_slot();
+    }
+}
+
+struct CompletionStream {
+    bytes_sent: usize,
+    slot_guard: SlotGuard,
+    tokens_sent: usize,
+}
+
+#[post("/{path:(completion|chat/completions|v1/chat/completions)}")]
+async fn respond(
+    fake_llamacpp: web::Data<FakeLlamacpp>,
+    request: HttpRequest,
+    body: Bytes,
+) -> impl Responder {
+    if fake_llamacpp.config.h2c && request.version() != Version::HTTP_2 {
+        return HttpResponse::VersionNotSupported().body("Completions require HTTP/2");
+    }
+
+    // the balancer has to forward the whole body, even if it buffered it
+    if serde_json::from_slice::<Value>(&body).is_err() {
+        return HttpResponse::BadRequest().body("Request body is not valid JSON");
+    }
+
+    if !fake_llamacpp.take_slot() {
+        return HttpResponse::ServiceUnavailable().body("No slot available");
+    }
+
+    let completion_stream = CompletionStream {
+        bytes_sent: 0,
+        slot_guard: SlotGuard {
+            fake_llamacpp: fake_llamacpp.clone(),
+        },
+        tokens_sent: 0,
+    };
+
+    let body = stream::unfold(completion_stream, |mut completion_stream| async move {
+        let config = completion_stream.slot_guard.fake_llamacpp.config.clone();
+
+        if completion_stream.tokens_sent > config.tokens {
+            return None;
+        }
+
+        if config.stall_after_tokens == Some(completion_stream.tokens_sent) {
+            future::pending::<()>().await;
+        }
+
+        if config
+            .fail_after_bytes
+            .is_some_and(|fail_after_bytes| completion_stream.bytes_sent >= fail_after_bytes)
+        {
+            return Some((
+                Err(io::Error::other("injected failure")),
+                completion_stream,
+            ));
+        }
+
+        sleep(config.token_latency).await;
+
+        let is_last = completion_stream.tokens_sent == config.tokens;
+        let chunk = json!({
+            "content": if is_last { String::new() } else { format!(" token{}", completion_stream.tokens_sent) },
+            "stop": is_last,
+        });
+        let chunk = Bytes::from(format!("data: {}\n\n", chunk));
+
+        completion_stream.bytes_sent += chunk.len();
+        completion_stream.tokens_sent += 1;
+
+        Some((Ok::<Bytes, io::Error>(chunk), completion_stream))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(body)
+}
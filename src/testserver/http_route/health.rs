@@ -0,0 +1,11 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/health")]
+async fn respond() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
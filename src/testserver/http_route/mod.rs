@@ -0,0 +1,4 @@
+pub mod completion;
+pub mod health;
+pub mod props;
+pub mod slots;
@@ -0,0 +1,20 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::testserver::fake_llamacpp::FakeLlamacpp;
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/props")]
+async fn respond(fake_llamacpp: web::Data<FakeLlamacpp>) -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "build_info": "paddler-testserver",
+        "default_generation_settings": {
+            "n_ctx": 4096,
+        },
+        "model_path": "/models/testserver.gguf",
+        "total_slots": fake_llamacpp.config.slots,
+    }))
+}
@@ -0,0 +1,20 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{llamacpp::slot::Slot, testserver::fake_llamacpp::FakeLlamacpp};
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(respond);
+}
+
+#[get("/slots")]
+async fn respond(fake_llamacpp: web::Data<FakeLlamacpp>) -> impl Responder {
+    let slots_processing = fake_llamacpp.slots_processing();
+    let slots: Vec<Slot> = (0..fake_llamacpp.config.slots)
+        .map(|id| Slot {
+            id,
+            is_processing: id < slots_processing,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(slots)
+}
@@ -0,0 +1,2 @@
+pub mod fake_llamacpp;
+pub mod http_route;
@@ -0,0 +1,214 @@
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Killed when dropped, so the failed tests do not leave the processes behind
+pub struct Process {
+    child: Child,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // the balancer waits for the grace period on SIGTERM, there is nothing to shut down
+        // gracefully in the tests
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn spawn(args: &[&str]) -> Process {
+    let child = Command::new(env!("CARGO_BIN_EXE_paddler"))
+        .args(args)
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("paddler should start");
+
+    Process { child }
+}
+
+/// Address nothing listens on, until the test binds it
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("a free port should be available")
+}
+
+pub struct Balancer {
+    pub management_addr: SocketAddr,
+    _process: Process,
+    pub reverseproxy_addr: SocketAddr,
+}
+
+impl Balancer {
+    pub async fn start(args: &[&str]) -> Self {
+        let management_addr = free_addr();
+        let reverseproxy_addr = free_addr();
+        let management_addr_arg = management_addr.to_string();
+        let reverseproxy_addr_arg = reverseproxy_addr.to_string();
+        let process = spawn(
+            &[
+                &[
+                    "balancer",
+                    "--management-addr",
+                    &management_addr_arg,
+                    "--reverseproxy-addr",
+                    &reverseproxy_addr_arg,
+                ],
+                args,
+            ]
+            .concat(),
+        );
+
+        wait_until_listening(management_addr).await;
+        wait_until_listening(reverseproxy_addr).await;
+
+        Balancer {
+            management_addr,
+            _process: process,
+            reverseproxy_addr,
+        }
+    }
+
+    pub async fn agents(&self) -> Vec<Value> {
+        let pool: Value = reqwest::get(format!("http://{}/api/v1/agents", self.management_addr))
+            .await
+            .expect("management server should respond")
+            .json()
+            .await
+            .expect("agents should be listed as json");
+
+        pool["agents"]
+            .as_array()
+            .expect("agents should be an array")
+            .to_owned()
+    }
+
+    pub async fn agent(&self, name: &str) -> Value {
+        self.agents()
+            .await
+            .into_iter()
+            .find(|agent| agent["agent_name"] == name)
+            .unwrap_or_else(|| panic!("agent {} should be registered", name))
+    }
+
+    pub async fn wait_for_agents(&self, names: &[&str]) {
+        wait_for("agents to register", || async {
+            let agents = self.agents().await;
+
+            names
+                .iter()
+                .all(|name| agents.iter().any(|agent| agent["agent_name"] == *name))
+        })
+        .await;
+    }
+
+    pub fn completion_url(&self) -> String {
+        format!("http://{}/completion", self.reverseproxy_addr)
+    }
+}
+
+pub struct Agent {
+    _process: Process,
+}
+
+impl Agent {
+    /// The balancer connects to `external_llamacpp_addr`, the agent only monitors
+    /// `local_llamacpp_addr`, so they can differ to simulate an unreachable llama.cpp
+    pub fn start(
+        name: &str,
+        balancer: &Balancer,
+        local_llamacpp_addr: SocketAddr,
+        external_llamacpp_addr: SocketAddr,
+        args: &[&str],
+    ) -> Self {
+        let local_llamacpp_addr_arg = local_llamacpp_addr.to_string();
+        let external_llamacpp_addr_arg = external_llamacpp_addr.to_string();
+        let management_addr_arg = balancer.management_addr.to_string();
+        let process = spawn(
+            &[
+                &[
+                    "agent",
+                    "--name",
+                    name,
+                    "--local-llamacpp-addr",
+                    &local_llamacpp_addr_arg,
+                    "--external-llamacpp-addr",
+                    &external_llamacpp_addr_arg,
+                    "--management-addr",
+                    &management_addr_arg,
+                ],
+                args,
+            ]
+            .concat(),
+        );
+
+        Agent { _process: process }
+    }
+}
+
+pub struct Testserver {
+    pub addr: SocketAddr,
+    _process: Process,
+}
+
+impl Testserver {
+    pub async fn start(args: &[&str]) -> Self {
+        let addr = free_addr();
+        let addr_arg = addr.to_string();
+        let process = spawn(&[&["testserver", "--addr", &addr_arg], args].concat());
+
+        wait_until_listening(addr).await;
+
+        Testserver {
+            addr,
+            _process: process,
+        }
+    }
+
+    pub async fn slots_processing(&self) -> usize {
+        let slots: Vec<Value> = reqwest::get(format!("http://{}/slots", self.addr))
+            .await
+            .expect("testserver should respond")
+            .json()
+            .await
+            .expect("slots should be listed as json");
+
+        slots
+            .iter()
+            .filter(|slot| slot["is_processing"] == true)
+            .count()
+    }
+}
+
+pub async fn wait_for<F, R>(what: &str, mut condition: F)
+where
+    F: FnMut() -> R,
+    R: Future<Output = bool>,
+{
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+
+    while !condition().await {
+        if Instant::now() > deadline {
+            panic!("Timed out waiting for {}", what);
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn wait_until_listening(addr: SocketAddr) {
+    wait_for(&format!("{} to listen", addr), || async {
+        tokio::net::TcpStream::connect(addr).await.is_ok()
+    })
+    .await;
+}
@@ -0,0 +1,162 @@
+#![cfg(all(feature = "agent", feature = "balancer"))]
+
+mod common;
+
+use common::{wait_for, Agent, Balancer, Testserver};
+use futures::future::join_all;
+use reqwest::{Client, Response};
+use serde_json::json;
+use std::time::Duration;
+
+/// Gives up on the responses that never come, instead of hanging the test
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn client() -> Client {
+    Client::builder()
+        .timeout(RESPONSE_TIMEOUT)
+        .build()
+        .expect("client should build")
+}
+
+async fn send_completion(
+    client: &Client,
+    balancer: &Balancer,
+    target_agent: Option<&str>,
+) -> Response {
+    let mut request = client
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }));
+
+    if let Some(target_agent) = target_agent {
+        request = request.header("X-Paddler-Target-Agent", target_agent);
+    }
+
+    request.send().await.expect("balancer should respond")
+}
+
+/// Chunks as they arrive, or None if the stream was cut off
+async fn read_chunks(mut response: Response) -> Option<Vec<String>> {
+    let mut chunks = Vec::new();
+
+    while let Some(chunk) = response.chunk().await.ok()? {
+        chunks.push(String::from_utf8_lossy(&chunk).into_owned());
+    }
+
+    Some(chunks)
+}
+
+async fn wait_for_idle_slots(balancer: &Balancer, agent_name: &str, slots_idle: usize) {
+    wait_for(&format!("{} to have idle slots", agent_name), || async {
+        let agent = balancer.agent(agent_name).await;
+
+        agent["slots_idle"] == slots_idle && agent["requests_in_flight"] == 0
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn completions_are_streamed_from_the_h2_upstream() {
+    // completions sent over HTTP/1.1 would get 505
+    let testserver = Testserver::start(&["--h2c", "--slots", "4", "--token-latency", "20"]).await;
+    let balancer = Balancer::start(&[]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms", "--upstream-protocol", "h2"],
+    );
+    let client = client();
+
+    balancer.wait_for_agents(&["agent"]).await;
+    wait_for_idle_slots(&balancer, "agent", 4).await;
+
+    // the requests in progress share the connection to llama.cpp
+    let responses = join_all((0..4).map(|_| send_completion(&client, &balancer, None))).await;
+
+    for response in responses {
+        assert_eq!(response.status(), 200);
+
+        let chunks = read_chunks(response)
+            .await
+            .expect("completion should not be cut off");
+
+        // tokens are forwarded as they come, not once the response is complete
+        assert!(chunks.len() > 1);
+        assert!(chunks.concat().contains("\"stop\":true"));
+    }
+
+    wait_for_idle_slots(&balancer, "agent", 4).await;
+    assert_eq!(testserver.slots_processing().await, 0);
+    assert_eq!(
+        balancer.agent("agent").await["response_status_counts"]["status_2xx"],
+        4
+    );
+}
+
+#[tokio::test]
+async fn stream_reset_by_the_h2_upstream_is_not_retried_and_frees_the_slot() {
+    let healthy_llamacpp =
+        Testserver::start(&["--h2c", "--slots", "4", "--token-latency", "10"]).await;
+    // has more idle slots, so the balancer picks it first
+    let failing_llamacpp = Testserver::start(&[
+        "--fail-after-bytes",
+        "100",
+        "--h2c",
+        "--slots",
+        "8",
+        "--token-latency",
+        "10",
+    ])
+    .await;
+    let balancer = Balancer::start(&[]).await;
+    let _healthy_agent = Agent::start(
+        "healthy",
+        &balancer,
+        healthy_llamacpp.addr,
+        healthy_llamacpp.addr,
+        &["--status-interval", "500ms", "--upstream-protocol", "h2"],
+    );
+    let _failing_agent = Agent::start(
+        "failing",
+        &balancer,
+        failing_llamacpp.addr,
+        failing_llamacpp.addr,
+        &["--status-interval", "500ms", "--upstream-protocol", "h2"],
+    );
+    let client = client();
+
+    balancer.wait_for_agents(&["healthy", "failing"]).await;
+    wait_for_idle_slots(&balancer, "failing", 8).await;
+
+    // the second one goes over the connection the first stream was reset on
+    for _ in 0..2 {
+        let response = send_completion(&client, &balancer, None).await;
+
+        assert_eq!(response.status(), 200);
+        assert!(read_chunks(response).await.is_none());
+    }
+
+    wait_for_idle_slots(&balancer, "failing", 8).await;
+    wait_for("llama.cpp to free the slots", || async {
+        failing_llamacpp.slots_processing().await == 0
+    })
+    .await;
+
+    // the client already got a part of the responses
+    assert_eq!(
+        balancer.agent("healthy").await["response_status_counts"]["status_2xx"],
+        0
+    );
+
+    let response = send_completion(&client, &balancer, Some("healthy")).await;
+
+    assert_eq!(response.status(), 200);
+    assert!(read_chunks(response)
+        .await
+        .expect("completion should not be cut off")
+        .concat()
+        .contains("\"stop\":true"));
+
+    wait_for_idle_slots(&balancer, "healthy", 4).await;
+}
@@ -0,0 +1,97 @@
+#![cfg(all(feature = "agent", feature = "balancer"))]
+
+mod common;
+
+use common::{free_addr, wait_for, Agent, Balancer, Testserver};
+use reqwest::Client;
+use serde_json::json;
+
+#[tokio::test]
+async fn unreachable_agent_is_quarantined_and_the_request_retried_on_another_one() {
+    let healthy_llamacpp = Testserver::start(&["--slots", "2", "--token-latency", "10"]).await;
+    // has more idle slots, so the balancer picks it first
+    let monitored_llamacpp = Testserver::start(&["--slots", "4"]).await;
+    let balancer = Balancer::start(&[]).await;
+    let _healthy_agent = Agent::start(
+        "healthy",
+        &balancer,
+        healthy_llamacpp.addr,
+        healthy_llamacpp.addr,
+        &["--status-interval", "500ms"],
+    );
+    // the agent sees a healthy llama.cpp, but the balancer can't connect to it
+    let _unreachable_agent = Agent::start(
+        "unreachable",
+        &balancer,
+        monitored_llamacpp.addr,
+        free_addr(),
+        &["--status-interval", "5s"],
+    );
+
+    balancer.wait_for_agents(&["healthy", "unreachable"]).await;
+
+    let response = Client::new()
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }))
+        .send()
+        .await
+        .expect("balancer should respond");
+
+    assert_eq!(response.status(), 200);
+    assert!(response
+        .text()
+        .await
+        .expect("completion should finish")
+        .contains("\"stop\":true"));
+
+    let unreachable = balancer.agent("unreachable").await;
+
+    assert!(!unreachable["quarantined_until"].is_null());
+    assert_eq!(unreachable["slots_processing"], 0);
+    assert_eq!(unreachable["requests_in_flight"], 0);
+    assert_eq!(
+        balancer.agent("healthy").await["response_status_counts"]["status_2xx"],
+        1
+    );
+
+    // the quarantine after a failed connection lasts until the next status update
+    wait_for("the quarantine to be lifted", || async {
+        balancer.agent("unreachable").await["quarantined_until"].is_null()
+    })
+    .await;
+
+    let healthy = balancer.agent("healthy").await;
+
+    assert_eq!(healthy["slots_idle"], 2);
+    assert_eq!(healthy["requests_in_flight"], 0);
+}
+
+#[tokio::test]
+async fn request_fails_when_no_agent_is_reachable() {
+    let monitored_llamacpp = Testserver::start(&[]).await;
+    let balancer = Balancer::start(&["--max-retries-per-request", "1"]).await;
+    let _unreachable_agent = Agent::start(
+        "unreachable",
+        &balancer,
+        monitored_llamacpp.addr,
+        free_addr(),
+        &["--status-interval", "5s"],
+    );
+
+    balancer.wait_for_agents(&["unreachable"]).await;
+
+    let response = Client::new()
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }))
+        .send()
+        .await
+        .expect("balancer should respond");
+
+    assert!(response.status().is_server_error());
+
+    let unreachable = balancer.agent("unreachable").await;
+
+    assert!(!unreachable["quarantined_until"].is_null());
+    assert_eq!(unreachable["slots_idle"], 4);
+    assert_eq!(unreachable["requests_in_flight"], 0);
+}
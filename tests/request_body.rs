@@ -0,0 +1,207 @@
+#![cfg(all(feature = "agent", feature = "balancer"))]
+
+mod common;
+
+use common::{wait_for, Agent, Balancer, Testserver};
+use futures::stream;
+use reqwest::{Body, Client};
+use serde_json::json;
+use std::{io, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// Gives up on the responses that never come, instead of hanging the test
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads until the end of the response head, and nothing more
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.expect("head should be complete"));
+    }
+
+    String::from_utf8(head).expect("head should be text")
+}
+
+/// Sends the body only after the interim response, like curl does with the large bodies
+async fn send_expecting_continue(balancer: &Balancer, body: &str) -> String {
+    let mut stream = TcpStream::connect(balancer.reverseproxy_addr)
+        .await
+        .expect("balancer should accept the connection");
+
+    stream
+        .write_all(
+            format!(
+                "POST /completion HTTP/1.1\r\n\
+                 Host: localhost\r\n\
+                 Connection: close\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Expect: 100-continue\r\n\r\n",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .expect("request head should be sent");
+
+    let interim_head = timeout(RESPONSE_TIMEOUT, read_head(&mut stream))
+        .await
+        .expect("balancer should respond with 100 Continue");
+
+    assert!(interim_head.starts_with("HTTP/1.1 100 Continue\r\n"));
+
+    stream
+        .write_all(body.as_bytes())
+        .await
+        .expect("request body should be sent");
+
+    let mut response = Vec::new();
+
+    timeout(RESPONSE_TIMEOUT, stream.read_to_end(&mut response))
+        .await
+        .expect("balancer should respond after the body")
+        .expect("response should be complete");
+
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn request_expecting_continue_sends_the_body_after_the_interim_response() {
+    let testserver = Testserver::start(&["--slots", "2", "--token-latency", "10"]).await;
+    let balancer = Balancer::start(&[]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms"],
+    );
+
+    balancer.wait_for_agents(&["agent"]).await;
+
+    // the smaller body is buffered before the request takes a slot, the larger one is
+    // forwarded as it arrives
+    for prompt_size in [1024, 1024 * 1024] {
+        let body = json!({ "prompt": "a".repeat(prompt_size) }).to_string();
+        let response = send_expecting_continue(&balancer, &body).await;
+
+        // llama.cpp responds with 400 if the body it got is not the whole JSON document
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\"stop\":true"));
+        // the interim response is only sent once, by the balancer
+        assert!(!response.contains("100 Continue"));
+    }
+
+    wait_for("the slot to be released", || async {
+        let agent = balancer.agent("agent").await;
+
+        agent["slots_idle"] == 2 && agent["requests_in_flight"] == 0
+    })
+    .await;
+
+    assert_eq!(testserver.slots_processing().await, 0);
+
+    let agent = balancer.agent("agent").await;
+
+    assert_eq!(agent["response_status_counts"]["status_2xx"], 2);
+    assert_eq!(agent["response_status_counts"]["status_4xx"], 0);
+}
+
+#[tokio::test]
+async fn inspected_body_larger_than_the_retry_buffer_is_forwarded_whole() {
+    let testserver = Testserver::start(&["--slots", "2", "--token-latency", "10"]).await;
+    // the response cache reads the completion request bodies
+    let balancer = Balancer::start(&["--response-cache-max-entries", "10"]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms"],
+    );
+    // the body that does not fit the retry buffer used to never reach llama.cpp
+    let client = Client::builder()
+        .timeout(RESPONSE_TIMEOUT)
+        .build()
+        .expect("client should build");
+
+    balancer.wait_for_agents(&["agent"]).await;
+
+    for prompt_size in [1024, 1024 * 1024] {
+        let response = client
+            .post(balancer.completion_url())
+            .json(&json!({ "prompt": "a".repeat(prompt_size), "temperature": 0 }))
+            .send()
+            .await
+            .expect("balancer should respond");
+
+        // llama.cpp responds with 400 if the body it got is not the whole JSON document
+        assert_eq!(response.status(), 200, "prompt of {} bytes", prompt_size);
+        assert!(response
+            .text()
+            .await
+            .expect("completion should finish")
+            .contains("\"stop\":true"));
+    }
+
+    let agent = balancer.agent("agent").await;
+
+    assert_eq!(agent["response_status_counts"]["status_2xx"], 2);
+    assert_eq!(agent["requests_in_flight"], 0);
+}
+
+#[tokio::test]
+async fn inspected_body_sent_without_content_length_is_forwarded_whole() {
+    let testserver = Testserver::start(&["--slots", "2", "--token-latency", "10"]).await;
+    // the response cache reads the completion request bodies
+    let balancer = Balancer::start(&["--response-cache-max-entries", "10"]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms"],
+    );
+    let client = Client::builder()
+        .timeout(RESPONSE_TIMEOUT)
+        .build()
+        .expect("client should build");
+
+    balancer.wait_for_agents(&["agent"]).await;
+
+    for prompt_size in [1024, 1024 * 1024] {
+        let body = json!({ "prompt": "a".repeat(prompt_size), "temperature": 0 }).to_string();
+        // a streamed body is sent in chunks, so the balancer only finds out its size while
+        // reading it, and the larger one used to be rejected with 413
+        let chunks: Vec<io::Result<Vec<u8>>> = body
+            .as_bytes()
+            .chunks(16 * 1024)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect();
+        let response = client
+            .post(balancer.completion_url())
+            .header("Content-Type", "application/json")
+            .body(Body::wrap_stream(stream::iter(chunks)))
+            .send()
+            .await
+            .expect("balancer should respond");
+
+        // llama.cpp responds with 400 if the body it got is not the whole JSON document
+        assert_eq!(response.status(), 200, "prompt of {} bytes", prompt_size);
+        assert!(response
+            .text()
+            .await
+            .expect("completion should finish")
+            .contains("\"stop\":true"));
+    }
+
+    let agent = balancer.agent("agent").await;
+
+    assert_eq!(agent["response_status_counts"]["status_2xx"], 2);
+    assert_eq!(agent["requests_in_flight"], 0);
+}
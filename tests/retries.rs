@@ -0,0 +1,141 @@
+#![cfg(all(feature = "agent", feature = "balancer"))]
+
+mod common;
+
+use common::{Agent, Balancer, Testserver};
+use reqwest::Client;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+const COMPLETION: &str = "data: {\"content\":\" token\",\"stop\":true}\n\n";
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Option<()> {
+    let mut head = Vec::new();
+
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.ok()?);
+    }
+
+    let content_length = String::from_utf8_lossy(&head)
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())
+                .flatten()
+        })
+        .unwrap_or(0);
+
+    stream.read_exact(&mut vec![0; content_length]).await.ok()?;
+
+    Some(())
+}
+
+/// Completes the first request on each connection, and cuts off the response to the next one
+/// after a token, so the balancer reads it from a reused connection
+async fn serve_then_cut_off(listener: TcpListener) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(stream);
+
+            read_request(&mut stream).await?;
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: {}\r\n\r\n{}",
+                        COMPLETION.len(),
+                        COMPLETION
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .ok()?;
+
+            read_request(&mut stream).await?;
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: 1000\r\n\r\ndata: {\"content\":\" token\",\"stop\":false}\n\n",
+                )
+                .await
+                .ok()?;
+
+            Some(())
+        });
+    }
+}
+
+#[tokio::test]
+async fn response_cut_off_after_bytes_reached_the_client_is_not_retried() {
+    let healthy_llamacpp = Testserver::start(&["--slots", "2", "--token-latency", "10"]).await;
+    // has more idle slots, so the balancer picks it first
+    let monitored_llamacpp = Testserver::start(&["--slots", "4"]).await;
+    let failing_llamacpp = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("a free port should be available");
+    let failing_llamacpp_addr = failing_llamacpp
+        .local_addr()
+        .expect("listener should have an address");
+    let balancer = Balancer::start(&[]).await;
+    let _healthy_agent = Agent::start(
+        "healthy",
+        &balancer,
+        healthy_llamacpp.addr,
+        healthy_llamacpp.addr,
+        &["--status-interval", "500ms"],
+    );
+    let _failing_agent = Agent::start(
+        "failing",
+        &balancer,
+        monitored_llamacpp.addr,
+        failing_llamacpp_addr,
+        &["--status-interval", "500ms"],
+    );
+    let client = Client::new();
+
+    tokio::spawn(serve_then_cut_off(failing_llamacpp));
+    balancer.wait_for_agents(&["healthy", "failing"]).await;
+
+    let response = client
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }))
+        .send()
+        .await
+        .expect("balancer should respond");
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.text().await.expect("completion should finish"),
+        COMPLETION
+    );
+
+    // a request on a reused connection is retried if it fails, unless the client already got
+    // a part of the response
+    let response = client
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }))
+        .send()
+        .await
+        .expect("balancer should respond");
+
+    assert_eq!(response.status(), 200);
+    assert!(response.bytes().await.is_err());
+
+    let healthy = balancer.agent("healthy").await;
+
+    assert_eq!(healthy["response_status_counts"]["status_2xx"], 0);
+    assert_eq!(healthy["requests_in_flight"], 0);
+
+    let failing = balancer.agent("failing").await;
+
+    assert_eq!(failing["response_status_counts"]["status_2xx"], 2);
+    assert_eq!(failing["requests_in_flight"], 0);
+    assert_eq!(failing["slots_processing"], 0);
+}
@@ -0,0 +1,126 @@
+#![cfg(all(feature = "agent", feature = "balancer"))]
+
+mod common;
+
+use common::{wait_for, Agent, Balancer, Testserver};
+use reqwest::{Client, Response};
+use serde_json::json;
+
+async fn start_completion(client: &Client, balancer: &Balancer) -> Response {
+    let mut response = client
+        .post(balancer.completion_url())
+        .json(&json!({ "prompt": "Hello" }))
+        .send()
+        .await
+        .expect("balancer should respond");
+
+    assert_eq!(response.status(), 200);
+    // the first token means llama.cpp is processing the request
+    response
+        .chunk()
+        .await
+        .expect("completion should stream")
+        .expect("completion should have tokens");
+
+    response
+}
+
+async fn wait_for_slots(balancer: &Balancer, slots_idle: usize, slots_processing: usize) {
+    wait_for(
+        &format!(
+            "{} idle and {} processing slots",
+            slots_idle, slots_processing
+        ),
+        || async {
+            let agent = balancer.agent("agent").await;
+
+            agent["slots_idle"] == slots_idle
+                && agent["slots_processing"] == slots_processing
+                && agent["requests_in_flight"] == slots_processing
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn slots_are_taken_for_the_requests_in_progress_and_released_after_them() {
+    let testserver = Testserver::start(&["--slots", "2", "--token-latency", "100"]).await;
+    let balancer = Balancer::start(&[]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms"],
+    );
+    let client = Client::new();
+
+    balancer.wait_for_agents(&["agent"]).await;
+    wait_for_slots(&balancer, 2, 0).await;
+
+    let first = start_completion(&client, &balancer).await;
+    let second = start_completion(&client, &balancer).await;
+
+    wait_for_slots(&balancer, 0, 2).await;
+    assert_eq!(testserver.slots_processing().await, 2);
+
+    // waits for a slot instead of overcommitting llama.cpp
+    let third = tokio::spawn({
+        let client = client.clone();
+        let url = balancer.completion_url();
+
+        async move {
+            client
+                .post(url)
+                .json(&json!({ "prompt": "Hello" }))
+                .send()
+                .await
+                .expect("balancer should respond")
+        }
+    });
+
+    for response in [first, second] {
+        response.bytes().await.expect("completion should finish");
+    }
+
+    let third = third.await.expect("third request should not panic");
+
+    assert_eq!(third.status(), 200);
+    assert!(third
+        .text()
+        .await
+        .expect("completion should finish")
+        .contains("\"stop\":true"));
+
+    wait_for_slots(&balancer, 2, 0).await;
+    assert_eq!(testserver.slots_processing().await, 0);
+}
+
+#[tokio::test]
+async fn slot_is_released_when_the_client_disconnects() {
+    let testserver = Testserver::start(&["--slots", "2", "--token-latency", "100"]).await;
+    let balancer = Balancer::start(&[]).await;
+    let _agent = Agent::start(
+        "agent",
+        &balancer,
+        testserver.addr,
+        testserver.addr,
+        &["--status-interval", "500ms"],
+    );
+    let client = Client::new();
+
+    balancer.wait_for_agents(&["agent"]).await;
+    wait_for_slots(&balancer, 2, 0).await;
+
+    let response = start_completion(&client, &balancer).await;
+
+    wait_for_slots(&balancer, 1, 1).await;
+
+    drop(response);
+
+    wait_for_slots(&balancer, 2, 0).await;
+    wait_for("llama.cpp to free the slot", || async {
+        testserver.slots_processing().await == 0
+    })
+    .await;
+}